@@ -0,0 +1,144 @@
+use std::collections::HashSet;
+
+use bigdecimal::BigDecimal;
+use toy_sql_parser::{expression::AggregateFunction, value::Value};
+
+use crate::{error::QueryExecutionError, eval::sort_order};
+
+/// The running state of an aggregate over the rows of a group
+///
+/// `NULL` values are skipped, the aggregates of no values are `NULL` except
+/// for `COUNT` which is 0.
+#[derive(Debug, Clone)]
+pub(crate) enum Accumulator {
+    Count(usize),
+    Sum(Option<BigDecimal>),
+    Min(Option<Value>),
+    Max(Option<Value>),
+    Avg {
+        sum: BigDecimal,
+        count: usize,
+    },
+    /// Only adds each value to `inner` the first time it is seen
+    Distinct {
+        seen: HashSet<Value>,
+        inner: Box<Accumulator>,
+    },
+}
+
+impl Accumulator {
+    pub fn new(function: AggregateFunction, distinct: bool) -> Self {
+        if distinct {
+            return Accumulator::Distinct {
+                seen: HashSet::new(),
+                inner: Box::new(Accumulator::new(function, false)),
+            };
+        }
+        match function {
+            AggregateFunction::Count => Accumulator::Count(0),
+            AggregateFunction::Sum => Accumulator::Sum(None),
+            AggregateFunction::Min => Accumulator::Min(None),
+            AggregateFunction::Max => Accumulator::Max(None),
+            AggregateFunction::Avg => Accumulator::Avg {
+                sum: BigDecimal::from(0),
+                count: 0,
+            },
+        }
+    }
+
+    pub fn add(&mut self, value: Value) -> Result<(), QueryExecutionError> {
+        match (self, value) {
+            (_, Value::Null) => {}
+            (Accumulator::Distinct { seen, inner }, value) => {
+                if seen.insert(value.clone()) {
+                    inner.add(value)?;
+                }
+            }
+            (Accumulator::Count(count), _) => *count += 1,
+            (Accumulator::Sum(sum), Value::Number(n)) => {
+                *sum = Some(sum.take().unwrap_or_default() + n);
+            }
+            (Accumulator::Avg { sum, count }, Value::Number(n)) => {
+                *sum += n;
+                *count += 1;
+            }
+            (Accumulator::Min(min), value) => {
+                let smaller = match min {
+                    Some(min) => sort_order(&value, min).is_lt(),
+                    None => true,
+                };
+                if smaller {
+                    *min = Some(value);
+                }
+            }
+            (Accumulator::Max(max), value) => {
+                let larger = match max {
+                    Some(max) => sort_order(&value, max).is_gt(),
+                    None => true,
+                };
+                if larger {
+                    *max = Some(value);
+                }
+            }
+            (Accumulator::Sum(_) | Accumulator::Avg { .. }, value) => {
+                return Err(QueryExecutionError::IncomparableValues(
+                    value,
+                    Value::Number(0.into()),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Take the values a `DISTINCT` aggregate saw, starting over without
+    /// them, to add them again with [`add_distinct`](Self::add_distinct)
+    pub fn take_distinct(&mut self) -> Vec<Value> {
+        match self {
+            Accumulator::Distinct { seen, inner } => {
+                **inner = inner.emptied();
+                seen.drain().collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    /// Add a value a `DISTINCT` aggregate is known not to have seen, without
+    /// keeping it
+    pub fn add_distinct(&mut self, value: Value) -> Result<(), QueryExecutionError> {
+        match self {
+            Accumulator::Distinct { inner, .. } => inner.add(value),
+            accumulator => accumulator.add(value),
+        }
+    }
+
+    /// The same aggregate, of no values
+    fn emptied(&self) -> Self {
+        match self {
+            Accumulator::Count(_) => Accumulator::Count(0),
+            Accumulator::Sum(_) => Accumulator::Sum(None),
+            Accumulator::Min(_) => Accumulator::Min(None),
+            Accumulator::Max(_) => Accumulator::Max(None),
+            Accumulator::Avg { .. } => Accumulator::Avg {
+                sum: BigDecimal::from(0),
+                count: 0,
+            },
+            Accumulator::Distinct { inner, .. } => Accumulator::Distinct {
+                seen: HashSet::new(),
+                inner: Box::new(inner.emptied()),
+            },
+        }
+    }
+
+    pub fn finish(self) -> Value {
+        match self {
+            Accumulator::Count(count) => Value::Number((count as u64).into()),
+            Accumulator::Sum(sum) => sum.map_or(Value::Null, Value::Number),
+            Accumulator::Min(value) | Accumulator::Max(value) => value.unwrap_or(Value::Null),
+            Accumulator::Distinct { inner, .. } => inner.finish(),
+            Accumulator::Avg { count: 0, .. } => Value::Null,
+            Accumulator::Avg { sum, count } => {
+                Value::Number((sum / BigDecimal::from(count as u64)).round(6).normalized())
+            }
+        }
+    }
+}
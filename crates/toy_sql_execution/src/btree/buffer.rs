@@ -0,0 +1,251 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+};
+
+use super::pager::{Page, PageId};
+
+/// A page held in the buffer pool, it can't be evicted while the handle is
+/// alive
+pub type PinnedPage = Arc<Page>;
+
+/// Counters of how well the buffer pool is doing, for tuning its size
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BufferPoolStats {
+    /// Reads of a page that was in memory
+    pub hits: u64,
+    /// Reads that had to go to the database file
+    pub misses: u64,
+    /// Pages dropped from memory to make room for others
+    pub evictions: u64,
+    /// Pages in memory
+    pub resident: usize,
+}
+
+#[derive(Debug)]
+struct Frame {
+    page: PinnedPage,
+    /// written since the last flush
+    dirty: bool,
+    /// position in the LRU order
+    last_used: u64,
+}
+
+impl Frame {
+    fn is_pinned(&self) -> bool {
+        Arc::strong_count(&self.page) > 1
+    }
+}
+
+/// Keeps up to `capacity` pages in memory, evicting the least recently used
+///
+/// Evicted dirty pages are handed back through
+/// [`take_evicted`](Self::take_evicted) for the pager to write out. Pinned
+/// pages stay until their handles are dropped, so the pool can temporarily
+/// grow past its capacity.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    capacity: usize,
+    frames: HashMap<PageId, Frame>,
+    /// last use to page, least recent first
+    lru: BTreeMap<u64, PageId>,
+    /// dirty pages evicted since the last `take_evicted`
+    evicted: Vec<(PageId, PinnedPage)>,
+    /// incremented on every access
+    clock: u64,
+    stats: BufferPoolStats,
+}
+
+impl BufferPool {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            frames: HashMap::new(),
+            lru: BTreeMap::new(),
+            evicted: Vec::new(),
+            clock: 0,
+            stats: BufferPoolStats::default(),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        self.evict();
+    }
+
+    pub fn stats(&self) -> BufferPoolStats {
+        BufferPoolStats {
+            resident: self.frames.len(),
+            ..self.stats
+        }
+    }
+
+    /// Pin a page if it is in memory, counting a hit or a miss
+    pub fn get(&mut self, id: PageId) -> Option<PinnedPage> {
+        if !self.frames.contains_key(&id) {
+            self.stats.misses += 1;
+            return None;
+        }
+
+        self.stats.hits += 1;
+        self.touch(id);
+        Some(self.frames[&id].page.clone())
+    }
+
+    /// Keep a page read from the database file, returning it pinned
+    pub fn insert(&mut self, id: PageId, page: Page) -> PinnedPage {
+        let page = Arc::new(page);
+        self.put(id, page.clone(), false);
+        page
+    }
+
+    /// Keep a dirty page read back after it was evicted, returning it pinned
+    pub fn reload(&mut self, id: PageId, page: Page) -> PinnedPage {
+        let page = Arc::new(page);
+        self.put(id, page.clone(), true);
+        page
+    }
+
+    /// Replace the content of a page, it is handed back if evicted before
+    /// being marked clean
+    pub fn write(&mut self, id: PageId, page: Page) {
+        self.put(id, Arc::new(page), true);
+    }
+
+    fn put(&mut self, id: PageId, page: PinnedPage, dirty: bool) {
+        self.clock += 1;
+        let frame = Frame {
+            page,
+            dirty,
+            last_used: self.clock,
+        };
+        if let Some(old) = self.frames.insert(id, frame) {
+            self.lru.remove(&old.last_used);
+        }
+        self.lru.insert(self.clock, id);
+        self.evict();
+    }
+
+    fn touch(&mut self, id: PageId) {
+        self.clock += 1;
+        let frame = self
+            .frames
+            .get_mut(&id)
+            .expect("touched page is in the pool");
+        self.lru.remove(&frame.last_used);
+        self.lru.insert(self.clock, id);
+        frame.last_used = self.clock;
+    }
+
+    /// The pages written since they were last marked clean, in id order
+    pub fn dirty_pages(&self) -> Vec<(PageId, PinnedPage)> {
+        let mut pages: Vec<_> = self
+            .frames
+            .iter()
+            .filter(|(_, frame)| frame.dirty)
+            .map(|(id, frame)| (*id, frame.page.clone()))
+            .collect();
+        pages.sort_unstable_by_key(|(id, _)| *id);
+        pages
+    }
+
+    pub fn dirty_count(&self) -> usize {
+        self.frames.values().filter(|frame| frame.dirty).count()
+    }
+
+    /// Call once all the dirty pages were written to the database file
+    pub fn mark_clean(&mut self) {
+        for frame in self.frames.values_mut() {
+            frame.dirty = false;
+        }
+    }
+
+    /// The dirty pages evicted since the last call, which have to be written
+    /// somewhere before they can be read again
+    pub fn take_evicted(&mut self) -> Vec<(PageId, PinnedPage)> {
+        std::mem::take(&mut self.evicted)
+    }
+
+    /// Drop the least recently used unpinned pages until the pool is back to
+    /// its capacity
+    fn evict(&mut self) {
+        while self.frames.len() > self.capacity {
+            let victim = self
+                .lru
+                .iter()
+                .find(|(_, id)| !self.frames[id].is_pinned())
+                .map(|(last_used, id)| (*last_used, *id));
+            let Some((last_used, id)) = victim else {
+                return;
+            };
+
+            self.lru.remove(&last_used);
+            let frame = self.frames.remove(&id).expect("victim is in the pool");
+            if frame.dirty {
+                self.evicted.push((id, frame.page));
+            }
+            self.stats.evictions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::btree::pager::empty_page;
+
+    #[test]
+    fn test_lru_eviction() {
+        let mut pool = BufferPool::new(2);
+        pool.insert(1, empty_page());
+        pool.insert(2, empty_page());
+        assert!(pool.get(1).is_some());
+
+        // 2 is the least recently used
+        pool.insert(3, empty_page());
+        assert!(pool.get(2).is_none());
+        assert!(pool.get(1).is_some());
+        assert!(pool.get(3).is_some());
+
+        assert_eq!(
+            pool.stats(),
+            BufferPoolStats {
+                hits: 3,
+                misses: 1,
+                evictions: 1,
+                resident: 2,
+            }
+        );
+    }
+
+    #[test]
+    fn test_pinned_pages_stay_and_dirty_pages_are_handed_back() {
+        let mut pool = BufferPool::new(2);
+        let pinned = pool.insert(1, empty_page());
+        pool.write(2, empty_page());
+        pool.insert(3, empty_page());
+
+        // 2 was the least recently used page that could go
+        let evicted: Vec<_> = pool.take_evicted().into_iter().map(|(id, _)| id).collect();
+        assert_eq!(evicted, vec![2]);
+        assert!(pool.get(2).is_none());
+        assert!(pool.get(1).is_some());
+        assert!(pool.get(3).is_some());
+
+        drop(pinned);
+        pool.write(4, empty_page());
+        assert!(pool.take_evicted().is_empty());
+        assert_eq!(pool.dirty_count(), 1);
+        assert_eq!(pool.stats().resident, 2);
+
+        pool.mark_clean();
+        pool.insert(5, empty_page());
+        pool.insert(6, empty_page());
+        assert!(pool.take_evicted().is_empty());
+        assert_eq!(pool.dirty_count(), 0);
+    }
+}
@@ -0,0 +1,547 @@
+use std::ops::{Bound, RangeBounds};
+
+use crate::error::QueryExecutionError;
+
+mod buffer;
+mod node;
+mod pager;
+
+pub use buffer::BufferPoolStats;
+use node::Node;
+pub(crate) use node::MAX_VALUE_SIZE;
+pub(crate) use pager::SharedPager;
+pub use pager::{Page, PageId, Pager, PAGE_SIZE};
+
+/// Nodes smaller than this are merged with a sibling after a delete
+const MIN_NODE_SIZE: usize = PAGE_SIZE / 4;
+
+/// A B+tree of `u64` keys to byte values, stored in the pages of a [`Pager`]
+///
+/// The root always stays on the same page so the catalog doesn't have to be
+/// updated when the tree grows or shrinks.
+#[derive(Debug, Clone)]
+pub(crate) struct BTree {
+    pager: SharedPager,
+    root: PageId,
+}
+
+impl BTree {
+    /// Create an empty tree in newly allocated pages
+    pub fn create(pager: SharedPager) -> Result<Self, QueryExecutionError> {
+        let root = pager.lock().allocate()?;
+        let tree = Self { pager, root };
+        tree.write(root, &Node::empty_leaf())?;
+        Ok(tree)
+    }
+
+    /// Open the tree rooted at the given page
+    pub fn open(pager: SharedPager, root: PageId) -> Self {
+        Self { pager, root }
+    }
+
+    pub fn root(&self) -> PageId {
+        self.root
+    }
+
+    fn read(&self, id: PageId) -> Result<Node, QueryExecutionError> {
+        let page = self.pager.lock().read(id)?;
+        Node::decode(&page, id)
+    }
+
+    fn write(&self, id: PageId, node: &Node) -> Result<(), QueryExecutionError> {
+        Ok(self.pager.lock().write(id, node.encode())?)
+    }
+
+    fn allocate(&self, node: &Node) -> Result<PageId, QueryExecutionError> {
+        let id = self.pager.lock().allocate()?;
+        self.write(id, node)?;
+        Ok(id)
+    }
+
+    fn free(&self, id: PageId) -> Result<(), QueryExecutionError> {
+        Ok(self.pager.lock().free(id)?)
+    }
+
+    /// Find the leaf that holds (or would hold) `key`
+    fn find_leaf(&self, key: u64) -> Result<Node, QueryExecutionError> {
+        let mut node = self.read(self.root)?;
+        while let Node::Interior { keys, children } = node {
+            let child = children[keys.partition_point(|k| *k <= key)];
+            node = self.read(child)?;
+        }
+        Ok(node)
+    }
+
+    pub fn get(&self, key: u64) -> Result<Option<Vec<u8>>, QueryExecutionError> {
+        let Node::Leaf { entries, .. } = self.find_leaf(key)? else {
+            unreachable!("find_leaf returns leaves")
+        };
+
+        Ok(entries
+            .binary_search_by_key(&key, |(k, _)| *k)
+            .ok()
+            .map(|pos| entries[pos].1.clone()))
+    }
+
+    /// The largest key in the tree
+    pub fn last_key(&self) -> Result<Option<u64>, QueryExecutionError> {
+        let mut node = self.read(self.root)?;
+        while let Node::Interior { children, .. } = node {
+            node = self.read(*children.last().unwrap())?;
+        }
+
+        let Node::Leaf { entries, .. } = node else {
+            unreachable!()
+        };
+        Ok(entries.last().map(|(k, _)| *k))
+    }
+
+    /// Insert a value, replacing the previous value of `key` if there is one
+    pub fn insert(&self, key: u64, value: Vec<u8>) -> Result<(), QueryExecutionError> {
+        if value.len() > MAX_VALUE_SIZE {
+            return Err(QueryExecutionError::RowTooLarge(value.len()));
+        }
+
+        let Some((split_key, right)) = self.insert_into(self.root, key, value)? else {
+            return Ok(());
+        };
+
+        // the root split: move its left half to a new page and make it the
+        // parent of both halves
+        let left = self.allocate(&self.read(self.root)?)?;
+        self.write(
+            self.root,
+            &Node::Interior {
+                keys: vec![split_key],
+                children: vec![left, right],
+            },
+        )
+    }
+
+    /// Insert into the subtree at `id`, returning the separator key and page
+    /// of the new right sibling if the node had to be split
+    fn insert_into(
+        &self,
+        id: PageId,
+        key: u64,
+        value: Vec<u8>,
+    ) -> Result<Option<(u64, PageId)>, QueryExecutionError> {
+        match self.read(id)? {
+            Node::Leaf { mut entries, next } => {
+                match entries.binary_search_by_key(&key, |(k, _)| *k) {
+                    Ok(pos) => entries[pos].1 = value,
+                    Err(pos) => entries.insert(pos, (key, value)),
+                }
+
+                let node = Node::Leaf { entries, next };
+                if node.fits() {
+                    self.write(id, &node)?;
+                    return Ok(None);
+                }
+
+                let Node::Leaf { mut entries, next } = node else {
+                    unreachable!()
+                };
+
+                // split where the left half reaches half a page
+                let mut size = 0;
+                let mid = entries
+                    .iter()
+                    .position(|(_, value)| {
+                        size += value.len() + 10;
+                        size > PAGE_SIZE / 2
+                    })
+                    .unwrap_or(entries.len() / 2)
+                    .clamp(1, entries.len() - 1);
+
+                let right_entries = entries.split_off(mid);
+                let split_key = right_entries[0].0;
+                let right = self.allocate(&Node::Leaf {
+                    entries: right_entries,
+                    next,
+                })?;
+                self.write(
+                    id,
+                    &Node::Leaf {
+                        entries,
+                        next: right,
+                    },
+                )?;
+
+                Ok(Some((split_key, right)))
+            }
+            Node::Interior {
+                mut keys,
+                mut children,
+            } => {
+                let pos = keys.partition_point(|k| *k <= key);
+                let Some((split_key, right)) = self.insert_into(children[pos], key, value)? else {
+                    return Ok(None);
+                };
+
+                keys.insert(pos, split_key);
+                children.insert(pos + 1, right);
+
+                let node = Node::Interior { keys, children };
+                if node.fits() {
+                    self.write(id, &node)?;
+                    return Ok(None);
+                }
+
+                let Node::Interior {
+                    mut keys,
+                    mut children,
+                } = node
+                else {
+                    unreachable!()
+                };
+
+                // the middle key moves up to the parent
+                let mid = keys.len() / 2;
+                let right_keys = keys.split_off(mid + 1);
+                let split_key = keys.pop().unwrap();
+                let right_children = children.split_off(mid + 1);
+
+                let right = self.allocate(&Node::Interior {
+                    keys: right_keys,
+                    children: right_children,
+                })?;
+                self.write(id, &Node::Interior { keys, children })?;
+
+                Ok(Some((split_key, right)))
+            }
+        }
+    }
+
+    /// Free all the pages of the tree
+    pub fn destroy(self) -> Result<(), QueryExecutionError> {
+        self.free_subtree(self.root)
+    }
+
+    fn free_subtree(&self, id: PageId) -> Result<(), QueryExecutionError> {
+        if let Node::Interior { children, .. } = self.read(id)? {
+            for child in children {
+                self.free_subtree(child)?;
+            }
+        }
+        self.free(id)
+    }
+
+    /// Remove a key, returning whether it was in the tree
+    pub fn delete(&self, key: u64) -> Result<bool, QueryExecutionError> {
+        let found = self.delete_from(self.root, key)?;
+
+        // an interior root left with a single child is replaced by that child
+        while let Node::Interior { keys, children } = self.read(self.root)? {
+            if !keys.is_empty() {
+                break;
+            }
+            let child = children[0];
+            self.write(self.root, &self.read(child)?)?;
+            self.free(child)?;
+        }
+
+        Ok(found)
+    }
+
+    fn delete_from(&self, id: PageId, key: u64) -> Result<bool, QueryExecutionError> {
+        match self.read(id)? {
+            Node::Leaf { mut entries, next } => {
+                let Ok(pos) = entries.binary_search_by_key(&key, |(k, _)| *k) else {
+                    return Ok(false);
+                };
+                entries.remove(pos);
+                self.write(id, &Node::Leaf { entries, next })?;
+                Ok(true)
+            }
+            Node::Interior {
+                mut keys,
+                mut children,
+            } => {
+                let pos = keys.partition_point(|k| *k <= key);
+                if !self.delete_from(children[pos], key)? {
+                    return Ok(false);
+                }
+
+                if self.read(children[pos])?.size() >= MIN_NODE_SIZE {
+                    return Ok(true);
+                }
+
+                // merge the underfull child with a sibling if both fit in one
+                // page, the right node of the pair is absorbed into the left
+                let left = if pos > 0 { pos - 1 } else { pos };
+                if left + 1 < children.len()
+                    && self.merge(keys[left], children[left], children[left + 1])?
+                {
+                    keys.remove(left);
+                    children.remove(left + 1);
+                    self.write(id, &Node::Interior { keys, children })?;
+                }
+
+                Ok(true)
+            }
+        }
+    }
+
+    /// Merge the `right` node into `left`, freeing `right`
+    ///
+    /// `separator` is the parent key between the two nodes. Returns false if
+    /// the merged node would not fit in a page.
+    fn merge(
+        &self,
+        separator: u64,
+        left: PageId,
+        right: PageId,
+    ) -> Result<bool, QueryExecutionError> {
+        let merged = match (self.read(left)?, self.read(right)?) {
+            (
+                Node::Leaf { mut entries, .. },
+                Node::Leaf {
+                    entries: right_entries,
+                    next,
+                },
+            ) => {
+                entries.extend(right_entries);
+                Node::Leaf { entries, next }
+            }
+            (
+                Node::Interior {
+                    mut keys,
+                    mut children,
+                },
+                Node::Interior {
+                    keys: right_keys,
+                    children: right_children,
+                },
+            ) => {
+                keys.push(separator);
+                keys.extend(right_keys);
+                children.extend(right_children);
+                Node::Interior { keys, children }
+            }
+            _ => {
+                return Err(QueryExecutionError::CorruptDatabase(format!(
+                    "b-tree siblings {left} and {right} are at different depths"
+                )))
+            }
+        };
+
+        if !merged.fits() {
+            return Ok(false);
+        }
+
+        self.write(left, &merged)?;
+        self.free(right)?;
+        Ok(true)
+    }
+
+    /// Iterate over the entries with keys in `range`, in key order
+    pub fn range(&self, range: impl RangeBounds<u64>) -> Result<BTreeIter, QueryExecutionError> {
+        let start = match range.start_bound() {
+            Bound::Included(start) => Some(*start),
+            Bound::Excluded(start) => start.checked_add(1),
+            Bound::Unbounded => Some(0),
+        };
+
+        let Some(start) = start else {
+            // nothing comes after u64::MAX
+            return Ok(BTreeIter {
+                pager: self.pager.clone(),
+                entries: Vec::new().into_iter(),
+                next: 0,
+                end: Bound::Unbounded,
+            });
+        };
+
+        let Node::Leaf { mut entries, next } = self.find_leaf(start)? else {
+            unreachable!("find_leaf returns leaves")
+        };
+        entries.retain(|(k, _)| *k >= start);
+
+        Ok(BTreeIter {
+            pager: self.pager.clone(),
+            entries: entries.into_iter(),
+            next,
+            end: range.end_bound().cloned(),
+        })
+    }
+}
+
+/// Iterator over the entries of a [`BTree`], following the leaf links
+pub(crate) struct BTreeIter {
+    pager: SharedPager,
+    /// remaining entries of the current leaf
+    entries: std::vec::IntoIter<(u64, Vec<u8>)>,
+    /// the leaf after the current one, 0 if it is the last
+    next: PageId,
+    end: Bound<u64>,
+}
+
+impl Iterator for BTreeIter {
+    type Item = Result<(u64, Vec<u8>), QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.entries.next() {
+                let in_range = match self.end {
+                    Bound::Included(end) => key <= end,
+                    Bound::Excluded(end) => key < end,
+                    Bound::Unbounded => true,
+                };
+                if !in_range {
+                    self.next = 0;
+                    self.entries = Vec::new().into_iter();
+                    return None;
+                }
+                return Some(Ok((key, value)));
+            }
+
+            if self.next == 0 {
+                return None;
+            }
+
+            let id = self.next;
+            let leaf = self
+                .pager
+                .lock()
+                .read(id)
+                .map_err(QueryExecutionError::from)
+                .and_then(|page| Node::decode(&page, id));
+
+            match leaf {
+                Ok(Node::Leaf { entries, next }) => {
+                    self.entries = entries.into_iter();
+                    self.next = next;
+                }
+                Ok(Node::Interior { .. }) => {
+                    self.next = 0;
+                    return Some(Err(QueryExecutionError::CorruptDatabase(format!(
+                        "leaf link to interior page {id}"
+                    ))));
+                }
+                Err(e) => {
+                    self.next = 0;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::NamedTempFile;
+
+    use super::*;
+
+    const KEYS: u64 = 3000;
+
+    fn value(key: u64) -> Vec<u8> {
+        // big enough values that a few thousand keys need three levels
+        format!("{key:0>1000}").into_bytes()
+    }
+
+    fn keys(tree: &BTree, range: impl RangeBounds<u64>) -> Vec<u64> {
+        tree.range(range)
+            .unwrap()
+            .map(|entry| entry.unwrap().0)
+            .collect()
+    }
+
+    #[test]
+    fn test_insert_get_range() {
+        let file = NamedTempFile::new().unwrap();
+        let pager = SharedPager::new(Pager::open(file.path()).unwrap());
+        let tree = BTree::create(pager).unwrap();
+
+        // insert out of order to split in the middle of nodes too
+        for key in (0..KEYS).rev().step_by(2).chain((0..KEYS).step_by(2)) {
+            tree.insert(key, value(key)).unwrap();
+        }
+        let Node::Interior { children, .. } = tree.read(tree.root()).unwrap() else {
+            panic!("root should have split");
+        };
+        assert!(matches!(
+            tree.read(children[0]).unwrap(),
+            Node::Interior { .. }
+        ));
+
+        for key in 0..KEYS {
+            assert_eq!(tree.get(key).unwrap(), Some(value(key)));
+        }
+        assert_eq!(tree.get(KEYS).unwrap(), None);
+        assert_eq!(tree.last_key().unwrap(), Some(KEYS - 1));
+
+        assert_eq!(keys(&tree, ..), (0..KEYS).collect::<Vec<_>>());
+        assert_eq!(keys(&tree, 100..=300), (100..=300).collect::<Vec<_>>());
+        assert_eq!(
+            keys(&tree, KEYS - 10..),
+            (KEYS - 10..KEYS).collect::<Vec<_>>()
+        );
+
+        tree.insert(42, b"replaced".to_vec()).unwrap();
+        assert_eq!(tree.get(42).unwrap(), Some(b"replaced".to_vec()));
+        assert_eq!(keys(&tree, ..).len(), KEYS as usize);
+        assert!(matches!(
+            tree.insert(1, vec![0; MAX_VALUE_SIZE + 1]),
+            Err(QueryExecutionError::RowTooLarge(_))
+        ));
+    }
+
+    #[test]
+    fn test_delete_merges() {
+        let file = NamedTempFile::new().unwrap();
+        let pager = SharedPager::new(Pager::open(file.path()).unwrap());
+        let tree = BTree::create(pager.clone()).unwrap();
+
+        for key in 0..KEYS {
+            tree.insert(key, value(key)).unwrap();
+        }
+        let last_page = pager.lock().allocate().unwrap();
+
+        for key in (0..KEYS).filter(|k| k % 10 != 0) {
+            assert!(tree.delete(key).unwrap());
+        }
+        assert!(!tree.delete(1).unwrap());
+        assert_eq!(keys(&tree, ..), (0..KEYS).step_by(10).collect::<Vec<_>>());
+
+        for key in (0..KEYS).step_by(10) {
+            assert!(tree.delete(key).unwrap());
+        }
+        // everything merged back into a single empty root leaf
+        assert_eq!(tree.read(tree.root()).unwrap(), Node::empty_leaf());
+        assert_eq!(tree.last_key().unwrap(), None);
+
+        // freed pages get reused instead of growing the file
+        for key in 0..KEYS {
+            tree.insert(key, value(key)).unwrap();
+        }
+        assert_eq!(pager.lock().allocate().unwrap(), last_page + 1);
+
+        // and so do the pages of a destroyed tree
+        tree.destroy().unwrap();
+        let tree = BTree::create(pager.clone()).unwrap();
+        for key in 0..KEYS {
+            tree.insert(key, value(key)).unwrap();
+        }
+        assert_eq!(pager.lock().allocate().unwrap(), last_page + 2);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let file = NamedTempFile::new().unwrap();
+        let pager = SharedPager::new(Pager::open(file.path()).unwrap());
+        let root = {
+            let tree = BTree::create(pager.clone()).unwrap();
+            for key in 0..100 {
+                tree.insert(key, value(key)).unwrap();
+            }
+            pager.lock().set_catalog(b"catalog").unwrap();
+            tree.root()
+        };
+
+        assert_eq!(pager.lock().catalog().unwrap(), b"catalog");
+        let tree = BTree::open(pager, root);
+        assert_eq!(keys(&tree, ..), (0..100).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,169 @@
+use crate::error::QueryExecutionError;
+
+use super::pager::{empty_page, read_u32, write_u32, Page, PageId, PAGE_SIZE};
+
+const LEAF: u8 = 1;
+const INTERIOR: u8 = 2;
+
+/// node type | entry count | next leaf or first child
+const HEADER_SIZE: usize = 1 + 2 + 4;
+/// key | value length
+const LEAF_ENTRY_HEADER_SIZE: usize = 8 + 2;
+/// key | child
+const INTERIOR_ENTRY_SIZE: usize = 8 + 4;
+
+/// Largest value that can be stored, small enough for a leaf to always hold
+/// a few entries
+pub const MAX_VALUE_SIZE: usize = (PAGE_SIZE - HEADER_SIZE) / 4 - LEAF_ENTRY_HEADER_SIZE;
+
+/// A decoded B+tree page
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Node {
+    /// Sorted key/value pairs and the page of the next leaf, 0 for the last
+    Leaf {
+        entries: Vec<(u64, Vec<u8>)>,
+        next: PageId,
+    },
+    /// `children[i]` holds the keys `< keys[i]`, `children[i + 1]` the keys
+    /// `>= keys[i]`
+    Interior {
+        keys: Vec<u64>,
+        children: Vec<PageId>,
+    },
+}
+
+impl Node {
+    pub fn empty_leaf() -> Self {
+        Node::Leaf {
+            entries: Vec::new(),
+            next: 0,
+        }
+    }
+
+    /// Size of the node once encoded in a page
+    pub fn size(&self) -> usize {
+        match self {
+            Node::Leaf { entries, .. } => {
+                HEADER_SIZE
+                    + entries
+                        .iter()
+                        .map(|(_, value)| LEAF_ENTRY_HEADER_SIZE + value.len())
+                        .sum::<usize>()
+            }
+            Node::Interior { keys, .. } => HEADER_SIZE + keys.len() * INTERIOR_ENTRY_SIZE,
+        }
+    }
+
+    pub fn fits(&self) -> bool {
+        self.size() <= PAGE_SIZE
+    }
+
+    /// Encode the node in a page
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node doesn't [`fit`](Self::fits) in a page
+    pub fn encode(&self) -> Page {
+        assert!(
+            self.fits(),
+            "node of {} bytes overflows a page",
+            self.size()
+        );
+
+        let mut page = empty_page();
+        let mut offset = HEADER_SIZE;
+        match self {
+            Node::Leaf { entries, next } => {
+                page[0] = LEAF;
+                page[1..3].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+                write_u32(&mut page[..], 3, *next);
+                for (key, value) in entries {
+                    page[offset..offset + 8].copy_from_slice(&key.to_le_bytes());
+                    page[offset + 8..offset + 10]
+                        .copy_from_slice(&(value.len() as u16).to_le_bytes());
+                    offset += LEAF_ENTRY_HEADER_SIZE;
+                    page[offset..offset + value.len()].copy_from_slice(value);
+                    offset += value.len();
+                }
+            }
+            Node::Interior { keys, children } => {
+                page[0] = INTERIOR;
+                page[1..3].copy_from_slice(&(keys.len() as u16).to_le_bytes());
+                write_u32(&mut page[..], 3, children[0]);
+                for (key, child) in keys.iter().zip(&children[1..]) {
+                    page[offset..offset + 8].copy_from_slice(&key.to_le_bytes());
+                    write_u32(&mut page[..], offset + 8, *child);
+                    offset += INTERIOR_ENTRY_SIZE;
+                }
+            }
+        }
+        page
+    }
+
+    /// Decode a page written by [`encode`](Self::encode)
+    pub fn decode(page: &Page, id: PageId) -> Result<Self, QueryExecutionError> {
+        let corrupt = || QueryExecutionError::CorruptDatabase(format!("bad b-tree page {id}"));
+
+        let count = u16::from_le_bytes([page[1], page[2]]) as usize;
+        let read_u64 =
+            |offset: usize| u64::from_le_bytes(page[offset..offset + 8].try_into().unwrap());
+        let mut offset = HEADER_SIZE;
+
+        match page[0] {
+            LEAF => {
+                let mut entries = Vec::with_capacity(count);
+                for _ in 0..count {
+                    if offset + LEAF_ENTRY_HEADER_SIZE > PAGE_SIZE {
+                        return Err(corrupt());
+                    }
+                    let key = read_u64(offset);
+                    let len = u16::from_le_bytes([page[offset + 8], page[offset + 9]]) as usize;
+                    offset += LEAF_ENTRY_HEADER_SIZE;
+                    let value = page.get(offset..offset + len).ok_or_else(corrupt)?;
+                    entries.push((key, value.to_vec()));
+                    offset += len;
+                }
+                Ok(Node::Leaf {
+                    entries,
+                    next: read_u32(&page[..], 3),
+                })
+            }
+            INTERIOR => {
+                if HEADER_SIZE + count * INTERIOR_ENTRY_SIZE > PAGE_SIZE {
+                    return Err(corrupt());
+                }
+                let mut keys = Vec::with_capacity(count);
+                let mut children = vec![read_u32(&page[..], 3)];
+                for _ in 0..count {
+                    keys.push(read_u64(offset));
+                    children.push(read_u32(&page[..], offset + 8));
+                    offset += INTERIOR_ENTRY_SIZE;
+                }
+                Ok(Node::Interior { keys, children })
+            }
+            _ => Err(corrupt()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let leaf = Node::Leaf {
+            entries: vec![(1, b"one".to_vec()), (7, vec![]), (u64::MAX, vec![9; 300])],
+            next: 42,
+        };
+        assert_eq!(Node::decode(&leaf.encode(), 1).unwrap(), leaf);
+
+        let interior = Node::Interior {
+            keys: vec![10, 20],
+            children: vec![3, 4, 5],
+        };
+        assert_eq!(Node::decode(&interior.encode(), 1).unwrap(), interior);
+
+        assert!(Node::decode(&empty_page(), 1).is_err());
+    }
+}
@@ -0,0 +1,359 @@
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::Path,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use super::buffer::{BufferPool, BufferPoolStats, PinnedPage};
+use crate::wal::{LogRecord, Wal};
+
+/// Size of every page in the database file
+pub const PAGE_SIZE: usize = 4096;
+
+/// Index of a page in the database file
+pub type PageId = u32;
+
+pub type Page = Box<[u8; PAGE_SIZE]>;
+
+const MAGIC: &[u8; 8] = b"toydb\0\0\x01";
+
+/// Pages kept in memory by default, 4MiB
+const DEFAULT_POOL_PAGES: usize = 1024;
+
+/// Bytes of a blob page used for the next page id and the data length
+const BLOB_HEADER_SIZE: usize = 8;
+
+pub fn empty_page() -> Page {
+    Box::new([0; PAGE_SIZE])
+}
+
+pub(crate) fn read_u32(page: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(page[offset..offset + 4].try_into().unwrap())
+}
+
+pub(crate) fn write_u32(page: &mut [u8], offset: usize, value: u32) {
+    page[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+/// Reads and writes the fixed size pages of a database file
+///
+/// Page 0 is the file header: `magic | page count | free list | catalog`.
+/// Freed pages form a linked list through their first 4 bytes so they can be
+/// reused, and the catalog is a blob spread over a linked list of pages.
+///
+/// Pages are cached in a [`BufferPool`]. Written pages stay there until they
+/// are [`flush`](Self::flush)ed, the file itself is left as it was so a crash
+/// can't leave it half updated. The changes since are recovered from the
+/// write-ahead log, which also takes the written pages the pool has no room
+/// for until the next [`checkpoint`](Self::checkpoint).
+#[derive(Debug)]
+pub struct Pager {
+    file: File,
+    /// number of pages in the file, including the header
+    page_count: PageId,
+    /// first freed page, 0 if there are none
+    free_list: PageId,
+    /// first page of the catalog blob, 0 if there is none
+    catalog: PageId,
+    pool: BufferPool,
+    /// log of the database, without one evicted pages are written straight
+    /// to the file
+    wal: Option<Wal>,
+    /// offset in the log of the written pages evicted from the pool
+    evicted: HashMap<PageId, u64>,
+}
+
+/// A [`Pager`] shared by the tables of a database file, and the sessions
+/// using them
+#[derive(Debug, Clone)]
+pub(crate) struct SharedPager(Arc<Mutex<Pager>>);
+
+impl SharedPager {
+    pub fn new(pager: Pager) -> Self {
+        Self(Arc::new(Mutex::new(pager)))
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, Pager> {
+        self.0
+            .lock()
+            .expect("a thread panicked while using the pager")
+    }
+}
+
+impl Pager {
+    /// Open a database file, creating it if it doesn't exist
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut pager = Self {
+            file,
+            page_count: 1,
+            free_list: 0,
+            catalog: 0,
+            pool: BufferPool::new(DEFAULT_POOL_PAGES),
+            wal: None,
+            evicted: HashMap::new(),
+        };
+
+        if pager.file.metadata()?.len() == 0 {
+            pager.write_header()?;
+            return Ok(pager);
+        }
+
+        pager.read_header()?;
+        Ok(pager)
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        let header = self.read(0)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a toydb database file",
+            ));
+        }
+        self.page_count = read_u32(&header[..], 8);
+        self.free_list = read_u32(&header[..], 12);
+        self.catalog = read_u32(&header[..], 16);
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        let mut header = empty_page();
+        header[..8].copy_from_slice(MAGIC);
+        write_u32(&mut header[..], 8, self.page_count);
+        write_u32(&mut header[..], 12, self.free_list);
+        write_u32(&mut header[..], 16, self.catalog);
+        self.write(0, header)
+    }
+
+    /// Read the page with the given id, pinning it in memory until the
+    /// returned page is dropped
+    pub fn read(&mut self, id: PageId) -> io::Result<PinnedPage> {
+        if let Some(page) = self.pool.get(id) {
+            return Ok(page);
+        }
+
+        self.write_back()?;
+        if let Some(page) = self.read_evicted(id)? {
+            let page = self.pool.reload(id, page);
+            self.write_back()?;
+            return Ok(page);
+        }
+
+        let mut page = empty_page();
+        self.file
+            .seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.read_exact(&mut page[..])?;
+        let page = self.pool.insert(id, page);
+        self.write_back()?;
+        Ok(page)
+    }
+
+    /// Overwrite the page with the given id
+    pub fn write(&mut self, id: PageId, page: Page) -> io::Result<()> {
+        self.evicted.remove(&id);
+        self.pool.write(id, page);
+        self.write_back()
+    }
+
+    /// Take back a written page that was evicted from the pool
+    fn read_evicted(&mut self, id: PageId) -> io::Result<Option<Page>> {
+        let (Some(wal), Some(offset)) = (&mut self.wal, self.evicted.remove(&id)) else {
+            return Ok(None);
+        };
+        wal.read_evicted(offset).map(Some)
+    }
+
+    /// Log the written pages the pool evicted, or write them to the file if
+    /// there is no log
+    fn write_back(&mut self) -> io::Result<()> {
+        for (id, page) in self.pool.take_evicted() {
+            match &mut self.wal {
+                Some(wal) => {
+                    let offset = wal.log_evicted(id, &page)?;
+                    self.evicted.insert(id, offset);
+                }
+                None => self.write_to_file(id, &page)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn write_to_file(&mut self, id: PageId, page: &Page) -> io::Result<()> {
+        self.file
+            .seek(SeekFrom::Start(id as u64 * PAGE_SIZE as u64))?;
+        self.file.write_all(&page[..])
+    }
+
+    /// Call `f` with each page written since the last flush, the ones in
+    /// memory first
+    fn for_each_dirty_page(
+        &mut self,
+        mut f: impl FnMut(&mut Self, PageId, &Page) -> io::Result<()>,
+    ) -> io::Result<()> {
+        self.write_back()?;
+        for (id, page) in self.pool.dirty_pages() {
+            f(self, id, &page)?;
+        }
+
+        let mut evicted: Vec<_> = self.evicted.iter().map(|(id, at)| (*id, *at)).collect();
+        evicted.sort_unstable();
+        for (id, offset) in evicted {
+            let wal = self.wal.as_mut().expect("evicted pages are logged");
+            let page = wal.read_evicted(offset)?;
+            f(self, id, &page)?;
+        }
+        Ok(())
+    }
+
+    /// Use `wal` for the written pages the pool has no room for, and for
+    /// [`commit`](Self::commit) and [`checkpoint`](Self::checkpoint)
+    pub(crate) fn set_wal(&mut self, wal: Wal) {
+        self.wal = Some(wal);
+    }
+
+    /// Size of the log in bytes
+    pub(crate) fn log_size(&self) -> u64 {
+        self.wal.as_ref().map_or(0, Wal::size)
+    }
+
+    /// Durably log committed changes
+    pub(crate) fn commit(&mut self, records: &[LogRecord]) -> io::Result<()> {
+        match &mut self.wal {
+            Some(wal) => wal.commit(records),
+            None => Ok(()),
+        }
+    }
+
+    /// Durably log the pages written since the last checkpoint, so they can
+    /// be written to the file again if it is interrupted
+    pub(crate) fn log_checkpoint(&mut self) -> io::Result<()> {
+        if self.wal.is_none() {
+            return Ok(());
+        }
+        self.for_each_dirty_page(|pager, id, page| {
+            let wal = pager.wal.as_mut().expect("the log was checked");
+            wal.log_page(id, page)
+        })?;
+        self.wal
+            .as_mut()
+            .expect("the log was checked")
+            .log_checkpoint()
+    }
+
+    /// Write the pages written since the last checkpoint to the file, then
+    /// empty the log
+    pub(crate) fn checkpoint(&mut self) -> io::Result<()> {
+        self.log_checkpoint()?;
+        self.flush()?;
+        match &mut self.wal {
+            Some(wal) => wal.truncate(),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether more pages were written since the last flush than the buffer
+    /// pool holds, the others are read back from the log
+    pub fn needs_flush(&self) -> bool {
+        self.pool.dirty_count() + self.evicted.len() >= self.pool.capacity()
+    }
+
+    /// Write the dirty pages to the file and wait for them to be on disk
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.for_each_dirty_page(|pager, id, page| pager.write_to_file(id, page))?;
+        self.file.sync_all()?;
+        self.pool.mark_clean();
+        self.evicted.clear();
+        Ok(())
+    }
+
+    pub fn buffer_pool_stats(&self) -> BufferPoolStats {
+        self.pool.stats()
+    }
+
+    /// Change how many pages the buffer pool keeps in memory
+    pub fn set_buffer_pool_size(&mut self, pages: usize) {
+        self.pool.set_capacity(pages);
+    }
+
+    /// Write back pages a checkpoint logged but may not have finished writing
+    pub fn restore(&mut self, pages: Vec<(PageId, Page)>) -> io::Result<()> {
+        for (id, page) in pages {
+            self.write(id, page)?;
+        }
+        self.flush()?;
+        self.read_header()
+    }
+
+    /// Get an unused page, reusing a freed one if possible
+    pub fn allocate(&mut self) -> io::Result<PageId> {
+        let id = if self.free_list != 0 {
+            let id = self.free_list;
+            self.free_list = read_u32(&self.read(id)?[..], 0);
+            id
+        } else {
+            self.page_count += 1;
+            self.page_count - 1
+        };
+
+        self.write(id, empty_page())?;
+        self.write_header()?;
+        Ok(id)
+    }
+
+    /// Give a page back to be reused by [`allocate`](Self::allocate)
+    pub fn free(&mut self, id: PageId) -> io::Result<()> {
+        let mut page = empty_page();
+        write_u32(&mut page[..], 0, self.free_list);
+        self.write(id, page)?;
+
+        self.free_list = id;
+        self.write_header()
+    }
+
+    /// Read the catalog blob, empty if none was written yet
+    pub fn catalog(&mut self) -> io::Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut id = self.catalog;
+        while id != 0 {
+            let page = self.read(id)?;
+            let len = read_u32(&page[..], 4) as usize;
+            data.extend_from_slice(&page[BLOB_HEADER_SIZE..BLOB_HEADER_SIZE + len]);
+            id = read_u32(&page[..], 0);
+        }
+        Ok(data)
+    }
+
+    /// Replace the catalog blob
+    pub fn set_catalog(&mut self, data: &[u8]) -> io::Result<()> {
+        let mut id = self.catalog;
+        while id != 0 {
+            let next = read_u32(&self.read(id)?[..], 0);
+            self.free(id)?;
+            id = next;
+        }
+
+        // write the chunks back to front so each page knows its successor
+        let mut next = 0;
+        for chunk in data.chunks(PAGE_SIZE - BLOB_HEADER_SIZE).rev() {
+            let id = self.allocate()?;
+            let mut page = empty_page();
+            write_u32(&mut page[..], 0, next);
+            write_u32(&mut page[..], 4, chunk.len() as u32);
+            page[BLOB_HEADER_SIZE..BLOB_HEADER_SIZE + chunk.len()].copy_from_slice(chunk);
+            self.write(id, page)?;
+            next = id;
+        }
+
+        self.catalog = next;
+        self.write_header()
+    }
+}
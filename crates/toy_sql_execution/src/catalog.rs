@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use toy_sql_parser::{commands::Engine, Column};
+
+use crate::{
+    btree::{PageId, SharedPager},
+    error::QueryExecutionError,
+    stats::TableStats,
+    storage::{self, BTreeStorage, Storage},
+    table::{StoredRow, Table},
+};
+
+/// What the database file stores about a table
+#[derive(Debug, Serialize, Deserialize)]
+struct TableMeta {
+    name: String,
+    columns: Vec<Column>,
+    engine: Engine,
+    /// first page of the table's rows, for engines storing them in the file
+    root: Option<PageId>,
+    indexes: Vec<IndexMeta>,
+    /// copy of the rows for engines that don't store them in the file
+    rows: Vec<(usize, StoredRow)>,
+    stats: Option<TableStats>,
+}
+
+/// Definition of an index, its entries are rebuilt when the file is opened
+#[derive(Debug, Serialize, Deserialize)]
+struct IndexMeta {
+    name: String,
+    columns: Vec<String>,
+}
+
+/// Open all the tables stored in the database file
+pub(crate) fn load(pager: &SharedPager) -> Result<HashMap<String, Table>, QueryExecutionError> {
+    let bytes = pager.lock().catalog()?;
+    if bytes.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let metas: Vec<TableMeta> = bincode::deserialize(&bytes)
+        .map_err(|e| QueryExecutionError::CorruptDatabase(format!("bad catalog: {e}")))?;
+
+    metas
+        .into_iter()
+        .map(|meta| {
+            let storage: Box<dyn Storage> = match (meta.engine, meta.root) {
+                (Engine::BTree, Some(root)) => Box::new(BTreeStorage::open(pager.clone(), root)),
+                (Engine::BTree, None) => {
+                    return Err(QueryExecutionError::CorruptDatabase(format!(
+                        "table {} has no root page",
+                        meta.name
+                    )))
+                }
+                (engine, _) => storage::create(engine, Some(pager))?,
+            };
+            let mut table = Table::new(meta.columns, storage);
+            for (id, row) in meta.rows {
+                table.insert(id, row)?;
+            }
+            for index in meta.indexes {
+                table.create_index(index.name, index.columns)?;
+            }
+            table.stats = meta.stats;
+            Ok((meta.name, table))
+        })
+        .collect()
+}
+
+/// Write the definitions of all the tables to the database file, along with
+/// the rows of the tables not stored in it
+pub(crate) fn save(
+    pager: &SharedPager,
+    tables: &HashMap<String, Table>,
+) -> Result<(), QueryExecutionError> {
+    let metas = tables
+        .iter()
+        .map(|(name, table)| {
+            let root = table.root();
+            let rows = match root {
+                Some(_) => Vec::new(),
+                None => table.rows()?.collect::<Result<_, _>>()?,
+            };
+
+            Ok(TableMeta {
+                name: name.clone(),
+                columns: table.columns().iter().cloned().collect(),
+                engine: table.engine(),
+                root,
+                indexes: table
+                    .indexes()
+                    .map(|(name, columns)| IndexMeta {
+                        name: name.clone(),
+                        columns: columns.to_vec(),
+                    })
+                    .collect(),
+                rows,
+                stats: table.stats.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+
+    let bytes = bincode::serialize(&metas)
+        .map_err(|e| QueryExecutionError::CorruptDatabase(format!("bad catalog: {e}")))?;
+    Ok(pager.lock().set_catalog(&bytes)?)
+}
@@ -0,0 +1,484 @@
+use std::collections::HashMap;
+
+use toy_sql_parser::{
+    commands::SetOperator,
+    expression::{BinaryOperator, Expression},
+    value::Value,
+};
+
+use crate::{
+    plan::{unqualify, JoinStrategy, LogicalPlan, Schema},
+    stats::{ColumnStats, TableStats},
+    table::{Table, UsableIndexes},
+};
+
+/// Rows assumed for the tables that were never analyzed
+const DEFAULT_ROWS: f64 = 1000.0;
+/// Fraction of the rows assumed to equal a value without statistics
+const DEFAULT_EQUAL: f64 = 0.1;
+/// Fraction of the rows assumed to satisfy any other predicate
+const DEFAULT_RANGE: f64 = 1.0 / 3.0;
+/// Cost of fetching a row through an index, relative to reading the next
+/// row of a scan
+const INDEX_ROW_COST: f64 = 4.0;
+/// Cost of adding a row to a hash table, relative to looking one up
+const HASH_BUILD_COST: f64 = 2.0;
+
+/// What the cost model knows about the tables
+pub(crate) trait Catalog {
+    /// The statistics of a table, if it was analyzed
+    fn stats(&self, table: &str) -> Option<&TableStats>;
+
+    /// The indexes that can find the rows of `table` matching `predicate`
+    fn usable_indexes(&self, table: &str, predicate: &Expression) -> UsableIndexes;
+}
+
+impl Catalog for HashMap<String, Table> {
+    fn stats(&self, table: &str) -> Option<&TableStats> {
+        self.get(table)?.stats.as_ref()
+    }
+
+    fn usable_indexes(&self, table: &str, predicate: &Expression) -> UsableIndexes {
+        match self.get(table) {
+            Some(table) => table.usable_indexes(predicate),
+            None => vec![],
+        }
+    }
+}
+
+/// Estimates how many rows the nodes of a plan produce and how much work it
+/// takes, from the statistics of the tables it reads
+pub(crate) struct Estimator<'a> {
+    catalog: &'a dyn Catalog,
+    /// alias to name of the tables the plan reads
+    tables: HashMap<String, String>,
+}
+
+impl<'a> Estimator<'a> {
+    pub fn new(catalog: &'a dyn Catalog, plan: &LogicalPlan) -> Self {
+        fn collect(plan: &LogicalPlan, tables: &mut HashMap<String, String>) {
+            if let LogicalPlan::Scan { table, alias, .. } = plan {
+                tables.insert(alias.clone(), table.clone());
+            }
+            for input in plan.inputs() {
+                collect(input, tables);
+            }
+        }
+
+        let mut tables = HashMap::new();
+        collect(plan, &mut tables);
+        Self { catalog, tables }
+    }
+
+    fn table_rows(&self, table: &str) -> f64 {
+        match self.catalog.stats(table) {
+            Some(stats) => stats.row_count as f64,
+            None => DEFAULT_ROWS,
+        }
+    }
+
+    /// The statistics of the table column `name` refers to in `schema`
+    fn column(&self, name: &str, schema: &Schema) -> Option<&ColumnStats> {
+        let column = &schema.columns[schema.resolve(name).ok()?];
+        let table = self.tables.get(column.table.as_ref()?)?;
+        self.catalog.stats(table)?.columns.get(&column.name)
+    }
+
+    /// Estimated fraction of the rows of `schema` satisfying `predicate`
+    pub fn selectivity(&self, predicate: &Expression, schema: &Schema) -> f64 {
+        let equal = |column: &str, value: &Value| match self.column(column, schema) {
+            Some(stats) => stats.equal(value),
+            None => DEFAULT_EQUAL,
+        };
+
+        let selectivity = match predicate {
+            Expression::Literal(Value::Bool(b)) => f64::from(u8::from(*b)),
+            Expression::Not(expr) => 1.0 - self.selectivity(expr, schema),
+            Expression::BinaryOp { left, op, right } => match (left.as_ref(), op, right.as_ref()) {
+                (left, BinaryOperator::And, right) => {
+                    self.selectivity(left, schema) * self.selectivity(right, schema)
+                }
+                (left, BinaryOperator::Or, right) => {
+                    let (l, r) = (
+                        self.selectivity(left, schema),
+                        self.selectivity(right, schema),
+                    );
+                    l + r - l * r
+                }
+                // the values of the column with the fewest distinct ones each
+                // find a match among the others
+                (Expression::Column(l), BinaryOperator::Eq, Expression::Column(r)) => {
+                    let distinct = |name| self.column(name, schema).map(|stats| stats.distinct);
+                    match (distinct(l), distinct(r)) {
+                        (Some(0), _) | (_, Some(0)) => 0.0,
+                        (Some(l), Some(r)) => 1.0 / l.max(r) as f64,
+                        (Some(d), None) | (None, Some(d)) => 1.0 / d as f64,
+                        (None, None) => DEFAULT_EQUAL,
+                    }
+                }
+                (Expression::Column(column), op, Expression::Literal(value)) => {
+                    self.comparison(column, *op, value, schema)
+                }
+                (Expression::Literal(value), op, Expression::Column(column)) => {
+                    let flipped = match op {
+                        BinaryOperator::Lt => BinaryOperator::Gt,
+                        BinaryOperator::LtEq => BinaryOperator::GtEq,
+                        BinaryOperator::Gt => BinaryOperator::Lt,
+                        BinaryOperator::GtEq => BinaryOperator::LtEq,
+                        op => *op,
+                    };
+                    self.comparison(column, flipped, value, schema)
+                }
+                _ => DEFAULT_RANGE,
+            },
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let matching: f64 = list
+                    .iter()
+                    .map(|item| match (expr.as_ref(), item) {
+                        (Expression::Column(column), Expression::Literal(value)) => {
+                            equal(column, value)
+                        }
+                        _ => DEFAULT_EQUAL,
+                    })
+                    .sum();
+                match negated {
+                    false => matching,
+                    true => 1.0 - matching,
+                }
+            }
+            _ => DEFAULT_RANGE,
+        };
+        selectivity.clamp(0.0, 1.0)
+    }
+
+    /// Estimated fraction of the rows where `<column> <op> <value>`
+    fn comparison(&self, column: &str, op: BinaryOperator, value: &Value, schema: &Schema) -> f64 {
+        let Some(stats) = self.column(column, schema) else {
+            return match op {
+                BinaryOperator::Eq => DEFAULT_EQUAL,
+                BinaryOperator::NotEq => 1.0 - DEFAULT_EQUAL,
+                _ => DEFAULT_RANGE,
+            };
+        };
+        match op {
+            BinaryOperator::Eq => stats.equal(value),
+            BinaryOperator::NotEq => stats.not_null() - stats.equal(value),
+            BinaryOperator::Lt | BinaryOperator::LtEq => stats.below(value),
+            BinaryOperator::Gt | BinaryOperator::GtEq => stats.not_null() - stats.below(value),
+            _ => DEFAULT_RANGE,
+        }
+    }
+
+    /// Estimated number of rows the plan produces
+    pub fn rows(&self, plan: &LogicalPlan) -> f64 {
+        match plan {
+            LogicalPlan::Scan { table, .. } => self.table_rows(table),
+            LogicalPlan::Single => 1.0,
+            LogicalPlan::Filter { input, predicate } => {
+                self.rows(input) * self.selectivity(predicate, &input.schema())
+            }
+            LogicalPlan::Join {
+                left, right, on, ..
+            } => self.rows(left) * self.rows(right) * self.selectivity(on, &plan.schema()),
+            LogicalPlan::Project { input, .. }
+            | LogicalPlan::Window { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Apply { input, .. } => self.rows(input),
+            LogicalPlan::Aggregate {
+                input, group_by, ..
+            } => match group_by.is_empty() {
+                true => 1.0,
+                false => self.groups(group_by.iter().map(|(expr, _)| expr), input),
+            },
+            // any row may differ from the others
+            LogicalPlan::Distinct { input, on } if on.is_empty() => self.rows(input),
+            LogicalPlan::Distinct { input, on } => self.groups(on.iter(), input),
+            // as if it stopped after one step
+            LogicalPlan::Recursive { base, step, .. } => self.rows(base) + self.rows(step),
+            LogicalPlan::SetOperation {
+                left,
+                right,
+                operator,
+            } => match operator {
+                SetOperator::Union | SetOperator::UnionAll => self.rows(left) + self.rows(right),
+                SetOperator::Intersect => self.rows(left).min(self.rows(right)),
+                SetOperator::Except => self.rows(left),
+            },
+            LogicalPlan::Limit {
+                input,
+                limit,
+                offset,
+            } => {
+                let rows = (self.rows(input) - *offset as f64).max(0.0);
+                match limit {
+                    Some(limit) => rows.min(*limit as f64),
+                    None => rows,
+                }
+            }
+        }
+    }
+
+    /// Estimated number of sets of input rows with equal values of `exprs`
+    fn groups<'e>(&self, exprs: impl Iterator<Item = &'e Expression>, input: &LogicalPlan) -> f64 {
+        let rows = self.rows(input);
+        let schema = input.schema();
+        // one group per combination of the values
+        let groups: f64 = exprs
+            .map(|expr| match expr {
+                Expression::Column(name) => match self.column(name, &schema) {
+                    Some(stats) => stats.distinct as f64,
+                    None => (rows * DEFAULT_EQUAL).max(1.0),
+                },
+                _ => (rows * DEFAULT_EQUAL).max(1.0),
+            })
+            .product();
+        groups.min(rows)
+    }
+
+    /// Estimated work running the plan takes, in rows read or compared
+    pub fn cost(&self, plan: &LogicalPlan) -> f64 {
+        match plan {
+            LogicalPlan::Scan { table, .. } => self.table_rows(table),
+            LogicalPlan::Single => 1.0,
+            LogicalPlan::Filter { input, predicate } => match input.as_ref() {
+                LogicalPlan::Scan {
+                    table,
+                    alias,
+                    index: Some(index),
+                    ..
+                } => {
+                    let predicate = unqualify(predicate, alias);
+                    self.index_scans(table, &predicate)
+                        .into_iter()
+                        .find(|(name, _)| name == index)
+                        .map_or_else(|| self.table_rows(table), |(_, cost)| cost)
+                }
+                input => self.cost(input),
+            },
+            LogicalPlan::Join {
+                left,
+                right,
+                strategy,
+                ..
+            } => {
+                self.cost(left)
+                    + self.cost(right)
+                    + join_cost(*strategy, self.rows(left), self.rows(right))
+            }
+            // windows sort the rows of their partitions
+            LogicalPlan::Sort { input, .. } | LogicalPlan::Window { input, .. } => {
+                let rows = self.rows(input);
+                self.cost(input) + rows + rows * rows.max(1.0).log2()
+            }
+            LogicalPlan::Apply {
+                input,
+                subquery,
+                outer,
+                ..
+            } => {
+                // correlated subqueries run again for each row
+                let rows = self.rows(input);
+                let runs = if outer.is_empty() { 1.0 } else { rows };
+                self.cost(input) + rows + runs * self.cost(subquery)
+            }
+            plan => plan
+                .inputs()
+                .into_iter()
+                .map(|input| self.cost(input) + self.rows(input))
+                .sum(),
+        }
+    }
+
+    /// The indexes that can find the rows of `table` matching `predicate`,
+    /// with the estimated cost of reading the rows through them
+    fn index_scans(&self, table: &str, predicate: &Expression) -> Vec<(String, f64)> {
+        let stats = self.catalog.stats(table);
+        self.catalog
+            .usable_indexes(table, predicate)
+            .into_iter()
+            .map(|(name, columns)| {
+                let fraction: f64 = columns
+                    .iter()
+                    .map(|(column, values)| {
+                        let column = stats.and_then(|stats| stats.columns.get(column));
+                        let matching: f64 = values
+                            .iter()
+                            .map(|value| match column {
+                                Some(column) => column.equal(value),
+                                None => DEFAULT_EQUAL,
+                            })
+                            .sum();
+                        matching.min(1.0)
+                    })
+                    .product();
+                (name, fraction * self.table_rows(table) * INDEX_ROW_COST)
+            })
+            .collect()
+    }
+
+    /// The index worth reading the rows of `table` matching `predicate`
+    /// through instead of scanning all of them, if there is one
+    pub fn best_index(&self, table: &str, predicate: &Expression) -> Option<String> {
+        let scan = self.table_rows(table);
+        self.index_scans(table, predicate)
+            .into_iter()
+            .filter(|(_, cost)| *cost < scan)
+            .min_by(|(l_name, l_cost), (r_name, r_cost)| {
+                l_cost.total_cmp(r_cost).then_with(|| l_name.cmp(r_name))
+            })
+            .map(|(name, _)| name)
+    }
+}
+
+/// Cost of joining rows, besides the cost of reading them
+///
+/// A nested loop join compares every row of one side to every row of the
+/// other, which are kept in memory. A hash join hashes the rows of the right
+/// side, then looks up the rows of the left side, and a merge join reads the
+/// sorted sides once.
+pub(crate) fn join_cost(strategy: JoinStrategy, left_rows: f64, right_rows: f64) -> f64 {
+    match strategy {
+        JoinStrategy::NestedLoop => left_rows * right_rows + right_rows,
+        JoinStrategy::Hash => left_rows + HASH_BUILD_COST * right_rows,
+        JoinStrategy::Merge => left_rows + right_rows,
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use toy_sql_parser::parse::Parse;
+
+    use crate::planner::plan_for_test;
+
+    /// Statistics and indexes of tables that don't exist
+    #[derive(Default)]
+    pub(crate) struct TestCatalog {
+        pub stats: HashMap<String, TableStats>,
+        /// table to the indexes on it, by name with their columns
+        pub indexes: HashMap<String, Vec<(String, Vec<String>)>>,
+    }
+
+    impl TestCatalog {
+        /// Set the statistics of a table with the given number of rows, each
+        /// column having the given number of distinct values from 0 up
+        pub fn analyzed(mut self, table: &str, rows: usize, distinct: &[(&str, usize)]) -> Self {
+            let rows: Vec<_> = (0..rows)
+                .map(|n| {
+                    distinct
+                        .iter()
+                        .map(|(column, distinct)| {
+                            let value = Value::Number(((n % distinct) as i64).into());
+                            (column.to_string(), value)
+                        })
+                        .collect::<HashMap<_, _>>()
+                        .into()
+                })
+                .collect();
+            let columns = distinct.iter().map(|(column, _)| *column);
+            self.stats
+                .insert(table.into(), TableStats::collect(columns, &rows));
+            self
+        }
+
+        pub fn indexed(mut self, table: &str, index: &str, columns: &[&str]) -> Self {
+            let columns = columns.iter().map(|column| column.to_string()).collect();
+            self.indexes
+                .entry(table.into())
+                .or_default()
+                .push((index.into(), columns));
+            self
+        }
+    }
+
+    impl Catalog for TestCatalog {
+        fn stats(&self, table: &str) -> Option<&TableStats> {
+            self.stats.get(table)
+        }
+
+        fn usable_indexes(&self, table: &str, predicate: &Expression) -> UsableIndexes {
+            // only `<column> = <literal>` conjuncts are used in tests
+            let equality = |column: &String| {
+                predicate
+                    .conjuncts()
+                    .into_iter()
+                    .find_map(|conjunct| match conjunct {
+                        Expression::BinaryOp {
+                            left,
+                            op: BinaryOperator::Eq,
+                            right,
+                        } => match (left.as_ref(), right.as_ref()) {
+                            (Expression::Column(c), Expression::Literal(v)) if c == column => {
+                                Some((c.clone(), vec![v.clone()]))
+                            }
+                            _ => None,
+                        },
+                        _ => None,
+                    })
+            };
+            self.indexes
+                .get(table)
+                .into_iter()
+                .flatten()
+                .filter_map(|(name, columns)| {
+                    let columns = columns.iter().map(equality).collect::<Option<_>>()?;
+                    Some((name.clone(), columns))
+                })
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_estimates() {
+        let catalog = TestCatalog::default()
+            .analyzed("foo", 1000, &[("id", 1000)])
+            .analyzed("bar", 100, &[("id", 100), ("foo_id", 10)]);
+        let rows = |query| {
+            let plan = plan_for_test(query).unwrap();
+            Estimator::new(&catalog, &plan).rows(&plan).round()
+        };
+
+        assert_eq!(rows("SELECT * FROM foo;"), 1000.0);
+        assert_eq!(rows("SELECT * FROM foo WHERE id = 3;"), 1.0);
+        assert_eq!(rows("SELECT * FROM foo WHERE id = 3000;"), 0.0);
+        assert_eq!(rows("SELECT * FROM foo WHERE id < 500;"), 501.0);
+        assert_eq!(rows("SELECT * FROM bar WHERE foo_id IN (1, 2);"), 20.0);
+        assert_eq!(rows("SELECT * FROM bar WHERE NOT foo_id = 1;"), 90.0);
+        assert_eq!(
+            rows("SELECT * FROM foo JOIN bar ON foo.id = bar.foo_id;"),
+            100.0
+        );
+        assert_eq!(rows("SELECT foo_id FROM bar GROUP BY foo_id;"), 10.0);
+        assert_eq!(rows("SELECT COUNT(*) FROM bar;"), 1.0);
+        assert_eq!(rows("SELECT * FROM bar LIMIT 5 OFFSET 2;"), 5.0);
+    }
+
+    #[test]
+    fn test_best_index() {
+        let catalog = TestCatalog::default()
+            .analyzed("bar", 100, &[("id", 100), ("foo_id", 2)])
+            .indexed("bar", "id_idx", &["id"])
+            .indexed("bar", "foo_id_idx", &["foo_id"])
+            .indexed("foo", "foo_idx", &["id"]);
+        let plan = plan_for_test("SELECT * FROM bar;").unwrap();
+        let estimator = Estimator::new(&catalog, &plan);
+        let best = |table, predicate| {
+            let Ok((_, predicate)) = Expression::parse_from_raw(predicate) else {
+                panic!("bad predicate {predicate}");
+            };
+            estimator.best_index(table, &predicate)
+        };
+
+        assert_eq!(best("bar", "id = 1"), Some("id_idx".into()));
+        // half the rows are cheaper to read in order
+        assert_eq!(best("bar", "foo_id = 1"), None);
+        assert_eq!(best("bar", "foo_id = 1 AND id = 2"), Some("id_idx".into()));
+        assert_eq!(best("bar", "id > 1"), None);
+        // an index is assumed to be selective without statistics
+        assert_eq!(best("foo", "id = 1"), Some("foo_idx".into()));
+    }
+}
@@ -1,6 +1,12 @@
 use miette::Diagnostic;
 use thiserror::Error;
-use toy_sql_parser::{error::FormattedError, value::Value, SqlTypeInfo};
+use toy_sql_parser::{
+    commands::{Engine, SetOperator},
+    error::FormattedError,
+    expression::BinaryOperator,
+    value::Value,
+    SqlTypeInfo,
+};
 
 #[derive(Error, Debug, Diagnostic)]
 #[error("Query Execution Error")]
@@ -14,8 +20,108 @@ pub enum QueryExecutionError {
     #[error("Column {0} does not exist")]
     ColumnDoesNotExist(String),
 
+    #[error("Column {0} is ambiguous, qualify it with its table")]
+    AmbiguousColumn(String),
+
+    #[error("Column {0} must appear in GROUP BY or be used in an aggregate")]
+    ColumnNotGrouped(String),
+
+    #[error("Aggregate {0} needs numbers")]
+    InvalidAggregate(String),
+
+    #[error("Subquery must return a single column, not {0}")]
+    SubqueryColumns(usize),
+
+    #[error("Subquery used as a value returned more than one row")]
+    SubqueryRows,
+
+    #[error("Query {0} returns {1} columns, but {2} are named")]
+    NamedColumns(String, usize, usize),
+
+    #[error("Queries combined by {0} return {1} and {2} columns")]
+    SetOperationColumns(SetOperator, usize, usize),
+
+    #[error("Column {1} combined by {0} is {2} in one query and {3} in the other")]
+    SetOperationTypes(SetOperator, String, SqlTypeInfo, SqlTypeInfo),
+
+    #[error(
+        "FOR UPDATE can only lock the rows of a single table, without aggregates, DISTINCT nor subqueries"
+    )]
+    InvalidForUpdate,
+
+    #[error("SELECT DISTINCT ON expressions must start the ORDER BY, not {0}")]
+    DistinctOnOrder(String),
+
+    #[error("ORDER BY {0} must be in the select list of SELECT DISTINCT")]
+    DistinctOrder(String),
+
     #[error("Value {1} can not be inserted into a {0} column")]
     InsertTypeMismatch(SqlTypeInfo, Value),
+
+    #[error("Table has {0} columns, but {1} values were inserted")]
+    InsertValueCount(usize, usize),
+
+    #[error("Index {0} already exists")]
+    IndexAlreadyExists(String),
+
+    #[error("Value {0} can not be compared with {1}")]
+    IncomparableValues(Value, Value),
+
+    #[error("Expression {0} can not be evaluated here")]
+    InvalidExpression(String),
+
+    #[error("Operator {0} can not be applied to {1} and {2}")]
+    InvalidOperands(BinaryOperator, Value, Value),
+
+    #[error("Operator {0} needs numbers, not {1} and {2}")]
+    OperandTypes(BinaryOperator, SqlTypeInfo, SqlTypeInfo),
+
+    #[error("CASE results can not be both {0} and {1}")]
+    CaseTypes(SqlTypeInfo, SqlTypeInfo),
+
+    #[error("Value {0} can not be cast to {1}")]
+    InvalidCast(Value, SqlTypeInfo),
+
+    #[error("Division by zero")]
+    DivisionByZero,
+
+    #[error("Row of {0} bytes is too large to be stored")]
+    RowTooLarge(usize),
+
+    #[error("Engine {0} needs a database file")]
+    EngineUnavailable(Engine),
+
+    #[error("A transaction is already open")]
+    TransactionAlreadyOpen,
+
+    #[error("No transaction is open")]
+    NoTransaction,
+
+    #[error("Savepoint {0} does not exist")]
+    SavepointNotFound(String),
+
+    #[error("Could not write a row changed by a concurrent transaction")]
+    WriteConflict,
+
+    #[error(
+        "Could not serialize access due to concurrent changes, the transaction was rolled back"
+    )]
+    SerializationFailure,
+
+    #[error("The isolation level must be set before any query of the transaction")]
+    IsolationLevelAfterQuery,
+
+    #[error("Deadlock detected, the transaction was rolled back")]
+    Deadlock,
+
+    #[error("Can not checkpoint while a transaction is open")]
+    CheckpointInTransaction,
+
+    #[error("Database file is corrupt: {0}")]
+    CorruptDatabase(String),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
 }
 
 #[derive(Error, Debug, Diagnostic)]
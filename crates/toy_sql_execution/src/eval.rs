@@ -0,0 +1,360 @@
+use std::{cmp::Ordering, str::FromStr};
+
+use bigdecimal::{BigDecimal, Zero};
+use toy_sql_parser::{
+    expression::{BinaryOperator, Expression},
+    value::Value,
+    SqlTypeInfo,
+};
+
+use crate::{error::QueryExecutionError, table::StoredRow};
+
+/// A row expressions can read column values from
+pub(crate) trait ColumnValues {
+    fn column(&self, name: &str) -> Result<Value, QueryExecutionError>;
+}
+
+impl ColumnValues for StoredRow {
+    fn column(&self, name: &str) -> Result<Value, QueryExecutionError> {
+        self.get(name)
+            .cloned()
+            .ok_or_else(|| QueryExecutionError::ColumnDoesNotExist(name.to_owned()))
+    }
+}
+
+/// Check if a row satisfies a predicate
+///
+/// Predicates that are `NULL`, such as comparisons with `NULL`, are never
+/// satisfied.
+pub(crate) fn satisfies<R: ColumnValues + ?Sized>(
+    predicate: &Expression,
+    row: &R,
+) -> Result<bool, QueryExecutionError> {
+    Ok(truth(predicate, row)?.unwrap_or(false))
+}
+
+/// The truth of a boolean expression, `None` when it is `NULL`
+fn truth<R: ColumnValues + ?Sized>(
+    expr: &Expression,
+    row: &R,
+) -> Result<Option<bool>, QueryExecutionError> {
+    match value_of(expr, row)? {
+        Value::Bool(b) => Ok(Some(b)),
+        Value::Null => Ok(None),
+        _ => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+    }
+}
+
+/// Evaluate an expression against a row
+///
+/// Operators on `NULL` are `NULL`, except `AND` and `OR` when the other side
+/// decides the result.
+pub(crate) fn value_of<R: ColumnValues + ?Sized>(
+    expr: &Expression,
+    row: &R,
+) -> Result<Value, QueryExecutionError> {
+    match expr {
+        Expression::Column(name) => row.column(name),
+        Expression::Literal(value) => Ok(value.clone()),
+        Expression::BinaryOp {
+            left,
+            op: op @ (BinaryOperator::And | BinaryOperator::Or),
+            right,
+        } => {
+            // `false AND x` is false and `true OR x` is true, whatever x is
+            let absorbing = *op == BinaryOperator::Or;
+            let left = truth(left, row)?;
+            if left == Some(absorbing) {
+                return Ok(Value::Bool(absorbing));
+            }
+            Ok(match (left, truth(right, row)?) {
+                (_, Some(right)) if right == absorbing => Value::Bool(absorbing),
+                (Some(_), Some(_)) => Value::Bool(!absorbing),
+                _ => Value::Null,
+            })
+        }
+        Expression::BinaryOp { left, op, right } => {
+            let (left, right) = (value_of(left, row)?, value_of(right, row)?);
+            if left == Value::Null || right == Value::Null {
+                return Ok(Value::Null);
+            }
+            if op.is_comparison() {
+                let ordering = compare(&left, &right)?;
+                return Ok(Value::Bool(match op {
+                    BinaryOperator::Eq => ordering.is_eq(),
+                    BinaryOperator::NotEq => ordering.is_ne(),
+                    BinaryOperator::Lt => ordering.is_lt(),
+                    BinaryOperator::LtEq => ordering.is_le(),
+                    BinaryOperator::Gt => ordering.is_gt(),
+                    _ => ordering.is_ge(),
+                }));
+            }
+            match (op, &left, &right) {
+                (BinaryOperator::Concat, ..) => Ok(Value::String(format!("{left}{right}"))),
+                (_, Value::Number(l), Value::Number(r)) => arithmetic(*op, l, r),
+                _ => Err(QueryExecutionError::InvalidOperands(*op, left, right)),
+            }
+        }
+        Expression::Not(expr) => Ok(match truth(expr, row)? {
+            Some(b) => Value::Bool(!b),
+            None => Value::Null,
+        }),
+        Expression::Negate(operand) => match value_of(operand, row)? {
+            Value::Number(n) => Ok(Value::Number(-n)),
+            Value::Null => Ok(Value::Null),
+            _ => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+        },
+        Expression::InList {
+            expr,
+            list,
+            negated,
+        } => {
+            let value = value_of(expr, row)?;
+            if value == Value::Null {
+                return Ok(Value::Null);
+            }
+            // not found among values that include a `NULL` is unknown
+            let mut unknown = false;
+            for item in list {
+                let item = value_of(item, row)?;
+                if item == Value::Null {
+                    unknown = true;
+                } else if compare(&value, &item)?.is_eq() {
+                    return Ok(Value::Bool(!negated));
+                }
+            }
+            Ok(match unknown {
+                true => Value::Null,
+                false => Value::Bool(*negated),
+            })
+        }
+        Expression::Like {
+            expr: like_expr,
+            pattern,
+            negated,
+            case_insensitive,
+        } => match (value_of(like_expr, row)?, value_of(pattern, row)?) {
+            (Value::Null, _) | (_, Value::Null) => Ok(Value::Null),
+            (Value::String(text), Value::String(pattern)) => {
+                let (text, pattern) = match case_insensitive {
+                    true => (text.to_lowercase(), pattern.to_lowercase()),
+                    false => (text, pattern),
+                };
+                let text: Vec<_> = text.chars().collect();
+                let pattern: Vec<_> = pattern.chars().collect();
+                Ok(Value::Bool(like(&text, &pattern) != *negated))
+            }
+            _ => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+        },
+        Expression::Between {
+            expr,
+            low,
+            high,
+            negated,
+        } => {
+            let (value, low, high) = (
+                value_of(expr, row)?,
+                value_of(low, row)?,
+                value_of(high, row)?,
+            );
+            let above = match (&value, &low) {
+                (Value::Null, _) | (_, Value::Null) => None,
+                (value, low) => Some(compare(value, low)?.is_ge()),
+            };
+            let below = match (&value, &high) {
+                (Value::Null, _) | (_, Value::Null) => None,
+                (value, high) => Some(compare(value, high)?.is_le()),
+            };
+            Ok(match (above, below) {
+                (Some(false), _) | (_, Some(false)) => Value::Bool(*negated),
+                (Some(true), Some(true)) => Value::Bool(!negated),
+                _ => Value::Null,
+            })
+        }
+        Expression::Case {
+            operand,
+            branches,
+            otherwise,
+        } => {
+            let operand = match operand {
+                Some(operand) => Some(value_of(operand, row)?),
+                None => None,
+            };
+            for (condition, result) in branches {
+                let matched = match &operand {
+                    // `NULL` is equal to nothing, not even `NULL`
+                    Some(Value::Null) => false,
+                    Some(operand) => match value_of(condition, row)? {
+                        Value::Null => false,
+                        value => compare(operand, &value)?.is_eq(),
+                    },
+                    None => truth(condition, row)? == Some(true),
+                };
+                if matched {
+                    return value_of(result, row);
+                }
+            }
+            match otherwise {
+                Some(otherwise) => value_of(otherwise, row),
+                None => Ok(Value::Null),
+            }
+        }
+        Expression::Cast { expr, type_info } => cast(value_of(expr, row)?, *type_info),
+        expr @ (Expression::Aggregate { .. }
+        | Expression::Window { .. }
+        | Expression::Subquery(_)
+        | Expression::Exists(_)
+        | Expression::InSubquery { .. }) => {
+            Err(QueryExecutionError::InvalidExpression(expr.to_string()))
+        }
+    }
+}
+
+/// Apply an arithmetic operator to two numbers
+fn arithmetic(
+    op: BinaryOperator,
+    left: &BigDecimal,
+    right: &BigDecimal,
+) -> Result<Value, QueryExecutionError> {
+    let result = match op {
+        BinaryOperator::Plus => left + right,
+        BinaryOperator::Minus => left - right,
+        BinaryOperator::Multiply => left * right,
+        BinaryOperator::Divide | BinaryOperator::Modulo if right.is_zero() => {
+            return Err(QueryExecutionError::DivisionByZero)
+        }
+        // integers divide into integers, truncated like casts to int
+        BinaryOperator::Divide if left.is_integer() && right.is_integer() => {
+            (left / right).with_scale(0)
+        }
+        // rounded like averages, as quotients can have endless digits
+        BinaryOperator::Divide => (left / right).round(6),
+        BinaryOperator::Modulo => left % right,
+        op => {
+            return Err(QueryExecutionError::InvalidOperands(
+                op,
+                Value::Number(left.clone()),
+                Value::Number(right.clone()),
+            ))
+        }
+    };
+    Ok(Value::Number(result.normalized()))
+}
+
+/// Check if a text matches a `LIKE` pattern, where `%` matches any
+/// characters and `_` a single one
+///
+/// On a mismatch only the last `%` is retried one character further, as the
+/// ones before it matched as little as possible, so this takes at most
+/// `text.len() * pattern.len()` steps.
+fn like(text: &[char], pattern: &[char]) -> bool {
+    let (mut t, mut p) = (0, 0);
+    // the position after the last `%` and the text it matches up to
+    let mut retry = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('%') => {
+                p += 1;
+                retry = Some((p, t));
+            }
+            Some(c) if *c == '_' || *c == text[t] => {
+                t += 1;
+                p += 1;
+            }
+            _ => match retry {
+                Some((after, matched)) => {
+                    p = after;
+                    t = matched + 1;
+                    retry = Some((after, t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '%')
+}
+
+/// Convert a value to a type
+fn cast(value: Value, type_info: SqlTypeInfo) -> Result<Value, QueryExecutionError> {
+    match (type_info, value) {
+        (_, Value::Null) => Ok(Value::Null),
+        (SqlTypeInfo::String, value) => Ok(Value::String(value.to_string())),
+        // ints drop the fraction, truncating towards zero
+        (SqlTypeInfo::Int, Value::Number(n)) => Ok(Value::Number(n.with_scale(0).normalized())),
+        (SqlTypeInfo::Bool, value @ Value::Bool(_)) => Ok(value),
+        (SqlTypeInfo::Int, Value::Bool(b)) => Ok(Value::Number(u8::from(b).into())),
+        (SqlTypeInfo::Bool, Value::Number(n)) => Ok(Value::Bool(!n.is_zero())),
+        (SqlTypeInfo::Int, Value::String(s)) => match BigDecimal::from_str(s.trim()) {
+            Ok(n) => Ok(Value::Number(n.with_scale(0).normalized())),
+            Err(_) => Err(QueryExecutionError::InvalidCast(
+                Value::String(s),
+                type_info,
+            )),
+        },
+        (SqlTypeInfo::Bool, Value::String(s)) => match s.trim().to_lowercase().as_str() {
+            "true" => Ok(Value::Bool(true)),
+            "false" => Ok(Value::Bool(false)),
+            _ => Err(QueryExecutionError::InvalidCast(
+                Value::String(s),
+                type_info,
+            )),
+        },
+    }
+}
+
+/// Compare two values of the same type
+pub(crate) fn compare(left: &Value, right: &Value) -> Result<Ordering, QueryExecutionError> {
+    match (left, right) {
+        (Value::Number(l), Value::Number(r)) => Ok(l.cmp(r)),
+        (Value::String(l), Value::String(r)) => Ok(l.cmp(r)),
+        (Value::Bool(l), Value::Bool(r)) => Ok(l.cmp(r)),
+        (l, r) => Err(QueryExecutionError::IncomparableValues(
+            l.clone(),
+            r.clone(),
+        )),
+    }
+}
+
+/// Order values to sort them, `NULL` first
+pub(crate) fn sort_order(left: &Value, right: &Value) -> Ordering {
+    // columns hold values of one type, but order mixed ones anyway
+    fn rank(value: &Value) -> u8 {
+        match value {
+            Value::Null => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) => 2,
+            Value::String(_) => 3,
+        }
+    }
+
+    compare(left, right).unwrap_or_else(|_| rank(left).cmp(&rank(right)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(text: &str, pattern: &str) -> bool {
+        let text: Vec<_> = text.chars().collect();
+        let pattern: Vec<_> = pattern.chars().collect();
+        like(&text, &pattern)
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(matches("banana", "b%"));
+        assert!(matches("banana", "%an_"));
+        assert!(matches("banana", "%%n%n%"));
+        assert!(matches("", "%"));
+        assert!(!matches("banana", "b%x"));
+        assert!(!matches("banana", "_"));
+        assert!(!matches("", "_"));
+        assert!(matches("a%b", "a%b"));
+
+        // backtracking over every `%` would never finish
+        let text = "x".repeat(10_000);
+        let pattern = format!("{}y", "%".repeat(50));
+        assert!(!matches(&text, &pattern));
+        assert!(matches(&format!("{text}y"), &pattern));
+    }
+}
@@ -0,0 +1,87 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use crate::{cost::Estimator, operator::Metrics, plan::LogicalPlan};
+
+/// The plan a query runs with, as `EXPLAIN` shows it
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryPlan {
+    /// The operators of the plan, each one before its inputs
+    pub nodes: Vec<PlanNode>,
+}
+
+/// An operator of a query plan
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlanNode {
+    /// Number of operators above this one
+    pub depth: usize,
+    pub description: String,
+    /// Rows the cost model expects the operator to produce
+    pub estimated_rows: f64,
+    /// What running the operator measured, for `EXPLAIN ANALYZE`
+    pub actual: Option<NodeMetrics>,
+}
+
+/// What an operator did while running a query
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NodeMetrics {
+    pub rows: usize,
+    /// Time spent in the operator and its inputs
+    pub elapsed: Duration,
+}
+
+impl QueryPlan {
+    /// Describe a plan, with the metrics of its nodes in pre-order if it ran
+    pub(crate) fn new(
+        plan: &LogicalPlan,
+        estimator: &Estimator,
+        metrics: Option<&[Arc<Metrics>]>,
+    ) -> Self {
+        fn add(plan: &LogicalPlan, depth: usize, estimator: &Estimator, nodes: &mut Vec<PlanNode>) {
+            nodes.push(PlanNode {
+                depth,
+                description: plan.describe(),
+                estimated_rows: estimator.rows(plan),
+                actual: None,
+            });
+            for input in plan.inputs() {
+                add(input, depth + 1, estimator, nodes);
+            }
+        }
+
+        let mut nodes = Vec::new();
+        add(plan, 0, estimator, &mut nodes);
+        for (node, metrics) in nodes.iter_mut().zip(metrics.into_iter().flatten()) {
+            node.actual = Some(NodeMetrics {
+                rows: metrics.rows(),
+                elapsed: metrics.elapsed(),
+            });
+        }
+        Self { nodes }
+    }
+
+    /// Whether the plan ran to measure its operators
+    pub fn analyzed(&self) -> bool {
+        self.nodes.iter().any(|node| node.actual.is_some())
+    }
+}
+
+/// One line per operator, inputs indented below the operator reading them
+impl fmt::Display for QueryPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for node in &self.nodes {
+            write!(
+                f,
+                "{:indent$}{} (estimated rows: {:.0}",
+                "",
+                node.description,
+                node.estimated_rows,
+                indent = node.depth * 2
+            )?;
+            if let Some(actual) = node.actual {
+                write!(f, ", rows: {}, time: {:.3?}", actual.rows, actual.elapsed)?;
+            }
+            writeln!(f, ")")?;
+        }
+        Ok(())
+    }
+}
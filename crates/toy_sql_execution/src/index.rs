@@ -0,0 +1,106 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use toy_sql_parser::value::Value;
+
+use crate::table::StoredRow;
+
+/// A hash index over one or more columns of a table
+///
+/// Maps the values of the indexed columns to the ids of the rows holding them,
+/// so equality lookups don't have to scan the whole table
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub(crate) struct HashIndex {
+    /// The indexed columns, in key order
+    columns: Vec<String>,
+    /// key => ids of the rows with that key
+    entries: HashMap<Vec<Value>, Vec<usize>>,
+}
+
+impl HashIndex {
+    pub fn new(columns: Vec<String>) -> Self {
+        Self {
+            columns,
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// The values of the indexed columns of a row, `NULL` for the ones it
+    /// doesn't have
+    pub fn key(&self, row: &StoredRow) -> Vec<Value> {
+        self.columns
+            .iter()
+            .map(|col| row.get(col).cloned().unwrap_or(Value::Null))
+            .collect()
+    }
+
+    /// Add a row to the index
+    pub fn insert(&mut self, id: usize, row: &StoredRow) {
+        let key = self.key(row);
+        self.entries.entry(key).or_default().push(id);
+    }
+
+    /// Remove a row added with [`insert`](Self::insert)
+    pub fn remove(&mut self, id: usize, row: &StoredRow) {
+        let key = self.key(row);
+        if let Some(ids) = self.entries.get_mut(&key) {
+            ids.retain(|i| *i != id);
+            if ids.is_empty() {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    /// Ids of the rows whose indexed columns equal `key`
+    pub fn get(&self, key: &[Value]) -> &[usize] {
+        self.entries.get(key).map_or(&[], |ids| ids.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn row(values: &[(&str, &str)]) -> StoredRow {
+        values
+            .iter()
+            .map(|(col, val)| (col.to_string(), Value::String(val.to_string())))
+            .collect::<HashMap<_, _>>()
+            .into()
+    }
+
+    #[test]
+    fn test_lookup() {
+        let mut index = HashIndex::new(vec!["a".into(), "b".into()]);
+        index.insert(0, &row(&[("a", "x"), ("b", "y"), ("c", "z")]));
+        index.insert(1, &row(&[("a", "x"), ("b", "z"), ("c", "z")]));
+        index.insert(2, &row(&[("a", "x"), ("b", "y"), ("c", "w")]));
+
+        let key = |a: &str, b: &str| vec![Value::String(a.into()), Value::String(b.into())];
+        assert_eq!(index.get(&key("x", "y")), &[0, 2]);
+        assert_eq!(index.get(&key("x", "z")), &[1]);
+        assert!(index.get(&key("y", "x")).is_empty());
+
+        index.remove(0, &row(&[("a", "x"), ("b", "y"), ("c", "z")]));
+        index.remove(1, &row(&[("a", "x"), ("b", "z"), ("c", "z")]));
+        assert_eq!(index.get(&key("x", "y")), &[2]);
+        assert!(index.get(&key("x", "z")).is_empty());
+    }
+
+    #[test]
+    fn test_missing_column() {
+        let mut index = HashIndex::new(vec!["a".into(), "b".into()]);
+        index.insert(0, &row(&[("a", "x")]));
+        assert_eq!(index.get(&[Value::String("x".into()), Value::Null]), &[0]);
+        index.remove(0, &row(&[("a", "x")]));
+        assert!(index
+            .get(&[Value::String("x".into()), Value::Null])
+            .is_empty());
+    }
+}
@@ -1,13 +1,50 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    time::{Duration, Instant},
+};
 
+pub use btree::BufferPoolStats;
+use btree::{Pager, SharedPager};
+use cost::Estimator;
 use derive_more::Display;
 pub use error::{QueryExecutionError, SQLError};
+pub use explain::{NodeMetrics, PlanNode, QueryPlan};
+use lock::LockManager;
+use mvcc::{Readers, Snapshot, Timestamp, Transactions, TxId};
+use plan::{unqualify, LogicalPlan};
 use table::{Table, TableIter};
-use toy_sql_parser::ast::{parse_multiple_queries, parse_sql_query, SqlQuery};
+use toy_sql_parser::{
+    ast::{parse_multiple_queries, parse_sql_query, SqlQuery},
+    commands::{Column, Engine, IsolationLevel, SelectItem, SelectStatement, TransactionStatement},
+    expression::Expression,
+};
+use transaction::{Savepoint, Transaction, Undo};
+use wal::{LogRecord, Wal};
 
+mod aggregate;
+mod btree;
+mod catalog;
+mod cost;
 mod error;
+mod eval;
+mod explain;
+mod index;
+mod lock;
+mod mvcc;
+mod operator;
+mod optimizer;
+mod plan;
+mod planner;
 mod row;
+mod spill;
+mod stats;
+mod storage;
 mod table;
+mod transaction;
+mod wal;
+mod window;
 
 // TODO: Eventually might be good to have to do something like
 // `query('..').fetch` to get values back the rest of the query types would
@@ -15,52 +52,939 @@ mod table;
 // see https://github.com/launchbadge/sqlx/tree/main#querying
 
 #[derive(Debug, Display)]
-pub enum ExecResponse<'a> {
+pub enum ExecResponse {
     #[display(fmt = "{_0:#?}")]
     // Select(Vec<Row<'a>>),
-    Select(TableIter<'a>),
+    Select(TableIter),
+    #[display(fmt = "{_0}")]
+    Explain(QueryPlan),
     Insert,
+    Update,
+    Delete,
     Create,
+    CreateIndex,
+    Analyze,
+    Checkpoint,
+    Begin,
+    Commit,
+    Rollback,
+    Savepoint,
+    RollbackToSavepoint,
+    ReleaseSavepoint,
+    SetIsolationLevel,
 }
 
-#[derive(Debug, Default)]
-pub struct Execution {
+/// When a database checkpoints on its own, checked after every commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CheckpointThresholds {
+    /// Checkpoint once the log grew to this many bytes
+    pub log_size: Option<u64>,
+    /// Checkpoint once this long passed since the last checkpoint
+    pub interval: Option<Duration>,
+}
+
+impl Default for CheckpointThresholds {
+    fn default() -> Self {
+        Self {
+            log_size: Some(4 * 1024 * 1024),
+            interval: None,
+        }
+    }
+}
+
+/// The tables and files shared by all the sessions of a database
+#[derive(Debug)]
+struct Shared {
     tables: HashMap<String, Table>,
+    /// The database file the tables are stored in, `None` if they only live
+    /// in memory
+    pager: Option<SharedPager>,
+    checkpoint_thresholds: CheckpointThresholds,
+    last_checkpoint: Instant,
+    transactions: Transactions,
+    /// Snapshots of the queries still reading rows
+    readers: Arc<Readers>,
+    /// Bytes of rows each query may hold in memory before spilling them to
+    /// temporary files
+    query_memory: usize,
 }
 
-impl Execution {
-    pub fn new() -> Self {
+impl Shared {
+    fn new() -> Self {
         Self {
             tables: HashMap::new(),
+            pager: None,
+            checkpoint_thresholds: CheckpointThresholds::default(),
+            last_checkpoint: Instant::now(),
+            transactions: Transactions::default(),
+            readers: Arc::default(),
+            query_memory: spill::DEFAULT_QUERY_MEMORY,
+        }
+    }
+
+    /// A table the snapshot can see, tables created by a transaction are
+    /// hidden from the others until it commits
+    fn table(&self, name: &str, snapshot: &Snapshot) -> Result<&Table, QueryExecutionError> {
+        self.tables
+            .get(name)
+            .filter(|table| table.creator.is_none() || table.creator == Some(snapshot.tx))
+            .ok_or_else(|| QueryExecutionError::TableNotFound(name.to_owned()))
+    }
+
+    /// The plan to run a select with
+    fn plan_select(
+        &self,
+        select: SelectStatement,
+        snapshot: &Snapshot,
+    ) -> Result<LogicalPlan, QueryExecutionError> {
+        let plan = planner::plan_select(select, |table| {
+            Ok(self
+                .table(table, snapshot)?
+                .columns()
+                .iter()
+                .cloned()
+                .collect())
+        })?;
+        Ok(optimizer::optimize(plan, &self.tables))
+    }
+
+    fn table_mut(
+        &mut self,
+        name: &str,
+        snapshot: &Snapshot,
+    ) -> Result<&mut Table, QueryExecutionError> {
+        self.table(name, snapshot)?;
+        Ok(self.tables.get_mut(name).expect("table was found"))
+    }
+
+    /// Write the changes in the log to the database file, then empty the log
+    fn checkpoint(&mut self) -> Result<(), QueryExecutionError> {
+        // the catalog would hold the tables and indexes of open transactions
+        if self.transactions.any_open() {
+            return Err(QueryExecutionError::CheckpointInTransaction);
+        }
+        let Some(pager) = &self.pager else {
+            return Ok(());
+        };
+
+        catalog::save(pager, &self.tables)?;
+        pager.lock().checkpoint()?;
+
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    fn checkpoint_due(&self) -> bool {
+        let Some(pager) = &self.pager else {
+            return false;
+        };
+        let CheckpointThresholds { log_size, interval } = self.checkpoint_thresholds;
+
+        // dirty pages the buffer pool has no room for are read from the log
+        // until a checkpoint
+        let pager = pager.lock();
+        pager.needs_flush()
+            || matches!(log_size, Some(size) if pager.log_size() >= size)
+            || matches!(interval, Some(interval) if self.last_checkpoint.elapsed() >= interval)
+    }
+
+    /// Checkpoint if a threshold is reached and no transaction is open
+    fn checkpoint_if_due(&mut self) -> Result<(), QueryExecutionError> {
+        if !self.transactions.any_open() && self.checkpoint_due() {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    /// Durably log committed changes
+    fn log(&mut self, records: &[LogRecord]) -> Result<(), QueryExecutionError> {
+        if let Some(pager) = &self.pager {
+            pager.lock().commit(records)?;
+        }
+        Ok(())
+    }
+
+    fn create_table(
+        &mut self,
+        name: String,
+        columns: Vec<Column>,
+        engine: Engine,
+    ) -> Result<&mut Table, QueryExecutionError> {
+        let storage = storage::create(engine, self.pager.as_ref())?;
+        Ok(self
+            .tables
+            .entry(name)
+            .or_insert(Table::new(columns, storage)))
+    }
+
+    /// Add the changes to the transaction, they are logged when it commits
+    fn write(
+        &mut self,
+        transaction: &mut Transaction,
+        records: Vec<LogRecord>,
+    ) -> Result<(), QueryExecutionError> {
+        for record in records {
+            let undo = self.apply(record.clone(), &transaction.snapshot)?;
+            transaction.undo.push(undo);
+            transaction.records.push(record);
+        }
+        Ok(())
+    }
+
+    /// Apply a change that was checked by [`Execution::run`] as a pending
+    /// change of the snapshot's transaction, returning how to revert it
+    fn apply(
+        &mut self,
+        record: LogRecord,
+        snapshot: &Snapshot,
+    ) -> Result<Undo, QueryExecutionError> {
+        match record {
+            LogRecord::CreateTable {
+                name,
+                columns,
+                engine,
+            } => {
+                let table = self.create_table(name.clone(), columns, engine)?;
+                table.creator = Some(snapshot.tx);
+                Ok(Undo::DropTable(name))
+            }
+            LogRecord::CreateIndex {
+                name,
+                table,
+                columns,
+            } => {
+                let table_ref = self.table_mut(&table, snapshot)?;
+                table_ref.create_index(name.clone(), columns)?;
+                Ok(Undo::DropIndex { table, name })
+            }
+            LogRecord::Insert { table, id, row } => {
+                self.table_mut(&table, snapshot)?
+                    .write(id, Some(row), snapshot)?;
+                Ok(Undo::Row { table, id })
+            }
+            LogRecord::Delete { table, id } => {
+                self.table_mut(&table, snapshot)?
+                    .write(id, None, snapshot)?;
+                Ok(Undo::Row { table, id })
+            }
+        }
+    }
+
+    /// Apply a change committed to the log before the database was opened
+    fn replay(&mut self, record: LogRecord) -> Result<(), QueryExecutionError> {
+        fn table_ref(
+            tables: &mut HashMap<String, Table>,
+            table: String,
+        ) -> Result<&mut Table, QueryExecutionError> {
+            tables
+                .get_mut(&table)
+                .ok_or(QueryExecutionError::TableNotFound(table))
+        }
+
+        match record {
+            LogRecord::CreateTable {
+                name,
+                columns,
+                engine,
+            } => {
+                self.create_table(name, columns, engine)?;
+            }
+            LogRecord::CreateIndex {
+                name,
+                table,
+                columns,
+            } => table_ref(&mut self.tables, table)?.create_index(name, columns)?,
+            LogRecord::Insert { table, id, row } => {
+                table_ref(&mut self.tables, table)?.insert(id, row)?;
+            }
+            LogRecord::Delete { table, id } => {
+                table_ref(&mut self.tables, table)?.delete(id)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn undo(&mut self, undo: Undo) -> Result<(), QueryExecutionError> {
+        match undo {
+            Undo::Row { table, id } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.undo_write(id)?;
+                }
+            }
+            Undo::DropTable(name) => {
+                if let Some(table) = self.tables.remove(&name) {
+                    table.destroy()?;
+                }
+            }
+            Undo::DropIndex { table, name } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.drop_index(&name);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Log the changes of a transaction and make them visible to the
+    /// transactions starting after it
+    ///
+    /// A serializable transaction fails if a table it read was changed by a
+    /// transaction it doesn't see, as the order they ran in is ambiguous.
+    fn commit(&mut self, transaction: Transaction) -> Result<(), QueryExecutionError> {
+        let Snapshot { tx, ts } = transaction.snapshot;
+        if transaction.isolation == IsolationLevel::Serializable
+            && self.transactions.changed_since(ts, &transaction.reads)
+        {
+            self.abort(transaction)?;
+            return Err(QueryExecutionError::SerializationFailure);
+        }
+        if let Err(e) = self.log(&transaction.records) {
+            self.abort(transaction)?;
+            return Err(e);
+        }
+
+        let ts = self.transactions.commit(tx, transaction.writes());
+        for undo in transaction.undo {
+            self.commit_change(undo, tx, ts)?;
+        }
+        self.collect_garbage()?;
+        self.checkpoint_if_due()
+    }
+
+    fn commit_change(
+        &mut self,
+        undo: Undo,
+        tx: TxId,
+        ts: Timestamp,
+    ) -> Result<(), QueryExecutionError> {
+        match undo {
+            Undo::Row { table, id } => {
+                if let Some(table) = self.tables.get_mut(&table) {
+                    table.commit_write(id, tx, ts)?;
+                }
+            }
+            Undo::DropTable(name) => {
+                if let Some(table) = self.tables.get_mut(&name) {
+                    table.creator = None;
+                }
+            }
+            Undo::DropIndex { .. } => {}
+        }
+        Ok(())
+    }
+
+    /// Revert every change of a transaction
+    fn abort(&mut self, transaction: Transaction) -> Result<(), QueryExecutionError> {
+        for undo in transaction.undo.into_iter().rev() {
+            self.undo(undo)?;
+        }
+        self.transactions.abort(transaction.snapshot.tx);
+        self.collect_garbage()
+    }
+
+    /// Drop the versions of rows no open transaction or running query sees
+    /// anymore
+    fn collect_garbage(&mut self) -> Result<(), QueryExecutionError> {
+        let horizon = match self.readers.horizon() {
+            Some(read) => self.transactions.horizon().min(read),
+            None => self.transactions.horizon(),
+        };
+        for table in self.tables.values_mut() {
+            table.collect_garbage(horizon)?;
+        }
+        Ok(())
+    }
+}
+
+/// A handle to a database, it can be cloned and shared between threads to
+/// run queries in concurrent sessions
+#[derive(Debug, Clone)]
+pub struct Database {
+    shared: Arc<RwLock<Shared>>,
+    /// Locks on rows, waited for without holding the lock on `shared`
+    locks: Arc<LockManager>,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Database {
+    /// A database that only lives in memory
+    pub fn new() -> Self {
+        Self::from_shared(Shared::new())
+    }
+
+    fn from_shared(shared: Shared) -> Self {
+        Self {
+            shared: Arc::new(RwLock::new(shared)),
+            locks: Arc::default(),
+        }
+    }
+
+    /// Open a database file, creating it if it doesn't exist
+    ///
+    /// The changes committed to the write-ahead log next to it are replayed,
+    /// restoring the state of the last commit before a crash.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QueryExecutionError> {
+        let path = path.as_ref();
+        let mut pager = Pager::open(path)?;
+        let (wal, recovery) = Wal::open(wal::path_for(path))?;
+        let recovered = !recovery.pages.is_empty() || !recovery.records.is_empty();
+        if !recovery.pages.is_empty() {
+            pager.restore(recovery.pages)?;
+        }
+        pager.set_wal(wal);
+
+        let pager = SharedPager::new(pager);
+        let mut shared = Shared {
+            tables: catalog::load(&pager)?,
+            pager: Some(pager),
+            ..Shared::new()
+        };
+        for record in recovery.records {
+            shared.replay(record)?;
+        }
+
+        if recovered {
+            shared.checkpoint()?;
+        }
+        Ok(Self::from_shared(shared))
+    }
+
+    /// Start a session to run queries with
+    pub fn session(&self) -> Execution {
+        Execution {
+            database: self.clone(),
+            transaction: None,
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, Shared> {
+        self.shared
+            .read()
+            .expect("a thread panicked while using the database")
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, Shared> {
+        self.shared
+            .write()
+            .expect("a thread panicked while using the database")
+    }
+
+    pub fn set_checkpoint_thresholds(&self, thresholds: CheckpointThresholds) {
+        self.write().checkpoint_thresholds = thresholds;
+    }
+
+    /// Change how many pages of the database file are cached in memory
+    pub fn set_buffer_pool_size(&self, pages: usize) {
+        if let Some(pager) = &self.read().pager {
+            pager.lock().set_buffer_pool_size(pages);
+        }
+    }
+
+    /// Change how many bytes of rows a query may hold in memory, beyond which
+    /// sorts and aggregates spill them to temporary files
+    pub fn set_query_memory(&self, bytes: usize) {
+        self.write().query_memory = bytes;
+    }
+
+    /// Counters of the page cache, `None` if there is no database file
+    pub fn buffer_pool_stats(&self) -> Option<BufferPoolStats> {
+        self.read()
+            .pager
+            .as_ref()
+            .map(|pager| pager.lock().buffer_pool_stats())
+    }
+
+    /// Write the changes in the log to the database file, then empty the log
+    ///
+    /// Does nothing if there is no database file, fails if a transaction is
+    /// open in any session.
+    pub fn checkpoint(&self) -> Result<(), QueryExecutionError> {
+        self.write().checkpoint()
+    }
+}
+
+/// A session running queries against a [`Database`]
+///
+/// Sessions of the same database each see a consistent snapshot of it inside
+/// their transactions. A statement run outside of a transaction is a
+/// transaction of its own.
+#[derive(Debug)]
+pub struct Execution {
+    database: Database,
+    /// The transaction opened with `BEGIN`, if any
+    transaction: Option<Transaction>,
+}
+
+impl Default for Execution {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Execution {
+    /// A session of a new database that only lives in memory
+    pub fn new() -> Self {
+        Database::new().session()
+    }
+
+    /// A session of the database in a file, see [`Database::open`]
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, QueryExecutionError> {
+        Ok(Database::open(path)?.session())
+    }
+
+    /// The database this session runs queries against
+    pub fn database(&self) -> &Database {
+        &self.database
+    }
+
+    /// Start another session on the same database
+    pub fn session(&self) -> Self {
+        self.database.session()
+    }
+
+    pub fn set_checkpoint_thresholds(&mut self, thresholds: CheckpointThresholds) {
+        self.database.set_checkpoint_thresholds(thresholds);
+    }
+
+    /// Change how many pages of the database file are cached in memory
+    pub fn set_buffer_pool_size(&mut self, pages: usize) {
+        self.database.set_buffer_pool_size(pages);
+    }
+
+    /// Change how many bytes of rows a query may hold in memory, see
+    /// [`Database::set_query_memory`]
+    pub fn set_query_memory(&mut self, bytes: usize) {
+        self.database.set_query_memory(bytes);
+    }
+
+    /// Counters of the page cache, `None` if there is no database file
+    pub fn buffer_pool_stats(&self) -> Option<BufferPoolStats> {
+        self.database.buffer_pool_stats()
+    }
+
+    /// Write the changes in the log to the database file, then empty the log
+    pub fn checkpoint(&mut self) -> Result<(), QueryExecutionError> {
+        self.database.checkpoint()
+    }
+
+    /// The database locked for changes by the open transaction
+    fn lock_for_write(&mut self) -> (RwLockWriteGuard<'_, Shared>, &mut Transaction) {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .expect("statements run in a transaction");
+        (self.database.write(), transaction)
+    }
+
+    /// The snapshot of the open transaction
+    fn snapshot(&self) -> Snapshot {
+        self.transaction
+            .as_ref()
+            .expect("statements run in a transaction")
+            .snapshot
+    }
+
+    fn begin(&mut self) -> Result<(), QueryExecutionError> {
+        if self.transaction.is_some() {
+            return Err(QueryExecutionError::TransactionAlreadyOpen);
+        }
+        let snapshot = self.database.write().transactions.begin();
+        self.transaction = Some(Transaction::new(snapshot));
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), QueryExecutionError> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(QueryExecutionError::NoTransaction)?;
+
+        // the changes are visible before the rows are unlocked
+        let tx = transaction.snapshot.tx;
+        let res = self.database.write().commit(transaction);
+        self.database.locks.release(tx);
+        res
+    }
+
+    /// Revert every change made since `BEGIN`
+    fn rollback(&mut self) -> Result<(), QueryExecutionError> {
+        let transaction = self
+            .transaction
+            .take()
+            .ok_or(QueryExecutionError::NoTransaction)?;
+
+        let tx = transaction.snapshot.tx;
+        let res = self.database.write().abort(transaction);
+        self.database.locks.release(tx);
+        res
+    }
+
+    /// Lock the rows of a table matching the predicate until the transaction
+    /// ends, waiting for the transactions holding them
+    ///
+    /// The transaction is rolled back if it would deadlock.
+    fn lock_rows(
+        &mut self,
+        table: &str,
+        predicate: Option<&Expression>,
+    ) -> Result<(), QueryExecutionError> {
+        loop {
+            let snapshot = self.snapshot();
+            let ids = self
+                .database
+                .read()
+                .table(table, &snapshot)?
+                .matching_ids(predicate, &snapshot)?;
+
+            if let Err(e) = self.database.locks.lock(snapshot.tx, table, &ids) {
+                self.rollback()?;
+                return Err(e);
+            }
+
+            // at read committed the statement works on the rows as committed
+            // by the transactions it waited for, which may match differently
+            let transaction = self.transaction.as_mut().expect("in a transaction");
+            if transaction.isolation != IsolationLevel::ReadCommitted {
+                return Ok(());
+            }
+            self.database
+                .write()
+                .transactions
+                .refresh(&mut transaction.snapshot);
+            if transaction.snapshot == snapshot {
+                return Ok(());
+            }
+        }
+    }
+
+    fn savepoint(&mut self, name: String) -> Result<(), QueryExecutionError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(QueryExecutionError::NoTransaction)?;
+
+        let len = transaction.undo.len();
+        transaction.savepoints.push(Savepoint { name, len });
+        Ok(())
+    }
+
+    /// Revert the changes made since the savepoint, which stays open
+    fn rollback_to_savepoint(&mut self, name: String) -> Result<(), QueryExecutionError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(QueryExecutionError::NoTransaction)?;
+        let position = transaction
+            .find_savepoint(&name)
+            .ok_or(QueryExecutionError::SavepointNotFound(name))?;
+
+        transaction.savepoints.truncate(position + 1);
+        let len = transaction.savepoints[position].len;
+        transaction.records.truncate(len);
+        let undo = transaction.undo.split_off(len);
+
+        let mut database = self.database.write();
+        for undo in undo.into_iter().rev() {
+            database.undo(undo)?;
         }
+        Ok(())
+    }
+
+    /// Forget the savepoint and the ones made after it, keeping their changes
+    fn release_savepoint(&mut self, name: String) -> Result<(), QueryExecutionError> {
+        let transaction = self
+            .transaction
+            .as_mut()
+            .ok_or(QueryExecutionError::NoTransaction)?;
+        let position = transaction
+            .find_savepoint(&name)
+            .ok_or(QueryExecutionError::SavepointNotFound(name))?;
+
+        transaction.savepoints.truncate(position);
+        Ok(())
     }
 
     pub fn run(&mut self, query: SqlQuery) -> Result<ExecResponse, QueryExecutionError> {
+        if self.transaction.is_some()
+            || matches!(query, SqlQuery::Checkpoint | SqlQuery::Transaction(_))
+        {
+            return self.run_statement(query);
+        }
+
+        self.begin()?;
+        match self.run_statement(query) {
+            Ok(res) => {
+                self.commit()?;
+                Ok(res)
+            }
+            Err(e) => {
+                // unless it was rolled back already
+                if self.transaction.is_some() {
+                    self.rollback()?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    fn run_statement(&mut self, query: SqlQuery) -> Result<ExecResponse, QueryExecutionError> {
+        if let Some(transaction) = self.transaction.as_mut() {
+            let reads = match &query {
+                SqlQuery::Select(select) | SqlQuery::Explain { select, .. } => {
+                    select.tables().into_iter().map(str::to_owned).collect()
+                }
+                SqlQuery::Update(update) => vec![update.table.clone()],
+                SqlQuery::Delete(delete) => vec![delete.table.clone()],
+                _ => vec![],
+            };
+            transaction.reads.extend(reads);
+
+            // at read committed every statement sees what was committed
+            // before it started
+            let statement = !matches!(query, SqlQuery::Transaction(_) | SqlQuery::Checkpoint);
+            if statement && transaction.isolation == IsolationLevel::ReadCommitted {
+                self.database
+                    .write()
+                    .transactions
+                    .refresh(&mut transaction.snapshot);
+            }
+        }
+
         match query {
-            SqlQuery::Select(mut select) => {
-                let columns = select.fields;
-                let table = select.table;
-                let table = self
-                    .tables
-                    .get(&table)
-                    .ok_or(QueryExecutionError::TableNotFound(table))?;
+            SqlQuery::Select(select) => {
+                // the rows to lock are found by the where clause alone
+                let locked = if select.for_update {
+                    let aggregated = select.distinct.is_some()
+                        || !select.group_by.is_empty()
+                        || select.fields.iter().any(|field| {
+                            matches!(field, SelectItem::Expr { expr, .. }
+                                if expr.has_aggregate() || expr.has_window())
+                        });
+                    let subqueries = select.with.is_some()
+                        || !select.set_operations.is_empty()
+                        || select.where_clause.iter().any(Expression::has_subquery);
+                    let table = match &select.table {
+                        Some(table) if table.subquery.is_none() => table,
+                        _ => return Err(QueryExecutionError::InvalidForUpdate),
+                    };
+                    if !select.joins.is_empty() || aggregated || subqueries {
+                        return Err(QueryExecutionError::InvalidForUpdate);
+                    }
+                    let predicate = select
+                        .where_clause
+                        .as_ref()
+                        .map(|predicate| unqualify(predicate, table.qualifier()));
+                    self.lock_rows(&table.name, predicate.as_ref())?;
+                    Some((table.name.clone(), predicate))
+                } else {
+                    None
+                };
+
+                let database = self.database.read();
+                let snapshot = self.snapshot();
+                if let Some((table, predicate)) = locked {
+                    let table = database.table(&table, &snapshot)?;
+                    let ids = table.matching_ids(predicate.as_ref(), &snapshot)?;
+                    table.check_writable(ids.into_iter(), &snapshot)?;
+                }
 
-                Ok(ExecResponse::Select(table.select(columns)?))
+                let plan = database.plan_select(select, &snapshot)?;
+                let rows = operator::build(&plan, &self.database.shared, &database, &snapshot)?;
+                Ok(ExecResponse::Select(TableIter::new(
+                    rows,
+                    Arc::new(plan.schema().column_info()),
+                )))
             }
+            // statements that write check and make their changes while
+            // holding the lock, so concurrent sessions can't write in between.
+            // The rows they change are locked first, waiting without it.
             SqlQuery::Insert(insert) => {
-                let Some(table) = self.tables.get_mut(&insert.table) else {
-                    return Err(QueryExecutionError::TableNotFound(insert.table))
-                };
+                let (mut database, transaction) = self.lock_for_write();
+                let (id, row) = database
+                    .table(&insert.table, &transaction.snapshot)?
+                    .new_row(insert.values)?;
 
-                table.insert(insert.values)?;
+                database.write(
+                    transaction,
+                    vec![LogRecord::Insert {
+                        table: insert.table,
+                        id,
+                        row,
+                    }],
+                )?;
                 Ok(ExecResponse::Insert)
             }
+            SqlQuery::Update(update) => {
+                self.lock_rows(&update.table, update.where_clause.as_ref())?;
+                let (mut database, transaction) = self.lock_for_write();
+                let rows = database
+                    .table(&update.table, &transaction.snapshot)?
+                    .updated_rows(
+                        &update.assignments,
+                        update.where_clause.as_ref(),
+                        &transaction.snapshot,
+                    )?;
+
+                let records = rows
+                    .into_iter()
+                    .map(|(id, row)| LogRecord::Insert {
+                        table: update.table.clone(),
+                        id,
+                        row,
+                    })
+                    .collect();
+                database.write(transaction, records)?;
+                Ok(ExecResponse::Update)
+            }
+            SqlQuery::Delete(delete) => {
+                self.lock_rows(&delete.table, delete.where_clause.as_ref())?;
+                let (mut database, transaction) = self.lock_for_write();
+                let ids = database
+                    .table(&delete.table, &transaction.snapshot)?
+                    .deleted_ids(delete.where_clause.as_ref(), &transaction.snapshot)?;
+
+                let records = ids
+                    .into_iter()
+                    .map(|id| LogRecord::Delete {
+                        table: delete.table.clone(),
+                        id,
+                    })
+                    .collect();
+                database.write(transaction, records)?;
+                Ok(ExecResponse::Delete)
+            }
             SqlQuery::Create(create) => {
-                let table = Table::new(create.columns);
+                let (mut database, transaction) = self.lock_for_write();
+                if database.tables.contains_key(&create.table) {
+                    return Err(QueryExecutionError::TableAlreadyExists(create.table));
+                }
 
-                self.tables.insert(create.table, table);
+                // tables go in the database file unless asked otherwise
+                let engine = create.engine.unwrap_or(match database.pager {
+                    Some(_) => Engine::BTree,
+                    None => Engine::Memory,
+                });
+                database.write(
+                    transaction,
+                    vec![LogRecord::CreateTable {
+                        name: create.table,
+                        columns: create.columns,
+                        engine,
+                    }],
+                )?;
                 Ok(ExecResponse::Create)
             }
+            SqlQuery::CreateIndex(create_index) => {
+                let (mut database, transaction) = self.lock_for_write();
+                // index names are unique across all tables
+                if database
+                    .tables
+                    .values()
+                    .any(|t| t.has_index(&create_index.name))
+                {
+                    return Err(QueryExecutionError::IndexAlreadyExists(create_index.name));
+                }
+
+                let table = database.table(&create_index.table, &transaction.snapshot)?;
+                for column in &create_index.columns {
+                    table.columns().find_column(column)?;
+                }
+
+                database.write(
+                    transaction,
+                    vec![LogRecord::CreateIndex {
+                        name: create_index.name,
+                        table: create_index.table,
+                        columns: create_index.columns,
+                    }],
+                )?;
+                Ok(ExecResponse::CreateIndex)
+            }
+            // the rows FOR UPDATE would select are not locked
+            SqlQuery::Explain { select, analyze } => {
+                let database = self.database.read();
+                let snapshot = self.snapshot();
+                let plan = database.plan_select(select, &snapshot)?;
+                if !analyze {
+                    let estimator = Estimator::new(&database.tables, &plan);
+                    let plan = QueryPlan::new(&plan, &estimator, None);
+                    return Ok(ExecResponse::Explain(plan));
+                }
+
+                let (rows, metrics) =
+                    operator::build_measured(&plan, &self.database.shared, &database, &snapshot)?;
+                // the scans lock the database as they read
+                drop(database);
+                for row in rows {
+                    row?;
+                }
+                let database = self.database.read();
+                let estimator = Estimator::new(&database.tables, &plan);
+                let plan = QueryPlan::new(&plan, &estimator, Some(&metrics));
+                Ok(ExecResponse::Explain(plan))
+            }
+            // statistics are estimates, they aren't logged nor undone
+            SqlQuery::Analyze(table) => {
+                let (mut database, transaction) = self.lock_for_write();
+                let snapshot = &transaction.snapshot;
+                let names = match table {
+                    Some(name) => vec![name],
+                    None => database
+                        .tables
+                        .keys()
+                        .filter(|name| database.table(name, snapshot).is_ok())
+                        .cloned()
+                        .collect(),
+                };
+                for name in names {
+                    database.table_mut(&name, snapshot)?.analyze(snapshot)?;
+                }
+                Ok(ExecResponse::Analyze)
+            }
+            SqlQuery::Checkpoint => {
+                self.checkpoint()?;
+                Ok(ExecResponse::Checkpoint)
+            }
+            SqlQuery::Transaction(TransactionStatement::Begin) => {
+                self.begin()?;
+                Ok(ExecResponse::Begin)
+            }
+            SqlQuery::Transaction(TransactionStatement::Commit) => {
+                self.commit()?;
+                Ok(ExecResponse::Commit)
+            }
+            SqlQuery::Transaction(TransactionStatement::Rollback) => {
+                self.rollback()?;
+                Ok(ExecResponse::Rollback)
+            }
+            SqlQuery::Transaction(TransactionStatement::Savepoint(name)) => {
+                self.savepoint(name)?;
+                Ok(ExecResponse::Savepoint)
+            }
+            SqlQuery::Transaction(TransactionStatement::RollbackTo(name)) => {
+                self.rollback_to_savepoint(name)?;
+                Ok(ExecResponse::RollbackToSavepoint)
+            }
+            SqlQuery::Transaction(TransactionStatement::Release(name)) => {
+                self.release_savepoint(name)?;
+                Ok(ExecResponse::ReleaseSavepoint)
+            }
+            SqlQuery::Transaction(TransactionStatement::SetIsolationLevel(level)) => {
+                let transaction = self
+                    .transaction
+                    .as_mut()
+                    .ok_or(QueryExecutionError::NoTransaction)?;
+                if transaction.has_run_queries() {
+                    return Err(QueryExecutionError::IsolationLevelAfterQuery);
+                }
+                transaction.isolation = level;
+                Ok(ExecResponse::SetIsolationLevel)
+            }
         }
     }
 
@@ -72,22 +996,2269 @@ impl Execution {
     }
 
     /// Run multiple queries and return the response for the last query
+    ///
+    /// Unless they manage transactions themselves the queries run in one
+    /// transaction, so either all or none of their changes are made.
     pub fn parse_multiple_and_run<'a>(
         &'a mut self,
         query: &'a str,
     ) -> Result<ExecResponse, SQLError<'a>> {
-        // TODO: avoid clones
         let queries = parse_multiple_queries(query)?;
 
-        let (last, rest) = queries
-            .split_last()
-            .expect("at least one query should have been parsed");
+        let implicit = self.transaction.is_none()
+            && !queries
+                .iter()
+                .any(|q| matches!(q, SqlQuery::Transaction(_)));
+        if implicit {
+            self.begin()?;
+        }
 
-        for q in rest {
-            self.run(q.clone())?;
+        let mut res = None;
+        for q in queries {
+            match self.run(q) {
+                Ok(r) => res = Some(r),
+                Err(e) => {
+                    if implicit && self.transaction.is_some() {
+                        self.rollback()?;
+                    }
+                    return Err(e.into());
+                }
+            }
         }
 
-        let res = self.run(last.clone())?;
-        Ok(res)
+        if implicit {
+            self.commit()?;
+        }
+        Ok(res.expect("at least one query should have been parsed"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+
+    use tempfile::tempdir;
+    use toy_sql_parser::{commands::SetOperator, value::Value, SqlTypeInfo};
+
+    use super::*;
+
+    fn select_strings(exec: &mut Execution, query: &str) -> Vec<Vec<String>> {
+        let ExecResponse::Select(rows) = exec.parse_and_run(query).unwrap() else {
+            panic!("expected rows from {query}");
+        };
+        rows.map(|row| {
+            let row = row.unwrap();
+            row.values().iter().cloned().map(Into::into).collect()
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_reopen_database_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE foo (id int, name string);
+                CREATE INDEX foo_name ON foo USING HASH (name);",
+            )
+            .unwrap();
+            for id in 0..200 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id}, 'name{}';", id % 10))
+                    .unwrap();
+            }
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM foo WHERE name = 'name3' AND id < 40;"
+            ),
+            vec![
+                vec!["3".to_string()],
+                vec!["13".into()],
+                vec!["23".into()],
+                vec!["33".into()]
+            ]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id, name FROM foo;").len(),
+            200
+        );
+
+        // new rows keep counting from the stored ones
+        exec.parse_and_run("INSERT INTO foo VALUES 200, 'name0';")
+            .unwrap();
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM foo WHERE name IN ('name0') AND id > 150;"
+            ),
+            vec![
+                vec!["160".to_string()],
+                vec!["170".into()],
+                vec!["180".into()],
+                vec!["190".into()],
+                vec!["200".into()]
+            ]
+        );
+        assert!(matches!(
+            exec.parse_and_run("CREATE TABLE foo (id int);"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::TableAlreadyExists(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_engine_per_table() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE disk (id int);
+                CREATE TABLE scratch (id int) ENGINE = memory;
+                INSERT INTO disk VALUES 1;
+                INSERT INTO scratch VALUES 2;",
+            )
+            .unwrap();
+            assert_eq!(
+                select_strings(&mut exec, "SELECT id FROM scratch;"),
+                vec![vec!["2".to_string()]]
+            );
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM disk;"),
+            vec![vec!["1".to_string()]]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM scratch;"),
+            vec![vec!["2".to_string()]]
+        );
+
+        let mut exec = Execution::new();
+        assert!(matches!(
+            exec.parse_and_run("CREATE TABLE foo (id int) ENGINE = btree;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::EngineUnavailable(Engine::BTree)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_recover_from_torn_log() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_and_run("CREATE TABLE foo (id int, name string);")
+                .unwrap();
+            for id in 0..10 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id}, 'name{id}';"))
+                    .unwrap();
+            }
+        }
+
+        // crash in the middle of writing the last insert
+        let wal = OpenOptions::new()
+            .write(true)
+            .open(wal::path_for(&path))
+            .unwrap();
+        let len = wal.metadata().unwrap().len();
+        wal.set_len(len - 10).unwrap();
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            assert_eq!(
+                select_strings(&mut exec, "SELECT id FROM foo;"),
+                (0..9).map(|id| vec![id.to_string()]).collect::<Vec<_>>()
+            );
+            exec.parse_and_run("INSERT INTO foo VALUES 42, 'again';")
+                .unwrap();
+        }
+
+        // the log keeps working after the torn record was dropped
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id, name FROM foo WHERE id > 7;"),
+            vec![
+                vec!["8".to_string(), "name8".into()],
+                vec!["42".into(), "again".into()]
+            ]
+        );
+    }
+
+    fn wal_size(path: &Path) -> u64 {
+        std::fs::metadata(wal::path_for(path)).unwrap().len()
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE disk (id int);
+                CREATE TABLE scratch (id int) ENGINE = memory;
+                CREATE INDEX scratch_id ON scratch USING HASH (id);
+                INSERT INTO disk VALUES 1;
+                INSERT INTO scratch VALUES 2;",
+            )
+            .unwrap();
+            assert!(wal_size(&path) > 0);
+
+            exec.parse_and_run("CHECKPOINT;").unwrap();
+            assert_eq!(wal_size(&path), 0);
+            assert!(std::fs::metadata(&path).unwrap().len() > 0);
+
+            exec.parse_multiple_and_run(
+                "INSERT INTO disk VALUES 3;
+                INSERT INTO scratch VALUES 4;",
+            )
+            .unwrap();
+        }
+
+        // rows from both the database file and the log
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM disk;"),
+            vec![vec!["1".to_string()], vec!["3".into()]]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM scratch WHERE id = 2;"),
+            vec![vec!["2".to_string()]]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM scratch;").len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_automatic_checkpoint() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_and_run("CREATE TABLE foo (id int);").unwrap();
+            exec.set_checkpoint_thresholds(CheckpointThresholds {
+                log_size: Some(200),
+                interval: None,
+            });
+
+            let mut checkpoints = 0;
+            for id in 0..20 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id};"))
+                    .unwrap();
+                let size = wal_size(&path);
+                assert!(size < 200);
+                if size == 0 {
+                    checkpoints += 1;
+                }
+            }
+            assert!(checkpoints > 1);
+
+            exec.set_checkpoint_thresholds(CheckpointThresholds {
+                log_size: None,
+                interval: Some(Duration::ZERO),
+            });
+            exec.parse_and_run("INSERT INTO foo VALUES 20;").unwrap();
+            assert_eq!(wal_size(&path), 0);
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo;"),
+            (0..=20).map(|id| vec![id.to_string()]).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_recover_interrupted_checkpoint() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE foo (id int);
+                INSERT INTO foo VALUES 1;
+                INSERT INTO foo VALUES 2;",
+            )
+            .unwrap();
+
+            // crash after logging the pages but before writing them
+            let database = exec.database.write();
+            let pager = database.pager.clone().unwrap();
+            catalog::save(&pager, &database.tables).unwrap();
+            pager.lock().log_checkpoint().unwrap();
+        }
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(wal_size(&path), 0);
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo;"),
+            vec![vec!["1".to_string()], vec!["2".into()]]
+        );
+    }
+
+    #[test]
+    fn test_buffer_pool() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.set_buffer_pool_size(8);
+            exec.parse_multiple_and_run(
+                "CREATE TABLE foo (id int, name string);
+                CREATE INDEX foo_id ON foo USING HASH (id);",
+            )
+            .unwrap();
+            // enough rows for a few hundred pages, flushed when the pool is
+            // full of dirty pages
+            let name = "x".repeat(900);
+            for id in 0..400 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id}, '{name}';"))
+                    .unwrap();
+            }
+            assert!(std::fs::metadata(&path).unwrap().len() > 50 * 4096);
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        exec.set_buffer_pool_size(8);
+        let opened = exec.buffer_pool_stats().unwrap();
+
+        assert_eq!(select_strings(&mut exec, "SELECT id FROM foo;").len(), 400);
+        let scanned = exec.buffer_pool_stats().unwrap();
+        assert!(scanned.misses - opened.misses > 50);
+        assert!(scanned.evictions > opened.evictions);
+
+        // the index lookups go through the same cached pages
+        select_strings(&mut exec, "SELECT id FROM foo WHERE id = 399;");
+        select_strings(&mut exec, "SELECT id FROM foo WHERE id = 399;");
+        let stats = exec.buffer_pool_stats().unwrap();
+        assert!(stats.hits > scanned.hits);
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo WHERE id = 399;"),
+            vec![vec!["399".to_string()]]
+        );
+
+        assert_eq!(Execution::new().buffer_pool_stats(), None);
+    }
+
+    #[test]
+    fn test_buffer_pool_bounded_in_transaction() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.set_buffer_pool_size(8);
+            exec.parse_and_run("CREATE TABLE foo (id int, name string);")
+                .unwrap();
+
+            // no checkpoint can run while the other session is in a
+            // transaction, so the written pages go to the log
+            let mut reader = exec.session();
+            reader.parse_and_run("BEGIN;").unwrap();
+            let name = "x".repeat(900);
+            exec.parse_and_run("BEGIN;").unwrap();
+            for id in 0..200 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id}, '{name}';"))
+                    .unwrap();
+            }
+            exec.parse_and_run("COMMIT;").unwrap();
+
+            let stats = exec.buffer_pool_stats().unwrap();
+            assert!(stats.resident <= 8);
+            assert!(stats.evictions > 50);
+            assert_eq!(select_strings(&mut exec, "SELECT id FROM foo;").len(), 200);
+            assert!(exec.buffer_pool_stats().unwrap().resident <= 8);
+        }
+
+        // the evicted pages weren't checkpointed, the log is replayed instead
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(select_strings(&mut exec, "SELECT id FROM foo;").len(), 200);
+        exec.checkpoint().unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT name FROM foo WHERE id = 199;"),
+            vec![vec!["x".repeat(900)]]
+        );
+    }
+
+    fn assert_err(exec: &mut Execution, query: &str, expected: QueryExecutionError) {
+        match exec.parse_and_run(query) {
+            Err(SQLError::QueryExecutionError(e)) => {
+                assert_eq!(e.to_string(), expected.to_string())
+            }
+            other => panic!("expected {expected:?} from {query}, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn test_insert_value_count() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE emp (id int, name string, dept int);
+            CREATE INDEX d ON emp USING HASH (dept);",
+        )
+        .unwrap();
+        assert_err(
+            &mut exec,
+            "INSERT INTO emp VALUES 1, 'x';",
+            QueryExecutionError::InsertValueCount(3, 2),
+        );
+        assert_err(
+            &mut exec,
+            "INSERT INTO emp VALUES 1, 'x', 2, 3;",
+            QueryExecutionError::InsertValueCount(3, 4),
+        );
+
+        // the database is still usable after the errors
+        exec.parse_and_run("INSERT INTO emp VALUES 1, 'x', 2;")
+            .unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT name FROM emp WHERE dept = 2;"),
+            strings(&[&["x"]])
+        );
+    }
+
+    #[test]
+    fn test_rollback() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+        let mut exec = Execution::open(&path).unwrap();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string);
+            CREATE TABLE scratch (id int) ENGINE = memory;
+            CREATE INDEX foo_name ON foo USING HASH (name);
+            INSERT INTO foo VALUES 1, 'a';
+            INSERT INTO scratch VALUES 1;",
+        )
+        .unwrap();
+
+        for query in [
+            "BEGIN;",
+            "INSERT INTO foo VALUES 2, 'a';",
+            "INSERT INTO scratch VALUES 2;",
+            "CREATE TABLE bar (id int);",
+            "INSERT INTO bar VALUES 1;",
+            "CREATE INDEX foo_id ON foo USING HASH (id);",
+        ] {
+            exec.parse_and_run(query).unwrap();
+        }
+        // the transaction sees its own changes
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo WHERE name = 'a';").len(),
+            2
+        );
+        exec.parse_and_run("ROLLBACK;").unwrap();
+
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo WHERE name = 'a';"),
+            vec![vec!["1".to_string()]]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM scratch;"),
+            vec![vec!["1".to_string()]]
+        );
+        assert_err(
+            &mut exec,
+            "SELECT id FROM bar;",
+            QueryExecutionError::TableNotFound("bar".into()),
+        );
+        exec.parse_and_run("CREATE INDEX foo_id ON foo USING HASH (id);")
+            .unwrap();
+
+        // nothing rolled back made it to the file either
+        drop(exec);
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(select_strings(&mut exec, "SELECT id FROM foo;").len(), 1);
+        assert_err(
+            &mut exec,
+            "SELECT id FROM bar;",
+            QueryExecutionError::TableNotFound("bar".into()),
+        );
+    }
+
+    #[test]
+    fn test_commit() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "BEGIN;
+                CREATE TABLE foo (id int);
+                INSERT INTO foo VALUES 1;
+                COMMIT;
+                BEGIN TRANSACTION;
+                INSERT INTO foo VALUES 2;",
+            )
+            .unwrap();
+            assert_err(
+                &mut exec,
+                "BEGIN;",
+                QueryExecutionError::TransactionAlreadyOpen,
+            );
+            assert_err(
+                &mut exec,
+                "CHECKPOINT;",
+                QueryExecutionError::CheckpointInTransaction,
+            );
+            // closed with the transaction still open
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo;"),
+            vec![vec!["1".to_string()]]
+        );
+        assert_err(&mut exec, "COMMIT;", QueryExecutionError::NoTransaction);
+        assert_err(&mut exec, "ROLLBACK;", QueryExecutionError::NoTransaction);
+    }
+
+    #[test]
+    fn test_failed_script_changes_nothing() {
+        let mut exec = Execution::new();
+        exec.parse_and_run("CREATE TABLE foo (id int);").unwrap();
+
+        assert!(exec
+            .parse_multiple_and_run(
+                "INSERT INTO foo VALUES 1;
+                CREATE TABLE bar (id int);
+                INSERT INTO foo VALUES 'not a number';",
+            )
+            .is_err());
+
+        assert!(select_strings(&mut exec, "SELECT id FROM foo;").is_empty());
+        exec.parse_and_run("CREATE TABLE bar (id int);").unwrap();
+    }
+
+    #[test]
+    fn test_savepoints() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "BEGIN;
+                CREATE TABLE foo (id int);
+                INSERT INTO foo VALUES 1;
+                SAVEPOINT a;
+                INSERT INTO foo VALUES 2;
+                SAVEPOINT b;
+                CREATE TABLE bar (id int);
+                INSERT INTO foo VALUES 3;
+                ROLLBACK TO SAVEPOINT a;",
+            )
+            .unwrap();
+            assert_eq!(
+                select_strings(&mut exec, "SELECT id FROM foo;"),
+                vec![vec!["1".to_string()]]
+            );
+            assert_err(
+                &mut exec,
+                "RELEASE b;",
+                QueryExecutionError::SavepointNotFound("b".into()),
+            );
+
+            // a is still there to roll back to again
+            exec.parse_multiple_and_run(
+                "INSERT INTO foo VALUES 4;
+                ROLLBACK TO a;
+                INSERT INTO foo VALUES 5;
+                SAVEPOINT c;
+                INSERT INTO foo VALUES 6;
+                RELEASE SAVEPOINT a;",
+            )
+            .unwrap();
+            assert_err(
+                &mut exec,
+                "ROLLBACK TO c;",
+                QueryExecutionError::SavepointNotFound("c".into()),
+            );
+            exec.parse_and_run("COMMIT;").unwrap();
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo;"),
+            vec![vec!["1".to_string()], vec!["5".into()], vec!["6".into()]]
+        );
+        assert_err(
+            &mut exec,
+            "SELECT id FROM bar;",
+            QueryExecutionError::TableNotFound("bar".into()),
+        );
+        assert_err(
+            &mut exec,
+            "SAVEPOINT a;",
+            QueryExecutionError::NoTransaction,
+        );
+    }
+
+    fn strings(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|row| row.iter().map(|value| value.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_select_plan() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE users (id int, name string);
+            CREATE TABLE orders (id int, user_id int, amount int);
+            INSERT INTO users VALUES 1, 'ann';
+            INSERT INTO users VALUES 2, 'bob';
+            INSERT INTO users VALUES 3, 'cy';
+            INSERT INTO orders VALUES 1, 1, 10;
+            INSERT INTO orders VALUES 2, 1, 5;
+            INSERT INTO orders VALUES 3, 2, 7;
+            INSERT INTO orders VALUES 4, 3, 1;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT u.name, o.amount FROM users AS u JOIN orders o ON u.id = o.user_id \
+                WHERE o.amount > 1 ORDER BY o.amount DESC LIMIT 2;"
+            ),
+            strings(&[&["ann", "10"], &["bob", "7"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT name, COUNT(*) AS n, SUM(amount), MAX(amount) FROM users \
+                JOIN orders ON users.id = orders.user_id GROUP BY name ORDER BY n DESC, name;"
+            ),
+            strings(&[
+                &["ann", "2", "15", "10"],
+                &["bob", "1", "7", "7"],
+                &["cy", "1", "1", "1"]
+            ])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT * FROM users ORDER BY id LIMIT 5 OFFSET 2;"
+            ),
+            strings(&[&["3", "cy"]])
+        );
+
+        // aggregates of no rows
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT COUNT(*), SUM(amount), AVG(amount) FROM orders WHERE id > 10;"
+            ),
+            strings(&[&["0", "NULL", "NULL"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT AVG(amount), MIN(id) FROM orders;"),
+            strings(&[&["5.75", "1"]])
+        );
+
+        assert_err(
+            &mut exec,
+            "SELECT id FROM users JOIN orders ON users.id = orders.user_id;",
+            QueryExecutionError::AmbiguousColumn("id".into()),
+        );
+        assert_err(
+            &mut exec,
+            "SELECT name FROM users JOIN orders ON users.id = orders.user_id FOR UPDATE;",
+            QueryExecutionError::InvalidForUpdate,
+        );
+    }
+
+    #[test]
+    fn test_streaming_select() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string);
+            INSERT INTO foo VALUES 1, 'a';
+            INSERT INTO foo VALUES 2, 'b';
+            INSERT INTO foo VALUES 3, 'c';",
+        )
+        .unwrap();
+
+        // rows are computed as they are pulled, the second one fails to
+        // compare a string with a number
+        let query = "SELECT foo.name FROM foo JOIN foo AS f2 ON foo.id = f2.id \
+            WHERE foo.id = 1 OR f2.name = 1";
+        let ExecResponse::Select(mut rows) = exec.parse_and_run(&format!("{query};")).unwrap()
+        else {
+            panic!("expected rows");
+        };
+        assert_eq!(
+            rows.next().unwrap().unwrap().get(&"name".into()),
+            Value::String("a".into())
+        );
+        assert!(matches!(
+            rows.next(),
+            Some(Err(QueryExecutionError::IncomparableValues(..)))
+        ));
+
+        // so a limit stops before reaching it
+        assert_eq!(
+            select_strings(&mut exec, &format!("{query} LIMIT 1;")),
+            strings(&[&["a"]])
+        );
+    }
+
+    #[test]
+    fn test_scan_reads_its_snapshot() {
+        let mut exec = Execution::new();
+        exec.parse_and_run("CREATE TABLE foo (id int);").unwrap();
+        let mut writer = exec.session();
+        let version_count = |exec: &Execution| exec.database.read().tables["foo"].version_count();
+        let ids = |rows: Vec<Vec<String>>| -> Vec<i32> {
+            rows.into_iter()
+                .map(|row| row[0].parse().unwrap())
+                .collect()
+        };
+
+        exec.parse_and_run("BEGIN;").unwrap();
+        for id in 0..1000 {
+            exec.parse_and_run(&format!("INSERT INTO foo VALUES {id};"))
+                .unwrap();
+        }
+        let ExecResponse::Select(mut rows) = exec.parse_and_run("SELECT id FROM foo;").unwrap()
+        else {
+            panic!("expected rows");
+        };
+        let mut read: Vec<_> = rows.by_ref().take(300).collect::<Result<_, _>>().unwrap();
+
+        // the rows are read a batch at a time, writers commit in between
+        exec.parse_and_run("COMMIT;").unwrap();
+        writer
+            .parse_multiple_and_run(
+                "DELETE FROM foo WHERE id >= 500;
+                INSERT INTO foo VALUES 5000;",
+            )
+            .unwrap();
+        read.extend(rows.by_ref().map(Result::unwrap));
+        let read = read
+            .iter()
+            .map(|row| vec![row.values()[0].to_string()])
+            .collect();
+        assert_eq!(ids(read), (0..1000).collect::<Vec<_>>());
+
+        // the versions are kept until the scan is dropped
+        writer
+            .parse_and_run("INSERT INTO foo VALUES 5001;")
+            .unwrap();
+        assert!(version_count(&writer) > 0);
+        drop(rows);
+        writer
+            .parse_and_run("INSERT INTO foo VALUES 5002;")
+            .unwrap();
+        assert_eq!(version_count(&writer), 0);
+        assert_eq!(
+            ids(select_strings(&mut exec, "SELECT id FROM foo;")),
+            (0..500).chain(5000..5003).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_self_join_columns() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE t (id int);
+            INSERT INTO t VALUES 1;
+            INSERT INTO t VALUES 2;
+            INSERT INTO t VALUES 3;
+            INSERT INTO t VALUES 4;",
+        )
+        .unwrap();
+
+        let query = "SELECT a.id, b.id FROM t AS a JOIN t AS b ON a.id = b.id + 1 ORDER BY a.id;";
+        assert_eq!(
+            select_strings(&mut exec, query),
+            strings(&[&["2", "1"], &["3", "2"], &["4", "3"]])
+        );
+        // columns sharing a name are told apart by their table
+        let ExecResponse::Select(rows) = exec.parse_and_run(query).unwrap() else {
+            panic!("expected rows");
+        };
+        let names: Vec<_> = rows.columns.iter().map(|col| col.name.as_str()).collect();
+        assert_eq!(names, ["a.id", "b.id"]);
+        let row = rows.last().unwrap().unwrap();
+        assert_eq!(row.get(&"b.id".to_owned()), Value::Number(3.into()));
+
+        let ExecResponse::Select(rows) = exec.parse_and_run("SELECT id FROM t;").unwrap() else {
+            panic!("expected rows");
+        };
+        assert_eq!(rows.columns.iter().next().unwrap().name, "id");
+    }
+
+    #[test]
+    fn test_join_strategies() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE bar (id int, foo_id int);
+            INSERT INTO bar VALUES 1, 1;
+            INSERT INTO bar VALUES 2, 1;
+            INSERT INTO bar VALUES 3, 2;
+            INSERT INTO bar VALUES 4, NULL;
+            INSERT INTO bar VALUES 5, 2;
+            INSERT INTO bar VALUES 6, 3;
+            ANALYZE bar;",
+        )
+        .unwrap();
+        let explained = |exec: &mut Execution, query: &str| {
+            let ExecResponse::Explain(plan) =
+                exec.parse_and_run(&format!("EXPLAIN {query}")).unwrap()
+            else {
+                panic!("expected a plan");
+            };
+            plan.to_string()
+        };
+
+        // the NULL key of the row missing foo_id matches nothing
+        let hashed = "SELECT a.id AS l, b.id AS r FROM bar AS a JOIN bar AS b \
+            ON a.foo_id = b.foo_id AND a.id < b.id;";
+        assert!(explained(&mut exec, hashed).contains("Hash join"));
+        assert_eq!(
+            select_strings(&mut exec, hashed),
+            strings(&[&["1", "2"], &["3", "5"]])
+        );
+
+        let merged = "SELECT a.id AS l, b.id AS r FROM bar AS a JOIN bar AS b \
+            ON a.foo_id = b.foo_id ORDER BY a.foo_id;";
+        assert!(explained(&mut exec, merged).contains("Merge join"));
+        let looped = "SELECT a.id AS l, b.id AS r FROM bar AS a JOIN bar AS b \
+            ON a.foo_id <= b.foo_id AND a.foo_id >= b.foo_id;";
+        assert!(explained(&mut exec, looped).contains("Nested loop join"));
+
+        let expected = strings(&[
+            &["1", "1"],
+            &["1", "2"],
+            &["2", "1"],
+            &["2", "2"],
+            &["3", "3"],
+            &["3", "5"],
+            &["5", "3"],
+            &["5", "5"],
+            &["6", "6"],
+        ]);
+        assert_eq!(select_strings(&mut exec, merged), expected);
+        assert_eq!(select_strings(&mut exec, looped), expected);
+    }
+
+    #[test]
+    fn test_spilling_to_disk() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run("CREATE TABLE foo (id int, name string);")
+            .unwrap();
+        for id in 0..300 {
+            exec.parse_and_run(&format!(
+                "INSERT INTO foo VALUES {}, 'name{}';",
+                (id * 7) % 300,
+                id % 120
+            ))
+            .unwrap();
+        }
+        let queries = [
+            "SELECT id, name FROM foo ORDER BY name DESC, id;",
+            "SELECT name, COUNT(*), MIN(id) FROM foo GROUP BY name;",
+            "SELECT name, SUM(id) AS total FROM foo GROUP BY name ORDER BY total, name;",
+        ];
+        let in_memory: Vec<_> = queries
+            .iter()
+            .map(|query| select_strings(&mut exec, query))
+            .collect();
+
+        // a few rows fit in memory, the sorts and groups spill the others, the
+        // sorts in more runs than are merged at once
+        exec.set_query_memory(2000);
+        for (query, expected) in queries.iter().zip(&in_memory) {
+            let mut rows = select_strings(&mut exec, query);
+            // groups that didn't fit come out in another order
+            if !query.contains("ORDER BY") {
+                let mut expected = expected.clone();
+                expected.sort();
+                rows.sort();
+                assert_eq!(rows, expected, "{query}");
+            } else {
+                assert_eq!(&rows, expected, "{query}");
+            }
+        }
+        assert_eq!(in_memory[1].len(), 120);
+    }
+
+    #[test]
+    fn test_nested_loop_join_spills() {
+        let mut exec = Execution::new();
+        exec.parse_and_run("CREATE TABLE foo (id int, name string);")
+            .unwrap();
+        for id in 0..200 {
+            exec.parse_and_run(&format!(
+                "INSERT INTO foo VALUES {}, '{}';",
+                (id * 7) % 200,
+                "x".repeat(id % 50)
+            ))
+            .unwrap();
+        }
+        let query = "SELECT a.id, b.id, b.name FROM foo AS a JOIN foo AS b \
+            ON a.id + 1 < b.id AND b.id < a.id + 4;";
+        let ExecResponse::Explain(plan) = exec.parse_and_run(&format!("EXPLAIN {query}")).unwrap()
+        else {
+            panic!("expected a plan");
+        };
+        assert!(plan.to_string().contains("Nested loop join"), "{plan}");
+        let in_memory = select_strings(&mut exec, query);
+        assert_eq!(in_memory.len(), 2 * 197 + 1);
+
+        // the inner rows are read from a file again for each outer row, in
+        // the same order
+        exec.set_query_memory(2000);
+        assert_eq!(select_strings(&mut exec, query), in_memory);
+    }
+
+    #[test]
+    fn test_blocking_operators_spill() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string, grp int); \
+            CREATE TABLE bar (id int, grp int);",
+        )
+        .unwrap();
+        for id in 0..400 {
+            exec.parse_and_run(&format!(
+                "INSERT INTO foo VALUES {}, 'name{}', {};",
+                (id * 7) % 400,
+                id % 150,
+                id % 9
+            ))
+            .unwrap();
+        }
+        for id in 0..60 {
+            exec.parse_and_run(&format!("INSERT INTO bar VALUES {id}, {};", id % 12))
+                .unwrap();
+        }
+        let queries = [
+            "SELECT id, ROW_NUMBER() OVER (PARTITION BY grp ORDER BY id DESC), \
+            SUM(id) OVER (PARTITION BY grp), RANK() OVER (ORDER BY name) FROM foo;",
+            "SELECT DISTINCT name FROM foo;",
+            "SELECT DISTINCT ON (grp) grp, id FROM foo ORDER BY grp, id DESC;",
+            "SELECT name FROM foo UNION SELECT name FROM foo WHERE id < 10;",
+            "SELECT grp, COUNT(DISTINCT name), SUM(DISTINCT id % 50), COUNT(name) \
+            FROM foo GROUP BY grp;",
+            "SELECT COUNT(DISTINCT name) FROM foo;",
+            "SELECT foo.id, bar.id FROM foo JOIN bar ON foo.grp = bar.grp;",
+            "SELECT name, grp FROM foo INTERSECT SELECT name, grp FROM foo WHERE id % 3 = 0;",
+            "SELECT name, grp FROM foo EXCEPT SELECT name, grp FROM foo WHERE id % 3 = 0;",
+        ];
+        let in_memory: Vec<_> = queries
+            .iter()
+            .map(|query| select_strings(&mut exec, query))
+            .collect();
+
+        // much less than the table takes
+        exec.set_query_memory(2000);
+        for (query, expected) in queries.iter().zip(&in_memory) {
+            let mut rows = select_strings(&mut exec, query);
+            // groups and rows paired in files come out in another order
+            if ["GROUP BY", "JOIN", "INTERSECT", "EXCEPT"]
+                .iter()
+                .any(|clause| query.contains(clause))
+            {
+                let mut expected = expected.clone();
+                expected.sort();
+                rows.sort();
+                assert_eq!(rows, expected, "{query}");
+            } else {
+                assert_eq!(&rows, expected, "{query}");
+            }
+        }
+        assert_eq!(in_memory[1].len(), 150);
+        assert_eq!(in_memory[5], strings(&[&["150"]]));
+        assert_eq!(in_memory[6].len(), 400 * 5);
+        // every row of foo is a distinct pair, in one or the other
+        assert_eq!(in_memory[7].len() + in_memory[8].len(), 400);
+    }
+
+    #[test]
+    fn test_subqueries() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string);
+            INSERT INTO foo VALUES 1, 'a';
+            INSERT INTO foo VALUES 2, 'b';
+            INSERT INTO foo VALUES 3, 'c';
+            CREATE TABLE bar (id int, foo_id int);
+            INSERT INTO bar VALUES 1, 1;
+            INSERT INTO bar VALUES 2, 1;
+            INSERT INTO bar VALUES 3, 2;",
+        )
+        .unwrap();
+        let rows = |exec: &mut Execution, query: &str| select_strings(exec, query);
+
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT name FROM foo WHERE id = (SELECT MAX(foo_id) FROM bar);"
+            ),
+            strings(&[&["b"]])
+        );
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT id FROM foo WHERE id IN (SELECT foo_id FROM bar) ORDER BY id;"
+            ),
+            strings(&[&["1"], &["2"]])
+        );
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT id FROM foo WHERE id NOT IN (SELECT foo_id FROM bar);"
+            ),
+            strings(&[&["3"]])
+        );
+        // correlated with the row of foo each runs for
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT id FROM foo WHERE NOT EXISTS \
+                (SELECT id FROM bar WHERE bar.foo_id = foo.id);"
+            ),
+            strings(&[&["3"]])
+        );
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT name, (SELECT COUNT(*) FROM bar WHERE foo_id = foo.id) AS n \
+                FROM foo ORDER BY n DESC, name;"
+            ),
+            strings(&[&["a", "2"], &["b", "1"], &["c", "0"]])
+        );
+        assert_eq!(
+            rows(
+                &mut exec,
+                "SELECT t.foo_id, t.n FROM \
+                (SELECT foo_id, COUNT(*) AS n FROM bar GROUP BY foo_id) AS t \
+                JOIN foo ON foo.id = t.foo_id WHERE foo.name = 'a';"
+            ),
+            strings(&[&["1", "2"]])
+        );
+
+        // each run of a correlated subquery adds to the metrics of its nodes
+        let ExecResponse::Explain(plan) = exec
+            .parse_and_run(
+                "EXPLAIN ANALYZE SELECT id FROM foo WHERE EXISTS \
+                (SELECT id FROM bar WHERE bar.foo_id = foo.id);",
+            )
+            .unwrap()
+        else {
+            panic!("expected a plan");
+        };
+        let plan = plan.to_string();
+        // each run stops at the first row found, foo 3 finds none
+        assert!(
+            plan.contains("Scan: bar (id, foo_id) (estimated rows: 1000, rows: 7"),
+            "{plan}"
+        );
+
+        // NOT IN is unknown once the subquery has a NULL
+        exec.parse_and_run("INSERT INTO bar VALUES 4, NULL;")
+            .unwrap();
+        assert!(rows(
+            &mut exec,
+            "SELECT id FROM foo WHERE id NOT IN (SELECT foo_id FROM bar);"
+        )
+        .is_empty());
+
+        // found as the rows are pulled
+        let ExecResponse::Select(mut selected) = exec
+            .parse_and_run("SELECT id FROM foo WHERE id = (SELECT foo_id FROM bar);")
+            .unwrap()
+        else {
+            panic!("expected rows");
+        };
+        assert!(matches!(
+            selected.find_map(Result::err),
+            Some(QueryExecutionError::SubqueryRows)
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM foo WHERE id IN (SELECT id, foo_id FROM bar);"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::SubqueryColumns(2)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM foo WHERE id IN (SELECT id FROM bar WHERE x = 1);"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::ColumnDoesNotExist(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_common_table_expressions() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE staff (id int, boss int);
+            INSERT INTO staff VALUES 1, NULL;
+            INSERT INTO staff VALUES 2, 1;
+            INSERT INTO staff VALUES 3, 1;
+            INSERT INTO staff VALUES 4, 3;
+            INSERT INTO staff VALUES 5, 4;
+            INSERT INTO staff VALUES 6, NULL;
+            INSERT INTO staff VALUES 7, 6;
+            CREATE TABLE edges (src int, dst int);
+            INSERT INTO edges VALUES 1, 2;
+            INSERT INTO edges VALUES 2, 3;
+            INSERT INTO edges VALUES 3, 1;
+            INSERT INTO edges VALUES 3, 4;",
+        )
+        .unwrap();
+
+        // the queries read the ones named before them
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "WITH bosses (person, manager) AS (SELECT id, boss FROM staff WHERE boss > 2), \
+                top AS (SELECT person FROM bosses WHERE manager < 6) \
+                SELECT top.person FROM top ORDER BY top.person;"
+            ),
+            strings(&[&["4"], &["5"]])
+        );
+
+        // everyone reporting to 3, directly or not
+        let reports = "WITH RECURSIVE reports (id) AS (SELECT id FROM staff WHERE boss = 3 \
+            UNION ALL SELECT staff.id FROM staff JOIN reports ON staff.boss = reports.id) \
+            SELECT id FROM reports ORDER BY id;";
+        assert_eq!(
+            select_strings(&mut exec, reports),
+            strings(&[&["4"], &["5"]])
+        );
+
+        // the cycle stops adding new rows, the union reaches a fixpoint
+        let reachable = "WITH RECURSIVE reachable (node) AS (SELECT dst FROM edges WHERE src = 1 \
+            UNION SELECT edges.dst FROM edges JOIN reachable r ON edges.src = r.node) \
+            SELECT node FROM reachable ORDER BY node;";
+        assert_eq!(
+            select_strings(&mut exec, reachable),
+            strings(&[&["1"], &["2"], &["3"], &["4"]])
+        );
+
+        let ExecResponse::Explain(plan) = exec
+            .parse_and_run(&format!("EXPLAIN ANALYZE {reachable}"))
+            .unwrap()
+        else {
+            panic!("expected a plan");
+        };
+        let plan = plan.to_string();
+        assert!(plan.contains("Recursive union: reachable (node)"), "{plan}");
+        // each iteration reads the rows the one before found: 2, 3, then 1 and 4
+        assert!(
+            plan.contains("Scan: reachable AS r (node) (estimated rows: 1000, rows: 4,"),
+            "{plan}"
+        );
+
+        // a select without FROM computes a single row
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "WITH RECURSIVE r (n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM r WHERE n < 5) \
+                SELECT n FROM r ORDER BY n;"
+            ),
+            strings(&[&["1"], &["2"], &["3"], &["4"], &["5"]])
+        );
+
+        assert!(matches!(
+            exec.parse_and_run("WITH t (a, b) AS (SELECT id FROM staff) SELECT a FROM t;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::NamedColumns(..)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run(
+                "WITH RECURSIVE t (a) AS (SELECT id FROM staff \
+                UNION SELECT id, boss FROM t JOIN staff ON staff.boss = t.a) SELECT a FROM t;"
+            ),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::SetOperationColumns(SetOperator::Union, 1, 2)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_set_operations() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string);
+            INSERT INTO foo VALUES 1, 'a';
+            INSERT INTO foo VALUES 2, 'b';
+            INSERT INTO foo VALUES 2, 'b';
+            INSERT INTO foo VALUES 3, NULL;
+            CREATE TABLE bar (id int, name string);
+            INSERT INTO bar VALUES 2, 'b';
+            INSERT INTO bar VALUES 3, NULL;
+            INSERT INTO bar VALUES 4, 'd';",
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, name FROM foo UNION SELECT id, name FROM bar ORDER BY id;"
+            ),
+            strings(&[&["1", "a"], &["2", "b"], &["3", "NULL"], &["4", "d"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM foo UNION ALL SELECT id FROM bar ORDER BY id LIMIT 4 OFFSET 1;"
+            ),
+            strings(&[&["2"], &["2"], &["2"], &["3"]])
+        );
+        // NULLs are equal to each other here
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, name FROM foo INTERSECT SELECT id, name FROM bar ORDER BY id;"
+            ),
+            strings(&[&["2", "b"], &["3", "NULL"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo EXCEPT SELECT id FROM bar;"),
+            strings(&[&["1"]])
+        );
+        // INTERSECT is combined first
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM bar WHERE id = 4 UNION SELECT id FROM foo \
+                INTERSECT SELECT id FROM bar ORDER BY id;"
+            ),
+            strings(&[&["2"], &["3"], &["4"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM foo EXCEPT SELECT id FROM bar WHERE id = 2 \
+                UNION SELECT id FROM bar WHERE id > 3 ORDER BY id;"
+            ),
+            strings(&[&["1"], &["3"], &["4"]])
+        );
+
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM foo UNION SELECT id, name FROM bar;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::SetOperationColumns(SetOperator::Union, 1, 2)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM foo EXCEPT SELECT name FROM bar;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::SetOperationTypes(SetOperator::Except, ..)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM foo UNION SELECT id FROM bar FOR UPDATE;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::InvalidForUpdate
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_distinct() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string, price int);
+            INSERT INTO foo VALUES 1, 'a', 10;
+            INSERT INTO foo VALUES 2, 'b', 10;
+            INSERT INTO foo VALUES 3, 'a', 20;
+            INSERT INTO foo VALUES 4, 'b', 10;
+            INSERT INTO foo VALUES 5, 'c', NULL;
+            INSERT INTO foo VALUES 6, 'a', 20;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_strings(&mut exec, "SELECT DISTINCT name FROM foo ORDER BY name;"),
+            strings(&[&["a"], &["b"], &["c"]])
+        );
+        // whole rows are compared, NULLs equal to each other
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT DISTINCT name, price FROM foo ORDER BY name, price DESC LIMIT 4;"
+            ),
+            strings(&[&["a", "20"], &["a", "10"], &["b", "10"], &["c", "NULL"]])
+        );
+        // the most expensive of each name, the first id of equal prices
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT DISTINCT ON (n) name AS n, id, price FROM foo \
+                ORDER BY n, price DESC, id;"
+            ),
+            strings(&[&["a", "3", "20"], &["b", "2", "10"], &["c", "5", "NULL"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT name, COUNT(DISTINCT price), COUNT(price), SUM(DISTINCT price) FROM foo \
+                GROUP BY name ORDER BY name;"
+            ),
+            strings(&[
+                &["a", "2", "3", "30"],
+                &["b", "1", "2", "10"],
+                &["c", "0", "0", "NULL"]
+            ])
+        );
+
+        let ExecResponse::Explain(plan) = exec
+            .parse_and_run("EXPLAIN SELECT DISTINCT name FROM foo;")
+            .unwrap()
+        else {
+            panic!("expected a plan");
+        };
+        assert!(plan.to_string().starts_with("Distinct"), "{plan}");
+        assert!(matches!(
+            exec.parse_and_run("SELECT DISTINCT name FROM foo FOR UPDATE;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::InvalidForUpdate
+            ))
+        ));
+        // sorted by other values, the rows kept would depend on the scan order
+        assert_err(
+            &mut exec,
+            "SELECT DISTINCT ON (name) id FROM foo ORDER BY price;",
+            QueryExecutionError::DistinctOnOrder("price".into()),
+        );
+        assert_err(
+            &mut exec,
+            "SELECT DISTINCT name, id FROM foo ORDER BY price;",
+            QueryExecutionError::DistinctOrder("price".into()),
+        );
+    }
+
+    #[test]
+    fn test_window_functions() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE sales (id int, region string, amount int);
+            INSERT INTO sales VALUES 1, 'east', 10;
+            INSERT INTO sales VALUES 2, 'east', 20;
+            INSERT INTO sales VALUES 3, 'east', 20;
+            INSERT INTO sales VALUES 4, 'west', 5;
+            INSERT INTO sales VALUES 5, 'west', 15;
+            INSERT INTO sales VALUES 6, 'east', 40;",
+        )
+        .unwrap();
+
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, ROW_NUMBER() OVER (PARTITION BY region ORDER BY amount DESC, id), \
+                RANK() OVER (PARTITION BY region ORDER BY amount DESC) AS r, \
+                DENSE_RANK() OVER (PARTITION BY region ORDER BY amount DESC) FROM sales ORDER BY id;"
+            ),
+            strings(&[
+                &["1", "4", "4", "3"],
+                &["2", "2", "2", "2"],
+                &["3", "3", "2", "2"],
+                &["4", "2", "2", "2"],
+                &["5", "1", "1", "1"],
+                &["6", "1", "1", "1"]
+            ])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, LAG(amount) OVER (ORDER BY id), LEAD(amount, 2, 0) OVER (ORDER BY id) \
+                FROM sales ORDER BY id;"
+            ),
+            strings(&[
+                &["1", "NULL", "20"],
+                &["2", "10", "5"],
+                &["3", "20", "15"],
+                &["4", "20", "40"],
+                &["5", "5", "0"],
+                &["6", "15", "0"]
+            ])
+        );
+        // a running sum per region, and a moving average over three rows
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, SUM(amount) OVER (PARTITION BY region ORDER BY id) AS running, \
+                AVG(amount) OVER (ORDER BY id ROWS BETWEEN 1 PRECEDING AND 1 FOLLOWING) \
+                FROM sales ORDER BY id;"
+            ),
+            strings(&[
+                &["1", "10", "15"],
+                &["2", "30", "16.666667"],
+                &["3", "50", "15"],
+                &["4", "5", "13.333333"],
+                &["5", "20", "20"],
+                &["6", "90", "27.5"]
+            ])
+        );
+        // without a frame the rows ordered alike are summed together
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, SUM(amount) OVER (ORDER BY amount), COUNT(*) OVER () \
+                FROM sales ORDER BY id;"
+            ),
+            strings(&[
+                &["1", "15", "6"],
+                &["2", "70", "6"],
+                &["3", "70", "6"],
+                &["4", "5", "6"],
+                &["5", "30", "6"],
+                &["6", "110", "6"]
+            ])
+        );
+        // ranking the groups, and sorting by a window function
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT region, SUM(amount), RANK() OVER (ORDER BY SUM(amount) DESC) FROM sales \
+                GROUP BY region ORDER BY region;"
+            ),
+            strings(&[&["east", "90", "1"], &["west", "20", "2"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM sales ORDER BY ROW_NUMBER() OVER (ORDER BY amount DESC, id) LIMIT 2;"
+            ),
+            strings(&[&["6"], &["2"]])
+        );
+
+        assert!(matches!(
+            exec.parse_and_run("SELECT id FROM sales WHERE ROW_NUMBER() OVER (ORDER BY id) > 1;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::InvalidExpression(_)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT SUM(region) OVER () FROM sales;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::InvalidAggregate(_)
+            ))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT LAG(id, 1, 0, 2) OVER () FROM sales;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::InvalidExpression(_)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_expressions() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE items (id int, name string, price int, qty int);
+            INSERT INTO items VALUES 1, 'Apple', 3, 4;
+            INSERT INTO items VALUES 2, 'banana', 5, 2;
+            INSERT INTO items VALUES 3, 'cherry', 7, NULL;",
+        )
+        .unwrap();
+
+        // operators on NULL are NULL
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, price * qty AS total, name || '!', price - qty, price / 2, price % 2 \
+                FROM items ORDER BY id;"
+            ),
+            strings(&[
+                &["1", "12", "Apple!", "-1", "1", "1"],
+                &["2", "10", "banana!", "3", "2", "1"],
+                &["3", "NULL", "cherry!", "NULL", "3", "1"]
+            ])
+        );
+        // ints divide and cast into ints, truncated towards zero
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT 11 / 3, -11 / 3, CAST('3.7' AS int), CAST('-3.7' AS int), \
+                CAST(AVG(price) / 2 AS int) FROM items;"
+            ),
+            strings(&[&["3", "-3", "3", "-3", "2"]])
+        );
+        exec.parse_multiple_and_run(
+            "CREATE TABLE counts (id int);
+            INSERT INTO counts VALUES 11;
+            UPDATE counts SET id = id / 3;",
+        )
+        .unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM counts;"),
+            strings(&[&["3"]])
+        );
+        // NULL is a value of any type, that no condition keeps rows for
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT NULL, NULL + 1, NULL || 'a', NULL = NULL FROM items WHERE id = 1;"
+            ),
+            strings(&[&["NULL", "NULL", "NULL", "NULL"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM items WHERE NULL OR id = 2;"),
+            strings(&[&["2"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM items WHERE NOT NULL;"),
+            Vec::<Vec<String>>::new()
+        );
+        exec.parse_multiple_and_run(
+            "INSERT INTO counts VALUES NULL;
+            UPDATE counts SET id = NULL WHERE id = 3;",
+        )
+        .unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id, id IN (1, NULL) FROM counts;"),
+            strings(&[&["NULL", "NULL"], &["NULL", "NULL"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT price > 4, price BETWEEN 4 AND 6, name LIKE 'b%', name ILIKE 'a_P%', \
+                id NOT IN (2, 4), qty < 3 FROM items ORDER BY id;"
+            ),
+            strings(&[
+                &["false", "false", "false", "true", "true", "false"],
+                &["true", "true", "true", "false", "false", "true"],
+                &["true", "false", "false", "false", "true", "NULL"]
+            ])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT CASE WHEN price < 4 THEN 'cheap' WHEN price < 6 THEN 'fair' ELSE 'dear' END, \
+                CASE id WHEN 1 THEN 'one' END, CAST(price AS string) || 'c', CAST('12' AS int) + id \
+                FROM items ORDER BY id;"
+            ),
+            strings(&[
+                &["cheap", "one", "3c", "13"],
+                &["fair", "NULL", "5c", "14"],
+                &["dear", "NULL", "7c", "15"]
+            ])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT SUM(price * qty), COUNT(*) + 1 FROM items;"
+            ),
+            strings(&[&["22", "4"]])
+        );
+
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT -id, id > 2 = true FROM items WHERE price > -5 ORDER BY -id;"
+            ),
+            strings(&[&["-3", "true"], &["-2", "false"], &["-1", "false"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT 1 + 2 * 3, 'a' || 'b' AS ab WHERE 1 < 2;"),
+            strings(&[&["7", "ab"]])
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT 1 WHERE 1 > 2;"),
+            Vec::<Vec<String>>::new()
+        );
+
+        // unknown conditions don't keep rows, but can be made known
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM items WHERE NOT qty > 2;"),
+            strings(&[&["2"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM items WHERE qty > 2 OR price > 6 ORDER BY id;"
+            ),
+            strings(&[&["1"], &["3"]])
+        );
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM items WHERE price * 2 BETWEEN 6 AND 10 ORDER BY price * qty;"
+            ),
+            strings(&[&["2"], &["1"]])
+        );
+
+        exec.parse_multiple_and_run(
+            "CREATE TABLE tasks (id int, done bool);
+            INSERT INTO tasks VALUES 1, true;
+            INSERT INTO tasks VALUES 2, false;",
+        )
+        .unwrap();
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id, CAST(done AS int) FROM tasks WHERE done OR id = 3;"
+            ),
+            strings(&[&["1", "1"]])
+        );
+
+        // rows fail as they are computed
+        let ExecResponse::Select(mut selected) = exec
+            .parse_and_run("SELECT price / (qty - qty) FROM items;")
+            .unwrap()
+        else {
+            panic!("expected rows");
+        };
+        assert!(matches!(
+            selected.find_map(Result::err),
+            Some(QueryExecutionError::DivisionByZero)
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT name * 2 FROM items;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::OperandTypes(_, SqlTypeInfo::String, SqlTypeInfo::Int)
+            ))
+        ));
+        let ExecResponse::Select(mut selected) = exec
+            .parse_and_run("SELECT CAST(name AS int) FROM items;")
+            .unwrap()
+        else {
+            panic!("expected rows");
+        };
+        assert!(matches!(
+            selected.find_map(Result::err),
+            Some(QueryExecutionError::InvalidCast(..))
+        ));
+        assert!(matches!(
+            exec.parse_and_run("SELECT CASE WHEN id = 1 THEN name ELSE price END FROM items;"),
+            Err(SQLError::QueryExecutionError(
+                QueryExecutionError::CaseTypes(SqlTypeInfo::String, SqlTypeInfo::Int)
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_analyze() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE foo (id int, name string);
+                CREATE INDEX foo_name ON foo USING HASH (name);
+                CREATE TABLE bar (id int);",
+            )
+            .unwrap();
+            for id in 0..50 {
+                exec.parse_and_run(&format!("INSERT INTO foo VALUES {id}, 'name{}';", id % 2))
+                    .unwrap();
+            }
+            assert!(matches!(
+                exec.parse_and_run("ANALYZE missing;"),
+                Err(SQLError::QueryExecutionError(
+                    QueryExecutionError::TableNotFound(_)
+                ))
+            ));
+            exec.parse_and_run("ANALYZE foo;").unwrap();
+            exec.parse_and_run("CHECKPOINT;").unwrap();
+            assert!(exec.database.read().tables["bar"].stats.is_none());
+        }
+
+        // the statistics are kept in the database file
+        let mut exec = Execution::open(&path).unwrap();
+        let stats = exec.database.read().tables["foo"].stats.clone().unwrap();
+        assert_eq!(stats.row_count, 50);
+        assert_eq!(stats.columns["id"].distinct, 50);
+        assert_eq!(stats.columns["name"].distinct, 2);
+        assert_eq!(
+            stats.columns["name"].min,
+            Some(Value::String("name0".into()))
+        );
+
+        // half the rows are read without the index, with the same result
+        assert_eq!(
+            select_strings(
+                &mut exec,
+                "SELECT id FROM foo WHERE name = 'name1' AND id < 6;"
+            ),
+            strings(&[&["1"], &["3"], &["5"]])
+        );
+
+        exec.parse_and_run("ANALYZE;").unwrap();
+        assert_eq!(
+            exec.database.read().tables["bar"]
+                .stats
+                .as_ref()
+                .unwrap()
+                .row_count,
+            0
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let mut exec = Execution::new();
+        exec.parse_multiple_and_run(
+            "CREATE TABLE foo (id int, name string);
+            CREATE INDEX foo_name ON foo USING HASH (name);
+            INSERT INTO foo VALUES 1, 'a';
+            INSERT INTO foo VALUES 2, 'b';
+            INSERT INTO foo VALUES 3, 'a';",
+        )
+        .unwrap();
+        let explain = |exec: &mut Execution, query: &str| match exec.parse_and_run(query) {
+            Ok(ExecResponse::Explain(plan)) => plan,
+            res => panic!("expected a plan, got {res:?}"),
+        };
+
+        let query = "SELECT id FROM foo WHERE name = 'a' ORDER BY id DESC;";
+        let plan = explain(&mut exec, &format!("EXPLAIN {query}"));
+        assert!(!plan.analyzed());
+        let tree: Vec<_> = plan
+            .nodes
+            .iter()
+            .map(|node| format!("{}{}", " ".repeat(node.depth), node.description))
+            .collect();
+        assert_eq!(
+            tree,
+            [
+                "Project: id",
+                " Sort: id DESC",
+                "  Filter: (name = 'a')",
+                "   Scan: foo (id, name) using foo_name",
+            ]
+        );
+        assert_eq!(plan.nodes[3].estimated_rows, 1000.0);
+
+        // the estimates follow the statistics
+        exec.parse_and_run("ANALYZE foo;").unwrap();
+        let plan = explain(&mut exec, &format!("EXPLAIN ANALYZE {query}"));
+        let rows = |plan: &QueryPlan| -> Vec<_> {
+            plan.nodes
+                .iter()
+                .map(|node| (node.estimated_rows, node.actual.unwrap().rows))
+                .collect()
+        };
+        assert_eq!(
+            rows(&plan),
+            [(1.5, 2), (1.5, 2), (1.5, 2), (3.0, 3)],
+            "{plan}"
+        );
+        assert!(plan.nodes[0].actual.unwrap().elapsed >= plan.nodes[3].actual.unwrap().elapsed);
+
+        // running it fails like the query would
+        assert!(exec
+            .parse_and_run("EXPLAIN ANALYZE SELECT id FROM foo WHERE name = 1;")
+            .is_err());
+        assert!(exec
+            .parse_and_run("EXPLAIN SELECT id FROM foo WHERE name = 1;")
+            .is_ok());
+    }
+
+    #[test]
+    fn test_update_and_delete() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test.db");
+
+        {
+            let mut exec = Execution::open(&path).unwrap();
+            exec.parse_multiple_and_run(
+                "CREATE TABLE foo (id int, name string);
+                CREATE INDEX foo_name ON foo USING HASH (name);
+                INSERT INTO foo VALUES 1, 'a';
+                INSERT INTO foo VALUES 2, 'b';
+                INSERT INTO foo VALUES 3, 'a';
+                UPDATE foo SET name = 'c', id = id WHERE name = 'a' AND id > 1;
+                DELETE FROM foo WHERE id = 1;",
+            )
+            .unwrap();
+            assert_err(
+                &mut exec,
+                "UPDATE foo SET id = name;",
+                QueryExecutionError::InsertTypeMismatch(
+                    SqlTypeInfo::Int,
+                    Value::String("b".into()),
+                ),
+            );
+        }
+
+        let mut exec = Execution::open(&path).unwrap();
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id, name FROM foo;"),
+            vec![
+                vec!["2".to_string(), "b".into()],
+                vec!["3".into(), "c".into()]
+            ]
+        );
+        assert_eq!(
+            select_strings(&mut exec, "SELECT id FROM foo WHERE name = 'a';"),
+            Vec::<Vec<String>>::new()
+        );
+    }
+
+    #[test]
+    fn test_snapshot_isolation() {
+        let mut writer = Execution::new();
+        writer
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int);
+                INSERT INTO foo VALUES 1;",
+            )
+            .unwrap();
+        let mut reader = writer.session();
+
+        reader.parse_and_run("BEGIN;").unwrap();
+        writer
+            .parse_multiple_and_run(
+                "BEGIN;
+                INSERT INTO foo VALUES 2;
+                UPDATE foo SET id = 10 WHERE id = 1;",
+            )
+            .unwrap();
+
+        // uncommitted changes are only seen by their transaction
+        assert_eq!(
+            select_strings(&mut writer, "SELECT id FROM foo;"),
+            vec![vec!["10".to_string()], vec!["2".into()]]
+        );
+        assert_eq!(
+            select_strings(&mut reader, "SELECT id FROM foo;"),
+            vec![vec!["1".to_string()]]
+        );
+
+        // and commits after the snapshot are not seen either
+        writer.parse_and_run("COMMIT;").unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT id FROM foo WHERE id < 5;"),
+            vec![vec!["1".to_string()]]
+        );
+
+        reader.parse_and_run("COMMIT;").unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT id FROM foo WHERE id < 5;"),
+            vec![vec!["2".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_write_conflict() {
+        let mut first = Execution::new();
+        first
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int, n int);
+                INSERT INTO foo VALUES 1, 0;
+                INSERT INTO foo VALUES 2, 0;",
+            )
+            .unwrap();
+        let mut second = first.session();
+
+        first.parse_and_run("BEGIN;").unwrap();
+        second.parse_and_run("BEGIN;").unwrap();
+        first
+            .parse_and_run("UPDATE foo SET n = 1 WHERE id = 1;")
+            .unwrap();
+
+        // other rows can still be written, new rows get their own ids
+        second
+            .parse_multiple_and_run(
+                "UPDATE foo SET n = 2 WHERE id = 2;
+                INSERT INTO foo VALUES 3, 2;",
+            )
+            .unwrap();
+        first.parse_and_run("INSERT INTO foo VALUES 4, 1;").unwrap();
+        first.parse_and_run("COMMIT;").unwrap();
+
+        // the change is committed, but after the snapshot was taken
+        assert_err(
+            &mut second,
+            "UPDATE foo SET n = 2 WHERE id = 1;",
+            QueryExecutionError::WriteConflict,
+        );
+        second.parse_and_run("COMMIT;").unwrap();
+
+        assert_eq!(
+            select_strings(&mut first, "SELECT id, n FROM foo;"),
+            vec![
+                vec!["1".to_string(), "1".into()],
+                vec!["2".into(), "2".into()],
+                vec!["3".into(), "2".into()],
+                vec!["4".into(), "1".into()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_garbage_collection() {
+        let mut writer = Execution::new();
+        writer
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int);
+                INSERT INTO foo VALUES 0;",
+            )
+            .unwrap();
+        let mut reader = writer.session();
+        let version_count = |exec: &Execution| exec.database.read().tables["foo"].version_count();
+
+        reader.parse_and_run("BEGIN;").unwrap();
+        for id in 1..5 {
+            writer
+                .parse_and_run(&format!("UPDATE foo SET id = {id};"))
+                .unwrap();
+        }
+        // every version since the reader's snapshot is kept
+        assert_eq!(version_count(&writer), 5);
+        assert_eq!(
+            select_strings(&mut reader, "SELECT id FROM foo;"),
+            vec![vec!["0".to_string()]]
+        );
+
+        reader.parse_and_run("ROLLBACK;").unwrap();
+        assert_eq!(version_count(&writer), 0);
+        assert_eq!(
+            select_strings(&mut reader, "SELECT id FROM foo;"),
+            vec![vec!["4".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_concurrent_sessions() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<Database>();
+        assert_send_sync::<Execution>();
+
+        const ACCOUNTS: usize = 5;
+        const TOTAL: usize = ACCOUNTS * 100;
+
+        let dir = tempdir().unwrap();
+        let database = Database::open(dir.path().join("test.db")).unwrap();
+        let mut setup = database.session();
+        setup
+            .parse_and_run("CREATE TABLE accounts (id int, balance int);")
+            .unwrap();
+        for id in 0..ACCOUNTS {
+            setup
+                .parse_and_run(&format!("INSERT INTO accounts VALUES {id}, 100;"))
+                .unwrap();
+        }
+
+        // move money between accounts, retrying on conflicts
+        let transfers = (0..4).map(|worker| {
+            let mut session = database.session();
+            std::thread::spawn(move || {
+                let mut done = 0;
+                let mut attempt = worker;
+                while done < 25 {
+                    attempt += 1;
+                    let (from, to) = (attempt % ACCOUNTS, (attempt * 7 + 1) % ACCOUNTS);
+                    if from == to {
+                        continue;
+                    }
+
+                    session.parse_and_run("BEGIN;").unwrap();
+                    let balances = select_strings(
+                        &mut session,
+                        &format!("SELECT id, balance FROM accounts WHERE id IN ({from}, {to});"),
+                    );
+                    let balance = |id: usize| -> usize {
+                        let row = balances.iter().find(|row| row[0] == id.to_string());
+                        row.unwrap()[1].parse().unwrap()
+                    };
+                    let (from_balance, to_balance) = (balance(from), balance(to));
+                    let amount = from_balance.min(3);
+
+                    let script = format!(
+                        "UPDATE accounts SET balance = {} WHERE id = {from};
+                        UPDATE accounts SET balance = {} WHERE id = {to};
+                        COMMIT;",
+                        from_balance - amount,
+                        to_balance + amount
+                    );
+                    match session.parse_multiple_and_run(&script) {
+                        Ok(_) => done += 1,
+                        Err(SQLError::QueryExecutionError(QueryExecutionError::WriteConflict)) => {
+                            session.parse_and_run("ROLLBACK;").unwrap();
+                        }
+                        // the transaction was rolled back already
+                        Err(SQLError::QueryExecutionError(QueryExecutionError::Deadlock)) => {}
+                        Err(e) => panic!("transfer failed: {e}"),
+                    }
+                }
+            })
+        });
+        let transfers: Vec<_> = transfers.collect();
+
+        // every snapshot holds all the money
+        let audits = (0..2).map(|_| {
+            let mut session = database.session();
+            std::thread::spawn(move || {
+                for _ in 0..25 {
+                    session.parse_and_run("BEGIN;").unwrap();
+                    let total: usize =
+                        select_strings(&mut session, "SELECT balance FROM accounts;")
+                            .iter()
+                            .map(|row| row[0].parse::<usize>().unwrap())
+                            .sum();
+                    assert_eq!(total, TOTAL);
+                    session.parse_and_run("COMMIT;").unwrap();
+                }
+            })
+        });
+        let audits: Vec<_> = audits.collect();
+
+        for thread in transfers.into_iter().chain(audits) {
+            thread.join().unwrap();
+        }
+
+        let balances = select_strings(&mut setup, "SELECT balance FROM accounts;");
+        let total: usize = balances
+            .iter()
+            .map(|row| row[0].parse::<usize>().unwrap())
+            .sum();
+        assert_eq!(total, TOTAL);
+        assert_eq!(database.read().tables["accounts"].version_count(), 0);
+    }
+
+    #[test]
+    fn test_row_locks() {
+        let mut first = Execution::new();
+        first
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int, n int);
+                INSERT INTO foo VALUES 1, 0;
+                INSERT INTO foo VALUES 2, 0;",
+            )
+            .unwrap();
+        let locks = first.database().locks.clone();
+        let update_in_thread = |mut session: Execution, query: &'static str| {
+            std::thread::spawn(move || {
+                let res = session.parse_and_run(query).map_err(|e| e.to_string());
+                (session, res.map(|_| ()))
+            })
+        };
+
+        // an update waits for the row to be unlocked, and goes ahead if the
+        // lock holder rolls back
+        first
+            .parse_multiple_and_run(
+                "BEGIN;
+                SELECT n FROM foo WHERE id = 1 FOR UPDATE;",
+            )
+            .unwrap();
+        let second = update_in_thread(first.session(), "UPDATE foo SET n = 2 WHERE id = 1;");
+        locks.wait_until_waiting(1);
+        first.parse_and_run("ROLLBACK;").unwrap();
+        let (second, res) = second.join().unwrap();
+        res.unwrap();
+
+        // if it commits, the row changed after the update's snapshot
+        first
+            .parse_multiple_and_run(
+                "BEGIN;
+                UPDATE foo SET n = 1 WHERE id = 1;",
+            )
+            .unwrap();
+        let waiting = update_in_thread(second, "DELETE FROM foo WHERE n > 0;");
+        locks.wait_until_waiting(1);
+        first.parse_and_run("COMMIT;").unwrap();
+        let (mut second, res) = waiting.join().unwrap();
+        assert_eq!(res, Err(QueryExecutionError::WriteConflict.to_string()));
+
+        // each waits for the other, the second one to wait is rolled back
+        first
+            .parse_multiple_and_run(
+                "BEGIN;
+                UPDATE foo SET n = 3 WHERE id = 1;",
+            )
+            .unwrap();
+        second
+            .parse_multiple_and_run(
+                "BEGIN;
+                UPDATE foo SET n = 4 WHERE id = 2;",
+            )
+            .unwrap();
+        let first = update_in_thread(first, "UPDATE foo SET n = 3 WHERE id = 2;");
+        locks.wait_until_waiting(1);
+        assert_err(
+            &mut second,
+            "SELECT n FROM foo WHERE id = 1 FOR UPDATE;",
+            QueryExecutionError::Deadlock,
+        );
+        assert_err(&mut second, "COMMIT;", QueryExecutionError::NoTransaction);
+
+        let (mut first, res) = first.join().unwrap();
+        res.unwrap();
+        first.parse_and_run("COMMIT;").unwrap();
+        assert_eq!(
+            select_strings(&mut second, "SELECT id, n FROM foo;"),
+            vec![
+                vec!["1".to_string(), "3".into()],
+                vec!["2".into(), "3".into()]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_committed() {
+        let mut writer = Execution::new();
+        writer
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int, n int);
+                INSERT INTO foo VALUES 1, 0;",
+            )
+            .unwrap();
+        let mut reader = writer.session();
+        reader
+            .parse_multiple_and_run(
+                "BEGIN;
+                SET TRANSACTION ISOLATION LEVEL READ COMMITTED;",
+            )
+            .unwrap();
+
+        // no dirty reads of uncommitted changes
+        writer
+            .parse_multiple_and_run(
+                "BEGIN;
+                UPDATE foo SET n = 1 WHERE id = 1;",
+            )
+            .unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT n FROM foo;"),
+            vec![vec!["0".to_string()]]
+        );
+
+        // but reading again after the commit gives a different result
+        writer.parse_and_run("COMMIT;").unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT n FROM foo;"),
+            vec![vec!["1".to_string()]]
+        );
+
+        // and writes go on top of the latest commit
+        writer
+            .parse_and_run("UPDATE foo SET n = 2 WHERE id = 1;")
+            .unwrap();
+        reader
+            .parse_multiple_and_run(
+                "UPDATE foo SET n = 3 WHERE n = 2;
+                COMMIT;",
+            )
+            .unwrap();
+        assert_eq!(
+            select_strings(&mut writer, "SELECT n FROM foo;"),
+            vec![vec!["3".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_repeatable_read() {
+        let mut writer = Execution::new();
+        writer
+            .parse_multiple_and_run(
+                "CREATE TABLE foo (id int, n int);
+                INSERT INTO foo VALUES 1, 0;",
+            )
+            .unwrap();
+        let mut reader = writer.session();
+        reader
+            .parse_multiple_and_run(
+                "BEGIN;
+                SET TRANSACTION ISOLATION LEVEL REPEATABLE READ;",
+            )
+            .unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT n FROM foo;"),
+            vec![vec!["0".to_string()]]
+        );
+
+        // the same query reads the same rows for the whole transaction
+        writer
+            .parse_and_run("UPDATE foo SET n = 1 WHERE id = 1;")
+            .unwrap();
+        assert_eq!(
+            select_strings(&mut reader, "SELECT n FROM foo;"),
+            vec![vec!["0".to_string()]]
+        );
+
+        // too late to change the level once the transaction read something
+        assert_err(
+            &mut reader,
+            "SET TRANSACTION ISOLATION LEVEL READ COMMITTED;",
+            QueryExecutionError::IsolationLevelAfterQuery,
+        );
+        reader.parse_and_run("COMMIT;").unwrap();
+        assert_err(
+            &mut reader,
+            "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE;",
+            QueryExecutionError::NoTransaction,
+        );
+    }
+
+    /// Two transactions each read both doctors on call and take one off call,
+    /// leaving nobody if both commit
+    fn write_skew(level: IsolationLevel) -> Result<(), QueryExecutionError> {
+        let mut first = Execution::new();
+        first
+            .parse_multiple_and_run(
+                "CREATE TABLE doctors (id int, on_call int);
+                CREATE TABLE shifts (id int);
+                INSERT INTO doctors VALUES 1, 1;
+                INSERT INTO doctors VALUES 2, 1;",
+            )
+            .unwrap();
+        let mut second = first.session();
+
+        for exec in [&mut first, &mut second] {
+            exec.parse_and_run("BEGIN;").unwrap();
+            exec.parse_and_run(&format!("SET TRANSACTION ISOLATION LEVEL {level};"))
+                .unwrap();
+            assert_eq!(
+                select_strings(exec, "SELECT id FROM doctors WHERE on_call = 1;").len(),
+                2
+            );
+        }
+        first
+            .parse_and_run("UPDATE doctors SET on_call = 0 WHERE id = 1;")
+            .unwrap();
+        second
+            .parse_and_run("UPDATE doctors SET on_call = 0 WHERE id = 2;")
+            .unwrap();
+
+        // changes to tables the transaction didn't read don't matter
+        let mut other = first.session();
+        other.parse_and_run("INSERT INTO shifts VALUES 1;").unwrap();
+
+        first.parse_and_run("COMMIT;").unwrap();
+        let res = second.run(parse_sql_query("COMMIT;").unwrap());
+        assert!(second.transaction.is_none());
+        res.map(|_| ())
+    }
+
+    #[test]
+    fn test_write_skew() {
+        write_skew(IsolationLevel::RepeatableRead).unwrap();
+        assert!(matches!(
+            write_skew(IsolationLevel::Serializable),
+            Err(QueryExecutionError::SerializationFailure)
+        ));
     }
 }
@@ -0,0 +1,145 @@
+use std::{
+    collections::HashMap,
+    sync::{Condvar, Mutex},
+};
+
+use crate::{error::QueryExecutionError, mvcc::TxId};
+
+/// A row of a table: table name, row id
+type RowKey = (String, usize);
+
+#[derive(Debug, Default)]
+struct Locks {
+    /// row => transaction holding its lock
+    holders: HashMap<RowKey, TxId>,
+    /// transaction => rows it holds
+    held: HashMap<TxId, Vec<RowKey>>,
+    /// The wait-for graph, transaction => the transaction it waits for
+    waits_for: HashMap<TxId, TxId>,
+}
+
+impl Locks {
+    /// Whether `tx` waiting for `holder` would close a cycle of transactions
+    /// waiting for each other
+    fn would_deadlock(&self, tx: TxId, holder: TxId) -> bool {
+        let mut next = Some(holder);
+        while let Some(waiting) = next {
+            if waiting == tx {
+                return true;
+            }
+            next = self.waits_for.get(&waiting).copied();
+        }
+        false
+    }
+}
+
+/// Exclusive locks on rows, held by transactions until they end
+///
+/// A transaction asking for a row another one holds waits for it to end. If
+/// that would deadlock the transaction asking is the victim and gets an
+/// error instead.
+#[derive(Debug, Default)]
+pub(crate) struct LockManager {
+    locks: Mutex<Locks>,
+    /// Notified when a transaction releases its locks
+    released: Condvar,
+}
+
+impl LockManager {
+    /// Lock rows of a table for `tx`, waiting while other transactions hold
+    /// them
+    ///
+    /// Locks taken before a deadlock is detected stay held.
+    pub fn lock(&self, tx: TxId, table: &str, ids: &[usize]) -> Result<(), QueryExecutionError> {
+        let mut locks = self.locks.lock().expect("lock manager poisoned");
+
+        for id in ids {
+            let key = (table.to_owned(), *id);
+            loop {
+                let holder = match locks.holders.get(&key) {
+                    Some(holder) if *holder == tx => break,
+                    Some(holder) => *holder,
+                    None => {
+                        locks.holders.insert(key.clone(), tx);
+                        locks.held.entry(tx).or_default().push(key);
+                        break;
+                    }
+                };
+
+                if locks.would_deadlock(tx, holder) {
+                    return Err(QueryExecutionError::Deadlock);
+                }
+                locks.waits_for.insert(tx, holder);
+                locks = self.released.wait(locks).expect("lock manager poisoned");
+                locks.waits_for.remove(&tx);
+            }
+        }
+        Ok(())
+    }
+
+    /// Release all the locks of a transaction that ended
+    pub fn release(&self, tx: TxId) {
+        let mut locks = self.locks.lock().expect("lock manager poisoned");
+        for key in locks.held.remove(&tx).unwrap_or_default() {
+            locks.holders.remove(&key);
+        }
+        self.released.notify_all();
+    }
+
+    /// Number of transactions waiting for a lock
+    #[cfg(test)]
+    pub fn waiting(&self) -> usize {
+        self.locks
+            .lock()
+            .expect("lock manager poisoned")
+            .waits_for
+            .len()
+    }
+
+    /// Block until the given number of transactions wait for a lock
+    #[cfg(test)]
+    pub fn wait_until_waiting(&self, waiting: usize) {
+        for _ in 0..500 {
+            if self.waiting() == waiting {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        panic!("{waiting} transactions should be waiting for a lock");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::*;
+
+    #[test]
+    fn test_deadlock() {
+        let locks = Arc::new(LockManager::default());
+        locks.lock(1, "foo", &[1]).unwrap();
+        locks.lock(2, "foo", &[2]).unwrap();
+        // taking a lock again is fine
+        locks.lock(2, "foo", &[2]).unwrap();
+
+        let first = {
+            let locks = locks.clone();
+            thread::spawn(move || locks.lock(1, "foo", &[2]))
+        };
+        locks.wait_until_waiting(1);
+
+        // 2 would wait for 1 which waits for 2
+        assert!(matches!(
+            locks.lock(2, "foo", &[1]),
+            Err(QueryExecutionError::Deadlock)
+        ));
+        // other tables have their own rows
+        locks.lock(2, "bar", &[1]).unwrap();
+
+        locks.release(2);
+        first.join().unwrap().unwrap();
+        locks.lock(3, "bar", &[1]).unwrap();
+        assert_eq!(locks.waiting(), 0);
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use crate::table::StoredRow;
+
+/// Identifies a transaction while it is open
+pub(crate) type TxId = u64;
+
+/// Position of a commit in the order of all commits
+pub(crate) type Timestamp = u64;
+
+/// Who wrote a version of a row
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stamp {
+    /// Written by a transaction that is still open
+    Pending(TxId),
+    Committed(Timestamp),
+}
+
+/// The state of the database a transaction reads: the commits up to `ts`
+/// and its own writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Snapshot {
+    pub tx: TxId,
+    pub ts: Timestamp,
+}
+
+impl Snapshot {
+    pub fn sees(&self, stamp: Stamp) -> bool {
+        match stamp {
+            Stamp::Pending(tx) => tx == self.tx,
+            Stamp::Committed(ts) => ts <= self.ts,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct Version {
+    pub stamp: Stamp,
+    /// `None` if the row was deleted
+    pub row: Option<StoredRow>,
+}
+
+/// The versions of a row changed since the oldest open snapshot, oldest first
+///
+/// The first version is the row as it was in the table's storage before the
+/// change, which every snapshot sees.
+#[derive(Debug)]
+pub(crate) struct VersionChain {
+    versions: Vec<Version>,
+}
+
+impl VersionChain {
+    pub fn new(base: Option<StoredRow>) -> Self {
+        Self {
+            versions: vec![Version {
+                stamp: Stamp::Committed(0),
+                row: base,
+            }],
+        }
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.versions.len()
+    }
+
+    /// The rows of all the versions
+    pub fn rows(&self) -> impl Iterator<Item = &StoredRow> {
+        self.versions.iter().filter_map(|v| v.row.as_ref())
+    }
+
+    /// The newest version the snapshot sees, `None` if it sees no row
+    pub fn visible(&self, snapshot: &Snapshot) -> Option<&StoredRow> {
+        self.versions
+            .iter()
+            .rev()
+            .find(|v| snapshot.sees(v.stamp))
+            .and_then(|v| v.row.as_ref())
+    }
+
+    /// The newest version if `tx` wrote it and didn't commit yet
+    pub fn pending(&self, tx: TxId) -> Option<&Version> {
+        self.versions
+            .last()
+            .filter(|v| v.stamp == Stamp::Pending(tx))
+    }
+
+    /// A snapshot can only write on top of the newest version, if another
+    /// transaction wrote it or committed it after the snapshot was taken the
+    /// writes conflict
+    pub fn writable(&self, snapshot: &Snapshot) -> bool {
+        let newest = self.versions.last().expect("chains have a base version");
+        snapshot.sees(newest.stamp)
+    }
+
+    pub fn push(&mut self, version: Version) {
+        self.versions.push(version);
+    }
+
+    /// Drop the newest version, undoing a pending write
+    pub fn pop(&mut self) -> Version {
+        debug_assert!(matches!(
+            self.versions.last(),
+            Some(Version {
+                stamp: Stamp::Pending(_),
+                ..
+            })
+        ));
+        self.versions.pop().expect("chains have a base version")
+    }
+
+    /// Commit the writes of `tx`, keeping the last one
+    ///
+    /// Returns the committed row, or `None` if `tx` wrote nothing here, along
+    /// with the versions it replaced.
+    pub fn commit(&mut self, tx: TxId, ts: Timestamp) -> Option<(Option<StoredRow>, Vec<Version>)> {
+        let newest = self.versions.last_mut()?;
+        if newest.stamp != Stamp::Pending(tx) {
+            return None;
+        }
+        newest.stamp = Stamp::Committed(ts);
+        let row = newest.row.clone();
+
+        // only the last write of the transaction is ever seen again
+        let (replaced, versions) = std::mem::take(&mut self.versions)
+            .into_iter()
+            .partition(|v| v.stamp == Stamp::Pending(tx));
+        self.versions = versions;
+        Some((row, replaced))
+    }
+
+    /// Drop the versions no open snapshot sees anymore, the ones older than
+    /// the newest version committed at or before `horizon`
+    pub fn collect_garbage(&mut self, horizon: Timestamp) -> Vec<Version> {
+        let keep_from = self
+            .versions
+            .iter()
+            .rposition(|v| matches!(v.stamp, Stamp::Committed(ts) if ts <= horizon))
+            .unwrap_or(0);
+        self.versions.drain(..keep_from).collect()
+    }
+
+    /// Whether only the version in the table's storage is left
+    pub fn is_settled(&self) -> bool {
+        matches!(
+            self.versions.as_slice(),
+            [Version {
+                stamp: Stamp::Committed(_),
+                ..
+            }]
+        )
+    }
+}
+
+/// Hands out snapshots and commit timestamps, and tracks the open
+/// transactions to know which versions are still needed
+#[derive(Debug, Default)]
+pub(crate) struct Transactions {
+    /// Timestamp of the last commit
+    clock: Timestamp,
+    last_tx: TxId,
+    /// Snapshot timestamp of each open transaction
+    open: HashMap<TxId, Timestamp>,
+    /// Timestamp and tables written of the commits some open transaction
+    /// doesn't see, oldest first
+    commits: Vec<(Timestamp, HashSet<String>)>,
+}
+
+impl Transactions {
+    /// Open a transaction seeing everything committed so far
+    pub fn begin(&mut self) -> Snapshot {
+        self.last_tx += 1;
+        let snapshot = Snapshot {
+            tx: self.last_tx,
+            ts: self.clock,
+        };
+        self.open.insert(snapshot.tx, snapshot.ts);
+        snapshot
+    }
+
+    /// Move a snapshot forward to see everything committed so far
+    pub fn refresh(&mut self, snapshot: &mut Snapshot) {
+        snapshot.ts = self.clock;
+        self.open.insert(snapshot.tx, snapshot.ts);
+        self.forget_commits();
+    }
+
+    /// Close a transaction that wrote to `tables`, returning the timestamp
+    /// of its commit
+    pub fn commit(&mut self, tx: TxId, tables: HashSet<String>) -> Timestamp {
+        self.open.remove(&tx);
+        self.clock += 1;
+        self.commits.push((self.clock, tables));
+        self.forget_commits();
+        self.clock
+    }
+
+    /// Close a transaction that was rolled back
+    pub fn abort(&mut self, tx: TxId) {
+        self.open.remove(&tx);
+        self.forget_commits();
+    }
+
+    /// Whether a transaction committed after `ts` wrote to one of the tables
+    pub fn changed_since(&self, ts: Timestamp, tables: &HashSet<String>) -> bool {
+        self.commits
+            .iter()
+            .filter(|(commit, _)| *commit > ts)
+            .any(|(_, written)| !written.is_disjoint(tables))
+    }
+
+    /// Drop the commits every open transaction sees
+    fn forget_commits(&mut self) {
+        let horizon = self.horizon();
+        self.commits.retain(|(ts, _)| *ts > horizon);
+    }
+
+    pub fn any_open(&self) -> bool {
+        !self.open.is_empty()
+    }
+
+    /// Every open transaction sees the commits up to this timestamp
+    pub fn horizon(&self) -> Timestamp {
+        self.open.values().copied().min().unwrap_or(self.clock)
+    }
+}
+
+/// The snapshots queries still read rows with, which can outlive their
+/// transactions
+///
+/// It is locked on its own so a query can let go of its snapshot while the
+/// database is locked.
+#[derive(Debug, Default)]
+pub(crate) struct Readers {
+    /// snapshot timestamp to the number of queries reading it
+    snapshots: Mutex<BTreeMap<Timestamp, usize>>,
+}
+
+impl Readers {
+    fn snapshots(&self) -> MutexGuard<'_, BTreeMap<Timestamp, usize>> {
+        self.snapshots
+            .lock()
+            .expect("a thread panicked while using the readers")
+    }
+
+    /// Keep the versions the snapshot sees until the guard is dropped
+    pub fn read(self: &Arc<Self>, snapshot: &Snapshot) -> ReadGuard {
+        *self.snapshots().entry(snapshot.ts).or_default() += 1;
+        ReadGuard {
+            readers: self.clone(),
+            ts: snapshot.ts,
+        }
+    }
+
+    /// Every query still reading sees the commits up to this timestamp
+    pub fn horizon(&self) -> Option<Timestamp> {
+        self.snapshots().keys().next().copied()
+    }
+}
+
+/// Keeps the versions a snapshot sees from being collected, see
+/// [`Readers::read`]
+#[derive(Debug)]
+pub(crate) struct ReadGuard {
+    readers: Arc<Readers>,
+    ts: Timestamp,
+}
+
+impl Drop for ReadGuard {
+    fn drop(&mut self) {
+        let mut snapshots = self.readers.snapshots();
+        if let Some(count) = snapshots.get_mut(&self.ts) {
+            *count -= 1;
+            if *count == 0 {
+                snapshots.remove(&self.ts);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use toy_sql_parser::value::Value;
+
+    use super::*;
+
+    fn row(value: i32) -> Option<StoredRow> {
+        let data = [("a".to_string(), Value::Number(value.into()))];
+        Some(StoredRow::from(HashMap::from(data)))
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut transactions = Transactions::default();
+        let mut chain = VersionChain::new(row(0));
+
+        let old = transactions.begin();
+        let writer = transactions.begin();
+        chain.push(Version {
+            stamp: Stamp::Pending(writer.tx),
+            row: row(1),
+        });
+        chain.push(Version {
+            stamp: Stamp::Pending(writer.tx),
+            row: row(2),
+        });
+        assert_eq!(chain.visible(&writer), row(2).as_ref());
+        assert_eq!(chain.visible(&old), row(0).as_ref());
+        assert!(!chain.writable(&old));
+
+        let ts = transactions.commit(writer.tx, HashSet::new());
+        let (committed, replaced) = chain.commit(writer.tx, ts).unwrap();
+        assert_eq!(committed, row(2));
+        assert_eq!(replaced.len(), 1);
+
+        // the old snapshot still sees the base version, and can't write
+        // over a change it doesn't see
+        let new = transactions.begin();
+        assert_eq!(chain.visible(&old), row(0).as_ref());
+        assert_eq!(chain.visible(&new), row(2).as_ref());
+        assert!(!chain.writable(&old));
+        assert!(chain.writable(&new));
+
+        assert!(chain.collect_garbage(transactions.horizon()).is_empty());
+        transactions.abort(old.tx);
+        assert_eq!(chain.collect_garbage(transactions.horizon()).len(), 1);
+        assert!(chain.is_settled());
+    }
+}
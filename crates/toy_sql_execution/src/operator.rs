@@ -0,0 +1,1903 @@
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    iter::{self, Peekable},
+    mem,
+    ops::Bound,
+    slice,
+    sync::{
+        atomic::{self, AtomicU64, AtomicUsize},
+        Arc, RwLock,
+    },
+    time::{Duration, Instant},
+    vec,
+};
+
+use toy_sql_parser::{
+    commands::{OrderBy, SetOperator},
+    expression::Expression,
+    value::Value,
+    Column,
+};
+
+use crate::{
+    aggregate::Accumulator,
+    error::QueryExecutionError,
+    eval::{satisfies, sort_order, value_of, ColumnValues},
+    mvcc::{ReadGuard, Snapshot},
+    plan::{join_keys, unqualify, JoinStrategy, LogicalPlan, Schema, SchemaRow, SubqueryKind},
+    spill::{size_of_values, MemoryBudget, Reservation, SpillFile, SpillReader, SpilledRows},
+    storage::RowResult,
+    table::{Overlaid, StoredRow},
+    window::{compute_windows, compute_windows_spilled, number},
+    Shared,
+};
+
+/// The values of a row produced by an operator, one per column of its schema
+pub(crate) type Tuple = Vec<Value>;
+
+/// A physical operator: an iterator producing its rows as they are pulled,
+/// pulling the rows of its inputs as it needs them
+pub(crate) type Operator = Box<dyn Iterator<Item = Result<Tuple, QueryExecutionError>> + Send>;
+
+/// Build the operators running a plan against the tables a snapshot sees
+///
+/// `shared` is the `database` the caller locked. The operators don't keep it
+/// locked, scans take the lock again for each batch of rows they read.
+/// Everything is computed as the rows are pulled.
+pub(crate) fn build(
+    plan: &LogicalPlan,
+    database: &Arc<RwLock<Shared>>,
+    shared: &Shared,
+    snapshot: &Snapshot,
+) -> Result<Operator, QueryExecutionError> {
+    Builder {
+        tables: &Tables::new(plan, database, shared, snapshot),
+        metrics: None,
+        budget: MemoryBudget::new(shared.query_memory),
+    }
+    .build(plan)
+}
+
+/// Build the operators running a plan like [`build`], measuring each of
+/// them
+///
+/// The metrics are those of the nodes of the plan in pre-order, each node
+/// before its inputs.
+pub(crate) fn build_measured(
+    plan: &LogicalPlan,
+    database: &Arc<RwLock<Shared>>,
+    shared: &Shared,
+    snapshot: &Snapshot,
+) -> Result<(Operator, Vec<Arc<Metrics>>), QueryExecutionError> {
+    let mut builder = Builder {
+        tables: &Tables::new(plan, database, shared, snapshot),
+        metrics: Some(Vec::new()),
+        budget: MemoryBudget::new(shared.query_memory),
+    };
+    let operator = builder.build(plan)?;
+    Ok((operator, builder.metrics.unwrap_or_default()))
+}
+
+/// What an operator did, updated as its rows are pulled
+#[derive(Debug, Default)]
+pub(crate) struct Metrics {
+    rows: AtomicUsize,
+    /// Time spent building the operator and producing its rows, its inputs
+    /// included
+    nanos: AtomicU64,
+}
+
+impl Metrics {
+    pub fn rows(&self) -> usize {
+        self.rows.load(atomic::Ordering::Relaxed)
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(atomic::Ordering::Relaxed))
+    }
+
+    fn add_time(&self, elapsed: Duration) {
+        self.nanos
+            .fetch_add(elapsed.as_nanos() as u64, atomic::Ordering::Relaxed);
+    }
+
+    /// Count what another run of the same operator did too
+    fn absorb(&self, other: &Metrics) {
+        self.rows.fetch_add(other.rows(), atomic::Ordering::Relaxed);
+        self.add_time(other.elapsed());
+    }
+}
+
+/// Rows a scan reads, in id order
+type Rows = Box<dyn Iterator<Item = RowResult> + Send>;
+
+/// The rows of a table written by a transaction that wasn't committed yet,
+/// see [`Table::pending_writes`](crate::table::Table::pending_writes)
+type PendingWrites = BTreeMap<usize, Option<StoredRow>>;
+
+/// The tables scans read from: the tables of the database as a snapshot
+/// sees them, and the working tables of recursive queries
+#[derive(Clone)]
+struct Tables {
+    database: Arc<RwLock<Shared>>,
+    snapshot: Snapshot,
+    /// Keeps the versions the snapshot sees while the query runs
+    _guard: Arc<ReadGuard>,
+    /// The rows the snapshot's transaction wrote to the tables the query
+    /// reads, as they were when the query started, in case it commits or
+    /// rolls back before they are read
+    pending: Arc<HashMap<String, PendingWrites>>,
+    /// The rows the last iteration of each recursive query found, by the
+    /// name its step reads them by
+    working: HashMap<String, Arc<Vec<(usize, StoredRow)>>>,
+}
+
+impl Tables {
+    fn new(
+        plan: &LogicalPlan,
+        database: &Arc<RwLock<Shared>>,
+        shared: &Shared,
+        snapshot: &Snapshot,
+    ) -> Self {
+        fn scanned<'a>(plan: &'a LogicalPlan, tables: &mut HashSet<&'a str>) {
+            if let LogicalPlan::Scan { table, .. } = plan {
+                tables.insert(table);
+            }
+            for input in plan.inputs() {
+                scanned(input, tables);
+            }
+        }
+
+        let mut names = HashSet::new();
+        scanned(plan, &mut names);
+        // the working tables of recursive queries aren't found
+        let pending = names
+            .into_iter()
+            .filter_map(|name| {
+                let writes = shared
+                    .table(name, snapshot)
+                    .ok()?
+                    .pending_writes(snapshot.tx);
+                (!writes.is_empty()).then(|| (name.to_owned(), writes))
+            })
+            .collect();
+
+        Self {
+            database: database.clone(),
+            snapshot: *snapshot,
+            _guard: Arc::new(shared.readers.read(snapshot)),
+            pending: Arc::new(pending),
+            working: HashMap::new(),
+        }
+    }
+
+    /// The rows of a table, only the ones an index says may match a
+    /// predicate if there is a lookup
+    ///
+    /// Lookups read every row of working tables, the filter above the scan
+    /// checks them anyway.
+    fn rows(&self, table: &str, lookup: Option<(&str, &Expression)>) -> Rows {
+        if let Some(rows) = self.working.get(table) {
+            let rows = rows.clone();
+            return Box::new((0..rows.len()).map(move |i| Ok(rows[i].clone())));
+        }
+
+        let lookup = match lookup {
+            Some((index, predicate)) => Lookup::Pending(index.to_owned(), predicate.clone()),
+            None => Lookup::None,
+        };
+        Box::new(Cursor {
+            tables: self.clone(),
+            table: table.to_owned(),
+            lookup,
+            after: Bound::Unbounded,
+            batch: Vec::new().into_iter(),
+            done: false,
+        })
+    }
+}
+
+/// Rows a [`Cursor`] reads from its table at a time
+const SCAN_BATCH: usize = 256;
+
+/// Which rows of its table a [`Cursor`] reads
+enum Lookup {
+    /// Every row
+    None,
+    /// The rows an index says may match a predicate, found on the first read
+    Pending(String, Expression),
+    /// The rows with these ids, in order
+    Ids(Vec<usize>),
+}
+
+/// Reads the rows of a table a snapshot sees as they are pulled, a batch at
+/// a time
+///
+/// The database is only locked while a batch is read, the next batch starts
+/// after the last row read. Rows changed in between are still read as the
+/// snapshot sees them, since their versions are kept for it.
+struct Cursor {
+    tables: Tables,
+    table: String,
+    lookup: Lookup,
+    /// the id of the last row read
+    after: Bound<usize>,
+    batch: vec::IntoIter<(usize, StoredRow)>,
+    /// whether the last batch read the last row
+    done: bool,
+}
+
+impl Cursor {
+    fn read_batch(&mut self) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        let Tables {
+            database,
+            snapshot,
+            pending,
+            ..
+        } = &self.tables;
+        let shared = database
+            .read()
+            .expect("a thread panicked while using the database");
+        let table = shared.table(&self.table, snapshot)?;
+
+        if let Lookup::Pending(index, predicate) = &self.lookup {
+            self.lookup = match table.index_scan(index, predicate) {
+                Some(ids) => Lookup::Ids(ids),
+                None => Lookup::None,
+            };
+        }
+        let range = (self.after, Bound::Unbounded);
+        let stored: Box<dyn Iterator<Item = RowResult>> = match &self.lookup {
+            Lookup::Ids(ids) => {
+                let start = match self.after {
+                    Bound::Excluded(after) => ids.partition_point(|id| *id <= after),
+                    _ => 0,
+                };
+                Box::new(ids[start..].iter().filter_map(|id| {
+                    let row = table.get(*id, snapshot).transpose()?;
+                    Some(row.map(|row| (*id, row)))
+                }))
+            }
+            _ => Box::new(table.visible_range(range, snapshot)?),
+        };
+
+        let written = pending
+            .get(&self.table)
+            .into_iter()
+            .flat_map(|writes| writes.range(range))
+            .map(|(id, row)| (*id, row.clone()));
+        let rows = Overlaid::new(stored, written)
+            .take(SCAN_BATCH)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        match rows.last() {
+            Some((id, _)) if rows.len() == SCAN_BATCH => self.after = Bound::Excluded(*id),
+            _ => self.done = true,
+        }
+        Ok(rows)
+    }
+}
+
+impl Iterator for Cursor {
+    type Item = RowResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(row) = self.batch.next() {
+                return Some(Ok(row));
+            }
+            if self.done {
+                return None;
+            }
+            match self.read_batch() {
+                Ok(rows) => self.batch = rows.into_iter(),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+struct Builder<'a> {
+    tables: &'a Tables,
+    /// The metrics of the operators built so far, if they are measured
+    metrics: Option<Vec<Arc<Metrics>>>,
+    /// The memory the operators of the query share
+    budget: Arc<MemoryBudget>,
+}
+
+impl Builder<'_> {
+    fn build(&mut self, plan: &LogicalPlan) -> Result<Operator, QueryExecutionError> {
+        let metrics = self.next_metrics();
+        let start = Instant::now();
+        let operator = self.build_node(plan)?;
+        Ok(measure(operator, metrics, start))
+    }
+
+    /// The metrics of the next node, in pre-order
+    fn next_metrics(&mut self) -> Option<Arc<Metrics>> {
+        let metrics = Arc::new(Metrics::default());
+        self.metrics.as_mut()?.push(metrics.clone());
+        Some(metrics)
+    }
+
+    fn build_node(&mut self, plan: &LogicalPlan) -> Result<Operator, QueryExecutionError> {
+        Ok(match plan {
+            LogicalPlan::Scan { table, columns, .. } => self.scan(table, columns, None)?,
+            LogicalPlan::Single => Box::new(iter::once(Ok(vec![]))),
+            LogicalPlan::Filter { input, predicate } => {
+                let input_op = match input.as_ref() {
+                    // the table only hands over the rows the index says can match
+                    LogicalPlan::Scan {
+                        table,
+                        alias,
+                        columns,
+                        index: Some(index),
+                    } => {
+                        let metrics = self.next_metrics();
+                        let start = Instant::now();
+                        let predicate = unqualify(predicate, alias);
+                        let lookup = Some((index.as_str(), &predicate));
+                        measure(self.scan(table, columns, lookup)?, metrics, start)
+                    }
+                    input => self.build(input)?,
+                };
+                Box::new(Filter {
+                    input: input_op,
+                    schema: input.schema(),
+                    predicate: predicate.clone(),
+                })
+            }
+            LogicalPlan::Project { input, exprs } => Box::new(Project {
+                input: self.build(input)?,
+                schema: input.schema(),
+                exprs: exprs.iter().map(|(expr, _)| expr.clone()).collect(),
+            }),
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                strategy: JoinStrategy::NestedLoop,
+            } => Box::new(NestedLoopJoin {
+                left: self.build(left)?,
+                right: Inner::Pending(self.build(right)?),
+                current: None,
+                schema: plan.schema(),
+                on: on.clone(),
+                reservation: self.budget.reserve(),
+            }),
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                strategy,
+            } => {
+                let (left_schema, right_schema) = (left.schema(), right.schema());
+                let (keys, rest) = join_keys(on, &left_schema, &right_schema);
+                let (left_keys, right_keys) = keys.into_iter().unzip();
+                let sides = Sides {
+                    left_keys,
+                    right_keys,
+                    left_schema,
+                    right_schema,
+                    schema: plan.schema(),
+                    rest,
+                };
+                match strategy {
+                    JoinStrategy::Hash => {
+                        let left = self.build(left)?;
+                        Box::new(Blocking::new(self.build(right)?, {
+                            let sides = Arc::new(sides);
+                            let budget = self.budget.clone();
+                            move |right| hash_join(left, right, sides, budget, 0)
+                        }))
+                    }
+                    _ => Box::new(MergeJoin {
+                        left: self.build(left)?,
+                        right: self.build(right)?.fuse(),
+                        lookahead: None,
+                        group: None,
+                        current: None,
+                        sides,
+                    }),
+                }
+            }
+            LogicalPlan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => Box::new(Blocking::new(self.build(input)?, {
+                let aggregation = Aggregation {
+                    schema: input.schema(),
+                    group_by: group_by.iter().map(|(expr, _)| expr.clone()).collect(),
+                    aggregates: aggregates.iter().map(|(expr, _)| expr.clone()).collect(),
+                    budget: self.budget.clone(),
+                };
+                move |input| aggregation.run(input, 0)
+            })),
+            LogicalPlan::Sort { input, order_by } => Box::new(Blocking::new(self.build(input)?, {
+                let schema = input.schema();
+                let order_by = order_by.clone();
+                let budget = self.budget.clone();
+                move |input| sort(&schema, input, order_by, &budget)
+            })),
+            LogicalPlan::Window { input, windows } => {
+                Box::new(Blocking::new(self.build(input)?, {
+                    let schema = input.schema();
+                    let windows: Vec<_> = windows.iter().map(|(expr, _)| expr.clone()).collect();
+                    let budget = self.budget.clone();
+                    move |mut input| {
+                        let mut reservation = budget.reserve();
+                        let mut rows = Vec::new();
+                        while let Some(values) = input.next().transpose()? {
+                            if !reservation.grow(size_of_values(&values)) {
+                                // the rows read so far are read again first
+                                let rows = rows.into_iter().map(Ok).chain(iter::once(Ok(values)));
+                                let input = Box::new(rows.chain(input));
+                                drop(reservation);
+                                return compute_windows_spilled(&schema, input, &windows, &budget);
+                            }
+                            rows.push(values);
+                        }
+                        let rows = compute_windows(&schema, rows, &windows)?;
+                        Ok(Box::new(rows.into_iter().map(move |row| {
+                            let _ = &reservation;
+                            Ok(row)
+                        })) as Operator)
+                    }
+                }))
+            }
+            LogicalPlan::Distinct { input, on } => Box::new(Distinct::on(
+                self.build(input)?,
+                input.schema(),
+                on.clone(),
+                self.budget.clone(),
+            )),
+            LogicalPlan::Limit {
+                input,
+                limit,
+                offset,
+            } => Box::new(Limit {
+                input: self.build(input)?,
+                offset: *offset,
+                remaining: *limit,
+            }),
+            LogicalPlan::Apply {
+                input,
+                subquery,
+                kind,
+                outer,
+                ..
+            } => {
+                let input_op = self.build(input)?;
+                // the subquery runs as the rows are pulled
+                // each run adds to the metrics of the nodes of the subquery
+                let metrics = match self.metrics.is_some() {
+                    true => Some(
+                        (0..subquery.nodes())
+                            .filter_map(|_| self.next_metrics())
+                            .collect(),
+                    ),
+                    false => None,
+                };
+                Box::new(Apply {
+                    input: input_op,
+                    schema: input.schema(),
+                    subquery: subquery.as_ref().clone(),
+                    kind: kind.clone(),
+                    outer: outer.clone(),
+                    tables: self.tables.clone(),
+                    budget: self.budget.clone(),
+                    metrics,
+                    results: HashMap::new(),
+                })
+            }
+            LogicalPlan::Recursive {
+                table,
+                columns,
+                base,
+                step,
+                all,
+                ..
+            } => {
+                let base_op = self.build(base)?;
+                let metrics = match self.metrics.is_some() {
+                    true => Some(
+                        (0..step.nodes())
+                            .filter_map(|_| self.next_metrics())
+                            .collect(),
+                    ),
+                    false => None,
+                };
+                Box::new(Recursive {
+                    table: table.clone(),
+                    columns: columns.iter().map(|col| col.name.clone()).collect(),
+                    step: step.as_ref().clone(),
+                    all: *all,
+                    tables: self.tables.clone(),
+                    budget: self.budget.clone(),
+                    metrics,
+                    current: base_op,
+                    current_metrics: vec![],
+                    found: vec![],
+                    seen: HashSet::new(),
+                })
+            }
+            LogicalPlan::SetOperation {
+                left,
+                right,
+                operator,
+            } => {
+                let left_op = self.build(left)?;
+                let right_op = self.build(right)?;
+                match operator {
+                    SetOperator::UnionAll => Box::new(left_op.chain(right_op)),
+                    SetOperator::Union => {
+                        Box::new(Distinct::new(left_op.chain(right_op), self.budget.clone()))
+                    }
+                    SetOperator::Intersect | SetOperator::Except => {
+                        let intersect = *operator == SetOperator::Intersect;
+                        let budget = self.budget.clone();
+                        Box::new(Blocking::new(right_op, move |right_op| {
+                            let kept = set_filter(left_op, right_op, intersect, budget.clone(), 0)?;
+                            Ok(Box::new(Distinct::new(kept, budget)) as Operator)
+                        }))
+                    }
+                }
+            }
+        })
+    }
+
+    /// Read the rows of a table, only the ones an index says may match a
+    /// predicate if there is a lookup
+    fn scan(
+        &self,
+        table: &str,
+        columns: &[Column],
+        lookup: Option<(&str, &Expression)>,
+    ) -> Result<Operator, QueryExecutionError> {
+        Ok(Box::new(Scan {
+            rows: self.tables.rows(table, lookup),
+            columns: columns.iter().map(|col| col.name.clone()).collect(),
+        }))
+    }
+}
+
+/// Count the rows of an operator and the time taking them, since `start`,
+/// if it is measured
+fn measure(operator: Operator, metrics: Option<Arc<Metrics>>, start: Instant) -> Operator {
+    let Some(metrics) = metrics else {
+        return operator;
+    };
+    metrics.add_time(start.elapsed());
+    Box::new(Measured {
+        input: operator,
+        metrics,
+    })
+}
+
+struct Measured {
+    input: Operator,
+    metrics: Arc<Metrics>,
+}
+
+impl Iterator for Measured {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let row = self.input.next();
+        self.metrics.add_time(start.elapsed());
+        if let Some(Ok(_)) = row {
+            self.metrics.rows.fetch_add(1, atomic::Ordering::Relaxed);
+        }
+        row
+    }
+}
+
+/// The rows of a table, with the values of the given columns
+struct Scan {
+    rows: Rows,
+    columns: Vec<String>,
+}
+
+impl Iterator for Scan {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (_, row) = match self.rows.next()? {
+            Ok(row) => row,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(Ok(self
+            .columns
+            .iter()
+            .map(|column| row.get(column).cloned().unwrap_or(Value::Null))
+            .collect()))
+    }
+}
+
+struct Filter {
+    input: Operator,
+    schema: Schema,
+    predicate: Expression,
+}
+
+impl Iterator for Filter {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        for values in self.input.by_ref() {
+            let keep = values.and_then(|values| {
+                let row = SchemaRow {
+                    schema: &self.schema,
+                    values: &values,
+                };
+                Ok(satisfies(&self.predicate, &row)?.then_some(values))
+            });
+            match keep {
+                Ok(None) => continue,
+                Ok(Some(values)) => return Some(Ok(values)),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        None
+    }
+}
+
+struct Project {
+    input: Operator,
+    schema: Schema,
+    exprs: Vec<Expression>,
+}
+
+impl Iterator for Project {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let values = match self.input.next()? {
+            Ok(values) => values,
+            Err(e) => return Some(Err(e)),
+        };
+        let row = SchemaRow {
+            schema: &self.schema,
+            values: &values,
+        };
+        Some(self.exprs.iter().map(|expr| value_of(expr, &row)).collect())
+    }
+}
+
+/// The inner side of a nested loop join, read once on the first pull
+enum Inner {
+    Pending(Operator),
+    Read(Vec<Tuple>),
+    /// The rows that didn't fit in memory, read again for each left row
+    Spilled(SpilledRows<Tuple>),
+}
+
+/// The next right row a left row is paired with
+enum InnerPosition {
+    Read(usize),
+    Spilled(SpillReader<Tuple>),
+}
+
+/// Pairs every row of `left` with every row of `right`, keeping the pairs
+/// satisfying `on`
+///
+/// The rows of `right` are kept in memory until the budget runs out, then
+/// they are all written to a temporary file instead.
+struct NestedLoopJoin {
+    left: Operator,
+    right: Inner,
+    /// The left row being paired, and the position of the next right row
+    current: Option<(Tuple, InnerPosition)>,
+    schema: Schema,
+    on: Expression,
+    /// The memory the rows of `right` are held in
+    reservation: Reservation,
+}
+
+impl NestedLoopJoin {
+    fn read_right(&mut self) -> Result<(), QueryExecutionError> {
+        let Inner::Pending(right) = &mut self.right else {
+            return Ok(());
+        };
+        let mut rows = Vec::new();
+        while let Some(values) = right.next().transpose()? {
+            if !self.reservation.grow(size_of_values(&values)) {
+                let mut file = SpillFile::new()?;
+                for values in rows.drain(..).chain(iter::once(values)) {
+                    file.write(&values)?;
+                }
+                self.reservation.free();
+                for values in right {
+                    file.write(&values?)?;
+                }
+                self.right = Inner::Spilled(file.close()?);
+                return Ok(());
+            }
+            rows.push(values);
+        }
+        self.right = Inner::Read(rows);
+        Ok(())
+    }
+
+    fn next_pair(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        self.read_right()?;
+        loop {
+            let (left, position) = match &mut self.current {
+                Some(current) => current,
+                None => match self.left.next().transpose()? {
+                    Some(left) => {
+                        let position = match &self.right {
+                            Inner::Spilled(rows) => InnerPosition::Spilled(rows.read()?),
+                            _ => InnerPosition::Read(0),
+                        };
+                        self.current.insert((left, position))
+                    }
+                    None => return Ok(None),
+                },
+            };
+            let right = match (position, &self.right) {
+                (InnerPosition::Read(position), Inner::Read(rows)) => {
+                    let right = rows.get(*position).map(Cow::Borrowed);
+                    *position += 1;
+                    right
+                }
+                (InnerPosition::Spilled(rows), _) => rows.next().transpose()?.map(Cow::Owned),
+                _ => unreachable!("read above"),
+            };
+            let Some(right) = right else {
+                self.current = None;
+                continue;
+            };
+
+            let values: Tuple = left.iter().chain(right.iter()).cloned().collect();
+            let row = SchemaRow {
+                schema: &self.schema,
+                values: &values,
+            };
+            if satisfies(&self.on, &row)? {
+                return Ok(Some(values));
+            }
+        }
+    }
+}
+
+impl Iterator for NestedLoopJoin {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// What hash and merge joins know about the rows they pair
+struct Sides {
+    /// The keys of the rows of each side, equal in the rows of the join
+    left_keys: Vec<Expression>,
+    right_keys: Vec<Expression>,
+    left_schema: Schema,
+    right_schema: Schema,
+    schema: Schema,
+    /// The rest of the join condition, checked on the pairs with equal keys
+    rest: Vec<Expression>,
+}
+
+impl Sides {
+    fn left_key(&self, values: &[Value]) -> Result<Option<Tuple>, QueryExecutionError> {
+        key_of(&self.left_keys, &self.left_schema, values)
+    }
+
+    fn right_key(&self, values: &[Value]) -> Result<Option<Tuple>, QueryExecutionError> {
+        key_of(&self.right_keys, &self.right_schema, values)
+    }
+
+    /// The row of the join pairing two rows, if it satisfies the rest of the
+    /// condition
+    fn pair(&self, left: &[Value], right: &[Value]) -> Result<Option<Tuple>, QueryExecutionError> {
+        let values: Tuple = left.iter().chain(right).cloned().collect();
+        let row = SchemaRow {
+            schema: &self.schema,
+            values: &values,
+        };
+        for predicate in &self.rest {
+            if !satisfies(predicate, &row)? {
+                return Ok(None);
+            }
+        }
+        Ok(Some(values))
+    }
+}
+
+/// The values of the keys of a row, or `None` if one is `NULL`, as it equals
+/// nothing
+fn key_of(
+    keys: &[Expression],
+    schema: &Schema,
+    values: &[Value],
+) -> Result<Option<Tuple>, QueryExecutionError> {
+    let row = SchemaRow { schema, values };
+    let key = keys
+        .iter()
+        .map(|expr| value_of(expr, &row))
+        .collect::<Result<Tuple, _>>()?;
+    Ok((!key.contains(&Value::Null)).then_some(key))
+}
+
+/// Join the rows of `left` and `right` with the same keys, looked up in a
+/// hash table of the rows of `right`
+///
+/// The table is kept in memory until the budget runs out. The rows of both
+/// sides are then split into temporary files by the hash of their keys, and
+/// the rows of each pair of files joined the same way, at the next `level`.
+fn hash_join(
+    left: Operator,
+    mut right: Operator,
+    sides: Arc<Sides>,
+    budget: Arc<MemoryBudget>,
+    level: u64,
+) -> Result<Operator, QueryExecutionError> {
+    let mut reservation = budget.reserve();
+    let mut table: HashMap<Tuple, Vec<Tuple>> = HashMap::new();
+    while let Some(values) = right.next().transpose()? {
+        let Some(key) = sides.right_key(&values)? else {
+            continue;
+        };
+        let size = size_of_values(&key) + size_of_values(&values);
+        // rows with a single key can't be split, they are kept anyway
+        if !reservation.grow(size) && table.len() > 1 {
+            let mut right_files = partition_files();
+            for (key, rows) in table.drain() {
+                for values in rows {
+                    write_partition(&mut right_files, level, &key, &values)?;
+                }
+            }
+            reservation.free();
+            write_partition(&mut right_files, level, &key, &values)?;
+            for values in right {
+                let values = values?;
+                if let Some(key) = sides.right_key(&values)? {
+                    write_partition(&mut right_files, level, &key, &values)?;
+                }
+            }
+            let mut left_files = partition_files();
+            for values in left {
+                let values = values?;
+                if let Some(key) = sides.left_key(&values)? {
+                    write_partition(&mut left_files, level, &key, &values)?;
+                }
+            }
+
+            let joined = left_files
+                .into_iter()
+                .zip(right_files)
+                .filter_map(|files| match files {
+                    (Some(left), Some(right)) => Some((left, right)),
+                    _ => None,
+                })
+                .flat_map(move |(left, right)| {
+                    let rows = left.read().and_then(|left| {
+                        let right = right.read()?;
+                        hash_join(
+                            Box::new(left),
+                            Box::new(right),
+                            sides.clone(),
+                            budget.clone(),
+                            level + 1,
+                        )
+                    });
+                    match rows {
+                        Ok(rows) => rows,
+                        Err(e) => Box::new(iter::once(Err(e))),
+                    }
+                });
+            return Ok(Box::new(joined));
+        }
+        table.entry(key).or_default().push(values);
+    }
+    Ok(Box::new(HashJoin {
+        left,
+        table,
+        _reservation: reservation,
+        current: None,
+        sides,
+    }))
+}
+
+/// Keep the rows of `left` found among the rows of `right`, or the ones not
+/// found unless `intersect`
+///
+/// The rows of `right` are kept in memory until the budget runs out, then
+/// the rows of both sides are split into temporary files like in
+/// [`hash_join`].
+fn set_filter(
+    left: Operator,
+    mut right: Operator,
+    intersect: bool,
+    budget: Arc<MemoryBudget>,
+    level: u64,
+) -> Result<Operator, QueryExecutionError> {
+    let mut reservation = budget.reserve();
+    let mut rows = HashSet::new();
+    while let Some(values) = right.next().transpose()? {
+        if rows.contains(&values) {
+            continue;
+        }
+        if !reservation.grow(size_of_values(&values)) && !rows.is_empty() {
+            let mut right_files = partition_files();
+            for values in rows.drain().chain(iter::once(values)) {
+                write_partition(&mut right_files, level, &values, &values)?;
+            }
+            reservation.free();
+            for values in right {
+                let values = values?;
+                write_partition(&mut right_files, level, &values, &values)?;
+            }
+            let mut left_files = partition_files();
+            for values in left {
+                let values = values?;
+                write_partition(&mut left_files, level, &values, &values)?;
+            }
+
+            let kept = left_files
+                .into_iter()
+                .zip(right_files)
+                .flat_map(move |files| {
+                    let rows = match files {
+                        (Some(left), right) => left.read().and_then(|left| {
+                            let right: Operator = match right {
+                                Some(right) => Box::new(right.read()?),
+                                None => Box::new(iter::empty()),
+                            };
+                            set_filter(Box::new(left), right, intersect, budget.clone(), level + 1)
+                        }),
+                        (None, _) => Ok(Box::new(iter::empty()) as Operator),
+                    };
+                    match rows {
+                        Ok(rows) => rows,
+                        Err(e) => Box::new(iter::once(Err(e))),
+                    }
+                });
+            return Ok(Box::new(kept));
+        }
+        rows.insert(values);
+    }
+    Ok(Box::new(left.filter(move |row| {
+        let _ = &reservation;
+        match row {
+            Ok(row) => rows.contains(row) == intersect,
+            Err(_) => true,
+        }
+    })))
+}
+
+/// Pairs every row of `left` with the rows of the right side of a join with
+/// the same keys, looked up in a hash table of them
+struct HashJoin {
+    left: Operator,
+    table: HashMap<Tuple, Vec<Tuple>>,
+    _reservation: Reservation,
+    /// The left row being paired, its key, and the position of the next
+    /// right row with it
+    current: Option<(Tuple, Tuple, usize)>,
+    sides: Arc<Sides>,
+}
+
+impl HashJoin {
+    fn next_pair(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        loop {
+            let (left, key, position) = match &mut self.current {
+                Some(current) => current,
+                None => {
+                    let Some(left) = self.left.next().transpose()? else {
+                        return Ok(None);
+                    };
+                    match self.sides.left_key(&left)? {
+                        Some(key) => self.current.insert((left, key, 0)),
+                        None => continue,
+                    }
+                }
+            };
+            let Some(right) = self.table.get(key).and_then(|rows| rows.get(*position)) else {
+                self.current = None;
+                continue;
+            };
+            *position += 1;
+            if let Some(values) = self.sides.pair(left, right)? {
+                return Ok(Some(values));
+            }
+        }
+    }
+}
+
+impl Iterator for HashJoin {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// Pairs the rows of `left` and `right`, both sorted by their keys, with
+/// the same keys, reading both sides once side by side
+struct MergeJoin {
+    left: Operator,
+    right: std::iter::Fuse<Operator>,
+    /// The next right row with a key, if it wasn't grouped yet
+    lookahead: Option<(Tuple, Tuple)>,
+    /// The right rows with the key of the last left row, and that key
+    group: Option<(Tuple, Vec<Tuple>)>,
+    /// The left row being paired, and the position of the next right row of
+    /// the group
+    current: Option<(Tuple, usize)>,
+    sides: Sides,
+}
+
+impl MergeJoin {
+    /// The next right row whose keys aren't `NULL`, with them
+    fn next_right(&mut self) -> Result<Option<(Tuple, Tuple)>, QueryExecutionError> {
+        while let Some(values) = self.right.next().transpose()? {
+            if let Some(key) = self.sides.right_key(&values)? {
+                return Ok(Some((key, values)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The right rows with a key, skipping the ones with smaller keys
+    fn group(&mut self, key: &[Value]) -> Result<Vec<Tuple>, QueryExecutionError> {
+        let mut group = vec![];
+        loop {
+            if self.lookahead.is_none() {
+                self.lookahead = self.next_right()?;
+            }
+            let Some((right_key, _)) = &self.lookahead else {
+                return Ok(group);
+            };
+            match compare_keys(right_key, key) {
+                Ordering::Less => self.lookahead = None,
+                Ordering::Equal => group.extend(self.lookahead.take().map(|(_, values)| values)),
+                Ordering::Greater => return Ok(group),
+            }
+        }
+    }
+
+    fn next_pair(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        loop {
+            if let Some((left, position)) = &mut self.current {
+                let rows = self.group.as_ref().map_or(&[][..], |(_, rows)| rows);
+                if let Some(right) = rows.get(*position) {
+                    *position += 1;
+                    match self.sides.pair(left, right)? {
+                        Some(values) => return Ok(Some(values)),
+                        None => continue,
+                    }
+                }
+                self.current = None;
+            }
+
+            let Some(left) = self.left.next().transpose()? else {
+                return Ok(None);
+            };
+            let Some(key) = self.sides.left_key(&left)? else {
+                continue;
+            };
+            // left rows with the same key pair with the same right rows
+            let same = matches!(&self.group, Some((last, _)) if compare_keys(last, &key) == Ordering::Equal);
+            if !same {
+                let rows = self.group(&key)?;
+                self.group = Some((key, rows));
+            }
+            self.current = Some((left, 0));
+        }
+    }
+}
+
+impl Iterator for MergeJoin {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_pair().transpose()
+    }
+}
+
+/// Order keys value by value, the way they are sorted
+pub(crate) fn compare_keys(left: &[Value], right: &[Value]) -> Ordering {
+    left.iter()
+        .zip(right)
+        .map(|(l, r)| sort_order(l, r))
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Skips `offset` rows then produces at most `remaining`, without pulling
+/// more rows than that from its input
+struct Limit {
+    input: Operator,
+    offset: usize,
+    remaining: Option<usize>,
+}
+
+impl Iterator for Limit {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == Some(0) {
+            return None;
+        }
+        while self.offset > 0 {
+            self.offset -= 1;
+            if let Err(e) = self.input.next()? {
+                return Some(Err(e));
+            }
+        }
+        if let Some(remaining) = &mut self.remaining {
+            *remaining -= 1;
+        }
+        self.input.next()
+    }
+}
+
+/// What a run of a subquery found
+enum SubqueryResult {
+    Value(Value),
+    /// The values of an IN subquery, and whether one was `NULL`
+    Set(HashSet<Value>, bool),
+}
+
+/// Adds the results of a subquery to each row, running it again for each
+/// distinct combination of the outer columns it refers to
+struct Apply {
+    input: Operator,
+    schema: Schema,
+    subquery: LogicalPlan,
+    kind: SubqueryKind,
+    outer: Vec<String>,
+    tables: Tables,
+    budget: Arc<MemoryBudget>,
+    /// The metrics of the nodes of the subquery, if it is measured
+    metrics: Option<Vec<Arc<Metrics>>>,
+    results: HashMap<Tuple, SubqueryResult>,
+}
+
+impl Apply {
+    fn run(&self, values: Tuple) -> Result<SubqueryResult, QueryExecutionError> {
+        let bound: Vec<_> = self.outer.iter().cloned().zip(values).collect();
+        let plan = self.subquery.clone().bind(&bound);
+        let mut builder = Builder {
+            tables: &self.tables,
+            metrics: self.metrics.as_ref().map(|_| Vec::new()),
+            budget: self.budget.clone(),
+        };
+        let mut rows = builder.build(&plan)?;
+
+        let result = match self.kind {
+            SubqueryKind::Scalar => {
+                let value = match rows.next().transpose()? {
+                    Some(mut row) => row.swap_remove(0),
+                    None => Value::Null,
+                };
+                if rows.next().transpose()?.is_some() {
+                    return Err(QueryExecutionError::SubqueryRows);
+                }
+                SubqueryResult::Value(value)
+            }
+            SubqueryKind::Exists => {
+                SubqueryResult::Value(Value::Bool(rows.next().transpose()?.is_some()))
+            }
+            SubqueryKind::In { .. } => {
+                let (mut set, mut has_null) = (HashSet::new(), false);
+                for row in rows {
+                    match row?.swap_remove(0) {
+                        Value::Null => has_null = true,
+                        value => {
+                            set.insert(value);
+                        }
+                    }
+                }
+                SubqueryResult::Set(set, has_null)
+            }
+        };
+
+        for (metrics, run) in self
+            .metrics
+            .iter()
+            .flatten()
+            .zip(builder.metrics.iter().flatten())
+        {
+            metrics.absorb(run);
+        }
+        Ok(result)
+    }
+
+    fn next_row(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        let Some(mut values) = self.input.next().transpose()? else {
+            return Ok(None);
+        };
+        let row = SchemaRow {
+            schema: &self.schema,
+            values: &values,
+        };
+        let outer = self
+            .outer
+            .iter()
+            .map(|name| row.column(name))
+            .collect::<Result<Tuple, _>>()?;
+        if !self.results.contains_key(&outer) {
+            let result = self.run(outer.clone())?;
+            self.results.insert(outer.clone(), result);
+        }
+
+        let value = match (&self.results[&outer], &self.kind) {
+            (SubqueryResult::Value(value), _) => value.clone(),
+            (SubqueryResult::Set(set, has_null), SubqueryKind::In { expr, negated }) => {
+                // unknown unless the value is found, or the set has no NULL
+                match value_of(expr, &row)? {
+                    Value::Null => Value::Null,
+                    value if set.contains(&value) => Value::Bool(!negated),
+                    _ if *has_null => Value::Null,
+                    _ => Value::Bool(*negated),
+                }
+            }
+            (SubqueryResult::Set(..), _) => unreachable!("only IN subqueries find sets"),
+        };
+        values.push(value);
+        Ok(Some(values))
+    }
+}
+
+impl Iterator for Apply {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+/// Produces the rows of a recursive query as each iteration finds them, the
+/// rows of its base query first
+struct Recursive {
+    /// The name the step reads the rows of the last iteration by
+    table: String,
+    columns: Vec<String>,
+    step: LogicalPlan,
+    all: bool,
+    /// The other tables the step reads
+    tables: Tables,
+    budget: Arc<MemoryBudget>,
+    /// The metrics of the nodes of the step, if it is measured
+    metrics: Option<Vec<Arc<Metrics>>>,
+    /// The running iteration, and the metrics of its nodes
+    current: Operator,
+    current_metrics: Vec<Arc<Metrics>>,
+    /// The new rows of the running iteration
+    found: Vec<Tuple>,
+    /// Every row found, to skip the ones found again without `all`
+    seen: HashSet<Tuple>,
+}
+
+impl Recursive {
+    fn next_row(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        loop {
+            if let Some(row) = self.current.next().transpose()? {
+                if !self.all && !self.seen.insert(row.clone()) {
+                    continue;
+                }
+                self.found.push(row.clone());
+                return Ok(Some(row));
+            }
+
+            for (metrics, run) in self.metrics.iter().flatten().zip(&self.current_metrics) {
+                metrics.absorb(run);
+            }
+            // done once an iteration finds nothing new
+            if self.found.is_empty() {
+                self.current = Box::new(iter::empty());
+                self.current_metrics.clear();
+                return Ok(None);
+            }
+
+            let working = mem::take(&mut self.found)
+                .into_iter()
+                .enumerate()
+                .map(|(id, values)| {
+                    let row: HashMap<_, _> = self.columns.iter().cloned().zip(values).collect();
+                    (id, StoredRow::from(row))
+                })
+                .collect();
+            let mut tables = self.tables.clone();
+            tables.working.insert(self.table.clone(), Arc::new(working));
+            let mut builder = Builder {
+                tables: &tables,
+                metrics: self.metrics.as_ref().map(|_| Vec::new()),
+                budget: self.budget.clone(),
+            };
+            self.current = builder.build(&self.step)?;
+            self.current_metrics = builder.metrics.unwrap_or_default();
+        }
+    }
+}
+
+impl Iterator for Recursive {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+/// Skips the rows of its input whose key it already saw: the values of `on`,
+/// or the whole row without any
+///
+/// The keys are kept in memory until the budget runs out. The rest of the
+/// input is then sorted by key, behind the keys already seen, to keep the
+/// first row of each key not seen yet, and sorted back into the order of
+/// the input.
+struct Distinct<I> {
+    input: I,
+    schema: Schema,
+    on: Vec<Expression>,
+    seen: HashSet<Tuple>,
+    budget: Arc<MemoryBudget>,
+    reservation: Reservation,
+    /// The first rows of the keys not seen before the budget ran out
+    spilled: Option<Run>,
+}
+
+impl<I: Iterator<Item = Result<Tuple, QueryExecutionError>>> Distinct<I> {
+    fn new(input: I, budget: Arc<MemoryBudget>) -> Self {
+        Self::on(input, Schema::default(), vec![], budget)
+    }
+
+    fn on(input: I, schema: Schema, on: Vec<Expression>, budget: Arc<MemoryBudget>) -> Self {
+        Self {
+            input,
+            schema,
+            on,
+            seen: HashSet::new(),
+            reservation: budget.reserve(),
+            budget,
+            spilled: None,
+        }
+    }
+
+    fn key(&self, values: &Tuple) -> Result<Tuple, QueryExecutionError> {
+        distinct_key(&self.schema, &self.on, values)
+    }
+
+    /// Find the first rows of the keys not seen yet among `row` and the rest
+    /// of the input
+    fn spill(&mut self, key: Tuple, row: Tuple) -> Result<Run, QueryExecutionError> {
+        // each row with its position, behind the keys seen without any
+        let seen = mem::take(&mut self.seen)
+            .into_iter()
+            .map(|key| Ok((key, vec![Value::Null])));
+        let (schema, on) = (&self.schema, &self.on);
+        let rest = iter::once(Ok((key, row)))
+            .chain(self.input.by_ref().map(|row| {
+                let row = row?;
+                Ok::<_, QueryExecutionError>((distinct_key(schema, on, &row)?, row))
+            }))
+            .enumerate()
+            .map(|(position, row)| {
+                let (key, row) = row?;
+                let mut values = vec![number(position)];
+                values.extend(row);
+                Ok((key, values))
+            });
+        // the keys seen are given back as the sort takes them
+        self.reservation.free();
+        let sorted = sort_by_key(seen.chain(rest), vec![], &self.budget)?;
+
+        let mut last: Option<Tuple> = None;
+        let first = sorted.filter_map(move |row| {
+            let (key, mut values) = match row {
+                Ok(row) => row,
+                Err(e) => return Some(Err(e)),
+            };
+            if matches!(&last, Some(last) if compare_keys(last, &key).is_eq()) {
+                return None;
+            }
+            last = Some(key);
+            match values[0] {
+                Value::Null => None,
+                _ => {
+                    let rest = values.split_off(1);
+                    Some(Ok((values, rest)))
+                }
+            }
+        });
+        sort_by_key(first, vec![], &self.budget)
+    }
+
+    fn next_row(&mut self) -> Result<Option<Tuple>, QueryExecutionError> {
+        loop {
+            if let Some(spilled) = &mut self.spilled {
+                return Ok(spilled.next().transpose()?.map(|(_, values)| values));
+            }
+            let Some(row) = self.input.next().transpose()? else {
+                return Ok(None);
+            };
+            let key = self.key(&row)?;
+            if self.seen.contains(&key) {
+                continue;
+            }
+            if !self.reservation.grow(size_of_values(&key)) && !self.seen.is_empty() {
+                self.spilled = Some(self.spill(key, row)?);
+                continue;
+            }
+            self.seen.insert(key);
+            return Ok(Some(row));
+        }
+    }
+}
+
+/// The values of `on` for a row, or the whole row without any
+fn distinct_key(
+    schema: &Schema,
+    on: &[Expression],
+    values: &Tuple,
+) -> Result<Tuple, QueryExecutionError> {
+    if on.is_empty() {
+        return Ok(values.clone());
+    }
+    let row = SchemaRow { schema, values };
+    on.iter().map(|expr| value_of(expr, &row)).collect()
+}
+
+impl<I: Iterator<Item = Result<Tuple, QueryExecutionError>>> Iterator for Distinct<I> {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
+
+type Compute = Box<dyn FnOnce(Operator) -> Result<Operator, QueryExecutionError> + Send>;
+
+/// An operator that needs all the rows of its input before producing any,
+/// reading them on the first pull
+struct Blocking {
+    state: BlockingState,
+}
+
+enum BlockingState {
+    Pending(Operator, Compute),
+    Producing(Operator),
+    Done,
+}
+
+impl Blocking {
+    fn new(
+        input: Operator,
+        compute: impl FnOnce(Operator) -> Result<Operator, QueryExecutionError> + Send + 'static,
+    ) -> Self {
+        Self {
+            state: BlockingState::Pending(input, Box::new(compute)),
+        }
+    }
+}
+
+impl Iterator for Blocking {
+    type Item = Result<Tuple, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let BlockingState::Pending(..) = self.state {
+            let BlockingState::Pending(input, compute) =
+                std::mem::replace(&mut self.state, BlockingState::Done)
+            else {
+                unreachable!("checked above");
+            };
+            match compute(input) {
+                Ok(rows) => self.state = BlockingState::Producing(rows),
+                Err(e) => return Some(Err(e)),
+            }
+        }
+        match &mut self.state {
+            BlockingState::Producing(rows) => rows.next(),
+            _ => None,
+        }
+    }
+}
+
+/// Number of files the rows that don't fit in memory are split into by the
+/// hash of their keys
+const PARTITIONS: usize = 16;
+
+/// A temporary file for each partition rows are split into, created once
+/// a row is written to it
+fn partition_files() -> Vec<Option<SpillFile<Tuple>>> {
+    (0..PARTITIONS).map(|_| None).collect()
+}
+
+/// Write a row to the file of the partition its key belongs to at `level`
+fn write_partition(
+    files: &mut [Option<SpillFile<Tuple>>],
+    level: u64,
+    key: &Tuple,
+    values: &Tuple,
+) -> Result<(), QueryExecutionError> {
+    let mut hasher = DefaultHasher::new();
+    (level, key).hash(&mut hasher);
+    let partition = &mut files[hasher.finish() as usize % PARTITIONS];
+    let file = match partition {
+        Some(file) => file,
+        None => partition.insert(SpillFile::new()?),
+    };
+    file.write(values)
+}
+
+/// What an aggregate computes from the rows of its input
+#[derive(Clone)]
+struct Aggregation {
+    schema: Schema,
+    group_by: Vec<Expression>,
+    aggregates: Vec<Expression>,
+    budget: Arc<MemoryBudget>,
+}
+
+impl Aggregation {
+    fn new_group(&self) -> Vec<Accumulator> {
+        self.aggregates
+            .iter()
+            .map(|expr| match expr {
+                Expression::Aggregate {
+                    function, distinct, ..
+                } => Accumulator::new(*function, *distinct),
+                expr => unreachable!("{expr} is not an aggregate"),
+            })
+            .collect()
+    }
+
+    /// One row per group of rows with the same `group_by` values
+    ///
+    /// The groups are kept in memory in the order they were first seen, until
+    /// the budget runs out. The rows of the groups seen after that are split
+    /// into temporary files by the hash of their values, each aggregated the
+    /// same way after the groups in memory, at the next `level`.
+    ///
+    /// The values `DISTINCT` aggregates see are kept in memory too. When one
+    /// doesn't fit, the values its group saw are written to a temporary file
+    /// with it, to be sorted and added once without duplicates when the
+    /// group is produced.
+    fn run(&self, input: Operator, level: u64) -> Result<Operator, QueryExecutionError> {
+        let mut reservation = self.budget.reserve();
+        let mut groups: Vec<(Tuple, Vec<Accumulator>)> = Vec::new();
+        let mut positions = HashMap::new();
+        let mut partitions = partition_files();
+        let mut full = false;
+        // the values of the DISTINCT aggregates written out, by the position
+        // of their group and their own
+        let mut distinct = None;
+        let mut spilled = BTreeSet::new();
+        // without GROUP BY there is a single group, even for no rows
+        if self.group_by.is_empty() {
+            groups.push((vec![], self.new_group()));
+            positions.insert(vec![], 0);
+        }
+
+        for values in input {
+            let values = values?;
+            let row = SchemaRow {
+                schema: &self.schema,
+                values: &values,
+            };
+            let key = self
+                .group_by
+                .iter()
+                .map(|expr| value_of(expr, &row))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let position = match positions.get(&key) {
+                Some(position) => *position,
+                None => {
+                    // the key is held by the group and its position
+                    let size = 2 * size_of_values(&key)
+                        + self.aggregates.len() * mem::size_of::<Accumulator>();
+                    // once a group is written out the memory DISTINCT
+                    // aggregates give back can't take it in again
+                    full = full || (!reservation.grow(size) && !groups.is_empty());
+                    if full {
+                        write_partition(&mut partitions, level, &key, &values)?;
+                        continue;
+                    }
+                    groups.push((key.clone(), self.new_group()));
+                    positions.insert(key, groups.len() - 1);
+                    groups.len() - 1
+                }
+            };
+
+            let accumulators = self.aggregates.iter().zip(&mut groups[position].1);
+            for (aggregate, (expr, accumulator)) in accumulators.enumerate() {
+                let value = match expr {
+                    Expression::Aggregate { arg: Some(arg), .. } => value_of(arg, &row)?,
+                    // COUNT(*) counts every row
+                    _ => Value::Number(1.into()),
+                };
+                let unseen = matches!(accumulator, Accumulator::Distinct { seen, .. } if value != Value::Null && !seen.contains(&value));
+                if unseen && !reservation.grow(size_of_values(slice::from_ref(&value))) {
+                    let file = match &mut distinct {
+                        Some(file) => file,
+                        None => distinct.insert(SpillFile::new()?),
+                    };
+                    spill_distinct(file, position, aggregate, accumulator, &mut reservation)?;
+                    file.write(&(vec![number(position), number(aggregate), value], vec![]))?;
+                    spilled.insert((position, aggregate));
+                    continue;
+                }
+                accumulator.add(value)?;
+            }
+        }
+
+        let mut distinct = match distinct {
+            Some(mut file) => {
+                for (position, aggregate) in spilled {
+                    let accumulator = &mut groups[position].1[aggregate];
+                    spill_distinct(
+                        &mut file,
+                        position,
+                        aggregate,
+                        accumulator,
+                        &mut reservation,
+                    )?;
+                }
+                Some(sort_by_key(file.read()?, vec![], &self.budget)?.peekable())
+            }
+            None => None,
+        };
+        // the memory of the groups is given back once they are produced
+        let in_memory =
+            groups
+                .into_iter()
+                .enumerate()
+                .map(move |(position, (mut key, mut accumulators))| {
+                    let _ = &reservation;
+                    if let Some(sorted) = &mut distinct {
+                        add_spilled_distinct(sorted, position, &mut accumulators)?;
+                    }
+                    key.extend(accumulators.into_iter().map(Accumulator::finish));
+                    Ok(key)
+                });
+        let aggregation = self.clone();
+        let spilled = partitions.into_iter().flatten().flat_map(move |file| {
+            let rows = file
+                .read()
+                .and_then(|rows| aggregation.run(Box::new(rows), level + 1));
+            match rows {
+                Ok(rows) => rows,
+                Err(e) => Box::new(iter::once(Err(e))),
+            }
+        });
+        Ok(Box::new(in_memory.chain(spilled)))
+    }
+}
+
+/// Write the values a `DISTINCT` aggregate saw to a file, giving their memory
+/// back, after the position of its group and its own
+fn spill_distinct(
+    file: &mut SpillFile<(Tuple, Tuple)>,
+    position: usize,
+    aggregate: usize,
+    accumulator: &mut Accumulator,
+    reservation: &mut Reservation,
+) -> Result<(), QueryExecutionError> {
+    for value in accumulator.take_distinct() {
+        reservation.shrink(size_of_values(slice::from_ref(&value)));
+        file.write(&(vec![number(position), number(aggregate), value], vec![]))?;
+    }
+    Ok(())
+}
+
+/// Add the values the `DISTINCT` aggregates of a group wrote out to them,
+/// each once, taking them from the sorted values of all the groups
+fn add_spilled_distinct(
+    sorted: &mut Peekable<Run>,
+    position: usize,
+    accumulators: &mut [Accumulator],
+) -> Result<(), QueryExecutionError> {
+    for (aggregate, accumulator) in accumulators.iter_mut().enumerate() {
+        let of = [number(position), number(aggregate)];
+        let mut last = None;
+        while let Some(Ok((key, _))) = sorted.peek() {
+            if key[..2] != of {
+                break;
+            }
+            let value = sorted.next().and_then(|row| row.ok()?.0.pop());
+            if value != last {
+                accumulator.add_distinct(value.clone().unwrap_or(Value::Null))?;
+                last = value;
+            }
+        }
+        if let Some(Err(_)) = sorted.peek() {
+            sorted.next().transpose()?;
+        }
+    }
+    Ok(())
+}
+
+/// A sorted sequence of rows with their sort keys
+pub(crate) type Run = Box<dyn Iterator<Item = Result<(Tuple, Tuple), QueryExecutionError>> + Send>;
+
+/// The most runs merged at once, each reading an open file
+const MERGE_FAN_IN: usize = 16;
+
+/// Sort the rows of `input` by the values of `order_by`, keeping the order of
+/// equal rows
+fn sort(
+    schema: &Schema,
+    input: Operator,
+    order_by: Vec<OrderBy>,
+    budget: &Arc<MemoryBudget>,
+) -> Result<Operator, QueryExecutionError> {
+    let keyed = input.map(|values| {
+        let values = values?;
+        let row = SchemaRow {
+            schema,
+            values: &values,
+        };
+        let key = order_by
+            .iter()
+            .map(|order| value_of(&order.expr, &row))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((key, values))
+    });
+    let descending = order_by.iter().map(|order| order.descending).collect();
+    let sorted = sort_by_key(keyed, descending, budget)?;
+    Ok(Box::new(sorted.map(|row| row.map(|(_, values)| values))))
+}
+
+/// Sort rows by their keys, the values for which `descending` is true in
+/// reverse and the others ascending, keeping the order of rows with equal
+/// keys
+///
+/// The rows are sorted in memory until the budget runs out, then each sorted
+/// run of them is written to a temporary file, and the runs are merged. When
+/// there are too many runs to merge at once, consecutive ones are merged into
+/// longer runs first.
+pub(crate) fn sort_by_key(
+    input: impl Iterator<Item = Result<(Tuple, Tuple), QueryExecutionError>>,
+    descending: Vec<bool>,
+    budget: &Arc<MemoryBudget>,
+) -> Result<Run, QueryExecutionError> {
+    let mut reservation = budget.reserve();
+    let mut rows = Vec::new();
+    let mut runs: Vec<SpilledRows<(Tuple, Tuple)>> = Vec::new();
+    for row in input {
+        let (key, values) = row?;
+        let size = size_of_values(&key) + size_of_values(&values);
+        if !reservation.grow(size) && !rows.is_empty() {
+            sort_keyed(&mut rows, &descending);
+            let mut file = SpillFile::new()?;
+            for row in rows.drain(..) {
+                file.write(&row)?;
+            }
+            runs.push(file.close()?);
+            reservation.free();
+            // a row larger than the whole budget is kept anyway
+            reservation.grow(size);
+        }
+        rows.push((key, values));
+    }
+
+    sort_keyed(&mut rows, &descending);
+    let in_memory = rows.into_iter().map(move |row| {
+        let _ = &reservation;
+        Ok(row)
+    });
+    if runs.is_empty() {
+        return Ok(Box::new(in_memory));
+    }
+    // the rows in memory are the last run of the final merge
+    while runs.len() >= MERGE_FAN_IN {
+        runs = merge_pass(runs, &descending)?;
+    }
+    let mut runs = runs
+        .into_iter()
+        .map(|run| Ok(Box::new(run.read()?) as Run))
+        .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+    runs.push(Box::new(in_memory));
+    Ok(Box::new(MergeRuns::new(runs, descending)?))
+}
+
+/// Merge each [`MERGE_FAN_IN`] consecutive runs into one, written to a file
+fn merge_pass(
+    runs: Vec<SpilledRows<(Tuple, Tuple)>>,
+    descending: &[bool],
+) -> Result<Vec<SpilledRows<(Tuple, Tuple)>>, QueryExecutionError> {
+    let mut merged = Vec::new();
+    let mut runs = runs.into_iter().peekable();
+    while runs.peek().is_some() {
+        let group = runs
+            .by_ref()
+            .take(MERGE_FAN_IN)
+            .map(|run| Ok(Box::new(run.read()?) as Run))
+            .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+        let mut file = SpillFile::new()?;
+        for row in MergeRuns::new(group, descending.to_vec())? {
+            file.write(&row?)?;
+        }
+        merged.push(file.close()?);
+    }
+    Ok(merged)
+}
+
+/// Order rows by their keys, keeping the order of rows with equal keys
+fn sort_keyed(rows: &mut [(Tuple, Tuple)], descending: &[bool]) {
+    rows.sort_by(|(left, _), (right, _)| compare_sort_keys(left, right, descending));
+}
+
+/// Order keys value by value, the values for which `descending` is true in
+/// reverse and the others ascending
+pub(crate) fn compare_sort_keys(left: &[Value], right: &[Value], descending: &[bool]) -> Ordering {
+    left.iter()
+        .zip(right)
+        .enumerate()
+        .map(|(i, (l, r))| {
+            let ordering = sort_order(l, r);
+            if descending.get(i) == Some(&true) {
+                ordering.reverse()
+            } else {
+                ordering
+            }
+        })
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Merges sorted runs into one, taking rows with equal keys from the earlier
+/// runs first, producing the rows with their keys
+struct MergeRuns {
+    runs: Vec<Run>,
+    /// The next row of each run, `None` once it is exhausted
+    heads: Vec<Option<(Tuple, Tuple)>>,
+    descending: Vec<bool>,
+}
+
+impl MergeRuns {
+    fn new(mut runs: Vec<Run>, descending: Vec<bool>) -> Result<Self, QueryExecutionError> {
+        let heads = runs
+            .iter_mut()
+            .map(|run| run.next().transpose())
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            runs,
+            heads,
+            descending,
+        })
+    }
+
+    fn next_row(&mut self) -> Result<Option<(Tuple, Tuple)>, QueryExecutionError> {
+        let mut smallest: Option<usize> = None;
+        for (i, head) in self.heads.iter().enumerate() {
+            let Some((key, _)) = head else {
+                continue;
+            };
+            let smaller = match smallest.and_then(|s| self.heads[s].as_ref()) {
+                Some((smallest, _)) => compare_sort_keys(key, smallest, &self.descending).is_lt(),
+                None => true,
+            };
+            if smaller {
+                smallest = Some(i);
+            }
+        }
+        let Some(i) = smallest else {
+            return Ok(None);
+        };
+        let next = self.runs[i].next().transpose()?;
+        Ok(mem::replace(&mut self.heads[i], next))
+    }
+}
+
+impl Iterator for MergeRuns {
+    type Item = Result<(Tuple, Tuple), QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_row().transpose()
+    }
+}
@@ -0,0 +1,960 @@
+use toy_sql_parser::{
+    commands::OrderBy,
+    expression::{binary, BinaryOperator, Expression},
+    value::Value,
+};
+
+use crate::{
+    cost::{join_cost, Catalog, Estimator},
+    error::QueryExecutionError,
+    eval::{value_of, ColumnValues},
+    plan::{join_keys, unqualify, JoinStrategy, LogicalPlan, Schema, SubqueryKind},
+};
+
+/// Most tables joined together whose join orders are all compared, more are
+/// joined in the order of the query
+const MAX_REORDERED_JOINS: usize = 8;
+
+/// Rewrite a plan into an equivalent one that is cheaper to run
+///
+/// The rules that always help are applied first, then the join order and the
+/// scans are chosen by the costs estimated from the statistics of the tables.
+pub(crate) fn optimize(plan: LogicalPlan, catalog: &dyn Catalog) -> LogicalPlan {
+    let plan = fold_constants(plan);
+    let plan = push_down_predicates(plan, vec![]);
+    let plan = remove_redundant_sorts(plan);
+    let estimator = Estimator::new(catalog, &plan);
+    let plan = order_joins(plan, &estimator);
+    let plan = choose_indexes(plan, &estimator);
+    let plan = choose_join_strategies(plan, &estimator);
+    prune_columns(plan, vec![])
+}
+
+/// A row without columns, to evaluate constant expressions
+struct NoColumns;
+
+impl ColumnValues for NoColumns {
+    fn column(&self, name: &str) -> Result<Value, QueryExecutionError> {
+        Err(QueryExecutionError::ColumnDoesNotExist(name.to_owned()))
+    }
+}
+
+fn literal(b: bool) -> Expression {
+    Expression::Literal(Value::Bool(b))
+}
+
+/// Evaluate the parts of an expression that don't depend on the row
+///
+/// Parts that would fail to evaluate are kept, so they still fail when the
+/// query runs.
+fn fold(expr: &Expression) -> Expression {
+    let expr = expr.map_children(fold);
+    match &expr {
+        Expression::BinaryOp {
+            left,
+            op: op @ (BinaryOperator::And | BinaryOperator::Or),
+            right,
+        } => {
+            // `false AND x` is false and `true AND x` is x, the reverse for OR
+            let absorbing = *op == BinaryOperator::Or;
+            match (left.as_ref(), right.as_ref()) {
+                (Expression::Literal(Value::Bool(b)), _)
+                | (_, Expression::Literal(Value::Bool(b)))
+                    if *b == absorbing =>
+                {
+                    literal(absorbing)
+                }
+                (Expression::Literal(Value::Bool(_)), other)
+                | (other, Expression::Literal(Value::Bool(_))) => other.clone(),
+                _ => expr,
+            }
+        }
+        Expression::Literal(_) | Expression::Column(_) => expr,
+        _ if expr.columns().is_empty()
+            && !expr.has_aggregate()
+            && !expr.has_window()
+            && !expr.has_subquery() =>
+        {
+            value_of(&expr, &NoColumns).map_or(expr, Expression::Literal)
+        }
+        _ => expr,
+    }
+}
+
+/// Fold the constants of every expression, dropping the filters that keep
+/// every row
+fn fold_constants(plan: LogicalPlan) -> LogicalPlan {
+    let fold_all = |exprs: Vec<(Expression, _)>| {
+        exprs
+            .into_iter()
+            .map(|(expr, col)| (fold(&expr), col))
+            .collect()
+    };
+
+    match plan.map_inputs(fold_constants) {
+        LogicalPlan::Filter { input, predicate } => match fold(&predicate) {
+            Expression::Literal(Value::Bool(true)) => *input,
+            predicate => LogicalPlan::Filter { input, predicate },
+        },
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            strategy,
+        } => LogicalPlan::Join {
+            left,
+            right,
+            on: fold(&on),
+            strategy,
+        },
+        LogicalPlan::Project { input, exprs } => LogicalPlan::Project {
+            input,
+            exprs: fold_all(exprs),
+        },
+        LogicalPlan::Aggregate {
+            input,
+            group_by,
+            aggregates,
+        } => LogicalPlan::Aggregate {
+            input,
+            group_by: fold_all(group_by),
+            aggregates: fold_all(aggregates),
+        },
+        LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort {
+            input,
+            order_by: order_by
+                .into_iter()
+                .map(|order| OrderBy {
+                    expr: fold(&order.expr),
+                    descending: order.descending,
+                })
+                .collect(),
+        },
+        plan => plan,
+    }
+}
+
+/// `<c1> AND <c2> AND ...`, `true` if there are none
+pub(crate) fn conjunction(conjuncts: Vec<Expression>) -> Expression {
+    conjuncts
+        .into_iter()
+        .reduce(|left, right| binary(left, BinaryOperator::And, right))
+        .unwrap_or_else(|| literal(true))
+}
+
+/// Whether all the columns of an expression are in the schema
+fn refers_to(expr: &Expression, schema: &Schema) -> bool {
+    expr.columns()
+        .into_iter()
+        .all(|column| schema.resolve(column).is_ok())
+}
+
+/// Filter the rows of `plan` by the conjuncts, moving each one as close to
+/// the scans as it can go, so fewer rows flow through the rest of the plan
+///
+/// Conjuncts of a join condition on a single side filter that side before
+/// the join.
+fn push_down_predicates(plan: LogicalPlan, mut conjuncts: Vec<Expression>) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            conjuncts.extend(predicate.conjuncts().into_iter().cloned());
+            push_down_predicates(*input, conjuncts)
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            strategy,
+        } => {
+            conjuncts.extend(on.conjuncts().into_iter().cloned());
+            let (left_schema, right_schema) = (left.schema(), right.schema());
+
+            let (mut left_only, mut right_only, mut both) = (vec![], vec![], vec![]);
+            for conjunct in conjuncts {
+                if refers_to(&conjunct, &left_schema) {
+                    left_only.push(conjunct);
+                } else if refers_to(&conjunct, &right_schema) {
+                    right_only.push(conjunct);
+                } else {
+                    both.push(conjunct);
+                }
+            }
+
+            LogicalPlan::Join {
+                left: Box::new(push_down_predicates(*left, left_only)),
+                right: Box::new(push_down_predicates(*right, right_only)),
+                on: conjunction(both),
+                strategy,
+            }
+        }
+        // conjuncts not needing the results of the subquery filter the rows
+        // it runs for
+        LogicalPlan::Apply {
+            input,
+            subquery,
+            kind,
+            outer,
+            column,
+        } => {
+            let (below, above): (Vec<_>, Vec<_>) = conjuncts
+                .into_iter()
+                .partition(|conjunct| refers_to(conjunct, &input.schema()));
+            let plan = LogicalPlan::Apply {
+                input: Box::new(push_down_predicates(*input, below)),
+                subquery: Box::new(push_down_predicates(*subquery, vec![])),
+                kind,
+                outer,
+                column,
+            };
+            match above.is_empty() {
+                true => plan,
+                false => LogicalPlan::Filter {
+                    input: Box::new(plan),
+                    predicate: conjunction(above),
+                },
+            }
+        }
+        // sorting fewer rows is cheaper
+        LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort {
+            input: Box::new(push_down_predicates(*input, conjuncts)),
+            order_by,
+        },
+        plan => {
+            let plan = plan.map_inputs(|input| push_down_predicates(input, vec![]));
+            if conjuncts.is_empty() {
+                return plan;
+            }
+            LogicalPlan::Filter {
+                input: Box::new(plan),
+                predicate: conjunction(conjuncts),
+            }
+        }
+    }
+}
+
+/// Whether the rows of `plan` already come in the order of `order_by`
+fn sorted_by(plan: &LogicalPlan, order_by: &[OrderBy]) -> bool {
+    match plan {
+        LogicalPlan::Sort {
+            order_by: sorted, ..
+        } => sorted.starts_with(order_by),
+        LogicalPlan::Filter { input, .. }
+        | LogicalPlan::Distinct { input, .. }
+        | LogicalPlan::Limit { input, .. } => sorted_by(input, order_by),
+        _ => false,
+    }
+}
+
+/// Drop a sort whose order is lost anyway, looking through the operators
+/// that keep the order of their rows without depending on it
+fn without_sort(plan: LogicalPlan) -> LogicalPlan {
+    match plan {
+        LogicalPlan::Sort { input, .. } => without_sort(*input),
+        LogicalPlan::Filter { .. } | LogicalPlan::Project { .. } => plan.map_inputs(without_sort),
+        plan => plan,
+    }
+}
+
+/// Remove the sorts of rows that are already sorted, and of rows sorted
+/// again or grouped afterwards
+fn remove_redundant_sorts(plan: LogicalPlan) -> LogicalPlan {
+    match plan.map_inputs(remove_redundant_sorts) {
+        LogicalPlan::Sort { input, order_by } => {
+            if order_by.is_empty() || sorted_by(&input, &order_by) {
+                return *input;
+            }
+            LogicalPlan::Sort {
+                input: Box::new(without_sort(*input)),
+                order_by,
+            }
+        }
+        LogicalPlan::Aggregate {
+            input,
+            group_by,
+            aggregates,
+        } => LogicalPlan::Aggregate {
+            input: Box::new(without_sort(*input)),
+            group_by,
+            aggregates,
+        },
+        plan => plan,
+    }
+}
+
+/// Join the inputs of each chain of joins in the order with the lowest
+/// estimated cost
+///
+/// Each conjunct of the join conditions is checked by the first join having
+/// the columns it refers to.
+fn order_joins(plan: LogicalPlan, estimator: &Estimator) -> LogicalPlan {
+    let LogicalPlan::Join { .. } = plan else {
+        return plan.map_inputs(|input| order_joins(input, estimator));
+    };
+
+    let (mut relations, mut conjuncts) = (vec![], vec![]);
+    flatten_joins(plan, &mut relations, &mut conjuncts);
+    let relations: Vec<_> = relations
+        .into_iter()
+        .map(|relation| order_joins(relation, estimator))
+        .collect();
+
+    // the relations each conjunct needs, as a bit set
+    let schemas: Vec<_> = relations.iter().map(LogicalPlan::schema).collect();
+    let conjuncts: Vec<_> = conjuncts
+        .into_iter()
+        .map(|conjunct| {
+            let needs = schemas
+                .iter()
+                .enumerate()
+                .filter(|(_, schema)| {
+                    conjunct
+                        .columns()
+                        .into_iter()
+                        .any(|column| schema.resolve(column).is_ok())
+                })
+                .fold(0, |needs, (i, _)| needs | 1 << i);
+            (needs, conjunct)
+        })
+        .collect();
+
+    let order = match relations.len() {
+        n if n > MAX_REORDERED_JOINS => (0..n).collect(),
+        _ => best_join_order(&relations, &schemas, &conjuncts, estimator),
+    };
+
+    let mut relations: Vec<_> = relations.into_iter().map(Some).collect();
+    let mut take = |i: usize| relations[i].take().expect("joined once");
+    let mut plan = take(order[0]);
+    let (mut joined, mut remaining) = (1 << order[0], conjuncts);
+    for &i in &order[1..] {
+        joined |= 1 << i;
+        let (on, rest): (Vec<_>, Vec<_>) = remaining
+            .into_iter()
+            .partition(|(needs, _)| needs & !joined == 0);
+        remaining = rest;
+        plan = LogicalPlan::Join {
+            left: Box::new(plan),
+            right: Box::new(take(i)),
+            on: conjunction(on.into_iter().map(|(_, conjunct)| conjunct).collect()),
+            strategy: JoinStrategy::NestedLoop,
+        };
+    }
+    plan
+}
+
+/// Collect the inputs of a chain of joins and the conjuncts of their
+/// conditions
+fn flatten_joins(
+    plan: LogicalPlan,
+    relations: &mut Vec<LogicalPlan>,
+    conjuncts: &mut Vec<Expression>,
+) {
+    match plan {
+        LogicalPlan::Join {
+            left, right, on, ..
+        } => {
+            flatten_joins(*left, relations, conjuncts);
+            flatten_joins(*right, relations, conjuncts);
+            let always = literal(true);
+            conjuncts.extend(
+                on.conjuncts()
+                    .into_iter()
+                    .filter(|c| **c != always)
+                    .cloned(),
+            );
+        }
+        plan => relations.push(plan),
+    }
+}
+
+/// The order to join the relations in, each joined to the ones before it,
+/// with the lowest estimated cost
+///
+/// Finds the cheapest order of every subset of the relations, from the
+/// cheapest orders of the subsets with one relation less.
+fn best_join_order(
+    relations: &[LogicalPlan],
+    schemas: &[Schema],
+    conjuncts: &[(usize, Expression)],
+    estimator: &Estimator,
+) -> Vec<usize> {
+    let rows: Vec<_> = relations.iter().map(|r| estimator.rows(r)).collect();
+    let costs: Vec<_> = relations.iter().map(|r| estimator.cost(r)).collect();
+    let schema = schemas
+        .iter()
+        .fold(Schema::default(), |all, schema| all.join(schema));
+    let selectivities: Vec<_> = conjuncts
+        .iter()
+        .map(|(needs, conjunct)| (*needs, estimator.selectivity(conjunct, &schema)))
+        .collect();
+    // the relations equalities between them compare, to join them on hash tables
+    let equalities: Vec<_> = conjuncts
+        .iter()
+        .filter(|(_, conjunct)| {
+            matches!(
+                conjunct,
+                Expression::BinaryOp {
+                    op: BinaryOperator::Eq,
+                    ..
+                }
+            )
+        })
+        .map(|(needs, _)| *needs)
+        .collect();
+
+    // rows of the relations of a set joined together
+    let rows_of = |set: usize| -> f64 {
+        let product: f64 = (0..rows.len())
+            .filter(|i| set & 1 << i != 0)
+            .map(|i| rows[i])
+            .product();
+        let selectivity: f64 = selectivities
+            .iter()
+            .filter(|(needs, _)| needs & !set == 0)
+            .map(|(_, selectivity)| selectivity)
+            .product();
+        product * selectivity
+    };
+
+    let all = (1 << relations.len()) - 1;
+    let mut best: Vec<Option<(f64, Vec<usize>)>> = vec![None; all + 1];
+    for (i, cost) in costs.iter().enumerate() {
+        best[1 << i] = Some((*cost, vec![i]));
+    }
+    for set in 1..=all {
+        if set.count_ones() < 2 {
+            continue;
+        }
+        // the last relation of the query first, to keep its order on ties
+        for last in (0..relations.len()).rev().filter(|i| set & 1 << i != 0) {
+            let rest = set & !(1 << last);
+            let Some((rest_cost, rest_order)) = &best[rest] else {
+                continue;
+            };
+            let strategy = match equalities
+                .iter()
+                .any(|needs| needs & !set == 0 && needs & 1 << last != 0 && needs & rest != 0)
+            {
+                true => JoinStrategy::Hash,
+                false => JoinStrategy::NestedLoop,
+            };
+            let cost = rest_cost + costs[last] + join_cost(strategy, rows_of(rest), rows[last]);
+            let cheaper = match &best[set] {
+                Some((best_cost, _)) => cost < *best_cost,
+                None => true,
+            };
+            if cheaper {
+                let mut order = rest_order.clone();
+                order.push(last);
+                best[set] = Some((cost, order));
+            }
+        }
+    }
+    best[all].take().map(|(_, order)| order).unwrap_or_default()
+}
+
+/// Read the rows a filter keeps from a table through an index, when the cost
+/// model estimates it's cheaper than reading all of them
+fn choose_indexes(plan: LogicalPlan, estimator: &Estimator) -> LogicalPlan {
+    match plan.map_inputs(|input| choose_indexes(input, estimator)) {
+        LogicalPlan::Filter { input, predicate } => match *input {
+            LogicalPlan::Scan {
+                table,
+                alias,
+                columns,
+                ..
+            } => {
+                let index = estimator.best_index(&table, &unqualify(&predicate, &alias));
+                LogicalPlan::Filter {
+                    input: Box::new(LogicalPlan::Scan {
+                        table,
+                        alias,
+                        columns,
+                        index,
+                    }),
+                    predicate,
+                }
+            }
+            input => LogicalPlan::Filter {
+                input: Box::new(input),
+                predicate,
+            },
+        },
+        plan => plan,
+    }
+}
+
+/// Join on the keys of equi-joins with hash tables, or by merging the inputs
+/// if they are sorted by them already
+///
+/// A sort of the rows of such a join by its keys is replaced by sorting the
+/// inputs and merging them instead, if the cost model estimates it's cheaper.
+fn choose_join_strategies(plan: LogicalPlan, estimator: &Estimator) -> LogicalPlan {
+    match plan.map_inputs(|input| choose_join_strategies(input, estimator)) {
+        LogicalPlan::Join {
+            left, right, on, ..
+        } => {
+            let (keys, _) = join_keys(&on, &left.schema(), &right.schema());
+            let sorted = sorted_by(&left, &ascending(keys.iter().map(|(l, _)| l)))
+                && sorted_by(&right, &ascending(keys.iter().map(|(_, r)| r)));
+            let strategy = match (keys.is_empty(), sorted) {
+                (true, _) => JoinStrategy::NestedLoop,
+                (false, true) => JoinStrategy::Merge,
+                (false, false) => JoinStrategy::Hash,
+            };
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                strategy,
+            }
+        }
+        LogicalPlan::Sort { input, order_by } => {
+            let sort = LogicalPlan::Sort { input, order_by };
+            let LogicalPlan::Sort { input, order_by } = &sort else {
+                unreachable!("just built a sort");
+            };
+            match merge_sorted(input, order_by) {
+                Some(merged) if estimator.cost(&merged) < estimator.cost(&sort) => merged,
+                _ => sort,
+            }
+        }
+        plan => plan,
+    }
+}
+
+fn ascending<'a>(exprs: impl Iterator<Item = &'a Expression>) -> Vec<OrderBy> {
+    exprs
+        .map(|expr| OrderBy {
+            expr: expr.clone(),
+            descending: false,
+        })
+        .collect()
+}
+
+/// The rows of a hash join, or of a filter of one, sorted by `order_by` by
+/// merging its inputs sorted by the keys instead
+///
+/// The keys are reordered so the ones `order_by` names come first.
+fn merge_sorted(plan: &LogicalPlan, order_by: &[OrderBy]) -> Option<LogicalPlan> {
+    let (left, right, on) = match plan {
+        LogicalPlan::Filter { input, predicate } => {
+            return Some(LogicalPlan::Filter {
+                input: Box::new(merge_sorted(input, order_by)?),
+                predicate: predicate.clone(),
+            })
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            strategy: JoinStrategy::Hash,
+        } => (left, right, on),
+        _ => return None,
+    };
+
+    let schema = plan.schema();
+    let same = |a: &Expression, b: &Expression| match (a, b) {
+        (Expression::Column(a), Expression::Column(b)) => {
+            matches!((schema.resolve(a), schema.resolve(b)), (Ok(a), Ok(b)) if a == b)
+        }
+        (a, b) => a == b,
+    };
+
+    // both sides of a key have the same value in the rows of the join
+    let (mut keys, rest) = join_keys(on, &left.schema(), &right.schema());
+    for (position, order) in order_by.iter().enumerate() {
+        let found = keys[position..]
+            .iter()
+            .position(|(l, r)| same(&order.expr, l) || same(&order.expr, r));
+        match found {
+            Some(found) if !order.descending => keys.swap(position, position + found),
+            _ => return None,
+        }
+    }
+
+    let sort = |input: &LogicalPlan, order_by: Vec<OrderBy>| LogicalPlan::Sort {
+        input: Box::new(input.clone()),
+        order_by,
+    };
+    let conjuncts = keys
+        .iter()
+        .map(|(l, r)| binary(l.clone(), BinaryOperator::Eq, r.clone()))
+        .chain(rest)
+        .collect();
+    Some(LogicalPlan::Join {
+        left: Box::new(sort(left, ascending(keys.iter().map(|(l, _)| l)))),
+        right: Box::new(sort(right, ascending(keys.iter().map(|(_, r)| r)))),
+        on: conjunction(conjuncts),
+        strategy: JoinStrategy::Merge,
+    })
+}
+
+/// Only read the columns of the tables the plan uses
+///
+/// `required` are the names the operators above refer to the columns of
+/// `plan` with.
+fn prune_columns(plan: LogicalPlan, mut required: Vec<String>) -> LogicalPlan {
+    let columns_of = |exprs: &mut dyn Iterator<Item = &Expression>| -> Vec<String> {
+        exprs.flat_map(|expr| expr.columns()).cloned().collect()
+    };
+
+    match plan {
+        LogicalPlan::Single => plan,
+        LogicalPlan::Scan {
+            table,
+            alias,
+            columns,
+            index,
+        } => {
+            let schema = LogicalPlan::Scan {
+                table: table.clone(),
+                alias: alias.clone(),
+                columns: columns.clone(),
+                index: None,
+            }
+            .schema();
+            let used: Vec<usize> = required
+                .iter()
+                .filter_map(|name| schema.resolve(name).ok())
+                .collect();
+            LogicalPlan::Scan {
+                table,
+                alias,
+                columns: columns
+                    .into_iter()
+                    .enumerate()
+                    .filter(|(position, _)| used.contains(position))
+                    .map(|(_, col)| col)
+                    .collect(),
+                index,
+            }
+        }
+        LogicalPlan::Filter { input, predicate } => {
+            required.extend(columns_of(&mut std::iter::once(&predicate)));
+            LogicalPlan::Filter {
+                input: Box::new(prune_columns(*input, required)),
+                predicate,
+            }
+        }
+        LogicalPlan::Project { input, exprs } => {
+            let required = columns_of(&mut exprs.iter().map(|(expr, _)| expr));
+            LogicalPlan::Project {
+                input: Box::new(prune_columns(*input, required)),
+                exprs,
+            }
+        }
+        LogicalPlan::Join {
+            left,
+            right,
+            on,
+            strategy,
+        } => {
+            required.extend(columns_of(&mut std::iter::once(&on)));
+            // each side keeps the columns the names refer to in it
+            LogicalPlan::Join {
+                left: Box::new(prune_columns(*left, required.clone())),
+                right: Box::new(prune_columns(*right, required)),
+                on,
+                strategy,
+            }
+        }
+        LogicalPlan::Aggregate {
+            input,
+            group_by,
+            aggregates,
+        } => {
+            let required = columns_of(&mut group_by.iter().chain(&aggregates).map(|(e, _)| e));
+            LogicalPlan::Aggregate {
+                input: Box::new(prune_columns(*input, required)),
+                group_by,
+                aggregates,
+            }
+        }
+        LogicalPlan::Sort { input, order_by } => {
+            required.extend(columns_of(&mut order_by.iter().map(|order| &order.expr)));
+            LogicalPlan::Sort {
+                input: Box::new(prune_columns(*input, required)),
+                order_by,
+            }
+        }
+        LogicalPlan::Window { input, windows } => {
+            required.extend(columns_of(&mut windows.iter().map(|(expr, _)| expr)));
+            LogicalPlan::Window {
+                input: Box::new(prune_columns(*input, required)),
+                windows,
+            }
+        }
+        LogicalPlan::Distinct { input, on } => {
+            // whole rows are compared without `on`
+            match on.is_empty() {
+                true => required.extend(input.schema().columns.iter().map(|c| c.to_string())),
+                false => required.extend(columns_of(&mut on.iter())),
+            }
+            LogicalPlan::Distinct {
+                input: Box::new(prune_columns(*input, required)),
+                on,
+            }
+        }
+        LogicalPlan::Limit { .. } => {
+            plan.map_inputs(|input| prune_columns(input, required.clone()))
+        }
+        // the rows of the inputs are read by position, so all their columns
+        // are kept
+        LogicalPlan::Recursive { .. } | LogicalPlan::SetOperation { .. } => {
+            plan.map_inputs(|input| prune_columns(input, vec![]))
+        }
+        LogicalPlan::Apply {
+            input,
+            subquery,
+            kind,
+            outer,
+            column,
+        } => {
+            // the subquery reads the outer columns from the rows it runs for
+            required.extend(outer.iter().cloned());
+            if let SubqueryKind::In { expr, .. } = &kind {
+                required.extend(columns_of(&mut std::iter::once(expr)));
+            }
+            LogicalPlan::Apply {
+                input: Box::new(prune_columns(*input, required)),
+                subquery: Box::new(prune_columns(*subquery, vec![])),
+                kind,
+                outer,
+                column,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cost::tests::TestCatalog, planner::plan_for_test};
+
+    /// The plan of a query before and after optimizing it
+    fn plans(query: &str) -> (String, String) {
+        let plan = plan_for_test(query).unwrap();
+        (
+            plan.to_string(),
+            optimize(plan, &TestCatalog::default()).to_string(),
+        )
+    }
+
+    #[test]
+    fn test_predicate_pushdown() {
+        let (before, after) = plans(
+            "SELECT bar.id FROM foo JOIN bar ON foo.id = bar.foo_id AND bar.id < 10 \
+            WHERE foo.id > 1 AND (foo.name = 'a' OR bar.id = 2);",
+        );
+        assert_eq!(
+            before,
+            "Project: bar.id
+  Filter: ((foo.id > 1) AND ((foo.name = 'a') OR (bar.id = 2)))
+    Nested loop join: ((foo.id = bar.foo_id) AND (bar.id < 10))
+      Scan: foo (id, name)
+      Scan: bar (id, foo_id)
+"
+        );
+        assert_eq!(
+            after,
+            "Project: bar.id
+  Hash join: (((foo.name = 'a') OR (bar.id = 2)) AND (foo.id = bar.foo_id))
+    Filter: (foo.id > 1)
+      Scan: foo (id, name)
+    Filter: (bar.id < 10)
+      Scan: bar (id, foo_id)
+"
+        );
+    }
+
+    #[test]
+    fn test_subquery_pushdown() {
+        let (_, after) = plans(
+            "SELECT name FROM foo WHERE id IN (SELECT foo_id FROM bar WHERE id > 2) AND id < 5;",
+        );
+        // only the rows left after the other conjuncts run the subquery
+        assert_eq!(
+            after,
+            "Project: name
+  Filter: $1
+    Apply: $1 = id IN
+      Filter: (id < 5)
+        Scan: foo (id, name)
+      Project: foo_id
+        Filter: (id > 2)
+          Scan: bar (id, foo_id)
+"
+        );
+    }
+
+    #[test]
+    fn test_projection_pruning() {
+        let (_, after) = plans(
+            "SELECT COUNT(*) FROM foo JOIN bar ON foo.id = bar.foo_id GROUP BY bar.id \
+            ORDER BY COUNT(*);",
+        );
+        assert_eq!(
+            after,
+            "Project: COUNT(*)
+  Sort: COUNT(*)
+    Aggregate: group by bar.id; COUNT(*)
+      Hash join: (foo.id = bar.foo_id)
+        Scan: foo (id)
+        Scan: bar (id, foo_id)
+"
+        );
+
+        let (_, after) = plans("SELECT COUNT(*) FROM foo;");
+        assert!(after.ends_with("Scan: foo ()\n"), "{after}");
+    }
+
+    #[test]
+    fn test_join_order() {
+        let catalog = TestCatalog::default()
+            .analyzed("foo", 1000, &[("id", 1000)])
+            .analyzed("bar", 100, &[("id", 100), ("foo_id", 10)])
+            .indexed("bar", "bar_id", &["id"]);
+        let plan = plan_for_test(
+            "SELECT foo.name FROM foo JOIN foo AS f ON foo.id = f.id \
+            JOIN bar ON bar.foo_id = f.id WHERE bar.id = 1;",
+        )
+        .unwrap();
+
+        // the single row of bar is joined first, hashed in a small table
+        assert_eq!(
+            optimize(plan, &catalog).to_string(),
+            "Project: foo.name
+  Hash join: (foo.id = f.id)
+    Hash join: (bar.foo_id = f.id)
+      Scan: foo AS f (id)
+      Filter: (bar.id = 1)
+        Scan: bar (id, foo_id) using bar_id
+    Scan: foo (id, name)
+"
+        );
+    }
+
+    #[test]
+    fn test_join_strategies() {
+        // bar joined with itself on foo_id pairs most of its rows
+        let catalog = TestCatalog::default().analyzed("bar", 100, &[("id", 100), ("foo_id", 2)]);
+        let optimized = |query| optimize(plan_for_test(query).unwrap(), &catalog).to_string();
+
+        assert_eq!(
+            optimized("SELECT a.id FROM bar AS a JOIN bar AS b ON a.id < b.id;"),
+            "Project: a.id
+  Nested loop join: (a.id < b.id)
+    Scan: bar AS a (id)
+    Scan: bar AS b (id)
+"
+        );
+
+        // sorting the few rows of each side is cheaper than sorting the join
+        assert_eq!(
+            optimized(
+                "SELECT a.id FROM bar AS a JOIN bar AS b ON a.id > b.id AND b.foo_id = a.foo_id \
+                ORDER BY b.foo_id;"
+            ),
+            "Project: a.id
+  Merge join: ((a.foo_id = b.foo_id) AND (a.id > b.id))
+    Sort: a.foo_id
+      Scan: bar AS a (id, foo_id)
+    Sort: b.foo_id
+      Scan: bar AS b (id, foo_id)
+"
+        );
+        assert_eq!(
+            optimized(
+                "SELECT a.id FROM bar AS a JOIN bar AS b ON a.foo_id = b.foo_id ORDER BY a.id;"
+            ),
+            "Project: a.id
+  Sort: a.id
+    Hash join: (a.foo_id = b.foo_id)
+      Scan: bar AS a (id, foo_id)
+      Scan: bar AS b (foo_id)
+"
+        );
+    }
+
+    #[test]
+    fn test_constant_folding() {
+        let (before, after) = plans("SELECT id FROM foo WHERE 1 < 2 AND (id = 3 OR 'a' = 'b');");
+        assert_eq!(
+            before,
+            "Project: id
+  Filter: ((1 < 2) AND ((id = 3) OR ('a' = 'b')))
+    Scan: foo (id, name)
+"
+        );
+        assert_eq!(
+            after,
+            "Project: id
+  Filter: (id = 3)
+    Scan: foo (id)
+"
+        );
+
+        let (_, after) = plans("SELECT id FROM foo WHERE 1 IN (2, 3) OR NOT 1 = 1;");
+        assert!(after.contains("Filter: false"), "{after}");
+        let (_, after) = plans("SELECT id FROM foo WHERE 1 = 1;");
+        assert!(!after.contains("Filter"), "{after}");
+        // errors are left for the query to report
+        let (_, after) = plans("SELECT id FROM foo WHERE 1 = 'a';");
+        assert!(after.contains("Filter: (1 = 'a')"), "{after}");
+    }
+
+    #[test]
+    fn test_redundant_sorts() {
+        let sort = |input, columns: &[&str]| LogicalPlan::Sort {
+            input: Box::new(input),
+            order_by: columns
+                .iter()
+                .map(|column| OrderBy {
+                    expr: Expression::Column(column.to_string()),
+                    descending: false,
+                })
+                .collect(),
+        };
+        let scan = || match plan_for_test("SELECT id FROM foo;").unwrap() {
+            LogicalPlan::Project { input, .. } => *input,
+            plan => panic!("unexpected plan {plan}"),
+        };
+
+        // sorting again replaces the first order
+        let plan = sort(sort(scan(), &["name"]), &["id"]);
+        assert_eq!(
+            remove_redundant_sorts(plan).to_string(),
+            "Sort: id
+  Scan: foo (id, name)
+"
+        );
+
+        // the rows are already in order
+        let plan = sort(
+            LogicalPlan::Filter {
+                input: Box::new(sort(scan(), &["id", "name"])),
+                predicate: literal(false),
+            },
+            &["id"],
+        );
+        assert_eq!(
+            remove_redundant_sorts(plan).to_string(),
+            "Filter: false
+  Sort: id, name
+    Scan: foo (id, name)
+"
+        );
+
+        // groups don't keep the order of their rows
+        let plan = LogicalPlan::Aggregate {
+            input: Box::new(sort(scan(), &["id"])),
+            group_by: vec![],
+            aggregates: vec![],
+        };
+        assert_eq!(
+            remove_redundant_sorts(plan).to_string(),
+            "Aggregate: \n  Scan: foo (id, name)\n"
+        );
+    }
+}
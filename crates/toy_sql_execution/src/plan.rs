@@ -0,0 +1,787 @@
+use std::fmt;
+
+use derive_more::Display;
+
+use toy_sql_parser::{
+    commands::{OrderBy, SetOperator},
+    expression::{AggregateFunction, BinaryOperator, Expression, WindowFunction},
+    value::Value,
+    Column, SqlTypeInfo,
+};
+
+use crate::{error::QueryExecutionError, eval::ColumnValues, table::ColumnInfo};
+
+/// A column of the rows a plan produces
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PlanColumn {
+    /// The table (or its alias) the column comes from, if any
+    pub table: Option<String>,
+    pub name: String,
+    pub type_info: SqlTypeInfo,
+}
+
+impl fmt::Display for PlanColumn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.table {
+            Some(table) => write!(f, "{table}.{}", self.name),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// The columns of the rows a plan produces, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct Schema {
+    pub columns: Vec<PlanColumn>,
+}
+
+impl Schema {
+    /// Position of the column an expression refers to by name, `<column>` or
+    /// `<table>.<column>`
+    pub fn resolve(&self, name: &str) -> Result<usize, QueryExecutionError> {
+        let qualified = name.split_once('.');
+        let mut found = self.columns.iter().enumerate().filter(|(_, col)| {
+            col.name == name
+                || matches!(qualified, Some((table, column))
+                    if col.table.as_deref() == Some(table) && col.name == column)
+        });
+
+        match (found.next(), found.next()) {
+            (Some((position, _)), None) => Ok(position),
+            (Some(_), Some(_)) => Err(QueryExecutionError::AmbiguousColumn(name.to_owned())),
+            (None, _) => Err(QueryExecutionError::ColumnDoesNotExist(name.to_owned())),
+        }
+    }
+
+    /// Check every column an expression refers to exists
+    pub fn check(&self, expr: &Expression) -> Result<(), QueryExecutionError> {
+        for column in expr.columns() {
+            self.resolve(column)?;
+        }
+        Ok(())
+    }
+
+    /// The type of the values of an expression
+    pub fn type_of(&self, expr: &Expression) -> Result<SqlTypeInfo, QueryExecutionError> {
+        type_of(
+            expr,
+            &|name| Ok(self.columns[self.resolve(name)?].type_info),
+        )
+    }
+
+    /// The column info of the rows handed to the caller
+    ///
+    /// Columns sharing their name with another one are named with their
+    /// table too, like `a.id` and `b.id` in a self join.
+    pub fn column_info(&self) -> ColumnInfo {
+        let shared = |col: &PlanColumn| {
+            self.columns
+                .iter()
+                .filter(|other| other.name == col.name)
+                .count()
+                > 1
+        };
+        self.columns
+            .iter()
+            .map(|col| Column {
+                name: match &col.table {
+                    Some(table) if shared(col) => format!("{table}.{}", col.name),
+                    _ => col.name.clone(),
+                },
+                type_info: col.type_info,
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    /// The columns of two schemas side by side, as a join produces them
+    pub fn join(&self, other: &Schema) -> Schema {
+        Schema {
+            columns: self.columns.iter().chain(&other.columns).cloned().collect(),
+        }
+    }
+}
+
+/// The type of the values of an expression, given the types of its columns
+pub(crate) fn type_of<F>(expr: &Expression, column: &F) -> Result<SqlTypeInfo, QueryExecutionError>
+where
+    F: Fn(&str) -> Result<SqlTypeInfo, QueryExecutionError>,
+{
+    match expr {
+        Expression::Column(name) => column(name),
+        Expression::Literal(Value::String(_)) => Ok(SqlTypeInfo::String),
+        Expression::Literal(Value::Bool(_)) => Ok(SqlTypeInfo::Bool),
+        Expression::Literal(_) => Ok(SqlTypeInfo::Int),
+        Expression::Aggregate {
+            function: AggregateFunction::Min | AggregateFunction::Max,
+            arg: Some(arg),
+            ..
+        } => type_of(arg, column),
+        Expression::Aggregate { .. } => Ok(SqlTypeInfo::Int),
+        Expression::Window {
+            function:
+                WindowFunction::Lag
+                | WindowFunction::Lead
+                | WindowFunction::Aggregate(AggregateFunction::Min | AggregateFunction::Max),
+            args,
+            ..
+        } => match args.first() {
+            Some(arg) => type_of(arg, column),
+            None => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+        },
+        Expression::Window { .. } => Ok(SqlTypeInfo::Int),
+        Expression::BinaryOp {
+            op: BinaryOperator::Concat,
+            ..
+        } => Ok(SqlTypeInfo::String),
+        Expression::BinaryOp { left, op, right }
+            if !op.is_comparison() && !matches!(op, BinaryOperator::And | BinaryOperator::Or) =>
+        {
+            match (type_of(left, column)?, type_of(right, column)?) {
+                (SqlTypeInfo::Int, SqlTypeInfo::Int) => Ok(SqlTypeInfo::Int),
+                (left, right) => Err(QueryExecutionError::OperandTypes(*op, left, right)),
+            }
+        }
+        Expression::BinaryOp { .. }
+        | Expression::Not(_)
+        | Expression::InList { .. }
+        | Expression::Like { .. }
+        | Expression::Between { .. } => Ok(SqlTypeInfo::Bool),
+        Expression::Case {
+            branches,
+            otherwise,
+            ..
+        } => {
+            // `NULL` results fit any type
+            let mut result = None;
+            for expr in branches
+                .iter()
+                .map(|(_, result)| result)
+                .chain(otherwise.as_deref())
+                .filter(|expr| **expr != Expression::Literal(Value::Null))
+            {
+                match (result, type_of(expr, column)?) {
+                    (Some(first), other) if first != other => {
+                        return Err(QueryExecutionError::CaseTypes(first, other))
+                    }
+                    (_, other) => result = Some(other),
+                }
+            }
+            Ok(result.unwrap_or(SqlTypeInfo::Int))
+        }
+        Expression::Cast { type_info, .. } => Ok(*type_info),
+        Expression::Negate(operand) => match type_of(operand, column)? {
+            SqlTypeInfo::Int => Ok(SqlTypeInfo::Int),
+            _ => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+        },
+        expr => Err(QueryExecutionError::InvalidExpression(expr.to_string())),
+    }
+}
+
+/// Refer to the columns of the table `qualifier` names by their bare name
+pub(crate) fn unqualify(expr: &Expression, qualifier: &str) -> Expression {
+    expr.replace(&|expr| match expr {
+        Expression::Column(name) => name
+            .strip_prefix(qualifier)
+            .and_then(|name| name.strip_prefix('.'))
+            .map(|name| Expression::Column(name.to_owned())),
+        _ => None,
+    })
+}
+
+/// The values of a row of a plan, with the schema to find them by name
+pub(crate) struct SchemaRow<'a> {
+    pub schema: &'a Schema,
+    pub values: &'a [Value],
+}
+
+impl<'a> ColumnValues for SchemaRow<'a> {
+    fn column(&self, name: &str) -> Result<Value, QueryExecutionError> {
+        Ok(self.values[self.schema.resolve(name)?].clone())
+    }
+}
+
+/// How a join finds the pairs of rows satisfying its condition
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Display)]
+pub(crate) enum JoinStrategy {
+    /// Compare every row of the left input to every row of the right one
+    #[display(fmt = "Nested loop join")]
+    NestedLoop,
+    /// Find the rows of the right input with the same keys as each row of the
+    /// left one in a hash table
+    #[display(fmt = "Hash join")]
+    Hash,
+    /// Walk both inputs together, both sorted by their keys
+    #[display(fmt = "Merge join")]
+    Merge,
+}
+
+/// The keys of a join: the `<left> = <right>` conjuncts of its condition
+/// comparing values of the same type from each side, then the other
+/// conjuncts
+pub(crate) fn join_keys(
+    on: &Expression,
+    left: &Schema,
+    right: &Schema,
+) -> (Vec<(Expression, Expression)>, Vec<Expression>) {
+    let side = |expr: &Expression, schema: &Schema| {
+        !expr.columns().is_empty() && schema.check(expr).is_ok()
+    };
+
+    let (mut keys, mut rest) = (vec![], vec![]);
+    for conjunct in on.conjuncts() {
+        let key = match conjunct {
+            Expression::BinaryOp {
+                left: l,
+                op: BinaryOperator::Eq,
+                right: r,
+            } => match (l.as_ref(), r.as_ref()) {
+                (l, r) if side(l, left) && side(r, right) => Some((l.clone(), r.clone())),
+                (l, r) if side(r, left) && side(l, right) => Some((r.clone(), l.clone())),
+                _ => None,
+            },
+            _ => None,
+        };
+        // comparing values of different types fails, which the keys of a
+        // hash table wouldn't
+        match key {
+            Some((l, r)) if matches!((left.type_of(&l), right.type_of(&r)), (Ok(a), Ok(b)) if a == b) => {
+                keys.push((l, r))
+            }
+            _ => rest.push(conjunct.clone()),
+        }
+    }
+    (keys, rest)
+}
+
+/// What a query computes, as a tree of relational operators
+///
+/// Each node produces rows from the rows of its inputs. The planner builds
+/// it from a statement, then it is executed.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum LogicalPlan {
+    /// All the rows of a table, with its columns qualified by `alias`
+    Scan {
+        table: String,
+        alias: String,
+        columns: Vec<Column>,
+        /// The index to find the rows the filter above keeps with, if it's
+        /// cheaper than reading all of them
+        index: Option<String>,
+    },
+    /// A single row without columns, read by a select without `FROM`
+    Single,
+    /// The rows of the input satisfying the predicate
+    Filter {
+        input: Box<LogicalPlan>,
+        predicate: Expression,
+    },
+    /// An expression evaluated for each row of the input, per output column
+    Project {
+        input: Box<LogicalPlan>,
+        exprs: Vec<(Expression, PlanColumn)>,
+    },
+    /// The pairs of rows of both inputs satisfying `on`
+    Join {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        on: Expression,
+        strategy: JoinStrategy,
+    },
+    /// One row per group of input rows with equal `group_by` values: the
+    /// group's values, then its aggregates
+    ///
+    /// Without `group_by` all the rows are one group, even if there are none.
+    Aggregate {
+        input: Box<LogicalPlan>,
+        group_by: Vec<(Expression, PlanColumn)>,
+        aggregates: Vec<(Expression, PlanColumn)>,
+    },
+    /// The rows of the input, with the value of a window function computed
+    /// for each, per added column
+    Window {
+        input: Box<LogicalPlan>,
+        windows: Vec<(Expression, PlanColumn)>,
+    },
+    Sort {
+        input: Box<LogicalPlan>,
+        order_by: Vec<OrderBy>,
+    },
+    /// The first row of the input of each set of rows with equal values of
+    /// `on`, or of all their columns without any
+    Distinct {
+        input: Box<LogicalPlan>,
+        on: Vec<Expression>,
+    },
+    /// At most `limit` rows of the input, after skipping `offset`
+    Limit {
+        input: Box<LogicalPlan>,
+        limit: Option<usize>,
+        offset: usize,
+    },
+    /// The rows of the input, with a column computed from the rows of a
+    /// subquery
+    ///
+    /// The subquery can refer to the `outer` columns of the input, it runs
+    /// again for each of their values.
+    Apply {
+        input: Box<LogicalPlan>,
+        subquery: Box<LogicalPlan>,
+        kind: SubqueryKind,
+        outer: Vec<String>,
+        column: PlanColumn,
+    },
+    /// The rows of a recursive query, with its columns qualified by `alias`:
+    /// the rows of `base`, then the new rows `step` finds reading the ones
+    /// found by the previous iteration as `table`, until there are none
+    Recursive {
+        table: String,
+        alias: String,
+        columns: Vec<Column>,
+        base: Box<LogicalPlan>,
+        step: Box<LogicalPlan>,
+        /// `UNION ALL`, rows already found are kept
+        all: bool,
+    },
+    /// The rows of both inputs combined, their columns matched by position
+    SetOperation {
+        left: Box<LogicalPlan>,
+        right: Box<LogicalPlan>,
+        operator: SetOperator,
+    },
+}
+
+/// What the column an [`LogicalPlan::Apply`] adds holds
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum SubqueryKind {
+    /// The value of the single row of the subquery, `NULL` without rows
+    Scalar,
+    /// Whether the subquery returns any row
+    Exists,
+    /// Whether the value of `expr` is one of the values of the subquery
+    In { expr: Expression, negated: bool },
+}
+
+impl fmt::Display for SubqueryKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubqueryKind::Scalar => write!(f, "value"),
+            SubqueryKind::Exists => write!(f, "EXISTS"),
+            SubqueryKind::In {
+                expr,
+                negated: false,
+            } => write!(f, "{expr} IN"),
+            SubqueryKind::In {
+                expr,
+                negated: true,
+            } => write!(f, "{expr} NOT IN"),
+        }
+    }
+}
+
+impl LogicalPlan {
+    /// The columns of the rows the plan produces
+    pub fn schema(&self) -> Schema {
+        match self {
+            LogicalPlan::Single => Schema { columns: vec![] },
+            LogicalPlan::Scan { alias, columns, .. }
+            | LogicalPlan::Recursive { alias, columns, .. } => Schema {
+                columns: columns
+                    .iter()
+                    .map(|col| PlanColumn {
+                        table: Some(alias.clone()),
+                        name: col.name.clone(),
+                        type_info: col.type_info,
+                    })
+                    .collect(),
+            },
+            LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Distinct { input, .. }
+            | LogicalPlan::Limit { input, .. } => input.schema(),
+            LogicalPlan::Project { exprs, .. } => Schema {
+                columns: exprs.iter().map(|(_, col)| col.clone()).collect(),
+            },
+            LogicalPlan::Join { left, right, .. } => left.schema().join(&right.schema()),
+            // named after the columns of the left input, not qualified by
+            // any table
+            LogicalPlan::SetOperation { left, .. } => Schema {
+                columns: left
+                    .schema()
+                    .columns
+                    .into_iter()
+                    .map(|col| PlanColumn { table: None, ..col })
+                    .collect(),
+            },
+            LogicalPlan::Apply { input, column, .. } => {
+                let mut schema = input.schema();
+                schema.columns.push(column.clone());
+                schema
+            }
+            LogicalPlan::Window { input, windows } => {
+                let mut schema = input.schema();
+                schema
+                    .columns
+                    .extend(windows.iter().map(|(_, col)| col.clone()));
+                schema
+            }
+            LogicalPlan::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } => Schema {
+                columns: group_by
+                    .iter()
+                    .chain(aggregates)
+                    .map(|(_, col)| col.clone())
+                    .collect(),
+            },
+        }
+    }
+
+    /// The plans this one reads the rows of
+    pub fn inputs(&self) -> Vec<&LogicalPlan> {
+        match self {
+            LogicalPlan::Scan { .. } | LogicalPlan::Single => vec![],
+            LogicalPlan::Filter { input, .. }
+            | LogicalPlan::Project { input, .. }
+            | LogicalPlan::Aggregate { input, .. }
+            | LogicalPlan::Window { input, .. }
+            | LogicalPlan::Sort { input, .. }
+            | LogicalPlan::Distinct { input, .. }
+            | LogicalPlan::Limit { input, .. } => vec![input],
+            LogicalPlan::Join { left, right, .. }
+            | LogicalPlan::SetOperation { left, right, .. } => vec![left, right],
+            LogicalPlan::Apply {
+                input, subquery, ..
+            } => vec![input, subquery],
+            LogicalPlan::Recursive { base, step, .. } => vec![base, step],
+        }
+    }
+
+    /// Rebuild the node with `f` applied to each of its inputs
+    pub fn map_inputs(self, mut f: impl FnMut(LogicalPlan) -> LogicalPlan) -> LogicalPlan {
+        let mut f = |input: Box<LogicalPlan>| Box::new(f(*input));
+        match self {
+            LogicalPlan::Scan { .. } | LogicalPlan::Single => self,
+            LogicalPlan::Filter { input, predicate } => LogicalPlan::Filter {
+                input: f(input),
+                predicate,
+            },
+            LogicalPlan::Project { input, exprs } => LogicalPlan::Project {
+                input: f(input),
+                exprs,
+            },
+            LogicalPlan::Join {
+                left,
+                right,
+                on,
+                strategy,
+            } => LogicalPlan::Join {
+                left: f(left),
+                right: f(right),
+                on,
+                strategy,
+            },
+            LogicalPlan::Aggregate {
+                input,
+                group_by,
+                aggregates,
+            } => LogicalPlan::Aggregate {
+                input: f(input),
+                group_by,
+                aggregates,
+            },
+            LogicalPlan::Window { input, windows } => LogicalPlan::Window {
+                input: f(input),
+                windows,
+            },
+            LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort {
+                input: f(input),
+                order_by,
+            },
+            LogicalPlan::Distinct { input, on } => LogicalPlan::Distinct {
+                input: f(input),
+                on,
+            },
+            LogicalPlan::Limit {
+                input,
+                limit,
+                offset,
+            } => LogicalPlan::Limit {
+                input: f(input),
+                limit,
+                offset,
+            },
+            LogicalPlan::Apply {
+                input,
+                subquery,
+                kind,
+                outer,
+                column,
+            } => LogicalPlan::Apply {
+                input: f(input),
+                subquery: f(subquery),
+                kind,
+                outer,
+                column,
+            },
+            LogicalPlan::Recursive {
+                table,
+                alias,
+                columns,
+                base,
+                step,
+                all,
+            } => LogicalPlan::Recursive {
+                table,
+                alias,
+                columns,
+                base: f(base),
+                step: f(step),
+                all,
+            },
+            LogicalPlan::SetOperation {
+                left,
+                right,
+                operator,
+            } => LogicalPlan::SetOperation {
+                left: f(left),
+                right: f(right),
+                operator,
+            },
+        }
+    }
+
+    /// Number of nodes of the plan, its own included
+    pub fn nodes(&self) -> usize {
+        1 + self
+            .inputs()
+            .into_iter()
+            .map(LogicalPlan::nodes)
+            .sum::<usize>()
+    }
+
+    /// Replace the columns named in `values` by their value, in the
+    /// expressions of this plan and of the subqueries referring to them
+    pub fn bind(self, values: &[(String, Value)]) -> LogicalPlan {
+        if values.is_empty() {
+            return self;
+        }
+        let bind = |expr: &Expression| {
+            expr.replace(&|expr| match expr {
+                Expression::Column(name) => values
+                    .iter()
+                    .find(|(bound, _)| bound == name)
+                    .map(|(_, value)| Expression::Literal(value.clone())),
+                _ => None,
+            })
+        };
+        let bind_all = |exprs: Vec<(Expression, PlanColumn)>| {
+            exprs
+                .into_iter()
+                .map(|(expr, col)| (bind(&expr), col))
+                .collect()
+        };
+
+        match self {
+            LogicalPlan::Apply {
+                input,
+                subquery,
+                kind,
+                mut outer,
+                column,
+            } => {
+                let (bound, unbound) = outer
+                    .drain(..)
+                    .partition::<Vec<_>, _>(|name| values.iter().any(|(bound, _)| bound == name));
+                let inner: Vec<_> = values
+                    .iter()
+                    .filter(|(name, _)| bound.contains(name))
+                    .cloned()
+                    .collect();
+                LogicalPlan::Apply {
+                    input: Box::new(input.bind(values)),
+                    subquery: Box::new(subquery.bind(&inner)),
+                    kind: match kind {
+                        SubqueryKind::In { expr, negated } => SubqueryKind::In {
+                            expr: bind(&expr),
+                            negated,
+                        },
+                        kind => kind,
+                    },
+                    outer: unbound,
+                    column,
+                }
+            }
+            plan => match plan.map_inputs(|input| input.bind(values)) {
+                LogicalPlan::Filter { input, predicate } => LogicalPlan::Filter {
+                    input,
+                    predicate: bind(&predicate),
+                },
+                LogicalPlan::Project { input, exprs } => LogicalPlan::Project {
+                    input,
+                    exprs: bind_all(exprs),
+                },
+                LogicalPlan::Join {
+                    left,
+                    right,
+                    on,
+                    strategy,
+                } => LogicalPlan::Join {
+                    left,
+                    right,
+                    on: bind(&on),
+                    strategy,
+                },
+                LogicalPlan::Aggregate {
+                    input,
+                    group_by,
+                    aggregates,
+                } => LogicalPlan::Aggregate {
+                    input,
+                    group_by: bind_all(group_by),
+                    aggregates: bind_all(aggregates),
+                },
+                LogicalPlan::Sort { input, order_by } => LogicalPlan::Sort {
+                    input,
+                    order_by: order_by
+                        .into_iter()
+                        .map(|order| OrderBy {
+                            expr: bind(&order.expr),
+                            descending: order.descending,
+                        })
+                        .collect(),
+                },
+                LogicalPlan::Distinct { input, on } => LogicalPlan::Distinct {
+                    input,
+                    on: on.iter().map(bind).collect(),
+                },
+                LogicalPlan::Window { input, windows } => LogicalPlan::Window {
+                    input,
+                    windows: bind_all(windows),
+                },
+                plan => plan,
+            },
+        }
+    }
+
+    /// Describe this node, without its inputs
+    pub fn describe(&self) -> String {
+        struct Node<'a>(&'a LogicalPlan);
+
+        impl fmt::Display for Node<'_> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fmt_node(f)
+            }
+        }
+
+        Node(self).to_string()
+    }
+
+    fn fmt_node(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list<T: fmt::Display>(items: impl Iterator<Item = T>) -> String {
+            items
+                .map(|item| item.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        match self {
+            LogicalPlan::Scan {
+                table,
+                alias,
+                columns,
+                index,
+            } => {
+                write!(f, "Scan: {table}")?;
+                if table != alias {
+                    write!(f, " AS {alias}")?;
+                }
+                write!(f, " ({})", list(columns.iter().map(|col| &col.name)))?;
+                match index {
+                    Some(index) => write!(f, " using {index}"),
+                    None => Ok(()),
+                }
+            }
+            LogicalPlan::Single => write!(f, "Single row"),
+            LogicalPlan::Filter { predicate, .. } => write!(f, "Filter: {predicate}"),
+            LogicalPlan::Project { exprs, .. } => {
+                let exprs = exprs.iter().map(|(expr, col)| match expr {
+                    Expression::Column(name) if *name == col.to_string() || *name == col.name => {
+                        name.clone()
+                    }
+                    expr => format!("{expr} AS {}", col.name),
+                });
+                write!(f, "Project: {}", list(exprs))
+            }
+            LogicalPlan::Join { on, strategy, .. } => write!(f, "{strategy}: {on}"),
+            LogicalPlan::SetOperation { operator, .. } => match operator {
+                SetOperator::Union => write!(f, "Union"),
+                SetOperator::UnionAll => write!(f, "Union all"),
+                SetOperator::Intersect => write!(f, "Intersect"),
+                SetOperator::Except => write!(f, "Except"),
+            },
+            LogicalPlan::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } => {
+                write!(f, "Aggregate: ")?;
+                if !group_by.is_empty() {
+                    write!(f, "group by {}; ", list(group_by.iter().map(|(e, _)| e)))?;
+                }
+                write!(f, "{}", list(aggregates.iter().map(|(e, _)| e)))
+            }
+            LogicalPlan::Window { windows, .. } => {
+                write!(f, "Window: {}", list(windows.iter().map(|(e, _)| e)))
+            }
+            LogicalPlan::Sort { order_by, .. } => write!(f, "Sort: {}", list(order_by.iter())),
+            LogicalPlan::Distinct { on, .. } if on.is_empty() => write!(f, "Distinct"),
+            LogicalPlan::Distinct { on, .. } => write!(f, "Distinct on: {}", list(on.iter())),
+            LogicalPlan::Apply { kind, column, .. } => write!(f, "Apply: {} = {kind}", column.name),
+            LogicalPlan::Recursive {
+                table,
+                alias,
+                columns,
+                all,
+                ..
+            } => {
+                let union = if *all { "union all" } else { "union" };
+                write!(f, "Recursive {union}: {table}")?;
+                if table != alias {
+                    write!(f, " AS {alias}")?;
+                }
+                write!(f, " ({})", list(columns.iter().map(|col| &col.name)))
+            }
+            LogicalPlan::Limit { limit, offset, .. } => {
+                write!(f, "Limit: ")?;
+                match limit {
+                    Some(limit) => write!(f, "{limit}")?,
+                    None => write!(f, "all")?,
+                }
+                if *offset > 0 {
+                    write!(f, " offset {offset}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        write!(f, "{:indent$}", "", indent = depth * 2)?;
+        self.fmt_node(f)?;
+        writeln!(f)?;
+        for input in self.inputs() {
+            input.fmt_tree(f, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+/// One line per node, inputs indented below the node reading them
+impl fmt::Display for LogicalPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.fmt_tree(f, 0)
+    }
+}
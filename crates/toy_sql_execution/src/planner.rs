@@ -0,0 +1,1133 @@
+use std::{cell::Cell, mem};
+
+use toy_sql_parser::{
+    commands::{
+        CommonTableExpression, Distinct, OrderBy, SelectItem, SelectStatement, SetOperation,
+        SetOperator, TableRef,
+    },
+    expression::{AggregateFunction, Expression, WindowFunction},
+    Column, SqlTypeInfo,
+};
+
+use crate::{
+    error::QueryExecutionError,
+    optimizer::conjunction,
+    plan::{self, JoinStrategy, LogicalPlan, PlanColumn, Schema, SubqueryKind},
+};
+
+/// Build the logical plan of a select statement, checking the columns it
+/// refers to exist
+///
+/// `columns_of` gives the columns of a table by name. The plan reads the
+/// tables, joins them, filters the rows, groups them, sorts them, then
+/// computes the select list and finally skips and limits the rows.
+pub(crate) fn plan_select(
+    select: SelectStatement,
+    columns_of: impl Fn(&str) -> Result<Vec<Column>, QueryExecutionError>,
+) -> Result<LogicalPlan, QueryExecutionError> {
+    let subqueries = Cell::new(0);
+    Planner {
+        columns_of: &columns_of,
+        outer: vec![],
+        correlated: vec![],
+        subqueries: &subqueries,
+        named: vec![],
+    }
+    .plan(select)
+}
+
+/// A table named by a `WITH` clause
+#[derive(Clone)]
+enum Named {
+    /// Recursive if its `WITH` clause is
+    Query(Box<CommonTableExpression>, bool),
+    /// The rows the last iteration of a recursive query found
+    Working(Vec<Column>),
+}
+
+/// Plans a select, and the subqueries nested in it with planners of their
+/// own
+struct Planner<'a> {
+    columns_of: &'a dyn Fn(&str) -> Result<Vec<Column>, QueryExecutionError>,
+    /// The columns of the queries the select is nested in, the nearest first
+    outer: Vec<Schema>,
+    /// The columns of the outer queries the select refers to
+    correlated: Vec<String>,
+    /// Number of subqueries planned so far, numbering the columns of their
+    /// results
+    subqueries: &'a Cell<usize>,
+    /// The tables named by the `WITH` clauses in scope, the latest last
+    named: Vec<(String, Named)>,
+}
+
+impl Planner<'_> {
+    fn plan(&mut self, mut select: SelectStatement) -> Result<LogicalPlan, QueryExecutionError> {
+        if let Some(with) = select.with.take() {
+            for query in with.queries {
+                let named = Named::Query(Box::new(query.clone()), with.recursive);
+                self.named.push((query.name, named));
+            }
+        }
+        if select.set_operations.is_empty() {
+            return self.plan_select(select);
+        }
+
+        // ORDER BY and LIMIT apply to the combined rows, whose columns are
+        // named as the ones of the first select
+        let operations = mem::take(&mut select.set_operations);
+        let order_by = mem::take(&mut select.order_by);
+        let (limit, offset) = (select.limit.take(), select.offset.take());
+        let first = self.plan_select(select)?;
+        let mut plan = self.combine(first, operations)?;
+        if !order_by.is_empty() {
+            let schema = plan.schema();
+            for order in &order_by {
+                self.check_predicate(&order.expr, &schema)?;
+            }
+            plan = LogicalPlan::Sort {
+                input: Box::new(plan),
+                order_by,
+            };
+        }
+        Ok(limited(plan, limit, offset))
+    }
+
+    /// Combine the rows of a select with the ones of set operations, the
+    /// `INTERSECT`s first then the others from left to right
+    fn combine(
+        &mut self,
+        first: LogicalPlan,
+        operations: Vec<SetOperation>,
+    ) -> Result<LogicalPlan, QueryExecutionError> {
+        let (mut plans, mut operators) = (vec![first], vec![]);
+        for operation in operations {
+            let right = self.plan_select(operation.select)?;
+            match operation.operator {
+                SetOperator::Intersect => {
+                    let left = plans.pop().expect("a select comes first");
+                    plans.push(set_operation(left, SetOperator::Intersect, right)?);
+                }
+                operator => {
+                    plans.push(right);
+                    operators.push(operator);
+                }
+            }
+        }
+
+        let mut plans = plans.into_iter();
+        let first = plans.next().expect("a select comes first");
+        plans
+            .zip(operators)
+            .try_fold(first, |left, (right, operator)| {
+                set_operation(left, operator, right)
+            })
+    }
+
+    /// Plan a select without set operations
+    fn plan_select(&mut self, select: SelectStatement) -> Result<LogicalPlan, QueryExecutionError> {
+        let mut plan = match select.table {
+            Some(table) => self.scan(table)?,
+            None => LogicalPlan::Single,
+        };
+        // the conditions of joins with subqueries filter the joined rows, as
+        // the rows of a subquery are added to the rows of a single input
+        let mut filters = Vec::new();
+        for join in select.joins {
+            let right = self.scan(join.table)?;
+            let (subqueries, on): (Vec<_>, Vec<_>) = join
+                .on
+                .conjuncts()
+                .into_iter()
+                .cloned()
+                .partition(Expression::has_subquery);
+            let on = conjunction(on);
+            filters.extend(subqueries);
+            self.check_predicate(&on, &plan.schema().join(&right.schema()))?;
+            plan = LogicalPlan::Join {
+                left: Box::new(plan),
+                right: Box::new(right),
+                on,
+                strategy: JoinStrategy::NestedLoop,
+            };
+        }
+
+        filters.extend(select.where_clause);
+        if !filters.is_empty() {
+            let predicate = conjunction(filters);
+            let (with_subqueries, found) = self.plan_subqueries(plan, &[&predicate])?;
+            plan = with_subqueries;
+            let predicate = replace_subqueries(&predicate, &found);
+            self.check_predicate(&predicate, &plan.schema())?;
+            plan = LogicalPlan::Filter {
+                input: Box::new(plan),
+                predicate,
+            };
+        }
+
+        // the select list, with `*` expanded to every column
+        let mut items = Vec::new();
+        for field in select.fields {
+            match field {
+                SelectItem::Wildcard => items.extend(
+                    plan.schema()
+                        .columns
+                        .iter()
+                        .map(|col| (Expression::Column(col.to_string()), None)),
+                ),
+                SelectItem::Expr { expr, alias } => items.push((expr, alias)),
+            }
+        }
+
+        // ORDER BY and DISTINCT ON can name the items of the select list by
+        // their alias
+        let aliased = |expr: Expression| match &expr {
+            Expression::Column(name) => items
+                .iter()
+                .find(|(_, alias)| alias.as_ref() == Some(name))
+                .map_or(expr.clone(), |(expr, _)| expr.clone()),
+            _ => expr,
+        };
+        let mut order_by: Vec<OrderBy> = select
+            .order_by
+            .into_iter()
+            .map(|order| OrderBy {
+                expr: aliased(order.expr),
+                descending: order.descending,
+            })
+            .collect();
+        let (distinct, mut distinct_on): (_, Vec<_>) = match select.distinct {
+            Some(Distinct::On(exprs)) => (false, exprs.into_iter().map(aliased).collect()),
+            distinct => (distinct.is_some(), vec![]),
+        };
+        // the rows DISTINCT keeps can't depend on the order they are read in,
+        // so the sort keys must be the keys DISTINCT ON keeps a row of first,
+        // or what plain DISTINCT compares
+        let input = plan.schema();
+        let same = |left: &Expression, right: &Expression| match (left, right) {
+            (Expression::Column(l), Expression::Column(r)) => {
+                l == r || matches!((input.resolve(l), input.resolve(r)), (Ok(l), Ok(r)) if l == r)
+            }
+            _ => left == right,
+        };
+        for order in order_by.iter().take(distinct_on.len()) {
+            if !distinct_on.iter().any(|expr| same(expr, &order.expr)) {
+                return Err(QueryExecutionError::DistinctOnOrder(order.expr.to_string()));
+            }
+        }
+        if distinct {
+            for order in &order_by {
+                if !items.iter().any(|(expr, _)| same(expr, &order.expr)) {
+                    return Err(QueryExecutionError::DistinctOrder(order.expr.to_string()));
+                }
+            }
+        }
+
+        let aggregated = !select.group_by.is_empty()
+            || items.iter().any(|(expr, _)| expr.has_aggregate())
+            || order_by.iter().any(|order| order.expr.has_aggregate())
+            || distinct_on.iter().any(Expression::has_aggregate);
+        if aggregated {
+            let input = plan.schema();
+            let keys: Vec<_> = order_by
+                .iter()
+                .map(|order| &order.expr)
+                .chain(&distinct_on)
+                .collect();
+            plan = self.aggregate(plan, select.group_by, &items, &keys)?;
+
+            // above the aggregate its results are columns
+            let LogicalPlan::Aggregate {
+                group_by,
+                aggregates,
+                ..
+            } = &plan
+            else {
+                unreachable!("just built an aggregate");
+            };
+            let computed = |expr: &Expression| {
+                group_by
+                    .iter()
+                    .chain(aggregates)
+                    .find(|(computed, _)| computed == expr)
+                    .filter(|(computed, _)| !matches!(computed, Expression::Column(_)))
+                    .map(|(_, col)| Expression::Column(col.name.clone()))
+            };
+            for (expr, _) in &mut items {
+                *expr = expr.replace(&computed);
+            }
+            for order in &mut order_by {
+                order.expr = order.expr.replace(&computed);
+            }
+            for expr in &mut distinct_on {
+                *expr = expr.replace(&computed);
+            }
+
+            let output = plan.schema();
+            for expr in items
+                .iter()
+                .map(|(expr, _)| expr)
+                .chain(order_by.iter().map(|o| &o.expr))
+                .chain(&distinct_on)
+            {
+                for column in expr.columns() {
+                    if output.resolve(column).is_err() && input.resolve(column).is_ok() {
+                        return Err(QueryExecutionError::ColumnNotGrouped(column.to_owned()));
+                    }
+                }
+            }
+        }
+
+        // the subqueries of the select list, ORDER BY and DISTINCT ON are
+        // computed for each row, the items keep their text as name
+        let exprs: Vec<_> = items
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(order_by.iter().map(|order| &order.expr))
+            .chain(&distinct_on)
+            .collect();
+        let (with_subqueries, found) = self.plan_subqueries(plan, &exprs)?;
+        plan = with_subqueries;
+        if !found.is_empty() {
+            for (expr, alias) in &mut items {
+                if expr.has_subquery() {
+                    alias.get_or_insert_with(|| expr.to_string());
+                    *expr = replace_subqueries(expr, &found);
+                }
+            }
+            for order in &mut order_by {
+                order.expr = replace_subqueries(&order.expr, &found);
+            }
+            for expr in &mut distinct_on {
+                *expr = replace_subqueries(expr, &found);
+            }
+        }
+
+        // window functions are computed on the grouped rows, each adding a
+        // column named by its text
+        let mut windows = Vec::new();
+        for expr in items
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(order_by.iter().map(|order| &order.expr))
+            .chain(&distinct_on)
+        {
+            collect_windows(expr, &mut windows);
+        }
+        if !windows.is_empty() {
+            let windows = self.window(plan, windows)?;
+            let LogicalPlan::Window { windows: found, .. } = &windows else {
+                unreachable!("just built a window");
+            };
+            let computed = |expr: &Expression| {
+                found
+                    .iter()
+                    .find(|(window, _)| window == expr)
+                    .map(|(_, col)| Expression::Column(col.name.clone()))
+            };
+            for (expr, alias) in &mut items {
+                if expr.has_window() {
+                    alias.get_or_insert_with(|| expr.to_string());
+                    *expr = expr.replace(&computed);
+                }
+            }
+            for order in &mut order_by {
+                order.expr = order.expr.replace(&computed);
+            }
+            for expr in &mut distinct_on {
+                *expr = expr.replace(&computed);
+            }
+            plan = windows;
+        }
+
+        if !order_by.is_empty() {
+            let schema = plan.schema();
+            for order in &order_by {
+                self.check(&order.expr, &schema)?;
+            }
+            plan = LogicalPlan::Sort {
+                input: Box::new(plan),
+                order_by,
+            };
+        }
+        // the first of the sorted rows of each key is kept
+        if !distinct_on.is_empty() {
+            let schema = plan.schema();
+            for expr in &distinct_on {
+                self.check(expr, &schema)?;
+            }
+            plan = LogicalPlan::Distinct {
+                input: Box::new(plan),
+                on: distinct_on,
+            };
+        }
+
+        let schema = plan.schema();
+        let exprs = items
+            .into_iter()
+            .map(|(expr, alias)| {
+                self.check(&expr, &schema)?;
+                let column = match (&expr, alias) {
+                    (expr, Some(alias)) => PlanColumn {
+                        table: None,
+                        name: alias,
+                        type_info: self.type_of(expr, &schema)?,
+                    },
+                    (Expression::Column(name), None) if schema.resolve(name).is_ok() => {
+                        schema.columns[schema.resolve(name)?].clone()
+                    }
+                    (expr, None) => PlanColumn {
+                        table: None,
+                        name: expr.to_string(),
+                        type_info: self.type_of(expr, &schema)?,
+                    },
+                };
+                Ok((expr, column))
+            })
+            .collect::<Result<_, QueryExecutionError>>()?;
+        plan = LogicalPlan::Project {
+            input: Box::new(plan),
+            exprs,
+        };
+        if distinct {
+            plan = LogicalPlan::Distinct {
+                input: Box::new(plan),
+                on: vec![],
+            };
+        }
+
+        Ok(limited(plan, select.limit, select.offset))
+    }
+
+    /// Read a table, or compute the rows of a derived table
+    fn scan(&mut self, table: TableRef) -> Result<LogicalPlan, QueryExecutionError> {
+        let alias = table.qualifier().to_owned();
+        if let Some(select) = table.subquery {
+            // it can't refer to the tables before it, only to the outer queries
+            let mut planner = self.nested(vec![]);
+            let plan = planner.plan(*select)?;
+            self.correlate(planner.correlated);
+            return Ok(qualify(plan, &alias, &[]));
+        }
+
+        match self.named.iter().rposition(|(name, _)| *name == table.name) {
+            Some(position) => self.plan_named(position, alias),
+            None => Ok(LogicalPlan::Scan {
+                columns: (self.columns_of)(&table.name)?,
+                table: table.name,
+                alias,
+                index: None,
+            }),
+        }
+    }
+
+    /// Compute the rows of a table a `WITH` clause names, read as `alias`
+    fn plan_named(
+        &mut self,
+        position: usize,
+        alias: String,
+    ) -> Result<LogicalPlan, QueryExecutionError> {
+        let (table, named) = self.named[position].clone();
+        let (query, recursive) = match named {
+            Named::Query(query, recursive) => (query, recursive),
+            Named::Working(columns) => {
+                return Ok(LogicalPlan::Scan {
+                    table,
+                    alias,
+                    columns,
+                    index: None,
+                })
+            }
+        };
+
+        // the last select of a recursive query reading it is its step
+        let mut select = query.query;
+        let step = match select.set_operations.last() {
+            Some(operation)
+                if recursive
+                    && matches!(
+                        operation.operator,
+                        SetOperator::Union | SetOperator::UnionAll
+                    )
+                    && operation.select.tables().contains(&table.as_str()) =>
+            {
+                select.set_operations.pop()
+            }
+            _ => None,
+        };
+
+        // it can read the tables named before it, and itself once it has a
+        // first iteration
+        let mut planner = self.nested(vec![]);
+        planner.named.truncate(position);
+        let base = planner.plan(select)?;
+        let mut columns: Vec<_> = base
+            .schema()
+            .columns
+            .into_iter()
+            .map(|col| Column {
+                name: col.name,
+                type_info: col.type_info,
+            })
+            .collect();
+        if !query.columns.is_empty() {
+            if query.columns.len() != columns.len() {
+                return Err(QueryExecutionError::NamedColumns(
+                    table,
+                    columns.len(),
+                    query.columns.len(),
+                ));
+            }
+            for (col, name) in columns.iter_mut().zip(query.columns) {
+                col.name = name;
+            }
+        }
+
+        let plan = match step {
+            None => {
+                let names: Vec<_> = columns.into_iter().map(|col| col.name).collect();
+                qualify(base, &alias, &names)
+            }
+            Some(step) => {
+                planner
+                    .named
+                    .push((table.clone(), Named::Working(columns.clone())));
+                let step_plan = planner.plan(step.select)?;
+                check_combined(step.operator, &base.schema(), &step_plan.schema())?;
+                LogicalPlan::Recursive {
+                    table,
+                    alias,
+                    columns,
+                    base: Box::new(base),
+                    step: Box::new(step_plan),
+                    all: step.operator == SetOperator::UnionAll,
+                }
+            }
+        };
+        self.correlate(planner.correlated);
+        Ok(plan)
+    }
+
+    /// A planner for a select nested in this one, that can refer to the
+    /// columns of `schemas` and of the queries this one is nested in
+    fn nested(&self, schemas: Vec<Schema>) -> Planner<'_> {
+        Planner {
+            columns_of: self.columns_of,
+            outer: schemas.into_iter().chain(self.outer.clone()).collect(),
+            correlated: vec![],
+            subqueries: self.subqueries,
+            named: self.named.clone(),
+        }
+    }
+
+    /// Note the columns of outer queries a nested select refers to, besides
+    /// the ones already noted
+    fn correlate(&mut self, columns: Vec<String>) {
+        for column in columns {
+            if !self.correlated.contains(&column) {
+                self.correlated.push(column);
+            }
+        }
+    }
+
+    /// The type of a column of the outer queries, from the nearest one
+    fn outer_type(&self, name: &str) -> Option<SqlTypeInfo> {
+        self.outer.iter().find_map(|schema| {
+            let position = schema.resolve(name).ok()?;
+            Some(schema.columns[position].type_info)
+        })
+    }
+
+    /// Check every column an expression refers to exists in the rows of
+    /// `schema`, or else in the outer queries, which correlates the select
+    /// with them
+    fn check(&mut self, expr: &Expression, schema: &Schema) -> Result<(), QueryExecutionError> {
+        for column in expr.columns() {
+            match schema.resolve(column) {
+                Ok(_) => {}
+                Err(QueryExecutionError::ColumnDoesNotExist(_))
+                    if self.outer_type(column).is_some() =>
+                {
+                    self.correlate(vec![column.clone()]);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    /// The type of the values of an expression, which can be a column of an
+    /// outer query
+    fn type_of(
+        &self,
+        expr: &Expression,
+        schema: &Schema,
+    ) -> Result<SqlTypeInfo, QueryExecutionError> {
+        plan::type_of(expr, &|name| {
+            schema
+                .type_of(&Expression::Column(name.to_owned()))
+                .or_else(|e| self.outer_type(name).ok_or(e))
+        })
+    }
+
+    /// Check a predicate filtering rows can be evaluated on them
+    fn check_predicate(
+        &mut self,
+        predicate: &Expression,
+        schema: &Schema,
+    ) -> Result<(), QueryExecutionError> {
+        if predicate.has_aggregate() || predicate.has_window() || predicate.has_subquery() {
+            return Err(QueryExecutionError::InvalidExpression(
+                predicate.to_string(),
+            ));
+        }
+        self.check(predicate, schema)
+    }
+
+    /// Plan the subqueries of some expressions, each adding a column with its
+    /// results to the rows of `plan`
+    ///
+    /// Returns the subqueries with the names of their columns, the same
+    /// subquery is computed once.
+    fn plan_subqueries(
+        &mut self,
+        mut plan: LogicalPlan,
+        exprs: &[&Expression],
+    ) -> Result<(LogicalPlan, Vec<(Expression, String)>), QueryExecutionError> {
+        let mut subqueries = Vec::new();
+        for expr in exprs {
+            collect_subqueries(expr, &mut subqueries);
+        }
+
+        let mut found: Vec<(Expression, String)> = Vec::new();
+        for subquery in subqueries {
+            if found.iter().any(|(expr, _)| *expr == subquery) {
+                continue;
+            }
+            let schema = plan.schema();
+            let select = match &subquery {
+                Expression::Subquery(select) | Expression::Exists(select) => select,
+                Expression::InSubquery { subquery, .. } => subquery,
+                expr => unreachable!("{expr} is not a subquery"),
+            };
+            let mut planner = self.nested(vec![schema.clone()]);
+            let subplan = planner.plan(select.as_ref().clone())?;
+            let correlated = planner.correlated;
+            // the columns of queries further out are bound before it runs
+            self.correlate(
+                correlated
+                    .iter()
+                    .filter(|column| schema.resolve(column).is_err())
+                    .cloned()
+                    .collect(),
+            );
+
+            let columns = subplan.schema().columns;
+            if !matches!(subquery, Expression::Exists(_)) && columns.len() != 1 {
+                return Err(QueryExecutionError::SubqueryColumns(columns.len()));
+            }
+            let (kind, type_info) = match &subquery {
+                Expression::InSubquery { expr, negated, .. } => {
+                    let expr = replace_subqueries(expr, &found);
+                    self.check(&expr, &schema)?;
+                    let kind = SubqueryKind::In {
+                        expr,
+                        negated: *negated,
+                    };
+                    // booleans are typed like their literals
+                    (kind, SqlTypeInfo::Int)
+                }
+                Expression::Exists(_) => (SubqueryKind::Exists, SqlTypeInfo::Int),
+                _ => (SubqueryKind::Scalar, columns[0].type_info),
+            };
+
+            self.subqueries.set(self.subqueries.get() + 1);
+            let name = format!("${}", self.subqueries.get());
+            plan = LogicalPlan::Apply {
+                input: Box::new(plan),
+                subquery: Box::new(subplan),
+                kind,
+                outer: correlated,
+                column: PlanColumn {
+                    table: None,
+                    name: name.clone(),
+                    type_info,
+                },
+            };
+            found.push((subquery, name));
+        }
+        Ok((plan, found))
+    }
+
+    /// Compute window functions for each row of `input`
+    fn window(
+        &mut self,
+        input: LogicalPlan,
+        windows: Vec<Expression>,
+    ) -> Result<LogicalPlan, QueryExecutionError> {
+        let schema = input.schema();
+        let windows = windows
+            .into_iter()
+            .map(|expr| {
+                let Expression::Window {
+                    function,
+                    args,
+                    window,
+                } = &expr
+                else {
+                    unreachable!("only window functions are collected");
+                };
+                let arity = match function {
+                    WindowFunction::RowNumber
+                    | WindowFunction::Rank
+                    | WindowFunction::DenseRank => 0..=0,
+                    WindowFunction::Lag | WindowFunction::Lead => 1..=3,
+                    WindowFunction::Aggregate(AggregateFunction::Count) => 0..=1,
+                    WindowFunction::Aggregate(_) => 1..=1,
+                };
+                if !arity.contains(&args.len()) {
+                    return Err(QueryExecutionError::InvalidExpression(expr.to_string()));
+                }
+                for arg in args
+                    .iter()
+                    .chain(&window.partition_by)
+                    .chain(window.order_by.iter().map(|order| &order.expr))
+                {
+                    self.check_predicate(arg, &schema)?;
+                }
+                let numeric = matches!(
+                    function,
+                    WindowFunction::Aggregate(AggregateFunction::Sum | AggregateFunction::Avg)
+                );
+                if numeric && self.type_of(&args[0], &schema)? != SqlTypeInfo::Int {
+                    return Err(QueryExecutionError::InvalidAggregate(expr.to_string()));
+                }
+                let column = PlanColumn {
+                    table: None,
+                    name: expr.to_string(),
+                    type_info: self.type_of(&expr, &schema)?,
+                };
+                Ok((expr, column))
+            })
+            .collect::<Result<_, QueryExecutionError>>()?;
+
+        Ok(LogicalPlan::Window {
+            input: Box::new(input),
+            windows,
+        })
+    }
+
+    /// Group the rows of `input`, computing the aggregates the select list and
+    /// the `keys` of `ORDER BY` and `DISTINCT ON` use
+    fn aggregate(
+        &mut self,
+        input: LogicalPlan,
+        group_by: Vec<Expression>,
+        items: &[(Expression, Option<String>)],
+        keys: &[&Expression],
+    ) -> Result<LogicalPlan, QueryExecutionError> {
+        let schema = input.schema();
+
+        let group_by = group_by
+            .into_iter()
+            .map(|expr| {
+                self.check_predicate(&expr, &schema)?;
+                let column = match &expr {
+                    // grouped columns keep their name, so they can still be
+                    // referred to the same way
+                    Expression::Column(name) => schema.columns[schema.resolve(name)?].clone(),
+                    expr => PlanColumn {
+                        table: None,
+                        name: expr.to_string(),
+                        type_info: self.type_of(expr, &schema)?,
+                    },
+                };
+                Ok((expr, column))
+            })
+            .collect::<Result<_, QueryExecutionError>>()?;
+
+        let mut found = Vec::new();
+        for expr in items
+            .iter()
+            .map(|(expr, _)| expr)
+            .chain(keys.iter().copied())
+        {
+            collect_aggregates(expr, &mut found);
+        }
+
+        let aggregates = found
+            .into_iter()
+            .map(|expr| {
+                let Expression::Aggregate { function, arg, .. } = &expr else {
+                    unreachable!("only aggregates are collected");
+                };
+                if let Some(arg) = arg {
+                    self.check_predicate(arg, &schema)?;
+                    let numeric =
+                        matches!(function, AggregateFunction::Sum | AggregateFunction::Avg);
+                    if numeric && self.type_of(arg, &schema)? != SqlTypeInfo::Int {
+                        return Err(QueryExecutionError::InvalidAggregate(expr.to_string()));
+                    }
+                }
+                let column = PlanColumn {
+                    table: None,
+                    name: expr.to_string(),
+                    type_info: self.type_of(&expr, &schema)?,
+                };
+                Ok((expr, column))
+            })
+            .collect::<Result<_, QueryExecutionError>>()?;
+
+        Ok(LogicalPlan::Aggregate {
+            input: Box::new(input),
+            group_by,
+            aggregates,
+        })
+    }
+}
+
+/// Add the subqueries of an expression to `found`, the ones the others are
+/// computed from first
+fn collect_subqueries(expr: &Expression, found: &mut Vec<Expression>) {
+    for child in expr.children() {
+        collect_subqueries(child, found);
+    }
+    if matches!(
+        expr,
+        Expression::Subquery(_) | Expression::Exists(_) | Expression::InSubquery { .. }
+    ) {
+        found.push(expr.clone());
+    }
+}
+
+/// Refer to the columns holding the results of subqueries instead
+fn replace_subqueries(expr: &Expression, found: &[(Expression, String)]) -> Expression {
+    expr.replace(&|expr| {
+        found
+            .iter()
+            .find(|(subquery, _)| subquery == expr)
+            .map(|(_, name)| Expression::Column(name.clone()))
+    })
+}
+
+/// Qualify the columns of the rows of a derived table with its name, and
+/// rename them to `names` if there are any
+fn qualify(plan: LogicalPlan, alias: &str, names: &[String]) -> LogicalPlan {
+    let column = |position: usize, col: PlanColumn| PlanColumn {
+        table: Some(alias.to_owned()),
+        name: names.get(position).cloned().unwrap_or(col.name),
+        ..col
+    };
+    match plan {
+        LogicalPlan::Project { input, exprs } => LogicalPlan::Project {
+            input,
+            exprs: exprs
+                .into_iter()
+                .enumerate()
+                .map(|(position, (expr, col))| (expr, column(position, col)))
+                .collect(),
+        },
+        LogicalPlan::Limit { .. } => plan.map_inputs(|input| qualify(input, alias, names)),
+        // the combined rows of set operations
+        plan => LogicalPlan::Project {
+            exprs: plan
+                .schema()
+                .columns
+                .into_iter()
+                .enumerate()
+                .map(|(position, col)| (Expression::Column(col.to_string()), column(position, col)))
+                .collect(),
+            input: Box::new(plan),
+        },
+    }
+}
+
+/// Skip and limit the rows of a plan, if asked to
+fn limited(plan: LogicalPlan, limit: Option<u64>, offset: Option<u64>) -> LogicalPlan {
+    if limit.is_none() && offset.is_none() {
+        return plan;
+    }
+    LogicalPlan::Limit {
+        input: Box::new(plan),
+        limit: limit.map(|limit| limit as usize),
+        offset: offset.unwrap_or(0) as usize,
+    }
+}
+
+/// Check the rows of two plans can be combined: they have as many columns,
+/// of the same types
+fn check_combined(
+    operator: SetOperator,
+    left: &Schema,
+    right: &Schema,
+) -> Result<(), QueryExecutionError> {
+    if left.columns.len() != right.columns.len() {
+        return Err(QueryExecutionError::SetOperationColumns(
+            operator,
+            left.columns.len(),
+            right.columns.len(),
+        ));
+    }
+    for (l, r) in left.columns.iter().zip(&right.columns) {
+        if l.type_info != r.type_info {
+            return Err(QueryExecutionError::SetOperationTypes(
+                operator,
+                l.name.clone(),
+                l.type_info,
+                r.type_info,
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Combine the rows of two plans
+fn set_operation(
+    left: LogicalPlan,
+    operator: SetOperator,
+    right: LogicalPlan,
+) -> Result<LogicalPlan, QueryExecutionError> {
+    check_combined(operator, &left.schema(), &right.schema())?;
+    Ok(LogicalPlan::SetOperation {
+        left: Box::new(left),
+        right: Box::new(right),
+        operator,
+    })
+}
+
+/// Add the distinct aggregates of an expression to `found`
+fn collect_aggregates(expr: &Expression, found: &mut Vec<Expression>) {
+    if let Expression::Aggregate { .. } = expr {
+        if !found.contains(expr) {
+            found.push(expr.clone());
+        }
+        return;
+    }
+    for child in expr.children() {
+        collect_aggregates(child, found);
+    }
+}
+
+/// Add the distinct window functions of an expression to `found`
+fn collect_windows(expr: &Expression, found: &mut Vec<Expression>) {
+    if let Expression::Window { .. } = expr {
+        if !found.contains(expr) {
+            found.push(expr.clone());
+        }
+        return;
+    }
+    for child in expr.children() {
+        collect_windows(child, found);
+    }
+}
+
+/// Plan a select on the tables `foo (id int, name string)` and
+/// `bar (id int, foo_id int)`
+#[cfg(test)]
+pub(crate) fn plan_for_test(query: &str) -> Result<LogicalPlan, QueryExecutionError> {
+    use toy_sql_parser::{ast::SqlQuery, parse::Parse};
+
+    let column = |name: &str, type_info| Column {
+        name: name.into(),
+        type_info,
+    };
+    let columns_of = |table: &str| match table {
+        "foo" => Ok(vec![
+            column("id", SqlTypeInfo::Int),
+            column("name", SqlTypeInfo::String),
+        ]),
+        "bar" => Ok(vec![
+            column("id", SqlTypeInfo::Int),
+            column("foo_id", SqlTypeInfo::Int),
+        ]),
+        _ => Err(QueryExecutionError::TableNotFound(table.into())),
+    };
+
+    let Ok((_, SqlQuery::Select(select))) = SqlQuery::parse_from_raw(query) else {
+        panic!("{query} should parse as a select");
+    };
+    plan_select(select, columns_of)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan(query: &str) -> Result<LogicalPlan, QueryExecutionError> {
+        plan_for_test(query)
+    }
+
+    #[test]
+    fn test_plan_shape() {
+        let plan = plan(
+            "SELECT f.name, COUNT(*) AS n FROM foo AS f JOIN bar ON f.id = bar.foo_id \
+            WHERE bar.id > 1 GROUP BY f.name ORDER BY n DESC LIMIT 3;",
+        )
+        .unwrap();
+        assert_eq!(
+            plan.to_string(),
+            "Limit: 3
+  Project: f.name, COUNT(*) AS n
+    Sort: COUNT(*) DESC
+      Aggregate: group by f.name; COUNT(*)
+        Filter: (bar.id > 1)
+          Nested loop join: (f.id = bar.foo_id)
+            Scan: foo AS f (id, name)
+            Scan: bar (id, foo_id)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_subqueries() {
+        let plan = plan(
+            "SELECT name, (SELECT MAX(id) FROM bar) FROM foo WHERE EXISTS \
+            (SELECT id FROM bar WHERE bar.foo_id = foo.id) AND id > 1;",
+        )
+        .unwrap();
+        // the subquery of the select list gets its text as name
+        assert_eq!(
+            plan.to_string(),
+            "Project: name, $2 AS (SELECT MAX(id) FROM bar)
+  Apply: $2 = value
+    Filter: ($1 AND (id > 1))
+      Apply: $1 = EXISTS
+        Scan: foo (id, name)
+        Project: id
+          Filter: (bar.foo_id = foo.id)
+            Scan: bar (id, foo_id)
+    Project: MAX(id)
+      Aggregate: MAX(id)
+        Scan: bar (id, foo_id)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_with() {
+        let plan = plan(
+            "WITH RECURSIVE ids (n) AS (SELECT id FROM foo UNION SELECT bar.id FROM bar \
+            JOIN ids ON bar.foo_id = ids.n), named AS (SELECT n FROM ids) \
+            SELECT x.n FROM named AS x;",
+        )
+        .unwrap();
+        // the step reads the rows found so far by the name of the query
+        assert_eq!(
+            plan.to_string(),
+            "Project: x.n
+  Project: n
+    Recursive union: ids (n)
+      Project: id
+        Scan: foo (id, name)
+      Project: bar.id
+        Nested loop join: (bar.foo_id = ids.n)
+          Scan: bar (id, foo_id)
+          Scan: ids (n)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_distinct() {
+        let plan = plan(
+            "SELECT DISTINCT ON (n) name AS n, COUNT(DISTINCT id) FROM foo GROUP BY name \
+            ORDER BY n LIMIT 2;",
+        )
+        .unwrap();
+        // the first row of each key is kept once the rows are sorted
+        assert_eq!(
+            plan.to_string(),
+            "Limit: 2
+  Project: name AS n, COUNT(DISTINCT id)
+    Distinct on: name
+      Sort: name
+        Aggregate: group by name; COUNT(DISTINCT id)
+          Scan: foo (id, name)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_window() {
+        let plan = plan(
+            "SELECT name, RANK() OVER (ORDER BY COUNT(*) DESC) AS r FROM foo GROUP BY name \
+            ORDER BY ROW_NUMBER() OVER (PARTITION BY name);",
+        )
+        .unwrap();
+        // the windows read the groups, and are sorted by like columns
+        assert_eq!(
+            plan.to_string(),
+            "Project: name, RANK() OVER (ORDER BY COUNT(*) DESC) AS r
+  Sort: ROW_NUMBER() OVER (PARTITION BY name)
+    Window: RANK() OVER (ORDER BY COUNT(*) DESC), ROW_NUMBER() OVER (PARTITION BY name)
+      Aggregate: group by name; COUNT(*)
+        Scan: foo (id, name)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_set_operations() {
+        let plan = plan(
+            "WITH ids AS (SELECT id FROM foo UNION ALL SELECT id FROM bar EXCEPT SELECT foo_id FROM bar \
+            INTERSECT SELECT id FROM foo ORDER BY id LIMIT 5) SELECT i.id FROM ids AS i;",
+        )
+        .unwrap();
+        // the rows of both sides of INTERSECT are combined first
+        assert_eq!(
+            plan.to_string(),
+            "Project: i.id
+  Limit: 5
+    Project: id
+      Sort: id
+        Except
+          Union all
+            Project: id
+              Scan: foo (id, name)
+            Project: id
+              Scan: bar (id, foo_id)
+          Intersect
+            Project: foo_id
+              Scan: bar (id, foo_id)
+            Project: id
+              Scan: foo (id, name)
+"
+        );
+    }
+
+    #[test]
+    fn test_plan_errors() {
+        assert!(matches!(
+            plan("SELECT id FROM foo JOIN bar ON foo.id = bar.foo_id;"),
+            Err(QueryExecutionError::AmbiguousColumn(_))
+        ));
+        assert!(matches!(
+            plan("SELECT name, COUNT(*) FROM foo;"),
+            Err(QueryExecutionError::ColumnNotGrouped(_))
+        ));
+        assert!(matches!(
+            plan("SELECT SUM(name) FROM foo;"),
+            Err(QueryExecutionError::InvalidAggregate(_))
+        ));
+        assert!(matches!(
+            plan("SELECT id FROM foo WHERE COUNT(*) > 1;"),
+            Err(QueryExecutionError::InvalidExpression(_))
+        ));
+        assert!(matches!(
+            plan("SELECT missing FROM foo;"),
+            Err(QueryExecutionError::ColumnDoesNotExist(_))
+        ));
+        // the rows kept would depend on the order they are read in
+        assert!(matches!(
+            plan("SELECT DISTINCT ON (name) id FROM foo ORDER BY id;"),
+            Err(QueryExecutionError::DistinctOnOrder(_))
+        ));
+        assert!(matches!(
+            plan("SELECT DISTINCT name FROM foo ORDER BY id;"),
+            Err(QueryExecutionError::DistinctOrder(_))
+        ));
+        assert!(
+            plan("SELECT DISTINCT ON (name, id) id FROM foo ORDER BY foo.id, name DESC;").is_ok()
+        );
+        assert!(plan("SELECT DISTINCT foo.name AS n FROM foo ORDER BY name, n;").is_ok());
+    }
+}
@@ -1,27 +1,34 @@
 use toy_sql_parser::value::Value;
 
 use crate::{table::ColumnInfo, QueryExecutionError};
-use std::{collections::HashMap, rc::Rc}; // Vec<Column>
+use std::sync::Arc; // Vec<Column>
 
 /// A Row in a Query response
 #[derive(Debug, Clone)]
-pub struct Row<'a> {
-    id: usize,
-    columns: Rc<ColumnInfo>, // reference to columnInfo
-    data: HashMap<&'a String, &'a Value>,
+pub struct Row {
+    columns: Arc<ColumnInfo>, // reference to columnInfo
+    /// One value per column, in order
+    values: Vec<Value>,
 }
 
-impl<'a> Row<'a> {
-    pub fn new(columns: Rc<ColumnInfo>, id: usize, data: HashMap<&'a String, &'a Value>) -> Self {
-        Self { id, columns, data }
+impl Row {
+    pub fn new(columns: Arc<ColumnInfo>, values: Vec<Value>) -> Self {
+        Self { columns, values }
     }
 
     pub fn columns(&self) -> &ColumnInfo {
         self.columns.as_ref()
     }
 
+    /// The values of the row, in the order of its columns
+    pub fn values(&self) -> &[Value] {
+        &self.values
+    }
+
     /// Get a single value from the row
     ///
+    /// If several columns have the name the first one's value is returned.
+    ///
     /// # Panics
     ///
     /// Panics if the column does not exist
@@ -33,9 +40,12 @@ impl<'a> Row<'a> {
 
     /// Get a single value from the row
     pub fn try_get(&self, column: &String) -> Result<Value, QueryExecutionError> {
-        self.data.get(column).map_or_else(
-            || Err(QueryExecutionError::ColumnDoesNotExist(column.to_owned())),
-            |val| Ok((*val).clone()),
-        )
+        self.columns
+            .iter()
+            .position(|col| col.name == *column)
+            .map_or_else(
+                || Err(QueryExecutionError::ColumnDoesNotExist(column.to_owned())),
+                |position| Ok(self.values[position].clone()),
+            )
     }
 }
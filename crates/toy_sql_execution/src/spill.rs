@@ -0,0 +1,281 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Seek, SeekFrom, Write},
+    marker::PhantomData,
+    mem,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{self, AtomicUsize},
+        Arc,
+    },
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+use toy_sql_parser::value::Value;
+
+use crate::error::QueryExecutionError;
+
+/// Memory a query may hold rows in by default, in bytes
+pub(crate) const DEFAULT_QUERY_MEMORY: usize = 64 * 1024 * 1024;
+
+/// The memory the operators of a query share to hold rows in, beyond which
+/// they spill them to temporary files
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    limit: usize,
+    used: AtomicUsize,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: usize) -> Arc<Self> {
+        Arc::new(Self {
+            limit,
+            used: AtomicUsize::new(0),
+        })
+    }
+
+    /// Take some bytes of the budget, held until the reservation is dropped
+    pub fn reserve(self: &Arc<Self>) -> Reservation {
+        Reservation {
+            budget: self.clone(),
+            bytes: 0,
+        }
+    }
+}
+
+/// Bytes of a [`MemoryBudget`] held by an operator
+#[derive(Debug)]
+pub(crate) struct Reservation {
+    budget: Arc<MemoryBudget>,
+    bytes: usize,
+}
+
+impl Reservation {
+    /// Hold `bytes` more, unless the budget doesn't have them left
+    pub fn grow(&mut self, bytes: usize) -> bool {
+        let budget = &self.budget;
+        let grown = budget
+            .used
+            .fetch_update(atomic::Ordering::SeqCst, atomic::Ordering::SeqCst, |used| {
+                (used + bytes <= budget.limit).then_some(used + bytes)
+            })
+            .is_ok();
+        if grown {
+            self.bytes += bytes;
+        }
+        grown
+    }
+
+    /// Give back `bytes` of what is held
+    pub fn shrink(&mut self, bytes: usize) {
+        let bytes = bytes.min(self.bytes);
+        self.bytes -= bytes;
+        self.budget.used.fetch_sub(bytes, atomic::Ordering::SeqCst);
+    }
+
+    /// Give back everything held
+    pub fn free(&mut self) {
+        self.budget
+            .used
+            .fetch_sub(mem::take(&mut self.bytes), atomic::Ordering::SeqCst);
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.free();
+    }
+}
+
+/// Rough number of bytes values take in memory
+pub(crate) fn size_of_values(values: &[Value]) -> usize {
+    let heap: usize = values
+        .iter()
+        .map(|value| match value {
+            Value::String(s) => s.capacity(),
+            _ => 0,
+        })
+        .sum();
+    mem::size_of::<Vec<Value>>() + mem::size_of_val(values) + heap
+}
+
+/// A file removed when dropped
+#[derive(Debug)]
+struct TempPath(PathBuf);
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+/// Rows written to a temporary file, to be read back once they are all
+/// written
+#[derive(Debug)]
+pub(crate) struct SpillFile<T> {
+    path: TempPath,
+    writer: BufWriter<File>,
+    rows: usize,
+    _rows: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> SpillFile<T> {
+    pub fn new() -> Result<Self, QueryExecutionError> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let name = format!(
+            "toy_sql-{}-{}.spill",
+            process::id(),
+            COUNTER.fetch_add(1, atomic::Ordering::Relaxed)
+        );
+        let path = TempPath(std::env::temp_dir().join(name));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path.0)?;
+        Ok(Self {
+            path,
+            writer: BufWriter::new(file),
+            rows: 0,
+            _rows: PhantomData,
+        })
+    }
+
+    pub fn write(&mut self, row: &T) -> Result<(), QueryExecutionError> {
+        bincode::serialize_into(&mut self.writer, row).map_err(invalid_data)?;
+        self.rows += 1;
+        Ok(())
+    }
+
+    /// Close the file once the rows are written, to read them back later
+    /// without holding it open until then
+    pub fn close(self) -> Result<SpilledRows<T>, QueryExecutionError> {
+        let SpillFile {
+            path, writer, rows, ..
+        } = self;
+        writer.into_inner().map_err(|e| e.into_error())?;
+        Ok(SpilledRows {
+            path: Arc::new(path),
+            rows,
+            _rows: PhantomData,
+        })
+    }
+
+    /// Read the rows back, in the order they were written
+    pub fn read(mut self) -> Result<SpillReader<T>, QueryExecutionError> {
+        self.writer.flush()?;
+        let mut file = self.writer.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        Ok(SpillReader {
+            _path: Arc::new(self.path),
+            reader: BufReader::new(file),
+            remaining: self.rows,
+            _rows: PhantomData,
+        })
+    }
+}
+
+/// The rows of a closed [`SpillFile`], removed once they and their readers
+/// are dropped
+#[derive(Debug)]
+pub(crate) struct SpilledRows<T> {
+    path: Arc<TempPath>,
+    rows: usize,
+    _rows: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> SpilledRows<T> {
+    /// Open the file to read the rows, in the order they were written, as
+    /// many times as needed
+    pub fn read(&self) -> Result<SpillReader<T>, QueryExecutionError> {
+        let file = File::open(&self.path.0)?;
+        Ok(SpillReader {
+            _path: self.path.clone(),
+            reader: BufReader::new(file),
+            remaining: self.rows,
+            _rows: PhantomData,
+        })
+    }
+}
+
+/// The rows of a [`SpillFile`], removed once they are read or dropped
+#[derive(Debug)]
+pub(crate) struct SpillReader<T> {
+    _path: Arc<TempPath>,
+    reader: BufReader<File>,
+    remaining: usize,
+    _rows: PhantomData<T>,
+}
+
+impl<T: DeserializeOwned> Iterator for SpillReader<T> {
+    type Item = Result<T, QueryExecutionError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        Some(bincode::deserialize_from(&mut self.reader).map_err(invalid_data))
+    }
+}
+
+fn invalid_data(e: bincode::Error) -> QueryExecutionError {
+    io::Error::new(io::ErrorKind::InvalidData, e).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_budget() {
+        let budget = MemoryBudget::new(100);
+        let mut first = budget.reserve();
+        assert!(first.grow(60));
+        let mut second = budget.reserve();
+        assert!(!second.grow(50));
+        assert!(second.grow(40));
+
+        drop(first);
+        assert!(second.grow(50));
+        second.shrink(30);
+        assert!(budget.reserve().grow(30));
+        second.free();
+        assert!(budget.reserve().grow(100));
+    }
+
+    #[test]
+    fn test_spill_file() {
+        let mut file = SpillFile::new().unwrap();
+        let rows: Vec<Vec<Value>> = (0..100)
+            .map(|n| vec![Value::Number(n.into()), Value::String(format!("row {n}"))])
+            .collect();
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        let path = file.path.0.clone();
+        let read: Vec<Vec<Value>> = file.read().unwrap().map(Result::unwrap).collect();
+        assert_eq!(read, rows);
+        assert!(!path.exists());
+
+        // closed files are opened again each time they are read, and removed
+        // once their readers are done too
+        let mut file = SpillFile::new().unwrap();
+        for row in &rows {
+            file.write(row).unwrap();
+        }
+        let closed = file.close().unwrap();
+        let path = closed.path.0.clone();
+        assert!(path.exists());
+        for _ in 0..2 {
+            let read: Vec<Vec<Value>> = closed.read().unwrap().map(Result::unwrap).collect();
+            assert_eq!(read, rows);
+        }
+        let reader = closed.read().unwrap();
+        drop(closed);
+        assert!(path.exists());
+        drop(reader);
+        assert!(!path.exists());
+    }
+}
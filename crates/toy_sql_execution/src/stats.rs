@@ -0,0 +1,173 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bigdecimal::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use toy_sql_parser::value::Value;
+
+use crate::{eval::sort_order, table::StoredRow};
+
+/// Number of buckets of the column histograms
+const HISTOGRAM_BUCKETS: usize = 10;
+
+/// What `ANALYZE` found out about the rows of a table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct TableStats {
+    pub row_count: usize,
+    /// column name to its statistics
+    pub columns: HashMap<String, ColumnStats>,
+}
+
+/// The distribution of the values of a column
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub(crate) struct ColumnStats {
+    /// Number of different values, besides `NULL`
+    pub distinct: usize,
+    /// Fraction of the rows whose value is `NULL`
+    pub null_fraction: f64,
+    pub min: Option<Value>,
+    pub max: Option<Value>,
+    /// Bounds of buckets holding as many values each, from the smallest value
+    /// to the largest
+    pub histogram: Vec<Value>,
+}
+
+impl TableStats {
+    /// Compute the statistics of the given columns of some rows
+    pub fn collect<'a>(columns: impl Iterator<Item = &'a str>, rows: &[StoredRow]) -> Self {
+        Self {
+            row_count: rows.len(),
+            columns: columns
+                .map(|column| {
+                    let values = rows
+                        .iter()
+                        .map(|row| row.get(column).unwrap_or(&Value::Null));
+                    (column.to_owned(), ColumnStats::collect(values))
+                })
+                .collect(),
+        }
+    }
+}
+
+impl ColumnStats {
+    fn collect<'a>(values: impl Iterator<Item = &'a Value>) -> Self {
+        let (nulls, mut values): (Vec<_>, Vec<_>) =
+            values.partition(|value| **value == Value::Null);
+        values.sort_by(|l, r| sort_order(l, r));
+
+        let mut distinct = values.clone();
+        distinct.dedup_by(|l, r| sort_order(l, r) == Ordering::Equal);
+
+        let histogram = match values.len() {
+            0 => vec![],
+            len => (0..=HISTOGRAM_BUCKETS)
+                .map(|bucket| values[bucket * (len - 1) / HISTOGRAM_BUCKETS].clone())
+                .collect(),
+        };
+
+        Self {
+            distinct: distinct.len(),
+            null_fraction: match nulls.len() {
+                0 => 0.0,
+                nulls => nulls as f64 / (nulls + values.len()) as f64,
+            },
+            min: values.first().cloned().cloned(),
+            max: values.last().cloned().cloned(),
+            histogram,
+        }
+    }
+
+    /// Fraction of the rows whose value isn't `NULL`
+    pub fn not_null(&self) -> f64 {
+        1.0 - self.null_fraction
+    }
+
+    /// Estimated fraction of the rows whose value equals `value`
+    pub fn equal(&self, value: &Value) -> f64 {
+        let outside = |bound: &Option<Value>, side| matches!(bound, Some(bound) if sort_order(value, bound) == side);
+        if self.distinct == 0
+            || outside(&self.min, Ordering::Less)
+            || outside(&self.max, Ordering::Greater)
+        {
+            return 0.0;
+        }
+        self.not_null() / self.distinct as f64
+    }
+
+    /// Estimated fraction of the rows whose value is below `value`
+    pub fn below(&self, value: &Value) -> f64 {
+        if self.histogram.is_empty() {
+            return 0.0;
+        }
+        let bounds_below = self
+            .histogram
+            .iter()
+            .filter(|bound| sort_order(bound, value) == Ordering::Less)
+            .count();
+        let buckets = match bounds_below {
+            0 => 0.0,
+            n if n == self.histogram.len() => HISTOGRAM_BUCKETS as f64,
+            // the part of the bucket the value is in that is below it, with
+            // the values spread evenly in it
+            n => {
+                let (low, high) = (&self.histogram[n - 1], &self.histogram[n]);
+                let part = match (low, high, value) {
+                    (Value::Number(low), Value::Number(high), Value::Number(value)) => {
+                        ((value - low) / (high - low)).to_f64().unwrap_or(0.5)
+                    }
+                    _ => 0.5,
+                };
+                (n - 1) as f64 + part
+            }
+        };
+        self.not_null() * buckets / HISTOGRAM_BUCKETS as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(values: impl Iterator<Item = Value>) -> Vec<StoredRow> {
+        values
+            .map(|value| HashMap::from([("a".to_owned(), value)]).into())
+            .collect()
+    }
+
+    fn number(n: i64) -> Value {
+        Value::Number(n.into())
+    }
+
+    #[test]
+    fn test_column_stats() {
+        // 0 to 99 twice, and 20 nulls
+        let values = (0..200)
+            .map(|n| number(n % 100))
+            .chain((0..20).map(|_| Value::Null));
+        let stats = TableStats::collect(["a"].into_iter(), &rows(values));
+        assert_eq!(stats.row_count, 220);
+
+        let column = &stats.columns["a"];
+        assert_eq!(column.distinct, 100);
+        assert!((column.null_fraction - 20.0 / 220.0).abs() < 1e-9);
+        assert_eq!(column.min, Some(number(0)));
+        assert_eq!(column.max, Some(number(99)));
+        assert_eq!(column.histogram.len(), HISTOGRAM_BUCKETS + 1);
+
+        assert_eq!(column.equal(&number(150)), 0.0);
+        assert!((column.equal(&number(50)) - 0.01 * 200.0 / 220.0).abs() < 1e-9);
+        assert_eq!(column.below(&number(-1)), 0.0);
+        let below = column.below(&number(50)) / column.not_null();
+        assert!((below - 0.5).abs() < 0.01, "{below}");
+        assert!((column.below(&number(1000)) - column.not_null()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_empty_stats() {
+        let stats = TableStats::collect(["a"].into_iter(), &[]);
+        let column = &stats.columns["a"];
+        assert_eq!((stats.row_count, column.distinct), (0, 0));
+        assert_eq!(column.null_fraction, 0.0);
+        assert_eq!(column.equal(&number(1)), 0.0);
+        assert_eq!(column.below(&number(1)), 0.0);
+    }
+}
@@ -0,0 +1,92 @@
+use std::ops::Bound;
+
+use toy_sql_parser::commands::Engine;
+
+use super::{RowIter, Storage};
+use crate::{
+    btree::{BTree, PageId, SharedPager},
+    error::QueryExecutionError,
+    table::StoredRow,
+};
+
+/// Rows encoded in a B+tree of the database file
+#[derive(Debug)]
+pub(crate) struct BTreeStorage {
+    tree: BTree,
+}
+
+impl BTreeStorage {
+    /// Create an empty tree in the database file
+    pub fn create(pager: SharedPager) -> Result<Self, QueryExecutionError> {
+        Ok(Self {
+            tree: BTree::create(pager)?,
+        })
+    }
+
+    /// Open the tree rooted at `root`
+    pub fn open(pager: SharedPager, root: PageId) -> Self {
+        Self {
+            tree: BTree::open(pager, root),
+        }
+    }
+}
+
+fn encode(row: &StoredRow) -> Result<Vec<u8>, QueryExecutionError> {
+    bincode::serialize(row).map_err(|e| QueryExecutionError::CorruptDatabase(e.to_string()))
+}
+
+fn decode(bytes: &[u8]) -> Result<StoredRow, QueryExecutionError> {
+    bincode::deserialize(bytes).map_err(|e| QueryExecutionError::CorruptDatabase(e.to_string()))
+}
+
+fn to_key(bound: Bound<usize>) -> Bound<u64> {
+    match bound {
+        Bound::Included(id) => Bound::Included(id as u64),
+        Bound::Excluded(id) => Bound::Excluded(id as u64),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl Storage for BTreeStorage {
+    fn engine(&self) -> Engine {
+        Engine::BTree
+    }
+
+    fn root_page(&self) -> Option<PageId> {
+        Some(self.tree.root())
+    }
+
+    fn get(&self, id: usize) -> Result<Option<StoredRow>, QueryExecutionError> {
+        self.tree
+            .get(id as u64)?
+            .map(|bytes| decode(&bytes))
+            .transpose()
+    }
+
+    fn insert(&mut self, id: usize, row: StoredRow) -> Result<(), QueryExecutionError> {
+        self.tree.insert(id as u64, encode(&row)?)
+    }
+
+    fn delete(&mut self, id: usize) -> Result<bool, QueryExecutionError> {
+        self.tree.delete(id as u64)
+    }
+
+    fn range(
+        &self,
+        range: (Bound<usize>, Bound<usize>),
+    ) -> Result<RowIter<'_>, QueryExecutionError> {
+        let range = (to_key(range.0), to_key(range.1));
+        Ok(Box::new(self.tree.range(range)?.map(|entry| {
+            let (id, bytes) = entry?;
+            Ok((id as usize, decode(&bytes)?))
+        })))
+    }
+
+    fn last_id(&self) -> Result<Option<usize>, QueryExecutionError> {
+        Ok(self.tree.last_key()?.map(|id| id as usize))
+    }
+
+    fn destroy(self: Box<Self>) -> Result<(), QueryExecutionError> {
+        self.tree.destroy()
+    }
+}
@@ -0,0 +1,47 @@
+use std::{collections::BTreeMap, ops::Bound};
+
+use toy_sql_parser::commands::Engine;
+
+use super::{RowIter, Storage};
+use crate::{error::QueryExecutionError, table::StoredRow};
+
+/// Rows kept in memory, lost when the database is closed
+#[derive(Debug, Default)]
+pub(crate) struct MemoryStorage {
+    /// row id to row
+    rows: BTreeMap<usize, StoredRow>,
+}
+
+impl Storage for MemoryStorage {
+    fn engine(&self) -> Engine {
+        Engine::Memory
+    }
+
+    fn get(&self, id: usize) -> Result<Option<StoredRow>, QueryExecutionError> {
+        Ok(self.rows.get(&id).cloned())
+    }
+
+    fn insert(&mut self, id: usize, row: StoredRow) -> Result<(), QueryExecutionError> {
+        self.rows.insert(id, row);
+        Ok(())
+    }
+
+    fn delete(&mut self, id: usize) -> Result<bool, QueryExecutionError> {
+        Ok(self.rows.remove(&id).is_some())
+    }
+
+    fn range(
+        &self,
+        range: (Bound<usize>, Bound<usize>),
+    ) -> Result<RowIter<'_>, QueryExecutionError> {
+        Ok(Box::new(
+            self.rows
+                .range(range)
+                .map(|(id, row)| Ok((*id, row.clone()))),
+        ))
+    }
+
+    fn last_id(&self) -> Result<Option<usize>, QueryExecutionError> {
+        Ok(self.rows.last_key_value().map(|(id, _)| *id))
+    }
+}
@@ -0,0 +1,73 @@
+use std::{fmt, ops::Bound};
+
+use toy_sql_parser::commands::Engine;
+
+use crate::{
+    btree::{PageId, SharedPager},
+    error::QueryExecutionError,
+    table::StoredRow,
+};
+
+mod disk;
+mod memory;
+
+pub(crate) use disk::BTreeStorage;
+pub(crate) use memory::MemoryStorage;
+
+pub(crate) type RowResult = Result<(usize, StoredRow), QueryExecutionError>;
+
+/// Iterator over (row id, row) pairs in id order
+pub(crate) type RowIter<'a> = Box<dyn Iterator<Item = RowResult> + 'a>;
+
+/// Where the rows of a table are kept, keyed by row id
+pub(crate) trait Storage: fmt::Debug + Send + Sync {
+    /// The engine implementing this storage
+    fn engine(&self) -> Engine;
+
+    /// The page the storage starts at in the database file, if it lives there
+    fn root_page(&self) -> Option<PageId> {
+        None
+    }
+
+    fn get(&self, id: usize) -> Result<Option<StoredRow>, QueryExecutionError>;
+
+    /// Insert a row, replacing the row with the same id if there is one
+    fn insert(&mut self, id: usize, row: StoredRow) -> Result<(), QueryExecutionError>;
+
+    /// Remove a row, returning whether it existed
+    fn delete(&mut self, id: usize) -> Result<bool, QueryExecutionError>;
+
+    /// The rows with ids in `range`, in id order
+    fn range(
+        &self,
+        range: (Bound<usize>, Bound<usize>),
+    ) -> Result<RowIter<'_>, QueryExecutionError>;
+
+    /// All the rows, in id order
+    fn scan(&self) -> Result<RowIter<'_>, QueryExecutionError> {
+        self.range((Bound::Unbounded, Bound::Unbounded))
+    }
+
+    /// The largest row id in use
+    fn last_id(&self) -> Result<Option<usize>, QueryExecutionError>;
+
+    /// Release everything the storage holds, e.g. its pages in the database file
+    fn destroy(self: Box<Self>) -> Result<(), QueryExecutionError> {
+        Ok(())
+    }
+}
+
+/// Create empty storage for a new table
+///
+/// `pager` is the database file, engines that keep their rows there can't be
+/// used without one.
+pub(crate) fn create(
+    engine: Engine,
+    pager: Option<&SharedPager>,
+) -> Result<Box<dyn Storage>, QueryExecutionError> {
+    match (engine, pager) {
+        (Engine::Memory, _) => Ok(Box::<MemoryStorage>::default()),
+        (Engine::BTree, Some(pager)) => Ok(Box::new(BTreeStorage::create(pager.clone())?)),
+        (Engine::BTree, None) => Err(QueryExecutionError::EngineUnavailable(engine)),
+    }
+}
@@ -1,20 +1,44 @@
 use std::{
     collections::{BTreeMap, HashMap},
-    rc::Rc,
+    fmt,
+    iter::Peekable,
+    ops::Bound,
+    sync::Arc,
 };
 
 use serde::{Deserialize, Serialize};
-use toy_sql_parser::{value::Value, Column, SqlTypeInfo};
+use toy_sql_parser::{
+    commands::Engine,
+    expression::{BinaryOperator, Expression},
+    value::Value,
+    Column, SqlTypeInfo,
+};
 
-use crate::{error::QueryExecutionError, row::Row};
+use crate::{
+    btree::PageId,
+    error::QueryExecutionError,
+    eval::{satisfies, value_of},
+    index::HashIndex,
+    mvcc::{Snapshot, Stamp, Timestamp, TxId, Version, VersionChain},
+    operator::Operator,
+    row::Row,
+    stats::TableStats,
+    storage::{RowIter, RowResult, Storage},
+};
 
 /// A row stored in a table col name => data
 // type StoredRow = HashMap<String, String>;
-#[derive(Debug, Clone, Default, Serialize, Deserialize, derive_more::From)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize, derive_more::From)]
 pub struct StoredRow {
     data: HashMap<String, Value>,
 }
 
+impl StoredRow {
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.data.get(column)
+    }
+}
+
 /// List of column info
 #[derive(Debug, Clone, Default, Serialize, Deserialize, derive_more::From)]
 pub struct ColumnInfo {
@@ -33,116 +57,869 @@ impl ColumnInfo {
     }
 }
 
-#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+/// Check if a value can be stored in a column of the given type, `NULL` in
+/// any, only integers in int columns
+fn type_matches(type_info: SqlTypeInfo, value: &Value) -> bool {
+    match (type_info, value) {
+        (SqlTypeInfo::Int, Value::Number(n)) => n.is_integer(),
+        (_, Value::Null)
+        | (SqlTypeInfo::String, Value::String(_))
+        | (SqlTypeInfo::Bool, Value::Bool(_)) => true,
+        _ => false,
+    }
+}
+
+/// The indexes of a table that can find the rows matching a predicate, with
+/// the values each of their columns can have
+pub(crate) type UsableIndexes = Vec<(String, Vec<(String, Vec<Value>)>)>;
+
+/// A table whose rows have multiple versions, so each transaction reads a
+/// consistent snapshot of it
+///
+/// The storage holds the newest committed version of every row. Rows changed
+/// since the oldest open snapshot also have a [`VersionChain`] with their
+/// older and pending versions. Indexes point to the rows of every version, so
+/// rows found through them still need to be checked.
+#[derive(Debug)]
 pub(crate) struct Table {
     /// row id to row
-    rows: BTreeMap<usize, StoredRow>,
+    rows: Box<dyn Storage>,
+    /// row id to the versions of the recently changed rows
+    versions: BTreeMap<usize, VersionChain>,
+    /// ids below this may belong to rows that are not committed yet
+    next_id: usize,
     /// Column info for all columns in the table
     columns: ColumnInfo,
+    /// index name to index
+    indexes: HashMap<String, HashIndex>,
+    /// The transaction that created the table, until it commits
+    pub creator: Option<TxId>,
+    /// What the last `ANALYZE` found out about the rows
+    pub stats: Option<TableStats>,
 }
 
 impl Table {
-    // Create a table with the given column definitions
-    pub fn new(columns: Vec<Column>) -> Self {
+    // Create a table with the given column definitions, keeping its rows in `rows`
+    pub fn new(columns: Vec<Column>, rows: Box<dyn Storage>) -> Self {
         Self {
-            rows: BTreeMap::new(),
+            rows,
+            versions: BTreeMap::new(),
+            next_id: 0,
             columns: columns.into(),
+            indexes: HashMap::new(),
+            creator: None,
+            stats: None,
+        }
+    }
+
+    pub fn columns(&self) -> &ColumnInfo {
+        &self.columns
+    }
+
+    /// The engine storing the table's rows
+    pub fn engine(&self) -> Engine {
+        self.rows.engine()
+    }
+
+    /// The root page of the table's rows, if they are stored in the database file
+    pub fn root(&self) -> Option<PageId> {
+        self.rows.root_page()
+    }
+
+    /// All the committed rows of the table, in id order
+    pub fn rows(&self) -> Result<RowIter<'_>, QueryExecutionError> {
+        self.rows.scan()
+    }
+
+    /// The version of a row the snapshot sees
+    pub fn get(
+        &self,
+        id: usize,
+        snapshot: &Snapshot,
+    ) -> Result<Option<StoredRow>, QueryExecutionError> {
+        match self.versions.get(&id) {
+            Some(chain) => Ok(chain.visible(snapshot).cloned()),
+            None => self.rows.get(id),
+        }
+    }
+
+    /// The rows the snapshot sees, in id order
+    pub fn visible_rows(
+        &self,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        self.visible_range((Bound::Unbounded, Bound::Unbounded), snapshot)?
+            .collect()
+    }
+
+    /// The rows the snapshot sees with ids in `range`, in id order, read
+    /// from the storage as they are pulled
+    pub fn visible_range<'a>(
+        &'a self,
+        range: (Bound<usize>, Bound<usize>),
+        snapshot: &'a Snapshot,
+    ) -> Result<impl Iterator<Item = RowResult> + 'a, QueryExecutionError> {
+        let changed = self
+            .versions
+            .range(range)
+            .map(|(id, chain)| (*id, chain.visible(snapshot).cloned()));
+        Ok(Overlaid::new(self.rows.range(range)?, changed))
+    }
+
+    /// The rows `tx` wrote and didn't commit yet, `None` for the ones it
+    /// deleted
+    pub fn pending_writes(&self, tx: TxId) -> BTreeMap<usize, Option<StoredRow>> {
+        self.versions
+            .iter()
+            .filter_map(|(id, chain)| Some((*id, chain.pending(tx)?.row.clone())))
+            .collect()
+    }
+
+    /// Number of versions kept besides the rows in storage
+    #[cfg(test)]
+    pub fn version_count(&self) -> usize {
+        self.versions.values().map(|chain| chain.len()).sum()
+    }
+
+    /// Names and columns of the table's indexes
+    pub fn indexes(&self) -> impl Iterator<Item = (&String, &[String])> {
+        self.indexes
+            .iter()
+            .map(|(name, index)| (name, index.columns()))
+    }
+
+    pub fn has_index(&self, name: &str) -> bool {
+        self.indexes.contains_key(name)
+    }
+
+    /// Compute the statistics of the rows the snapshot sees
+    pub fn analyze(&mut self, snapshot: &Snapshot) -> Result<(), QueryExecutionError> {
+        let rows: Vec<_> = self
+            .visible_rows(snapshot)?
+            .into_iter()
+            .map(|(_, row)| row)
+            .collect();
+        let columns = self.columns.iter().map(|col| col.name.as_str());
+        self.stats = Some(TableStats::collect(columns, &rows));
+        Ok(())
+    }
+
+    /// Create a hash index on the given columns, indexing the existing rows
+    pub fn create_index(
+        &mut self,
+        name: String,
+        columns: Vec<String>,
+    ) -> Result<(), QueryExecutionError> {
+        for column in &columns {
+            self.columns.find_column(column)?;
+        }
+
+        let mut index = HashIndex::new(columns);
+        for entry in self.rows.scan()? {
+            let (id, row) = entry?;
+            if !self.versions.contains_key(&id) {
+                index.insert(id, &row);
+            }
+        }
+        for (id, chain) in &self.versions {
+            for row in chain.rows() {
+                index.insert(*id, row);
+            }
         }
+
+        self.indexes.insert(name, index);
+        Ok(())
     }
 
-    /// Insert values (a row) into the table
+    /// Check values (a row) can be inserted into the table, returning the id
+    /// and row to [`insert`](Self::insert)
     ///
     /// assumes the values are in the same order of the columns passed to create
-    pub fn insert(&mut self, values: Vec<Value>) -> Result<(), QueryExecutionError> {
-        // id = max_id +1 or 0
+    pub fn new_row(&self, values: Vec<Value>) -> Result<(usize, StoredRow), QueryExecutionError> {
+        let columns = self.columns.iter().count();
+        if values.len() != columns {
+            return Err(QueryExecutionError::InsertValueCount(columns, values.len()));
+        }
+
+        // id = max_id +1 or 0, skipping the ids of uncommitted rows
         let id = self
             .rows
-            .last_key_value()
-            .map_or(0, |(max_id, _)| max_id + 1);
+            .last_id()?
+            .map_or(0, |max_id| max_id + 1)
+            .max(self.next_id);
 
         // map value to col, i.e. row = [(col1, val1), (col2, val2)...]
         let row = values
             .into_iter()
             .zip(self.columns.iter())
-            .map(|(value, col)| match (col.type_info, value) {
-                (SqlTypeInfo::String, v @ Value::String(_)) => Ok((col.name.to_owned(), v)),
-                (SqlTypeInfo::Int, v @ Value::Number(_)) => Ok((col.name.to_owned(), v)),
-                (_, v) => Err(QueryExecutionError::InsertTypeMismatch(col.type_info, v)),
+            .map(|(value, col)| match type_matches(col.type_info, &value) {
+                true => Ok((col.name.to_owned(), value)),
+                false => Err(QueryExecutionError::InsertTypeMismatch(
+                    col.type_info,
+                    value,
+                )),
             })
             .collect::<Result<HashMap<_, _>, _>>()?;
 
-        self.rows.insert(id, row.into());
+        Ok((id, row.into()))
+    }
+
+    /// Store a row, replacing the row with the same id if there is one
+    ///
+    /// Returns the replaced row.
+    pub fn insert(
+        &mut self,
+        id: usize,
+        row: StoredRow,
+    ) -> Result<Option<StoredRow>, QueryExecutionError> {
+        let replaced = self.delete(id)?;
+        self.rows.insert(id, row.clone())?;
+
+        for index in self.indexes.values_mut() {
+            index.insert(id, &row);
+        }
+        Ok(replaced)
+    }
+
+    /// Remove a row, returning it if it existed
+    pub fn delete(&mut self, id: usize) -> Result<Option<StoredRow>, QueryExecutionError> {
+        let Some(row) = self.rows.get(id)? else {
+            return Ok(None);
+        };
+        self.rows.delete(id)?;
+
+        for index in self.indexes.values_mut() {
+            index.remove(id, &row);
+        }
+        Ok(Some(row))
+    }
+
+    /// The new rows after an `UPDATE`, checking they can be written
+    pub fn updated_rows(
+        &self,
+        assignments: &[(String, Expression)],
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        for (column, value) in assignments {
+            self.columns.find_column(column)?;
+            for column in value.columns() {
+                self.columns.find_column(column)?;
+            }
+        }
+
+        self.matching_rows(predicate, snapshot)?
+            .into_iter()
+            .map(|(id, mut row)| {
+                let mut new_values = Vec::with_capacity(assignments.len());
+                for (column, value) in assignments {
+                    let value = value_of(value, &row)?;
+                    let type_info = self.columns.find_column(column)?.type_info;
+                    if !type_matches(type_info, &value) {
+                        return Err(QueryExecutionError::InsertTypeMismatch(type_info, value));
+                    }
+                    new_values.push((column.clone(), value));
+                }
+                // assignments all see the old row
+                row.data.extend(new_values);
+                Ok((id, row))
+            })
+            .collect()
+    }
+
+    /// The ids of the rows a `DELETE` removes, checking they can be written
+    pub fn deleted_ids(
+        &self,
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<usize>, QueryExecutionError> {
+        Ok(self
+            .matching_rows(predicate, snapshot)?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    /// The visible rows matching the predicate, failing if one of them was
+    /// changed by a concurrent transaction
+    fn matching_rows(
+        &self,
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        let rows = self.filter(predicate, snapshot)?;
+        self.check_writable(rows.iter().map(|(id, _)| *id), snapshot)?;
+        Ok(rows)
+    }
+
+    /// The ids of the visible rows matching the predicate
+    pub fn matching_ids(
+        &self,
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<usize>, QueryExecutionError> {
+        Ok(self
+            .filter(predicate, snapshot)?
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect())
+    }
+
+    /// Fail if one of the rows was changed by a concurrent transaction
+    pub fn check_writable(
+        &self,
+        mut ids: impl Iterator<Item = usize>,
+        snapshot: &Snapshot,
+    ) -> Result<(), QueryExecutionError> {
+        if ids.any(|id| !self.writable(id, snapshot)) {
+            return Err(QueryExecutionError::WriteConflict);
+        }
+        Ok(())
+    }
+
+    fn writable(&self, id: usize, snapshot: &Snapshot) -> bool {
+        match self.versions.get(&id) {
+            Some(chain) => chain.writable(snapshot),
+            None => true,
+        }
+    }
+
+    /// Add a pending version of a row, `None` deleting it
+    pub fn write(
+        &mut self,
+        id: usize,
+        row: Option<StoredRow>,
+        snapshot: &Snapshot,
+    ) -> Result<(), QueryExecutionError> {
+        if !self.writable(id, snapshot) {
+            return Err(QueryExecutionError::WriteConflict);
+        }
+
+        if !self.versions.contains_key(&id) {
+            let base = self.rows.get(id)?;
+            self.versions.insert(id, VersionChain::new(base));
+        }
+        if let Some(row) = &row {
+            for index in self.indexes.values_mut() {
+                index.insert(id, row);
+            }
+        }
+        self.next_id = self.next_id.max(id + 1);
+
+        let chain = self.versions.get_mut(&id).expect("chain was just added");
+        chain.push(Version {
+            stamp: Stamp::Pending(snapshot.tx),
+            row,
+        });
+        Ok(())
+    }
+
+    /// Drop the newest pending version of a row
+    pub fn undo_write(&mut self, id: usize) -> Result<(), QueryExecutionError> {
+        let Some(chain) = self.versions.get_mut(&id) else {
+            return Ok(());
+        };
+        let version = chain.pop();
+        if chain.is_settled() {
+            self.versions.remove(&id);
+        }
+
+        self.unindex(id, version.row)
+    }
+
+    /// Make the last version `tx` wrote of a row the committed one
+    pub fn commit_write(
+        &mut self,
+        id: usize,
+        tx: TxId,
+        ts: Timestamp,
+    ) -> Result<(), QueryExecutionError> {
+        let Some(chain) = self.versions.get_mut(&id) else {
+            return Ok(());
+        };
+        let Some((row, replaced)) = chain.commit(tx, ts) else {
+            return Ok(());
+        };
+
+        match row {
+            Some(row) => self.rows.insert(id, row)?,
+            None => {
+                self.rows.delete(id)?;
+            }
+        }
+        for version in replaced {
+            self.unindex(id, version.row)?;
+        }
+        Ok(())
+    }
+
+    /// Drop the versions older than what the snapshots taken at or after
+    /// `horizon` see
+    pub fn collect_garbage(&mut self, horizon: Timestamp) -> Result<(), QueryExecutionError> {
+        let mut dropped = Vec::new();
+        self.versions.retain(|id, chain| {
+            dropped.extend(
+                chain
+                    .collect_garbage(horizon)
+                    .into_iter()
+                    .map(|version| (*id, version.row)),
+            );
+            !chain.is_settled()
+        });
+
+        for (id, row) in dropped {
+            self.unindex(id, row)?;
+        }
+        Ok(())
+    }
+
+    /// Remove a dropped version from the indexes, unless a version still kept
+    /// has the same key
+    fn unindex(&mut self, id: usize, row: Option<StoredRow>) -> Result<(), QueryExecutionError> {
+        let Some(row) = row else {
+            return Ok(());
+        };
+        let kept: Vec<StoredRow> = match self.versions.get(&id) {
+            Some(chain) => chain.rows().cloned().collect(),
+            None => self.rows.get(id)?.into_iter().collect(),
+        };
+
+        for index in self.indexes.values_mut() {
+            let key = index.key(&row);
+            if kept.iter().all(|kept| index.key(kept) != key) {
+                index.remove(id, &row);
+            }
+        }
         Ok(())
     }
 
-    // select rows from a table, verify columns exist
-    pub fn select(&self, columns: Vec<String>) -> Result<TableIter, QueryExecutionError> {
-        let selected_columns = columns
+    pub fn drop_index(&mut self, name: &str) {
+        self.indexes.remove(name);
+    }
+
+    /// Release the storage of a dropped table
+    pub fn destroy(self) -> Result<(), QueryExecutionError> {
+        self.rows.destroy()
+    }
+
+    /// The rows the snapshot sees that match the predicate, in id order
+    pub fn filter(
+        &self,
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        let Some(predicate) = predicate else {
+            return self.visible_rows(snapshot);
+        };
+
+        for column in predicate.columns() {
+            self.columns.find_column(column)?;
+        }
+
+        let mut selected = Vec::new();
+        for (id, row) in self.candidates(Some(predicate), snapshot)? {
+            if satisfies(predicate, &row)? {
+                selected.push((id, row));
+            }
+        }
+        Ok(selected)
+    }
+
+    /// The rows the snapshot sees that may match the predicate, in id order
+    ///
+    /// Only the rows an index says can match are visited, if there is one,
+    /// so they still have to be checked against the predicate.
+    pub fn candidates(
+        &self,
+        predicate: Option<&Expression>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        match predicate.and_then(|predicate| self.index_lookup(predicate)) {
+            Some(ids) => self.rows_by_id(ids, snapshot),
+            None => self.visible_rows(snapshot),
+        }
+    }
+
+    /// Ids of the rows, of any version, that an index says can match the
+    /// predicate, in id order
+    ///
+    /// `None` if the index can't be used for it.
+    pub fn index_scan(&self, index: &str, predicate: &Expression) -> Option<Vec<usize>> {
+        let (index, column_values) = self
+            .usable_indexes(predicate)
+            .into_iter()
+            .find(|(name, _)| *name == index)?;
+        Some(self.index_ids(&index, column_values))
+    }
+
+    /// The versions the snapshot sees of the rows with the given ids
+    fn rows_by_id(
+        &self,
+        ids: Vec<usize>,
+        snapshot: &Snapshot,
+    ) -> Result<Vec<(usize, StoredRow)>, QueryExecutionError> {
+        ids.into_iter()
+            .filter_map(|id| {
+                self.get(id, snapshot)
+                    .transpose()
+                    .map(|row| row.map(|row| (id, row)))
+            })
+            .collect()
+    }
+
+    /// Ids of the rows that can match `predicate` according to the index
+    /// with the most columns that can be used, `None` if there is none
+    fn index_lookup(&self, predicate: &Expression) -> Option<Vec<usize>> {
+        let (index, column_values) = self
+            .usable_indexes(predicate)
             .into_iter()
-            .map(|column_name| {
-                self.columns
-                    .find_column(&column_name)
-                    .map(|col| col.clone())
+            .max_by_key(|(_, column_values)| column_values.len())?;
+        Some(self.index_ids(&index, column_values))
+    }
+
+    /// The indexes that can find the rows matching `predicate`, with the
+    /// values each of their columns can have
+    ///
+    /// An index is only usable if every one of its columns is constrained by a
+    /// `col = literal` or `col IN (literals)` conjunct.
+    pub fn usable_indexes(&self, predicate: &Expression) -> UsableIndexes {
+        let conjuncts = predicate.conjuncts();
+
+        self.indexes
+            .iter()
+            .filter_map(|(name, index)| {
+                let column_values = index
+                    .columns()
+                    .iter()
+                    .map(|column| Some((column.clone(), self.equality_values(column, &conjuncts)?)))
+                    .collect::<Option<Vec<_>>>()?;
+                Some((name.clone(), column_values))
             })
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect()
+    }
 
-        let col_info: Rc<ColumnInfo> = Rc::new(selected_columns.into());
+    /// Ids of the rows, of any version, whose indexed columns have one of
+    /// the allowed values
+    fn index_ids(&self, index: &str, column_values: Vec<(String, Vec<Value>)>) -> Vec<usize> {
+        let index = &self.indexes[index];
 
-        Ok(TableIter::new(self.rows.iter(), col_info))
+        // every combination of the allowed values for each column
+        let keys =
+            column_values
+                .into_iter()
+                .fold(vec![vec![]], |keys: Vec<Vec<Value>>, (_, values)| {
+                    keys.iter()
+                        .flat_map(|key| {
+                            values.iter().map(move |value| {
+                                let mut key = key.clone();
+                                key.push(value.clone());
+                                key
+                            })
+                        })
+                        .collect()
+                });
+
+        let mut ids: Vec<usize> = keys
+            .iter()
+            .flat_map(|key| index.get(key).iter().copied())
+            .collect();
+        ids.sort_unstable();
+        ids.dedup();
+        ids
     }
 
-    // iter on (row_id, row_values)
-    pub fn iter(&self) -> impl Iterator<Item = Row> {
-        self.into_iter()
+    /// The values `column` must equal to satisfy one of the `conjuncts`
+    fn equality_values(&self, column: &String, conjuncts: &[&Expression]) -> Option<Vec<Value>> {
+        let type_info = self.columns.find_column(column).ok()?.type_info;
+        let is_column = |expr: &Expression| matches!(expr, Expression::Column(c) if c == column);
+
+        conjuncts.iter().find_map(|conjunct| {
+            let values = match conjunct {
+                Expression::BinaryOp {
+                    left,
+                    op: BinaryOperator::Eq,
+                    right,
+                } => match (left.as_ref(), right.as_ref()) {
+                    (col, Expression::Literal(v)) | (Expression::Literal(v), col)
+                        if is_column(col) =>
+                    {
+                        vec![v.clone()]
+                    }
+                    _ => return None,
+                },
+                Expression::InList {
+                    expr,
+                    list,
+                    negated: false,
+                } if is_column(expr) => list
+                    .iter()
+                    .map(|item| match item {
+                        Expression::Literal(v) => Some(v.clone()),
+                        _ => None,
+                    })
+                    .collect::<Option<Vec<_>>>()?,
+                _ => return None,
+            };
+
+            // a lookup on a value of the wrong type would silently skip the
+            // type error a scan reports
+            values
+                .iter()
+                .all(|v| type_matches(type_info, v))
+                .then_some(values)
+        })
     }
 }
 
-// Table.into_iter() -> TableIter
-impl<'a> IntoIterator for &'a Table {
-    type Item = Row<'a>;
+/// Rows in id order, the versions of `changed` replacing the rows of
+/// `stored` with the same id, or hiding them if they are `None`
+pub(crate) struct Overlaid<S: Iterator, C: Iterator> {
+    stored: Peekable<S>,
+    changed: Peekable<C>,
+}
+
+impl<S, C> Overlaid<S, C>
+where
+    S: Iterator<Item = RowResult>,
+    C: Iterator<Item = (usize, Option<StoredRow>)>,
+{
+    pub fn new(stored: S, changed: C) -> Self {
+        Self {
+            stored: stored.peekable(),
+            changed: changed.peekable(),
+        }
+    }
+}
 
-    type IntoIter = TableIter<'a>;
+impl<S, C> Iterator for Overlaid<S, C>
+where
+    S: Iterator<Item = RowResult>,
+    C: Iterator<Item = (usize, Option<StoredRow>)>,
+{
+    type Item = RowResult;
 
-    fn into_iter(self) -> Self::IntoIter {
-        let col_info = Rc::new(self.columns.clone());
-        TableIter::new(self.rows.iter(), col_info)
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let stored = match self.stored.peek() {
+                Some(Ok((id, _))) => Some(*id),
+                Some(Err(_)) => return self.stored.next(),
+                None => None,
+            };
+            let changed = self.changed.peek().map(|(id, _)| *id);
+            match (stored, changed) {
+                (None, None) => return None,
+                (Some(stored), Some(changed)) if stored < changed => return self.stored.next(),
+                (Some(_), None) => return self.stored.next(),
+                (stored, Some(changed)) => {
+                    if stored == Some(changed) {
+                        self.stored.next();
+                    }
+                    if let Some((id, Some(row))) = self.changed.next() {
+                        return Some(Ok((id, row)));
+                    }
+                }
+            }
+        }
     }
 }
 
-/// Iterator of [`Row`]s from a table
-#[derive(Debug)]
-pub struct TableIter<'a> {
-    /// Underlying iterator over the btree_map
-    map_iter: std::collections::btree_map::Iter<'a, usize, StoredRow>,
-    /// The columns of the [`Table`]
-    pub columns: Rc<ColumnInfo>,
+/// Iterator of the [`Row`]s a query selects
+///
+/// The rows are computed as they are pulled, so evaluating the query can
+/// still fail while iterating.
+pub struct TableIter {
+    /// The operator producing the rows
+    rows: Operator,
+    /// The columns of the rows
+    pub columns: Arc<ColumnInfo>,
 }
 
-impl<'a> TableIter<'a> {
+impl TableIter {
     /// construct iter
-    pub fn new(
-        map_iter: std::collections::btree_map::Iter<'a, usize, StoredRow>,
-        columns: Rc<ColumnInfo>,
-    ) -> Self {
-        Self { map_iter, columns }
+    pub(crate) fn new(rows: Operator, columns: Arc<ColumnInfo>) -> Self {
+        Self { rows, columns }
+    }
+}
+
+impl fmt::Debug for TableIter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TableIter")
+            .field("columns", &self.columns)
+            .finish_non_exhaustive()
     }
 }
 
-impl<'a> Iterator for TableIter<'a> {
-    type Item = Row<'a>;
-    /// Iterator -> map id, StoredRow => new Row<columns, id, data(StoredRow)>
+impl Iterator for TableIter {
+    type Item = Result<Row, QueryExecutionError>;
+
     fn next(&mut self) -> Option<Self::Item> {
-        self.map_iter.next().map(|(id, data)| {
-            let projected_data = data
-                .data
-                .iter()
-                .filter_map(|(key, value)| self.columns.find_column(key).ok().map(|_| (key, value)))
-                .collect();
+        self.rows
+            .next()
+            .map(|values| Ok(Row::new(self.columns.clone(), values?)))
+    }
+}
 
-            Row::new(self.columns.clone(), *id, projected_data)
-        })
-        // self.map_iter
-        //     .next()
-        //     .map(|(id, data)| Row::new(self.columns.clone(), id.clone(), data))
+#[cfg(test)]
+mod tests {
+    use toy_sql_parser::parse::Parse;
+
+    use super::*;
+    use crate::storage::MemoryStorage;
+
+    fn insert(table: &mut Table, values: Vec<Value>) {
+        let (id, row) = table.new_row(values).unwrap();
+        table.insert(id, row).unwrap();
+    }
+
+    fn table() -> Table {
+        let mut table = Table::new(
+            vec![
+                Column {
+                    name: "id".into(),
+                    type_info: SqlTypeInfo::Int,
+                },
+                Column {
+                    name: "name".into(),
+                    type_info: SqlTypeInfo::String,
+                },
+            ],
+            Box::<MemoryStorage>::default(),
+        );
+
+        for (id, name) in [("1", "a"), ("2", "b"), ("3", "a"), ("4", "c")] {
+            insert(
+                &mut table,
+                vec![
+                    Value::Number(id.parse().unwrap()),
+                    Value::String(name.into()),
+                ],
+            );
+        }
+
+        table
+    }
+
+    fn number(n: u64) -> Value {
+        Value::Number(n.into())
+    }
+
+    fn name(name: &str) -> Value {
+        Value::String(name.into())
+    }
+
+    fn predicate(input: &str) -> Expression {
+        Expression::parse_from_raw(input).unwrap().1
+    }
+
+    const SNAPSHOT: Snapshot = Snapshot { tx: 1, ts: 0 };
+
+    fn selected_ids(table: &Table, input: &str) -> Vec<usize> {
+        selected_ids_at(table, input, &SNAPSHOT)
+    }
+
+    fn selected_ids_at(table: &Table, input: &str, snapshot: &Snapshot) -> Vec<usize> {
+        table
+            .filter(Some(&predicate(input)), snapshot)
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    #[test]
+    fn test_hash_index_lookup() {
+        let mut table = table();
+        table
+            .create_index("name_idx".into(), vec!["name".into()])
+            .unwrap();
+        // rows inserted after the index is created are indexed too
+        insert(
+            &mut table,
+            vec![Value::Number(5.into()), Value::String("b".into())],
+        );
+
+        assert_eq!(
+            table.index_lookup(&predicate("name = 'a'")),
+            Some(vec![0, 2])
+        );
+        assert_eq!(
+            table.index_lookup(&predicate("id > 1 AND name IN ('b', 'c')")),
+            Some(vec![1, 3, 4])
+        );
+        assert_eq!(table.index_lookup(&predicate("name = 'a' OR id = 2")), None);
+        assert_eq!(table.index_lookup(&predicate("id = 2")), None);
+
+        assert_eq!(selected_ids(&table, "name = 'a'"), vec![0, 2]);
+        assert_eq!(
+            selected_ids(&table, "id > 2 AND name IN ('b', 'a')"),
+            vec![2, 4]
+        );
+    }
+
+    #[test]
+    fn test_select_without_index() {
+        let table = table();
+
+        assert_eq!(selected_ids(&table, "name = 'a'"), vec![0, 2]);
+        assert_eq!(selected_ids(&table, "NOT (id <= 2 OR name = 'c')"), vec![2]);
+        assert!(table
+            .filter(Some(&predicate("name = 1")), &SNAPSHOT)
+            .is_err());
+    }
+
+    #[test]
+    fn test_versions_in_index() {
+        let mut table = table();
+        table
+            .create_index("name_idx".into(), vec!["name".into()])
+            .unwrap();
+        let writer = Snapshot { tx: 2, ts: 0 };
+
+        let assignments = [("name".to_string(), predicate("'z'"))];
+        let updated = table
+            .updated_rows(&assignments, Some(&predicate("name = 'a'")), &writer)
+            .unwrap();
+        for (id, row) in updated {
+            table.write(id, Some(row), &writer).unwrap();
+        }
+        table.write(3, None, &writer).unwrap();
+
+        // each snapshot finds its own version through the index
+        assert_eq!(selected_ids_at(&table, "name = 'a'", &writer), vec![]);
+        assert_eq!(selected_ids_at(&table, "name = 'z'", &writer), vec![0, 2]);
+        assert_eq!(selected_ids(&table, "name = 'a'"), vec![0, 2]);
+        assert_eq!(selected_ids(&table, "name = 'c'"), vec![3]);
+        assert!(matches!(
+            table.updated_rows(&assignments, None, &SNAPSHOT),
+            Err(QueryExecutionError::WriteConflict)
+        ));
+
+        for id in [0, 2, 3] {
+            table.commit_write(id, writer.tx, 1).unwrap();
+        }
+        table.collect_garbage(1).unwrap();
+        assert_eq!(table.version_count(), 0);
+        assert_eq!(table.index_lookup(&predicate("name = 'a'")), Some(vec![]));
+        assert_eq!(table.index_lookup(&predicate("name = 'c'")), Some(vec![]));
+        assert_eq!(selected_ids(&table, "name = 'z'"), vec![0, 2]);
+    }
+
+    #[test]
+    fn test_row_value_count() {
+        let mut table = table();
+        for values in [vec![number(5)], vec![number(5), name("e"), number(6)]] {
+            assert!(matches!(
+                table.new_row(values),
+                Err(QueryExecutionError::InsertValueCount(2, _))
+            ));
+        }
+
+        // rows stored without a column are indexed with NULL for it
+        let short: HashMap<_, _> = [("id".to_string(), number(5))].into();
+        table.insert(4, short.into()).unwrap();
+        table
+            .create_index("name_idx".into(), vec!["name".into()])
+            .unwrap();
+        assert_eq!(selected_ids(&table, "name = 'a'"), vec![0, 2]);
     }
 }
@@ -0,0 +1,80 @@
+use std::collections::HashSet;
+
+use toy_sql_parser::commands::IsolationLevel;
+
+use crate::{mvcc::Snapshot, wal::LogRecord};
+
+/// How to revert a change made inside a transaction
+#[derive(Debug)]
+pub(crate) enum Undo {
+    /// Drop the newest pending version of a row
+    Row {
+        table: String,
+        id: usize,
+    },
+    DropTable(String),
+    DropIndex {
+        table: String,
+        name: String,
+    },
+}
+
+/// The changes of an open transaction
+///
+/// Changes are added to the tables right away as pending versions only the
+/// transaction sees, and only reach the log once it commits.
+#[derive(Debug)]
+pub(crate) struct Transaction {
+    pub snapshot: Snapshot,
+    pub isolation: IsolationLevel,
+    /// Tables the transaction read rows of
+    pub reads: HashSet<String>,
+    /// Records to log on commit, in order
+    pub records: Vec<LogRecord>,
+    /// Undo of every applied record, in order
+    pub undo: Vec<Undo>,
+    /// Open savepoints, innermost last
+    pub savepoints: Vec<Savepoint>,
+}
+
+/// A point a transaction can be partially rolled back to
+#[derive(Debug)]
+pub(crate) struct Savepoint {
+    pub name: String,
+    /// Number of records and undo entries when the savepoint was made
+    pub len: usize,
+}
+
+impl Transaction {
+    pub fn new(snapshot: Snapshot) -> Self {
+        Self {
+            snapshot,
+            isolation: IsolationLevel::default(),
+            reads: HashSet::new(),
+            records: Vec::new(),
+            undo: Vec::new(),
+            savepoints: Vec::new(),
+        }
+    }
+
+    /// Whether the transaction read or changed anything yet
+    pub fn has_run_queries(&self) -> bool {
+        !self.reads.is_empty() || !self.undo.is_empty()
+    }
+
+    /// Tables the transaction changed rows of
+    pub fn writes(&self) -> HashSet<String> {
+        self.undo
+            .iter()
+            .filter_map(|undo| match undo {
+                Undo::Row { table, .. } => Some(table.clone()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Position of the innermost savepoint with the given name
+    pub fn find_savepoint(&self, name: &str) -> Option<usize> {
+        self.savepoints.iter().rposition(|s| s.name == name)
+    }
+}
@@ -0,0 +1,354 @@
+use std::{
+    ffi::OsString,
+    fs::{File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use toy_sql_parser::{commands::Engine, Column};
+
+use crate::{
+    btree::{Page, PageId},
+    table::StoredRow,
+};
+
+/// length | checksum
+const FRAME_HEADER_SIZE: usize = 4 + 4;
+
+/// A change to the database, logged before it is applied
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(crate) enum LogRecord {
+    CreateTable {
+        name: String,
+        columns: Vec<Column>,
+        engine: Engine,
+    },
+    CreateIndex {
+        name: String,
+        table: String,
+        columns: Vec<String>,
+    },
+    /// Add a row, or replace the row with the same id
+    Insert {
+        table: String,
+        id: usize,
+        row: StoredRow,
+    },
+    Delete {
+        table: String,
+        id: usize,
+    },
+}
+
+/// What a frame of the log holds
+#[derive(Debug, Serialize, Deserialize)]
+enum Entry<R> {
+    Record(R),
+    /// The records since the previous commit were all written
+    Commit,
+    /// Content of a page written by a checkpoint
+    Page(PageId, Vec<u8>),
+    /// The pages since the previous commit or checkpoint were all written,
+    /// they hold every change logged before them
+    Checkpoint,
+    /// Content of a dirty page evicted from the buffer pool, only read back
+    /// until the next checkpoint and ignored by recovery
+    Evicted(PageId, Vec<u8>),
+}
+
+/// What the log holds after the last checkpoint that was logged entirely
+#[derive(Debug, Default)]
+pub(crate) struct Recovery {
+    /// Pages of the checkpoint, which may not have made it to the database file
+    pub pages: Vec<(PageId, Page)>,
+    /// Records committed after the checkpoint, in order
+    pub records: Vec<LogRecord>,
+}
+
+/// The log next to the database file at `path`
+pub(crate) fn path_for(path: &Path) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push("-wal");
+    name.into()
+}
+
+/// Append only log of the committed changes
+///
+/// Every entry is a frame `length | crc32 | bincode entry`, so a frame torn by
+/// a crash is detected and dropped when the log is opened.
+///
+/// A checkpoint logs the pages it is about to write to the database file
+/// before writing them, then empties the log. If it crashes half way the
+/// pages are still in the log to be written again.
+///
+/// The log also holds the dirty pages the buffer pool has no room for, the
+/// database file must keep the state of the last checkpoint so they can't go
+/// there.
+#[derive(Debug)]
+pub(crate) struct Wal {
+    file: File,
+    /// size of the file in bytes
+    size: u64,
+}
+
+impl Wal {
+    /// Open the log, creating it if it doesn't exist
+    ///
+    /// Anything after the last commit or checkpoint is cut off the file.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<(Self, Recovery)> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+
+        let mut recovery = Recovery::default();
+        let mut uncommitted = Vec::new();
+        let mut pages = Vec::new();
+        let mut offset = 0;
+        let mut committed = 0;
+        while let Some((entry, len)) = decode_frame(&bytes[offset..]) {
+            offset += len;
+            match entry {
+                Entry::Record(record) => uncommitted.push(record),
+                Entry::Commit => {
+                    recovery.records.append(&mut uncommitted);
+                    committed = offset;
+                }
+                Entry::Page(id, data) => {
+                    let Ok(page) = data.into_boxed_slice().try_into() else {
+                        break;
+                    };
+                    pages.push((id, page));
+                }
+                Entry::Checkpoint => {
+                    recovery.pages = std::mem::take(&mut pages);
+                    recovery.records.clear();
+                    committed = offset;
+                }
+                Entry::Evicted(..) => {}
+            }
+        }
+
+        // new frames must not follow a torn one or join an unfinished commit
+        file.set_len(committed as u64)?;
+        file.seek(SeekFrom::End(0))?;
+
+        let wal = Self {
+            file,
+            size: committed as u64,
+        };
+        Ok((wal, recovery))
+    }
+
+    /// Size of the log in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn append(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.append(bytes)?;
+        self.file.sync_data()
+    }
+
+    /// Durably append the records followed by a commit
+    pub fn commit(&mut self, records: &[LogRecord]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        for record in records {
+            encode_frame(&Entry::Record(record), &mut bytes)?;
+        }
+        encode_frame(&Entry::<&LogRecord>::Commit, &mut bytes)?;
+
+        self.write(&bytes)
+    }
+
+    /// Log a page a checkpoint is about to write, it only counts once
+    /// [`log_checkpoint`](Self::log_checkpoint) follows
+    pub fn log_page(&mut self, id: PageId, page: &Page) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        encode_frame(&Entry::Page(id, page.to_vec()), &mut bytes)?;
+        self.append(&bytes)
+    }
+
+    /// Durably end the pages logged for a checkpoint
+    pub fn log_checkpoint(&mut self) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        encode_frame(&Entry::Checkpoint, &mut bytes)?;
+        self.write(&bytes)
+    }
+
+    /// Keep an evicted dirty page until the next checkpoint, returning where
+    /// to [`read_evicted`](Self::read_evicted) it from
+    ///
+    /// Nothing depends on it after a crash, so it isn't synced.
+    pub fn log_evicted(&mut self, id: PageId, page: &Page) -> io::Result<u64> {
+        let offset = self.size;
+        let mut bytes = Vec::new();
+        encode_frame(&Entry::Evicted(id, page.to_vec()), &mut bytes)?;
+        self.append(&bytes)?;
+        Ok(offset)
+    }
+
+    /// Read back a page logged by [`log_evicted`](Self::log_evicted)
+    pub fn read_evicted(&mut self, offset: u64) -> io::Result<Page> {
+        let mut header = [0; FRAME_HEADER_SIZE];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut header)?;
+        let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+        let mut frame = header.to_vec();
+        frame.resize(FRAME_HEADER_SIZE + len, 0);
+        self.file.read_exact(&mut frame[FRAME_HEADER_SIZE..])?;
+        // new frames are appended where the cursor is
+        self.file.seek(SeekFrom::End(0))?;
+
+        match decode_frame(&frame) {
+            Some((Entry::Evicted(_, data), _)) => data
+                .into_boxed_slice()
+                .try_into()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "evicted page size")),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "damaged evicted page in the log",
+            )),
+        }
+    }
+
+    /// Empty the log once a checkpoint made it to the database file
+    pub fn truncate(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.sync_data()?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn encode_frame(entry: &Entry<&LogRecord>, out: &mut Vec<u8>) -> io::Result<()> {
+    let payload =
+        bincode::serialize(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+    out.extend_from_slice(&payload);
+    Ok(())
+}
+
+/// The entry at the start of `bytes` and the size of its frame, `None` if
+/// the frame is incomplete or damaged
+fn decode_frame(bytes: &[u8]) -> Option<(Entry<LogRecord>, usize)> {
+    let header = bytes.get(..FRAME_HEADER_SIZE)?;
+    let len = u32::from_le_bytes(header[..4].try_into().unwrap()) as usize;
+    let checksum = u32::from_le_bytes(header[4..].try_into().unwrap());
+
+    let payload = bytes.get(FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + len)?;
+    if crc32fast::hash(payload) != checksum {
+        return None;
+    }
+
+    let entry = bincode::deserialize(payload).ok()?;
+    Some((entry, FRAME_HEADER_SIZE + len))
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::*;
+    use crate::btree::PAGE_SIZE;
+
+    fn insert(id: usize) -> LogRecord {
+        LogRecord::Insert {
+            table: "foo".into(),
+            id,
+            row: StoredRow::default(),
+        }
+    }
+
+    #[test]
+    fn test_torn_frame() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db-wal");
+
+        {
+            let (mut wal, recovery) = Wal::open(&path).unwrap();
+            assert!(recovery.records.is_empty());
+            wal.commit(&[insert(0), insert(1)]).unwrap();
+            wal.commit(&[insert(2)]).unwrap();
+        }
+
+        // cut the last commit marker in half
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 3).unwrap();
+
+        {
+            let (mut wal, recovery) = Wal::open(&path).unwrap();
+            assert_eq!(recovery.records, vec![insert(0), insert(1)]);
+            wal.commit(&[insert(3)]).unwrap();
+        }
+
+        let (_, recovery) = Wal::open(&path).unwrap();
+        assert_eq!(recovery.records, vec![insert(0), insert(1), insert(3)]);
+    }
+
+    #[test]
+    fn test_checkpoint_replaces_records() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db-wal");
+        let page = Box::new([7; PAGE_SIZE]);
+
+        {
+            let (mut wal, _) = Wal::open(&path).unwrap();
+            wal.commit(&[insert(0)]).unwrap();
+            wal.log_page(3, &page).unwrap();
+            wal.log_checkpoint().unwrap();
+            wal.commit(&[insert(1)]).unwrap();
+            // a checkpoint torn half way is ignored
+            wal.log_page(4, &page).unwrap();
+            wal.log_checkpoint().unwrap();
+        }
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let len = file.metadata().unwrap().len();
+        file.set_len(len - 1).unwrap();
+
+        let (mut wal, recovery) = Wal::open(&path).unwrap();
+        assert_eq!(recovery.pages, vec![(3, page.clone())]);
+        assert_eq!(recovery.records, vec![insert(1)]);
+
+        wal.truncate().unwrap();
+        assert_eq!(wal.size(), 0);
+        let (_, recovery) = Wal::open(&path).unwrap();
+        assert!(recovery.pages.is_empty() && recovery.records.is_empty());
+    }
+
+    #[test]
+    fn test_evicted_pages() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db-wal");
+        let page = Box::new([7; PAGE_SIZE]);
+
+        {
+            let (mut wal, _) = Wal::open(&path).unwrap();
+            wal.commit(&[insert(0)]).unwrap();
+            let offset = wal.log_evicted(3, &page).unwrap();
+            wal.commit(&[insert(1)]).unwrap();
+            assert_eq!(wal.read_evicted(offset).unwrap(), page);
+            // reading doesn't move where frames are appended
+            wal.commit(&[insert(2)]).unwrap();
+        }
+
+        let (_, recovery) = Wal::open(&path).unwrap();
+        assert!(recovery.pages.is_empty());
+        assert_eq!(recovery.records, vec![insert(0), insert(1), insert(2)]);
+    }
+}
@@ -0,0 +1,338 @@
+use std::{iter, sync::Arc};
+
+use bigdecimal::ToPrimitive;
+use toy_sql_parser::{
+    expression::{AggregateFunction, Expression, FrameBound, Window, WindowFunction},
+    value::Value,
+};
+
+use crate::{
+    aggregate::Accumulator,
+    error::QueryExecutionError,
+    eval::value_of,
+    operator::{compare_keys, compare_sort_keys, sort_by_key, Operator, Tuple},
+    plan::{Schema, SchemaRow},
+    spill::MemoryBudget,
+};
+
+/// Add the value of each window function to the rows, kept in their order
+///
+/// Each window sorts the rows of its partitions its own way, rows ordered
+/// alike keeping their order.
+pub(crate) fn compute_windows(
+    schema: &Schema,
+    mut rows: Vec<Tuple>,
+    windows: &[Expression],
+) -> Result<Vec<Tuple>, QueryExecutionError> {
+    let mut columns = Vec::with_capacity(windows.len());
+    for expr in windows {
+        columns.push(compute_window(schema, &rows, expr)?.into_iter());
+    }
+    for row in &mut rows {
+        row.extend(
+            columns
+                .iter_mut()
+                .map(|values| values.next().expect("a value per row")),
+        );
+    }
+    Ok(rows)
+}
+
+/// The value of a window function for each row
+fn compute_window(
+    schema: &Schema,
+    rows: &[Tuple],
+    expr: &Expression,
+) -> Result<Vec<Value>, QueryExecutionError> {
+    let Expression::Window { window, .. } = expr else {
+        unreachable!("{expr} is not a window function");
+    };
+
+    // the partition and sort keys of each row, with its position
+    let mut keyed = rows
+        .iter()
+        .enumerate()
+        .map(|(position, values)| {
+            let row = SchemaRow { schema, values };
+            let partition = window
+                .partition_by
+                .iter()
+                .map(|expr| value_of(expr, &row))
+                .collect::<Result<Tuple, _>>()?;
+            let order = window
+                .order_by
+                .iter()
+                .map(|order| value_of(&order.expr, &row))
+                .collect::<Result<Tuple, _>>()?;
+            Ok((partition, order, position))
+        })
+        .collect::<Result<Vec<_>, QueryExecutionError>>()?;
+    let descending: Vec<_> = window
+        .order_by
+        .iter()
+        .map(|order| order.descending)
+        .collect();
+    keyed.sort_by(|(left, left_order, _), (right, right_order, _)| {
+        compare_keys(left, right)
+            .then_with(|| compare_sort_keys(left_order, right_order, &descending))
+    });
+
+    let mut values = vec![Value::Null; rows.len()];
+    let mut start = 0;
+    while start < keyed.len() {
+        let len = keyed[start..]
+            .iter()
+            .take_while(|(partition, ..)| compare_keys(partition, &keyed[start].0).is_eq())
+            .count();
+        let partition = &keyed[start..start + len];
+        let partition_rows: Vec<_> = partition
+            .iter()
+            .map(|(_, _, position)| SchemaRow {
+                schema,
+                values: &rows[*position],
+            })
+            .collect();
+        let orders: Vec<_> = partition.iter().map(|(_, order, _)| order).collect();
+        let computed = compute_partition(expr, &partition_rows, &orders)?;
+        for ((_, _, position), value) in partition.iter().zip(computed) {
+            values[*position] = value;
+        }
+        start += len;
+    }
+    Ok(values)
+}
+
+/// Add the value of each window function to the rows like
+/// [`compute_windows`], for rows that don't fit in memory
+///
+/// For each window the rows are sorted by partition, then by position so
+/// they keep their order once its value is added, both sorts spilling to
+/// temporary files. Only the rows of one partition are held at once.
+pub(crate) fn compute_windows_spilled(
+    schema: &Schema,
+    mut rows: Operator,
+    windows: &[Expression],
+    budget: &Arc<MemoryBudget>,
+) -> Result<Operator, QueryExecutionError> {
+    for expr in windows {
+        rows = compute_window_spilled(schema, rows, expr, budget)?;
+    }
+    Ok(rows)
+}
+
+/// Add the value of a window function to each row, in their order
+fn compute_window_spilled(
+    schema: &Schema,
+    rows: Operator,
+    expr: &Expression,
+    budget: &Arc<MemoryBudget>,
+) -> Result<Operator, QueryExecutionError> {
+    let Expression::Window { window, .. } = expr else {
+        unreachable!("{expr} is not a window function");
+    };
+
+    // sorted by partition, sort keys, then position
+    let partitions = window.partition_by.len();
+    let orders = window.order_by.len();
+    let keyed = rows.enumerate().map(|(position, values)| {
+        let values = values?;
+        let row = SchemaRow {
+            schema,
+            values: &values,
+        };
+        let mut key = window
+            .partition_by
+            .iter()
+            .map(|expr| value_of(expr, &row))
+            .collect::<Result<Tuple, _>>()?;
+        for order in &window.order_by {
+            key.push(value_of(&order.expr, &row)?);
+        }
+        key.push(number(position));
+        Ok((key, values))
+    });
+    let mut descending = vec![false; partitions];
+    descending.extend(window.order_by.iter().map(|order| order.descending));
+    let mut sorted = sort_by_key(keyed, descending, budget)?.peekable();
+
+    let computed = iter::from_fn(move || {
+        let first = match sorted.next()? {
+            Ok(first) => first,
+            Err(e) => return Some(vec![Err(e)]),
+        };
+        let mut partition = vec![first];
+        while let Some(Ok((key, _))) = sorted.peek() {
+            if compare_keys(&key[..partitions], &partition[0].0[..partitions]).is_ne() {
+                break;
+            }
+            partition.extend(sorted.next().and_then(Result::ok));
+        }
+
+        let rows: Vec<_> = partition
+            .iter()
+            .map(|(_, values)| SchemaRow { schema, values })
+            .collect();
+        let keys: Vec<Tuple> = partition
+            .iter()
+            .map(|(key, _)| key[partitions..partitions + orders].to_vec())
+            .collect();
+        let computed = match compute_partition(expr, &rows, &keys.iter().collect::<Vec<_>>()) {
+            Ok(computed) => computed,
+            Err(e) => return Some(vec![Err(e)]),
+        };
+        let rows = partition
+            .into_iter()
+            .zip(computed)
+            .map(|((mut key, mut values), value)| {
+                values.push(value);
+                Ok((key.split_off(partitions + orders), values))
+            });
+        Some(rows.collect())
+    });
+    let numbered = sort_by_key(computed.flatten(), vec![], budget)?;
+    Ok(Box::new(numbered.map(|row| row.map(|(_, values)| values))))
+}
+
+/// The value of a window function for each row of a sorted partition, given
+/// their sort keys
+fn compute_partition(
+    expr: &Expression,
+    rows: &[SchemaRow<'_>],
+    orders: &[&Tuple],
+) -> Result<Vec<Value>, QueryExecutionError> {
+    let Expression::Window {
+        function,
+        args,
+        window,
+    } = expr
+    else {
+        unreachable!("{expr} is not a window function");
+    };
+
+    // the bounds of the rows ordered like each one, its peers
+    let mut peers = Vec::with_capacity(rows.len());
+    for position in 0..rows.len() {
+        let first = match position {
+            0 => 0,
+            _ if compare_keys(orders[position - 1], orders[position]).is_eq() => {
+                let (first, _) = peers[position - 1];
+                first
+            }
+            _ => position,
+        };
+        let last = position
+            + orders[position..]
+                .iter()
+                .take_while(|order| compare_keys(order, orders[position]).is_eq())
+                .count();
+        peers.push((first, last));
+    }
+
+    match function {
+        WindowFunction::RowNumber => Ok((1..=rows.len()).map(number).collect()),
+        WindowFunction::Rank => Ok(peers.iter().map(|(first, _)| number(first + 1)).collect()),
+        WindowFunction::DenseRank => {
+            let mut rank = 0;
+            let ranks = peers
+                .iter()
+                .enumerate()
+                .map(|(position, (first, _))| {
+                    if *first == position {
+                        rank += 1;
+                    }
+                    number(rank)
+                })
+                .collect();
+            Ok(ranks)
+        }
+        WindowFunction::Lag | WindowFunction::Lead => (0..rows.len())
+            .map(|position| {
+                let row = &rows[position];
+                let offset = match args.get(1) {
+                    Some(offset) => match value_of(offset, row)? {
+                        Value::Number(n) => n.to_usize(),
+                        _ => None,
+                    }
+                    .ok_or_else(|| QueryExecutionError::InvalidExpression(expr.to_string()))?,
+                    None => 1,
+                };
+                let target = match function {
+                    WindowFunction::Lag => position.checked_sub(offset),
+                    _ => position.checked_add(offset),
+                };
+                match (target.and_then(|target| rows.get(target)), args.get(2)) {
+                    (Some(target), _) => value_of(&args[0], target),
+                    (None, Some(default)) => value_of(default, row),
+                    (None, None) => Ok(Value::Null),
+                }
+            })
+            .collect(),
+        WindowFunction::Aggregate(function) => {
+            aggregate_frames(*function, args.first(), window, rows, &peers)
+        }
+    }
+}
+
+/// The aggregate of the rows of the frame of each row
+///
+/// Frames starting at the first row of the partition grow from one row to
+/// the next, their values are only added once.
+fn aggregate_frames(
+    function: AggregateFunction,
+    arg: Option<&Expression>,
+    window: &Window,
+    rows: &[SchemaRow<'_>],
+    peers: &[(usize, usize)],
+) -> Result<Vec<Value>, QueryExecutionError> {
+    let values = rows
+        .iter()
+        .map(|row| match arg {
+            Some(arg) => value_of(arg, row),
+            // COUNT(*) counts every row
+            None => Ok(number(1)),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bound = |bound: FrameBound, position: usize| match bound {
+        FrameBound::UnboundedPreceding => 0,
+        FrameBound::Preceding(n) => position.saturating_sub(n as usize),
+        FrameBound::CurrentRow => position,
+        FrameBound::Following(n) => position.saturating_add(n as usize),
+        FrameBound::UnboundedFollowing => rows.len(),
+    };
+
+    let mut running = Accumulator::new(function, false);
+    let mut added = 0;
+    let mut aggregates = Vec::with_capacity(rows.len());
+    for (position, (_, last_peer)) in peers.iter().enumerate() {
+        let (start, end) = match &window.frame {
+            Some(frame) => (
+                bound(frame.start, position),
+                bound(frame.end, position).saturating_add(1),
+            ),
+            None if window.order_by.is_empty() => (0, rows.len()),
+            None => (0, *last_peer),
+        };
+        let end = end.min(rows.len());
+
+        let aggregate = if start == 0 && end >= added {
+            for value in &values[added..end] {
+                running.add(value.clone())?;
+            }
+            added = end;
+            running.clone().finish()
+        } else {
+            let mut accumulator = Accumulator::new(function, false);
+            for value in values.get(start..end).unwrap_or_default() {
+                accumulator.add(value.clone())?;
+            }
+            accumulator.finish()
+        };
+        aggregates.push(aggregate);
+    }
+    Ok(aggregates)
+}
+
+pub(crate) fn number(n: usize) -> Value {
+    Value::Number((n as u64).into())
+}
@@ -1,24 +1,43 @@
 use nom::{
     branch::alt,
-    character::complete::{char, multispace0},
-    combinator::{eof, map},
+    character::complete::{char, multispace0, multispace1},
+    combinator::{eof, map, opt},
     error::context,
     multi::many1,
-    sequence::{preceded, tuple},
+    sequence::{preceded, terminated, tuple},
 };
+use nom_supreme::tag::complete::tag_no_case;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    commands::{CreateStatement, InsertStatement, SelectStatement},
+    commands::{
+        CreateIndexStatement, CreateStatement, DeleteStatement, InsertStatement, SelectStatement,
+        TransactionStatement, UpdateStatement,
+    },
     error::FormattedError,
-    parse::Parse,
+    parse::{identifier, Parse},
 };
 
 #[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum SqlQuery {
     Select(SelectStatement),
     Insert(InsertStatement),
+    Update(UpdateStatement),
+    Delete(DeleteStatement),
     Create(CreateStatement),
+    CreateIndex(CreateIndexStatement),
+    /// Write the changes in the log to the database file
+    Checkpoint,
+    Transaction(TransactionStatement),
+    /// Show the plan of a select, running it to measure its operators if
+    /// `analyze` is set
+    Explain {
+        select: SelectStatement,
+        analyze: bool,
+    },
+    /// Collect the statistics the planner estimates costs with, of a table or
+    /// of all of them
+    Analyze(Option<String>),
 }
 
 impl<'a> Parse<'a> for SqlQuery {
@@ -30,8 +49,32 @@ impl<'a> Parse<'a> for SqlQuery {
                 tuple((
                     alt((
                         map(SelectStatement::parse, SqlQuery::Select),
+                        map(
+                            tuple((
+                                tag_no_case("explain"),
+                                multispace1,
+                                opt(terminated(tag_no_case("analyze"), multispace1)),
+                                SelectStatement::parse,
+                            )),
+                            |(_, _, analyze, select)| SqlQuery::Explain {
+                                select,
+                                analyze: analyze.is_some(),
+                            },
+                        ),
                         map(InsertStatement::parse, SqlQuery::Insert),
+                        map(UpdateStatement::parse, SqlQuery::Update),
+                        map(DeleteStatement::parse, SqlQuery::Delete),
                         map(CreateStatement::parse, SqlQuery::Create),
+                        map(CreateIndexStatement::parse, SqlQuery::CreateIndex),
+                        map(tag_no_case("checkpoint"), |_| SqlQuery::Checkpoint),
+                        map(TransactionStatement::parse, SqlQuery::Transaction),
+                        map(
+                            preceded(
+                                tag_no_case("analyze"),
+                                opt(preceded(multispace1, identifier)),
+                            ),
+                            SqlQuery::Analyze,
+                        ),
                     )),
                     multispace0,
                     char(';'),
@@ -78,16 +121,24 @@ pub fn parse_multiple_queries(input: &str) -> Result<Vec<SqlQuery>, FormattedErr
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::{commands::TableRef, expression::Expression};
     #[test]
     fn test_error() {
-        let query = SqlQuery::parse_from_raw("select fart;");
+        let query = SqlQuery::parse_from_raw("select fart from;");
         assert!(query.is_err(), "expected parse to fail, got {query:?}");
     }
     #[test]
     fn test_select() {
         let expected = SelectStatement {
-            table: "t1".to_string(),
-            fields: vec!["foo".to_string(), "bar".to_string()],
+            table: Some(TableRef {
+                name: "t1".to_string(),
+                ..Default::default()
+            }),
+            fields: vec![
+                Expression::Column("foo".to_string()).into(),
+                Expression::Column("bar".to_string()).into(),
+            ],
+            ..Default::default()
         };
         assert_eq!(
             SqlQuery::parse_from_raw("select foo, bar from t1;")
@@ -96,4 +147,44 @@ mod tests {
             SqlQuery::Select(expected)
         )
     }
+
+    #[test]
+    fn test_checkpoint() {
+        assert_eq!(
+            SqlQuery::parse_from_raw("CHECKPOINT ;").unwrap().1,
+            SqlQuery::Checkpoint
+        )
+    }
+
+    #[test]
+    fn test_analyze() {
+        assert_eq!(
+            SqlQuery::parse_from_raw("ANALYZE;").unwrap().1,
+            SqlQuery::Analyze(None)
+        );
+        assert_eq!(
+            SqlQuery::parse_from_raw("analyze foo ;").unwrap().1,
+            SqlQuery::Analyze(Some("foo".into()))
+        );
+    }
+
+    #[test]
+    fn test_explain() {
+        let SqlQuery::Explain { select, analyze } =
+            SqlQuery::parse_from_raw("EXPLAIN select foo from t1;")
+                .unwrap()
+                .1
+        else {
+            panic!("expected explain");
+        };
+        assert_eq!(select.table.unwrap().name, "t1");
+        assert!(!analyze);
+
+        assert!(matches!(
+            SqlQuery::parse_from_raw("explain analyze select foo from t1;")
+                .unwrap()
+                .1,
+            SqlQuery::Explain { analyze: true, .. }
+        ));
+    }
 }
@@ -6,7 +6,7 @@ use derive_more::Display;
 use nom::{
     branch::alt,
     character::complete::{char, multispace0, multispace1},
-    combinator::map,
+    combinator::{map, opt},
     error::context,
     sequence::{preceded, separated_pair, tuple},
 };
@@ -20,9 +20,10 @@ use crate::parse::{comma_sep, identifier, Parse, ParseResult, RawSpan};
 pub enum SqlTypeInfo {
     String,
     Int,
+    Bool,
 }
 
-// parses "string | int"
+// parses "string | int | bool"
 impl<'a> Parse<'a> for SqlTypeInfo {
     fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
         // context will help give better error messages later on
@@ -32,6 +33,7 @@ impl<'a> Parse<'a> for SqlTypeInfo {
             alt((
                 map(tag_no_case("string"), |_| Self::String),
                 map(tag_no_case("int"), |_| Self::Int),
+                map(tag_no_case("bool"), |_| Self::Bool),
             )),
         )(input)
     }
@@ -60,11 +62,35 @@ impl<'a> Parse<'a> for Column {
     }
 }
 
+/// The storage engine a table's rows are kept in
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum Engine {
+    #[display(fmt = "memory")]
+    Memory,
+    #[display(fmt = "btree")]
+    BTree,
+}
+
+// parses "memory | btree"
+impl<'a> Parse<'a> for Engine {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "Engine",
+            alt((
+                map(tag_no_case("memory"), |_| Self::Memory),
+                map(tag_no_case("btree"), |_| Self::BTree),
+            )),
+        )(input)
+    }
+}
+
 /// The table and its columns to create
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct CreateStatement {
     pub table: String,
     pub columns: Vec<Column>,
+    /// The engine picked with `ENGINE = <engine>`, if any
+    pub engine: Option<Engine>,
 }
 
 // parse a comma separated list of column definitions contained in parens
@@ -88,26 +114,41 @@ fn column_definitions(input: RawSpan<'_>) -> ParseResult<'_, Vec<Column>> {
     )(input)
 }
 
-// parses "CREATE TABLE <table name> <column defs>
+// parses "ENGINE = <engine>"
+fn engine(input: RawSpan<'_>) -> ParseResult<'_, Engine> {
+    preceded(
+        tuple((tag_no_case("engine"), multispace0, char('='), multispace0)),
+        Engine::parse,
+    )(input)
+}
+
+// parses "CREATE TABLE <table name> <column defs> [ENGINE = <engine>]
 impl<'a> Parse<'a> for CreateStatement {
     fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
         map(
-            separated_pair(
-                preceded(
-                    tuple((
-                        tag_no_case("create"),
-                        multispace1,
-                        tag_no_case("table"),
-                        multispace1,
-                    )),
-                    identifier.context("Table Name"),
+            tuple((
+                separated_pair(
+                    preceded(
+                        tuple((
+                            tag_no_case("create"),
+                            multispace1,
+                            tag_no_case("table"),
+                            multispace1,
+                        )),
+                        identifier.context("Table Name"),
+                    ),
+                    multispace1,
+                    // column defs
+                    column_definitions,
                 ),
-                multispace1,
-                // column defs
-                column_definitions,
-            )
+                opt(preceded(multispace0, engine)),
+            ))
             .context("Create Table"),
-            |(table, columns)| Self { table, columns },
+            |((table, columns), engine)| Self {
+                table,
+                columns,
+                engine,
+            },
         )(input)
     }
 }
@@ -134,6 +175,7 @@ mod tests {
                     type_info: SqlTypeInfo::String,
                 },
             ],
+            engine: None,
         };
 
         assert_eq!(
@@ -145,4 +187,15 @@ mod tests {
             expected
         )
     }
+
+    #[test]
+    fn test_create_with_engine() {
+        let (_, create) =
+            CreateStatement::parse_from_raw("CREATE TABLE foo (col1 int) ENGINE = memory").unwrap();
+        assert_eq!(create.engine, Some(Engine::Memory));
+
+        let (_, create) =
+            CreateStatement::parse_from_raw("create table foo (col1 int) engine=BTREE").unwrap();
+        assert_eq!(create.engine, Some(Engine::BTree));
+    }
 }
@@ -0,0 +1,74 @@
+use core::fmt;
+
+// DELETE FROM foo WHERE col1 = 1
+use nom::{
+    character::complete::multispace1,
+    combinator::{map, opt},
+    error::context,
+    sequence::{preceded, tuple},
+};
+use nom_supreme::{tag::complete::tag_no_case, ParserExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::Expression,
+    parse::{identifier, Parse, ParseResult, RawSpan},
+};
+
+/// The rows to remove from a table
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct DeleteStatement {
+    pub table: String,
+    /// The optional `WHERE` predicate, every row is deleted without one
+    pub where_clause: Option<Expression>,
+}
+
+impl fmt::Display for DeleteStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DELETE FROM {}", self.table)?;
+
+        if let Some(predicate) = &self.where_clause {
+            write!(f, " WHERE {predicate}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// parses "DELETE FROM <table> [WHERE <predicate>]"
+impl<'a> Parse<'a> for DeleteStatement {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "Delete statement",
+            map(
+                tuple((
+                    tag_no_case("delete"),
+                    preceded(multispace1, tag_no_case("from")),
+                    preceded(multispace1, identifier.context("Table Name")),
+                    opt(preceded(
+                        tuple((multispace1, tag_no_case("where"), multispace1)),
+                        Expression::parse.context("Where Clause"),
+                    )),
+                )),
+                |(_, _, table, where_clause)| Self {
+                    table,
+                    where_clause,
+                },
+            ),
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delete() {
+        let (_, delete) = DeleteStatement::parse_from_raw("DELETE FROM foo WHERE a < 3").unwrap();
+        assert_eq!(delete.to_string(), "DELETE FROM foo WHERE (a < 3)");
+
+        let (_, delete) = DeleteStatement::parse_from_raw("delete from foo").unwrap();
+        assert_eq!(delete.where_clause, None);
+    }
+}
@@ -0,0 +1,86 @@
+use derive_more::Display;
+// CREATE INDEX foo_col1 ON foo USING HASH (col1)
+use nom::{
+    character::complete::{char, multispace0, multispace1},
+    combinator::map,
+    error::context,
+    sequence::{preceded, tuple},
+};
+use nom_supreme::{tag::complete::tag_no_case, ParserExt};
+use serde::{Deserialize, Serialize};
+
+use crate::parse::{comma_sep, identifier, Parse, ParseResult, RawSpan};
+
+/// The data structure backing an index
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum IndexKind {
+    #[display(fmt = "HASH")]
+    Hash,
+}
+
+// parses "hash"
+impl<'a> Parse<'a> for IndexKind {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context("Index Kind", map(tag_no_case("hash"), |_| Self::Hash))(input)
+    }
+}
+
+/// The index to create and the columns it covers
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct CreateIndexStatement {
+    pub name: String,
+    pub table: String,
+    pub kind: IndexKind,
+    pub columns: Vec<String>,
+}
+
+// parses "CREATE INDEX <name> ON <table> USING <kind> (<col>, ...)"
+impl<'a> Parse<'a> for CreateIndexStatement {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            tuple((
+                tag_no_case("create"),
+                preceded(multispace1, tag_no_case("index")),
+                preceded(multispace1, identifier.context("Index Name")),
+                preceded(multispace1, tag_no_case("on")),
+                preceded(multispace1, identifier.context("Table Name")),
+                preceded(multispace1, tag_no_case("using")),
+                preceded(multispace1, IndexKind::parse),
+                preceded(multispace0, char('(')),
+                preceded(multispace0, comma_sep(identifier).context("Index Columns")),
+                preceded(multispace0, char(')')),
+            ))
+            .context("Create Index"),
+            |(_, _, name, _, table, _, kind, _, columns, _)| Self {
+                name,
+                table,
+                kind,
+                columns,
+            },
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_create_index() {
+        let expected = CreateIndexStatement {
+            name: "foo_idx".into(),
+            table: "foo".into(),
+            kind: IndexKind::Hash,
+            columns: vec!["col1".into(), "col2".into()],
+        };
+
+        assert_eq!(
+            CreateIndexStatement::parse_from_raw(
+                "CREATE INDEX foo_idx ON foo USING HASH (col1, col2)"
+            )
+            .unwrap()
+            .1,
+            expected
+        )
+    }
+}
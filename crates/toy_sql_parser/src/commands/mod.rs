@@ -1,6 +1,17 @@
 mod create;
+mod delete;
+mod index;
 mod insert;
 mod select;
-pub use create::{Column, CreateStatement, SqlTypeInfo};
+mod transaction;
+mod update;
+pub use create::{Column, CreateStatement, Engine, SqlTypeInfo};
+pub use delete::DeleteStatement;
+pub use index::{CreateIndexStatement, IndexKind};
 pub use insert::InsertStatement;
-pub use select::SelectStatement;
+pub use select::{
+    CommonTableExpression, Distinct, Join, OrderBy, SelectItem, SelectStatement, SetOperation,
+    SetOperator, TableRef, With,
+};
+pub use transaction::{IsolationLevel, TransactionStatement};
+pub use update::UpdateStatement;
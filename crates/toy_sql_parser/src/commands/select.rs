@@ -1,47 +1,822 @@
 use core::fmt;
 
-// SELECT col1, col2 FROM foo;
-use nom::{character::complete::multispace1, error::context, sequence::tuple};
+use derive_more::Display;
+
+// SELECT col1, COUNT(*) FROM foo JOIN bar ON foo.id = bar.id WHERE col1 = 1
+//     GROUP BY col1 ORDER BY col1 DESC LIMIT 10;
+use nom::{
+    branch::alt,
+    character::complete::{char, multispace0, multispace1, u64},
+    combinator::{map, opt, verify},
+    error::context,
+    multi::many0,
+    sequence::{delimited, pair, preceded, terminated, tuple},
+};
 use nom_supreme::{tag::complete::tag_no_case, ParserExt};
 use serde::{Deserialize, Serialize};
 
-use crate::parse::{comma_sep, identifier, Parse, ParseResult, RawSpan};
+use crate::{
+    expression::{join_display, subquery, Expression},
+    parse::{comma_sep, identifier, Parse, ParseResult, RawSpan},
+};
+
+/// Words that end a clause, so they can't be used as an alias
+const KEYWORDS: &[&str] = &[
+    "select",
+    "from",
+    "where",
+    "join",
+    "inner",
+    "on",
+    "group",
+    "order",
+    "by",
+    "limit",
+    "offset",
+    "for",
+    "as",
+    "and",
+    "or",
+    "not",
+    "in",
+    "asc",
+    "desc",
+    "exists",
+    "with",
+    "union",
+    "intersect",
+    "except",
+    "case",
+    "when",
+    "then",
+    "else",
+    "end",
+    "like",
+    "ilike",
+    "between",
+];
+
+// parses " [AS] <alias>"
+fn alias(input: RawSpan<'_>) -> ParseResult<'_, Option<String>> {
+    opt(preceded(
+        pair(multispace1, opt(pair(tag_no_case("as"), multispace1))),
+        verify(identifier, |alias: &String| {
+            !KEYWORDS.contains(&alias.to_lowercase().as_str())
+        }),
+    ))(input)
+}
+
+/// A table read by a query, `<name> [[AS] <alias>]`, or a derived table
+/// `(<select>) [AS] <alias>` named by its alias
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct TableRef {
+    pub name: String,
+    pub alias: Option<String>,
+    /// The select computing the rows of a derived table
+    pub subquery: Option<Box<SelectStatement>>,
+}
+
+impl TableRef {
+    /// The name the table's columns are qualified with in the query
+    pub fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.name)
+    }
+}
+
+impl fmt::Display for TableRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.subquery {
+            Some(select) => write!(f, "({select})")?,
+            None => write!(f, "{}", self.name)?,
+        }
+        if let Some(alias) = &self.alias {
+            write!(f, " AS {alias}")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Parse<'a> for TableRef {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        alt((
+            map(
+                pair(
+                    subquery,
+                    verify(alias, |alias: &Option<String>| alias.is_some()).context("Alias"),
+                ),
+                |(select, alias)| Self {
+                    name: alias.clone().unwrap_or_default(),
+                    alias,
+                    subquery: Some(Box::new(select)),
+                },
+            ),
+            map(
+                pair(identifier.context("Table Name"), alias),
+                |(name, alias)| Self {
+                    name,
+                    alias,
+                    subquery: None,
+                },
+            ),
+        ))(input)
+    }
+}
+
+/// An item of the select list
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum SelectItem {
+    /// `*`, all the columns of every table
+    Wildcard,
+    /// `<expr> [[AS] <alias>]`
+    Expr {
+        expr: Expression,
+        alias: Option<String>,
+    },
+}
+
+impl From<Expression> for SelectItem {
+    fn from(expr: Expression) -> Self {
+        SelectItem::Expr { expr, alias: None }
+    }
+}
+
+impl fmt::Display for SelectItem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SelectItem::Wildcard => write!(f, "*"),
+            SelectItem::Expr { expr, alias: None } => write!(f, "{expr}"),
+            SelectItem::Expr {
+                expr,
+                alias: Some(alias),
+            } => write!(f, "{expr} AS {alias}"),
+        }
+    }
+}
+
+impl<'a> Parse<'a> for SelectItem {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        alt((
+            map(char('*'), |_| SelectItem::Wildcard),
+            map(pair(Expression::parse, alias), |(expr, alias)| {
+                SelectItem::Expr { expr, alias }
+            }),
+        ))(input)
+    }
+}
+
+/// `DISTINCT [ON (<expr>, ...)]`, which rows of a select are kept
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Distinct {
+    /// `DISTINCT`, one of each selected row
+    Rows,
+    /// `DISTINCT ON (<expr>, ...)`, the first row, in the `ORDER BY` order,
+    /// of the rows with equal values
+    On(Vec<Expression>),
+}
+
+impl fmt::Display for Distinct {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Distinct::Rows => write!(f, "DISTINCT"),
+            Distinct::On(exprs) => write!(f, "DISTINCT ON ({})", join_display(exprs)),
+        }
+    }
+}
+
+impl<'a> Parse<'a> for Distinct {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            preceded(
+                tag_no_case("distinct"),
+                opt(preceded(
+                    tuple((multispace1, tag_no_case("on"), multispace0)),
+                    delimited(
+                        pair(char('('), multispace0),
+                        comma_sep(Expression::parse).context("Distinct On"),
+                        pair(multispace0, char(')')),
+                    ),
+                )),
+            ),
+            |on| match on {
+                Some(exprs) => Distinct::On(exprs),
+                None => Distinct::Rows,
+            },
+        )(input)
+    }
+}
+
+/// `[INNER] JOIN <table> ON <predicate>`
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Join {
+    pub table: TableRef,
+    pub on: Expression,
+}
+
+impl fmt::Display for Join {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "JOIN {} ON {}", self.table, self.on)
+    }
+}
+
+impl<'a> Parse<'a> for Join {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            tuple((
+                opt(pair(tag_no_case("inner"), multispace1)),
+                tag_no_case("join"),
+                preceded(multispace1, TableRef::parse),
+                preceded(
+                    tuple((multispace1, tag_no_case("on"), multispace1)),
+                    Expression::parse.context("Join Condition"),
+                ),
+            )),
+            |(_, _, table, on)| Self { table, on },
+        )(input)
+    }
+}
+
+/// `<expr> [ASC | DESC]`
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct OrderBy {
+    pub expr: Expression,
+    pub descending: bool,
+}
+
+impl fmt::Display for OrderBy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.expr)?;
+        if self.descending {
+            write!(f, " DESC")?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Parse<'a> for OrderBy {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            pair(
+                Expression::parse,
+                opt(preceded(
+                    multispace1,
+                    alt((
+                        map(tag_no_case("asc"), |_| false),
+                        map(tag_no_case("desc"), |_| true),
+                    )),
+                )),
+            ),
+            |(expr, descending)| Self {
+                expr,
+                descending: descending.unwrap_or(false),
+            },
+        )(input)
+    }
+}
+
+/// `WITH [RECURSIVE] <query>, ...`, queries the select reads like tables
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct With {
+    /// `RECURSIVE`, the queries can read the rows they found so far
+    pub recursive: bool,
+    pub queries: Vec<CommonTableExpression>,
+}
+
+impl fmt::Display for With {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "WITH ")?;
+        if self.recursive {
+            write!(f, "RECURSIVE ")?;
+        }
+        write!(f, "{}", join_display(&self.queries))
+    }
+}
+
+impl<'a> Parse<'a> for With {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "With",
+            map(
+                tuple((
+                    tag_no_case("with"),
+                    opt(preceded(multispace1, tag_no_case("recursive"))),
+                    multispace1,
+                    comma_sep(CommonTableExpression::parse),
+                )),
+                |(_, recursive, _, queries)| Self {
+                    recursive: recursive.is_some(),
+                    queries,
+                },
+            ),
+        )(input)
+    }
+}
+
+/// `<name> [(<column>, ...)] AS (<select>)`
+///
+/// In a recursive `WITH`, a query whose last select reads it by its name
+/// is recursive: that select is `UNION`-ed to the others again on the new
+/// rows it finds, until it finds none.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct CommonTableExpression {
+    pub name: String,
+    /// Names given to the columns of the query, if any
+    pub columns: Vec<String>,
+    pub query: SelectStatement,
+}
+
+impl fmt::Display for CommonTableExpression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)?;
+        if !self.columns.is_empty() {
+            write!(f, " ({})", self.columns.join(", "))?;
+        }
+        write!(f, " AS ({})", self.query)
+    }
+}
+
+impl<'a> Parse<'a> for CommonTableExpression {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            tuple((
+                identifier.context("Query Name"),
+                opt(preceded(
+                    multispace0,
+                    delimited(
+                        pair(char('('), multispace0),
+                        comma_sep(identifier),
+                        pair(multispace0, char(')')),
+                    ),
+                )),
+                tuple((multispace1, tag_no_case("as"), multispace0)),
+                pair(char('('), multispace0),
+                SelectStatement::parse,
+                pair(multispace0, char(')')),
+            )),
+            |(name, columns, _, _, query, _)| Self {
+                name,
+                columns: columns.unwrap_or_default(),
+                query,
+            },
+        )(input)
+    }
+}
+
+/// How a set operation combines the rows of two selects
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum SetOperator {
+    /// The distinct rows of either
+    #[display(fmt = "UNION")]
+    Union,
+    /// The rows of both, duplicates included
+    #[display(fmt = "UNION ALL")]
+    UnionAll,
+    /// The distinct rows of the first also in the second
+    #[display(fmt = "INTERSECT")]
+    Intersect,
+    /// The distinct rows of the first not in the second
+    #[display(fmt = "EXCEPT")]
+    Except,
+}
+
+impl<'a> Parse<'a> for SetOperator {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        alt((
+            map(
+                pair(
+                    tag_no_case("union"),
+                    opt(pair(multispace1, tag_no_case("all"))),
+                ),
+                |(_, all)| match all {
+                    Some(_) => SetOperator::UnionAll,
+                    None => SetOperator::Union,
+                },
+            ),
+            map(tag_no_case("intersect"), |_| SetOperator::Intersect),
+            map(tag_no_case("except"), |_| SetOperator::Except),
+        ))(input)
+    }
+}
+
+/// `<operator> <select>`, combining the rows of the select with the ones
+/// before
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct SetOperation {
+    pub operator: SetOperator,
+    /// Only has the clauses up to `GROUP BY`
+    pub select: SelectStatement,
+}
+
+impl fmt::Display for SetOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.operator, self.select)
+    }
+}
+
+impl<'a> Parse<'a> for SetOperation {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            tuple((SetOperator::parse, multispace1, select_core)),
+            |(operator, _, select)| Self { operator, select },
+        )(input)
+    }
+}
 
 #[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub struct SelectStatement {
-    pub table: String,
-    pub fields: Vec<String>,
+    /// The queries named by a `WITH` clause before the select
+    pub with: Option<With>,
+    pub distinct: Option<Distinct>,
+    pub fields: Vec<SelectItem>,
+    /// The first table of the `FROM` clause, without one the select
+    /// computes a single row
+    pub table: Option<TableRef>,
+    /// The tables joined to it, in order
+    pub joins: Vec<Join>,
+    /// The optional `WHERE` predicate
+    pub where_clause: Option<Expression>,
+    /// `GROUP BY` expressions, the rows with equal values form a group
+    pub group_by: Vec<Expression>,
+    /// The selects whose rows are combined with the rows of this one, in
+    /// order but for `INTERSECT` coming first
+    ///
+    /// The clauses from `ORDER BY` on apply to the combined rows.
+    pub set_operations: Vec<SetOperation>,
+    pub order_by: Vec<OrderBy>,
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+    /// `FOR UPDATE`, lock the selected rows until the transaction ends
+    pub for_update: bool,
+}
+
+impl SelectStatement {
+    /// The names of the tables the select reads, in its subqueries too
+    pub fn tables(&self) -> Vec<&str> {
+        let expressions = self
+            .fields
+            .iter()
+            .filter_map(|field| match field {
+                SelectItem::Expr { expr, .. } => Some(expr),
+                SelectItem::Wildcard => None,
+            })
+            .chain(self.distinct.iter().flat_map(|distinct| match distinct {
+                Distinct::On(exprs) => exprs.as_slice(),
+                Distinct::Rows => &[],
+            }))
+            .chain(self.joins.iter().map(|join| &join.on))
+            .chain(&self.where_clause)
+            .chain(&self.group_by)
+            .chain(self.order_by.iter().map(|order| &order.expr));
+
+        let mut tables = Vec::new();
+        for query in self.with.iter().flat_map(|with| &with.queries) {
+            tables.extend(query.query.tables());
+        }
+        for operation in &self.set_operations {
+            tables.extend(operation.select.tables());
+        }
+        for table in self
+            .table
+            .iter()
+            .chain(self.joins.iter().map(|join| &join.table))
+        {
+            match &table.subquery {
+                Some(select) => tables.extend(select.tables()),
+                None => tables.push(table.name.as_str()),
+            }
+        }
+        for select in expressions.flat_map(Expression::subqueries) {
+            tables.extend(select.tables());
+        }
+        tables
+    }
 }
 
 impl fmt::Display for SelectStatement {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(with) = &self.with {
+            write!(f, "{with} ")?;
+        }
+
         write!(f, "SELECT ")?;
 
-        write!(f, "{}", self.fields.join(", "))?;
+        if let Some(distinct) = &self.distinct {
+            write!(f, "{distinct} ")?;
+        }
+
+        write!(f, "{}", join_display(&self.fields))?;
+
+        if let Some(table) = &self.table {
+            write!(f, " FROM {table}")?;
+        }
+
+        for join in &self.joins {
+            write!(f, " {join}")?;
+        }
+
+        if let Some(predicate) = &self.where_clause {
+            write!(f, " WHERE {predicate}")?;
+        }
+
+        if !self.group_by.is_empty() {
+            write!(f, " GROUP BY {}", join_display(&self.group_by))?;
+        }
+
+        for operation in &self.set_operations {
+            write!(f, " {operation}")?;
+        }
+
+        if !self.order_by.is_empty() {
+            write!(f, " ORDER BY {}", join_display(&self.order_by))?;
+        }
 
-        write!(f, " FROM ")?;
+        if let Some(limit) = self.limit {
+            write!(f, " LIMIT {limit}")?;
+        }
 
-        write!(f, "{}", self.table)?;
+        if let Some(offset) = self.offset {
+            write!(f, " OFFSET {offset}")?;
+        }
+
+        if self.for_update {
+            write!(f, " FOR UPDATE")?;
+        }
 
         Ok(())
     }
 }
 
+// parses " <keyword> <keyword> ..." before a clause
+fn keywords<'a>(words: &'static [&'static str]) -> impl FnMut(RawSpan<'a>) -> ParseResult<'a, ()> {
+    move |mut input| {
+        for word in words {
+            (input, _) = pair(multispace1, tag_no_case(*word))(input)?;
+        }
+        multispace1(input).map(|(rest, _)| (rest, ()))
+    }
+}
+
+// parses "SELECT [DISTINCT] <fields> [FROM <table> <joins>] [WHERE] [GROUP BY]", the
+// clauses of a select before the ones applying to combined rows
+fn select_core(input: RawSpan<'_>) -> ParseResult<'_, SelectStatement> {
+    map(
+        tuple((
+            pair(tag_no_case("select"), multispace1),
+            opt(terminated(Distinct::parse, multispace1)),
+            comma_sep(SelectItem::parse).context("Select Columns"),
+            opt(preceded(
+                keywords(&["from"]),
+                pair(TableRef::parse, many0(preceded(multispace1, Join::parse))),
+            )),
+            opt(preceded(
+                keywords(&["where"]),
+                Expression::parse.context("Where Clause"),
+            )),
+            opt(preceded(
+                keywords(&["group", "by"]),
+                comma_sep(Expression::parse).context("Group By"),
+            )),
+        )),
+        |(_, distinct, fields, from, where_clause, group_by)| {
+            let (table, joins) = match from {
+                Some((table, joins)) => (Some(table), joins),
+                None => (None, vec![]),
+            };
+            SelectStatement {
+                distinct,
+                fields,
+                table,
+                joins,
+                where_clause,
+                group_by: group_by.unwrap_or_default(),
+                ..Default::default()
+            }
+        },
+    )(input)
+}
+
 impl<'a> Parse<'a> for SelectStatement {
     fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
-        let (remaining_input, (_, _, fields, _, _, _, table)) = context(
-            "Select statement",
-            tuple((
-                tag_no_case("select"),
-                multispace1,
-                comma_sep(identifier).context("Select Columns"),
-                multispace1,
-                tag_no_case("from"),
-                multispace1,
-                identifier.context("Table Name"),
-            )),
-        )(input)?;
+        let (remaining_input, (with, select, set_operations, order_by, limit, offset, for_update)) =
+            context(
+                "Select statement",
+                tuple((
+                    opt(terminated(With::parse, multispace1)),
+                    select_core,
+                    many0(preceded(multispace1, SetOperation::parse)),
+                    opt(preceded(
+                        keywords(&["order", "by"]),
+                        comma_sep(OrderBy::parse).context("Order By"),
+                    )),
+                    opt(preceded(keywords(&["limit"]), u64.context("Limit"))),
+                    opt(preceded(keywords(&["offset"]), u64.context("Offset"))),
+                    opt(pair(keywords(&["for"]), tag_no_case("update"))),
+                )),
+            )(input)?;
+
+        Ok((
+            remaining_input,
+            SelectStatement {
+                with,
+                set_operations,
+                order_by: order_by.unwrap_or_default(),
+                limit,
+                offset,
+                for_update: for_update.is_some(),
+                ..select
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+    use crate::{expression::BinaryOperator, value::Value};
+
+    #[test]
+    fn test_select_where() {
+        let expected = SelectStatement {
+            table: Some(TableRef {
+                name: "t1".into(),
+                ..Default::default()
+            }),
+            fields: vec![Expression::Column("foo".into()).into()],
+            where_clause: Some(Expression::BinaryOp {
+                left: Box::new(Expression::Column("bar".into())),
+                op: BinaryOperator::Eq,
+                right: Box::new(Expression::Literal(Value::Number(
+                    BigDecimal::from_str("1").unwrap(),
+                ))),
+            }),
+            ..Default::default()
+        };
+
+        let (_, select) =
+            SelectStatement::parse_from_raw("select foo from t1 where bar = 1").unwrap();
+        assert_eq!(select, expected);
+        assert_eq!(select.to_string(), "SELECT foo FROM t1 WHERE (bar = 1)");
+    }
+
+    #[test]
+    fn test_select_for_update() {
+        let (_, select) =
+            SelectStatement::parse_from_raw("SELECT foo FROM t1 WHERE bar = 1 for update").unwrap();
+        assert!(select.for_update);
+        assert_eq!(
+            select.to_string(),
+            "SELECT foo FROM t1 WHERE (bar = 1) FOR UPDATE"
+        );
+
+        let (_, select) = SelectStatement::parse_from_raw("SELECT foo FROM t1 FOR UPDATE").unwrap();
+        assert!(select.for_update && select.where_clause.is_none());
+    }
+
+    #[test]
+    fn test_select_clauses() {
+        let query = "select f.a AS x, count(*) n, * from foo f inner join bar on f.id = bar.id \
+            join baz as b on b.id = bar.id where f.a > 1 group by f.a, b.c \
+            order by n desc, x asc limit 10 offset 5";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(select.table.as_ref().unwrap().qualifier(), "f");
+        assert_eq!(select.joins[0].table.qualifier(), "bar");
+        assert_eq!(select.joins[1].table.qualifier(), "b");
+        assert_eq!(select.group_by.len(), 2);
+        assert_eq!((select.limit, select.offset), (Some(10), Some(5)));
+        assert_eq!(
+            select.to_string(),
+            "SELECT f.a AS x, COUNT(*) AS n, * FROM foo AS f JOIN bar ON (f.id = bar.id) \
+            JOIN baz AS b ON (b.id = bar.id) WHERE (f.a > 1) GROUP BY f.a, b.c \
+            ORDER BY n DESC, x LIMIT 10 OFFSET 5"
+        );
+
+        // clause keywords are not aliases
+        let (_, select) = SelectStatement::parse_from_raw("SELECT a FROM foo LIMIT 1").unwrap();
+        assert_eq!(select.table.unwrap().alias, None);
+        assert_eq!(select.fields, vec![Expression::Column("a".into()).into()]);
+    }
+
+    #[test]
+    fn test_select_distinct() {
+        let (_, select) = SelectStatement::parse_from_raw("select distinct a, b from foo").unwrap();
+        assert_eq!(select.distinct, Some(Distinct::Rows));
+        assert_eq!(select.fields.len(), 2);
+        assert_eq!(select.to_string(), "SELECT DISTINCT a, b FROM foo");
+
+        let query = "SELECT DISTINCT ON( a , b ) a, c FROM foo ORDER BY a, c DESC";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(
+            select.distinct,
+            Some(Distinct::On(vec![
+                Expression::Column("a".into()),
+                Expression::Column("b".into())
+            ]))
+        );
+        assert_eq!(
+            select.to_string(),
+            "SELECT DISTINCT ON (a, b) a, c FROM foo ORDER BY a, c DESC"
+        );
+
+        // a column whose name starts like the keyword
+        let (_, select) = SelectStatement::parse_from_raw("SELECT distinctive FROM foo").unwrap();
+        assert_eq!(select.distinct, None);
+    }
+
+    #[test]
+    fn test_subqueries() {
+        let query = "SELECT name, (SELECT COUNT(*) FROM bar WHERE bar.foo_id = f.id) n \
+            FROM (SELECT id, name FROM foo) f \
+            WHERE EXISTS(select id from bar) AND id NOT IN ( SELECT foo_id FROM bar )";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(select.table.as_ref().unwrap().qualifier(), "f");
+        assert!(select.table.as_ref().unwrap().subquery.is_some());
+        assert!(matches!(
+            &select.fields[1],
+            SelectItem::Expr { expr: Expression::Subquery(_), alias: Some(alias) } if alias == "n"
+        ));
+        assert_eq!(
+            select.to_string(),
+            "SELECT name, (SELECT COUNT(*) FROM bar WHERE (bar.foo_id = f.id)) AS n \
+            FROM (SELECT id, name FROM foo) AS f \
+            WHERE (EXISTS (SELECT id FROM bar) AND id NOT IN (SELECT foo_id FROM bar))"
+        );
+
+        // a derived table needs a name
+        assert!(SelectStatement::parse_format_error("SELECT a FROM (SELECT a FROM foo)").is_err());
+    }
+
+    #[test]
+    fn test_with() {
+        let query = "with recursive reports(id, boss) as (select id, boss from staff where id = 1 \
+            union all select staff.id, staff.boss from staff join reports on staff.boss = reports.id), \
+            names AS (SELECT id, name FROM staff) \
+            SELECT name FROM names JOIN reports ON reports.id = names.id";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        let with = select.with.as_ref().unwrap();
+        assert!(with.recursive);
+        assert_eq!(with.queries[0].columns, ["id", "boss"]);
+        let operations = &with.queries[0].query.set_operations;
+        assert_eq!(operations[0].operator, SetOperator::UnionAll);
+        assert!(with.queries[1].query.set_operations.is_empty());
+        assert_eq!(
+            select.to_string(),
+            "WITH RECURSIVE reports (id, boss) AS (SELECT id, boss FROM staff WHERE (id = 1) \
+            UNION ALL SELECT staff.id, staff.boss FROM staff JOIN reports ON (staff.boss = reports.id)), \
+            names AS (SELECT id, name FROM staff) \
+            SELECT name FROM names JOIN reports ON (reports.id = names.id)"
+        );
+    }
+
+    #[test]
+    fn test_select_without_from() {
+        let (rest, select) = SelectStatement::parse_from_raw("SELECT 1 + 2 * 3 AS n").unwrap();
+        assert!(rest.is_empty());
+        assert_eq!(select.table, None);
+        assert_eq!(select.to_string(), "SELECT (1 + (2 * 3)) AS n");
+
+        let query = "WITH RECURSIVE r(n) AS (SELECT 1 UNION ALL SELECT n + 1 FROM r WHERE n < 5) \
+            SELECT n FROM r";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        let base = &select.with.as_ref().unwrap().queries[0].query;
+        assert_eq!(base.table, None);
+        assert!(base.set_operations[0].select.table.is_some());
+        assert!(SelectStatement::parse_format_error("SELECT 1 JOIN foo ON 1 = 1").is_err());
+    }
 
-        Ok((remaining_input, SelectStatement { table, fields }))
+    #[test]
+    fn test_set_operations() {
+        let query = "select a from foo where a > 1 union all select b from bar \
+            intersect select c from baz group by c except select d from qux order by a desc limit 3";
+        let (rest, select) = SelectStatement::parse_from_raw(query).unwrap();
+        assert!(rest.is_empty());
+        let operators: Vec<_> = select
+            .set_operations
+            .iter()
+            .map(|operation| operation.operator)
+            .collect();
+        assert_eq!(
+            operators,
+            [
+                SetOperator::UnionAll,
+                SetOperator::Intersect,
+                SetOperator::Except
+            ]
+        );
+        // the clauses after the last select apply to all of them
+        assert!(select.set_operations[2].select.order_by.is_empty());
+        assert_eq!(select.limit, Some(3));
+        assert_eq!(
+            select.to_string(),
+            "SELECT a FROM foo WHERE (a > 1) UNION ALL SELECT b FROM bar \
+            INTERSECT SELECT c FROM baz GROUP BY c EXCEPT SELECT d FROM qux ORDER BY a DESC LIMIT 3"
+        );
     }
 }
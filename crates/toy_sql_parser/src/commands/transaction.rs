@@ -0,0 +1,175 @@
+// BEGIN; ... COMMIT;
+use derive_more::Display;
+use nom::{
+    branch::alt,
+    character::complete::multispace1,
+    combinator::{map, opt},
+    error::context,
+    sequence::{pair, preceded, terminated, tuple},
+};
+use nom_supreme::tag::complete::tag_no_case;
+use serde::{Deserialize, Serialize};
+
+use crate::parse::{identifier, Parse, ParseResult, RawSpan};
+
+/// A statement controlling the current transaction
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum TransactionStatement {
+    /// `BEGIN [TRANSACTION]`
+    Begin,
+    /// `COMMIT [TRANSACTION]`
+    Commit,
+    /// `ROLLBACK [TRANSACTION]`
+    Rollback,
+    /// `SAVEPOINT <name>`
+    Savepoint(String),
+    /// `ROLLBACK [TRANSACTION] TO [SAVEPOINT] <name>`
+    RollbackTo(String),
+    /// `RELEASE [SAVEPOINT] <name>`
+    Release(String),
+    /// `SET TRANSACTION ISOLATION LEVEL <level>`
+    SetIsolationLevel(IsolationLevel),
+}
+
+/// Which anomalies concurrent transactions may observe
+#[derive(Debug, Clone, Copy, Default, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum IsolationLevel {
+    /// Every statement sees the changes committed before it started
+    #[display(fmt = "READ COMMITTED")]
+    ReadCommitted,
+    /// The transaction sees the changes committed before it started
+    #[default]
+    #[display(fmt = "REPEATABLE READ")]
+    RepeatableRead,
+    /// The transaction behaves as if it ran alone
+    #[display(fmt = "SERIALIZABLE")]
+    Serializable,
+}
+
+// parses "READ COMMITTED", "REPEATABLE READ" or "SERIALIZABLE"
+impl<'a> Parse<'a> for IsolationLevel {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "Isolation Level",
+            alt((
+                map(
+                    tuple((tag_no_case("read"), multispace1, tag_no_case("committed"))),
+                    |_| Self::ReadCommitted,
+                ),
+                map(
+                    tuple((tag_no_case("repeatable"), multispace1, tag_no_case("read"))),
+                    |_| Self::RepeatableRead,
+                ),
+                map(tag_no_case("serializable"), |_| Self::Serializable),
+            )),
+        )(input)
+    }
+}
+
+// parses "<keyword> [TRANSACTION]"
+fn keyword<'a>(keyword: &'static str) -> impl FnMut(RawSpan<'a>) -> ParseResult<'a, RawSpan<'a>> {
+    terminated(
+        tag_no_case(keyword),
+        opt(pair(multispace1, tag_no_case("transaction"))),
+    )
+}
+
+// parses "[SAVEPOINT] <name>"
+fn savepoint_name(input: RawSpan<'_>) -> ParseResult<'_, String> {
+    preceded(opt(pair(tag_no_case("savepoint"), multispace1)), identifier)(input)
+}
+
+impl<'a> Parse<'a> for TransactionStatement {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "Transaction",
+            alt((
+                map(keyword("begin"), |_| Self::Begin),
+                map(keyword("commit"), |_| Self::Commit),
+                map(
+                    preceded(
+                        tuple((
+                            keyword("rollback"),
+                            multispace1,
+                            tag_no_case("to"),
+                            multispace1,
+                        )),
+                        savepoint_name,
+                    ),
+                    Self::RollbackTo,
+                ),
+                map(keyword("rollback"), |_| Self::Rollback),
+                map(
+                    preceded(pair(tag_no_case("savepoint"), multispace1), identifier),
+                    Self::Savepoint,
+                ),
+                map(
+                    preceded(pair(tag_no_case("release"), multispace1), savepoint_name),
+                    Self::Release,
+                ),
+                map(
+                    preceded(
+                        tuple((
+                            tag_no_case("set"),
+                            multispace1,
+                            tag_no_case("transaction"),
+                            multispace1,
+                            tag_no_case("isolation"),
+                            multispace1,
+                            tag_no_case("level"),
+                            multispace1,
+                        )),
+                        IsolationLevel::parse,
+                    ),
+                    Self::SetIsolationLevel,
+                ),
+            )),
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transaction() {
+        for (input, expected) in [
+            ("BEGIN", TransactionStatement::Begin),
+            ("begin transaction", TransactionStatement::Begin),
+            ("COMMIT", TransactionStatement::Commit),
+            ("ROLLBACK TRANSACTION", TransactionStatement::Rollback),
+            ("SAVEPOINT a", TransactionStatement::Savepoint("a".into())),
+            (
+                "ROLLBACK TO SAVEPOINT a",
+                TransactionStatement::RollbackTo("a".into()),
+            ),
+            (
+                "rollback transaction to a",
+                TransactionStatement::RollbackTo("a".into()),
+            ),
+            ("RELEASE a", TransactionStatement::Release("a".into())),
+            (
+                "RELEASE SAVEPOINT a",
+                TransactionStatement::Release("a".into()),
+            ),
+            (
+                "SET TRANSACTION ISOLATION LEVEL read committed",
+                TransactionStatement::SetIsolationLevel(IsolationLevel::ReadCommitted),
+            ),
+            (
+                "set transaction isolation level REPEATABLE READ",
+                TransactionStatement::SetIsolationLevel(IsolationLevel::RepeatableRead),
+            ),
+            (
+                "SET TRANSACTION ISOLATION LEVEL SERIALIZABLE",
+                TransactionStatement::SetIsolationLevel(IsolationLevel::Serializable),
+            ),
+        ] {
+            assert_eq!(
+                TransactionStatement::parse_from_raw(input).unwrap().1,
+                expected
+            );
+        }
+    }
+}
@@ -0,0 +1,100 @@
+use core::fmt;
+
+// UPDATE foo SET col1 = 1, col2 = 'a' WHERE col3 = 2
+use nom::{
+    character::complete::{char, multispace0, multispace1},
+    combinator::{map, opt},
+    error::context,
+    sequence::{preceded, separated_pair, tuple},
+};
+use nom_supreme::{tag::complete::tag_no_case, ParserExt};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    expression::Expression,
+    parse::{comma_sep, identifier, Parse, ParseResult, RawSpan},
+};
+
+/// The rows to change and their new values
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct UpdateStatement {
+    pub table: String,
+    /// column => new value, evaluated against the old row
+    pub assignments: Vec<(String, Expression)>,
+    /// The optional `WHERE` predicate
+    pub where_clause: Option<Expression>,
+}
+
+impl fmt::Display for UpdateStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "UPDATE {} SET ", self.table)?;
+
+        let assignments: Vec<_> = self
+            .assignments
+            .iter()
+            .map(|(column, value)| format!("{column} = {value}"))
+            .collect();
+        write!(f, "{}", assignments.join(", "))?;
+
+        if let Some(predicate) = &self.where_clause {
+            write!(f, " WHERE {predicate}")?;
+        }
+
+        Ok(())
+    }
+}
+
+// parses "<col> = <expr>"
+fn assignment(input: RawSpan<'_>) -> ParseResult<'_, (String, Expression)> {
+    separated_pair(
+        identifier.context("Column Name"),
+        tuple((multispace0, char('='), multispace0)),
+        Expression::parse.context("Value"),
+    )(input)
+}
+
+// parses "UPDATE <table> SET <col> = <expr>, ... [WHERE <predicate>]"
+impl<'a> Parse<'a> for UpdateStatement {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context(
+            "Update statement",
+            map(
+                tuple((
+                    tag_no_case("update"),
+                    preceded(multispace1, identifier.context("Table Name")),
+                    preceded(multispace1, tag_no_case("set")),
+                    preceded(multispace1, comma_sep(assignment).context("Assignments")),
+                    opt(preceded(
+                        tuple((multispace1, tag_no_case("where"), multispace1)),
+                        Expression::parse.context("Where Clause"),
+                    )),
+                )),
+                |(_, table, _, assignments, where_clause)| Self {
+                    table,
+                    assignments,
+                    where_clause,
+                },
+            ),
+        )(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update() {
+        let (_, update) =
+            UpdateStatement::parse_from_raw("update foo set a = 1, b = c where c != 'x'").unwrap();
+        assert_eq!(update.table, "foo");
+        assert_eq!(update.assignments.len(), 2);
+        assert_eq!(
+            update.to_string(),
+            "UPDATE foo SET a = 1, b = c WHERE (c != 'x')"
+        );
+
+        let (_, update) = UpdateStatement::parse_from_raw("UPDATE foo SET a = 'b'").unwrap();
+        assert_eq!(update.where_clause, None);
+    }
+}
@@ -0,0 +1,1391 @@
+use core::fmt;
+
+// a = 1 AND b IN ('x', 'y')
+use derive_more::Display;
+use nom::{
+    branch::alt,
+    character::complete::{char, multispace0, multispace1, u64},
+    combinator::{map, not, opt, verify},
+    error::context,
+    multi::{many0, many1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
+};
+use nom_supreme::tag::complete::{tag, tag_no_case};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    commands::{OrderBy, SelectStatement, SqlTypeInfo},
+    parse::{comma_sep, identifier, Parse, ParseResult, RawSpan},
+    value::{parse_literal, Value},
+};
+
+/// A binary operator between two expressions
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum BinaryOperator {
+    #[display(fmt = "=")]
+    Eq,
+    #[display(fmt = "!=")]
+    NotEq,
+    #[display(fmt = "<")]
+    Lt,
+    #[display(fmt = "<=")]
+    LtEq,
+    #[display(fmt = ">")]
+    Gt,
+    #[display(fmt = ">=")]
+    GtEq,
+    #[display(fmt = "AND")]
+    And,
+    #[display(fmt = "OR")]
+    Or,
+    #[display(fmt = "+")]
+    Plus,
+    #[display(fmt = "-")]
+    Minus,
+    #[display(fmt = "*")]
+    Multiply,
+    #[display(fmt = "/")]
+    Divide,
+    #[display(fmt = "%")]
+    Modulo,
+    /// `||`, string concatenation
+    #[display(fmt = "||")]
+    Concat,
+}
+
+impl BinaryOperator {
+    /// Whether the operator compares its operands
+    pub fn is_comparison(self) -> bool {
+        matches!(
+            self,
+            BinaryOperator::Eq
+                | BinaryOperator::NotEq
+                | BinaryOperator::Lt
+                | BinaryOperator::LtEq
+                | BinaryOperator::Gt
+                | BinaryOperator::GtEq
+        )
+    }
+}
+
+/// A function computing one value from the rows of a group
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum AggregateFunction {
+    #[display(fmt = "COUNT")]
+    Count,
+    #[display(fmt = "SUM")]
+    Sum,
+    #[display(fmt = "MIN")]
+    Min,
+    #[display(fmt = "MAX")]
+    Max,
+    #[display(fmt = "AVG")]
+    Avg,
+}
+
+/// A function computing one value per row from the rows of its window
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum WindowFunction {
+    /// Position of the row in its partition, from 1
+    #[display(fmt = "ROW_NUMBER")]
+    RowNumber,
+    /// Position of the first row of the partition ordered like this one
+    #[display(fmt = "RANK")]
+    Rank,
+    /// Rank without gaps after rows ordered alike
+    #[display(fmt = "DENSE_RANK")]
+    DenseRank,
+    /// `LAG(<expr> [, <offset> [, <default>]])`, the value of a row before
+    /// this one in its partition
+    #[display(fmt = "LAG")]
+    Lag,
+    /// `LEAD(<expr> [, <offset> [, <default>]])`, the value of a row after
+    /// this one in its partition
+    #[display(fmt = "LEAD")]
+    Lead,
+    /// An aggregate of the rows of the frame
+    #[display(fmt = "{_0}")]
+    Aggregate(AggregateFunction),
+}
+
+/// One end of a window frame
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+pub enum FrameBound {
+    #[display(fmt = "UNBOUNDED PRECEDING")]
+    UnboundedPreceding,
+    #[display(fmt = "{_0} PRECEDING")]
+    Preceding(u64),
+    #[display(fmt = "CURRENT ROW")]
+    CurrentRow,
+    #[display(fmt = "{_0} FOLLOWING")]
+    Following(u64),
+    #[display(fmt = "UNBOUNDED FOLLOWING")]
+    UnboundedFollowing,
+}
+
+/// `ROWS BETWEEN <start> AND <end>`, the rows of the partition around the
+/// current one an aggregate reads
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize, Display)]
+#[display(fmt = "ROWS BETWEEN {start} AND {end}")]
+pub struct Frame {
+    pub start: FrameBound,
+    pub end: FrameBound,
+}
+
+/// `([PARTITION BY <expr>, ...] [ORDER BY <order>, ...] [<frame>])`, the
+/// rows a window function reads for each row
+///
+/// Without a frame an aggregate reads the rows of the partition up to the
+/// last one ordered like the current row, all of them without `ORDER BY`.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub partition_by: Vec<Expression>,
+    pub order_by: Vec<OrderBy>,
+    pub frame: Option<Frame>,
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut clauses = Vec::new();
+        if !self.partition_by.is_empty() {
+            clauses.push(format!("PARTITION BY {}", join_display(&self.partition_by)));
+        }
+        if !self.order_by.is_empty() {
+            clauses.push(format!("ORDER BY {}", join_display(&self.order_by)));
+        }
+        if let Some(frame) = &self.frame {
+            clauses.push(frame.to_string());
+        }
+        write!(f, "({})", clauses.join(" "))
+    }
+}
+
+pub(crate) fn join_display<T: fmt::Display>(items: &[T]) -> String {
+    let items: Vec<_> = items.iter().map(|item| item.to_string()).collect();
+    items.join(", ")
+}
+
+/// A sql expression, e.g. the predicate of a `WHERE` clause
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Expression {
+    /// A column of the tables being queried, `<column>` or `<table>.<column>`
+    Column(String),
+    /// A literal value
+    Literal(Value),
+    /// `<left> <op> <right>`
+    BinaryOp {
+        left: Box<Expression>,
+        op: BinaryOperator,
+        right: Box<Expression>,
+    },
+    /// `NOT <expr>`
+    Not(Box<Expression>),
+    /// `-<expr>`
+    Negate(Box<Expression>),
+    /// `<expr> [NOT] IN (<list>)`
+    InList {
+        expr: Box<Expression>,
+        list: Vec<Expression>,
+        negated: bool,
+    },
+    /// `<function>([DISTINCT] <expr>)`, or `COUNT(*)` if there is no
+    /// argument
+    Aggregate {
+        function: AggregateFunction,
+        arg: Option<Box<Expression>>,
+        /// Only the distinct values of the argument are aggregated
+        distinct: bool,
+    },
+    /// `(<select>)`, the value of the single column of the single row a
+    /// subquery returns
+    Subquery(Box<SelectStatement>),
+    /// `EXISTS (<select>)`, whether a subquery returns any row
+    Exists(Box<SelectStatement>),
+    /// `<expr> [NOT] IN (<select>)`
+    InSubquery {
+        expr: Box<Expression>,
+        subquery: Box<SelectStatement>,
+        negated: bool,
+    },
+    /// `<function>(<args>) OVER <window>`
+    Window {
+        function: WindowFunction,
+        args: Vec<Expression>,
+        window: Window,
+    },
+    /// `CASE [<operand>] WHEN <condition> THEN <result> ... [ELSE <result>] END`,
+    /// the result of the first condition satisfied, or equal to the operand
+    Case {
+        operand: Option<Box<Expression>>,
+        branches: Vec<(Expression, Expression)>,
+        otherwise: Option<Box<Expression>>,
+    },
+    /// `CAST(<expr> AS <type>)`
+    Cast {
+        expr: Box<Expression>,
+        type_info: SqlTypeInfo,
+    },
+    /// `<expr> [NOT] LIKE <pattern>`, or `ILIKE` ignoring case, where `%`
+    /// matches any characters and `_` a single one
+    Like {
+        expr: Box<Expression>,
+        pattern: Box<Expression>,
+        negated: bool,
+        case_insensitive: bool,
+    },
+    /// `<expr> [NOT] BETWEEN <low> AND <high>`, bounds included
+    Between {
+        expr: Box<Expression>,
+        low: Box<Expression>,
+        high: Box<Expression>,
+        negated: bool,
+    },
+}
+
+impl Expression {
+    /// Split a chain of `AND`s into its operands
+    pub fn conjuncts(&self) -> Vec<&Expression> {
+        match self {
+            Expression::BinaryOp {
+                left,
+                op: BinaryOperator::And,
+                right,
+            } => {
+                let mut conjuncts = left.conjuncts();
+                conjuncts.extend(right.conjuncts());
+                conjuncts
+            }
+            expr => vec![expr],
+        }
+    }
+
+    /// All the columns referenced by this expression, outside of its
+    /// subqueries
+    pub fn columns(&self) -> Vec<&String> {
+        match self {
+            Expression::Column(name) => vec![name],
+            Expression::Literal(_) => vec![],
+            Expression::BinaryOp { left, right, .. } => {
+                let mut columns = left.columns();
+                columns.extend(right.columns());
+                columns
+            }
+            Expression::Not(expr) | Expression::Negate(expr) => expr.columns(),
+            Expression::InList { expr, list, .. } => {
+                let mut columns = expr.columns();
+                columns.extend(list.iter().flat_map(|e| e.columns()));
+                columns
+            }
+            Expression::Aggregate { arg, .. } => {
+                arg.as_ref().map(|arg| arg.columns()).unwrap_or_default()
+            }
+            Expression::Subquery(_) | Expression::Exists(_) => vec![],
+            Expression::InSubquery { expr, .. } => expr.columns(),
+            Expression::Window { .. }
+            | Expression::Case { .. }
+            | Expression::Cast { .. }
+            | Expression::Like { .. }
+            | Expression::Between { .. } => self
+                .children()
+                .into_iter()
+                .flat_map(Expression::columns)
+                .collect(),
+        }
+    }
+
+    /// Rebuild the expression, replacing each sub expression `replace` returns
+    /// a replacement for
+    ///
+    /// Replacements are not visited again.
+    pub fn replace(&self, replace: &impl Fn(&Expression) -> Option<Expression>) -> Expression {
+        match replace(self) {
+            Some(replacement) => replacement,
+            None => self.map_children(|child| child.replace(replace)),
+        }
+    }
+
+    /// Rebuild the expression with `f` applied to each of its children
+    pub fn map_children(&self, mut f: impl FnMut(&Expression) -> Expression) -> Expression {
+        match self {
+            Expression::Column(_)
+            | Expression::Literal(_)
+            | Expression::Subquery(_)
+            | Expression::Exists(_) => self.clone(),
+            Expression::BinaryOp { left, op, right } => binary(f(left), *op, f(right)),
+            Expression::Not(expr) => Expression::Not(Box::new(f(expr))),
+            Expression::Negate(expr) => Expression::Negate(Box::new(f(expr))),
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => Expression::InList {
+                expr: Box::new(f(expr)),
+                list: list.iter().map(&mut f).collect(),
+                negated: *negated,
+            },
+            Expression::Aggregate {
+                function,
+                arg,
+                distinct,
+            } => Expression::Aggregate {
+                function: *function,
+                arg: arg.as_ref().map(|arg| Box::new(f(arg))),
+                distinct: *distinct,
+            },
+            Expression::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => Expression::InSubquery {
+                expr: Box::new(f(expr)),
+                subquery: subquery.clone(),
+                negated: *negated,
+            },
+            Expression::Window {
+                function,
+                args,
+                window,
+            } => Expression::Window {
+                function: *function,
+                args: args.iter().map(&mut f).collect(),
+                window: Window {
+                    partition_by: window.partition_by.iter().map(&mut f).collect(),
+                    order_by: window
+                        .order_by
+                        .iter()
+                        .map(|order| OrderBy {
+                            expr: f(&order.expr),
+                            descending: order.descending,
+                        })
+                        .collect(),
+                    frame: window.frame.clone(),
+                },
+            },
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => Expression::Case {
+                operand: operand.as_ref().map(|operand| Box::new(f(operand))),
+                branches: branches
+                    .iter()
+                    .map(|(condition, result)| (f(condition), f(result)))
+                    .collect(),
+                otherwise: otherwise.as_ref().map(|otherwise| Box::new(f(otherwise))),
+            },
+            Expression::Cast { expr, type_info } => Expression::Cast {
+                expr: Box::new(f(expr)),
+                type_info: *type_info,
+            },
+            Expression::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+            } => Expression::Like {
+                expr: Box::new(f(expr)),
+                pattern: Box::new(f(pattern)),
+                negated: *negated,
+                case_insensitive: *case_insensitive,
+            },
+            Expression::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => Expression::Between {
+                expr: Box::new(f(expr)),
+                low: Box::new(f(low)),
+                high: Box::new(f(high)),
+                negated: *negated,
+            },
+        }
+    }
+
+    /// The expressions this one is computed from, besides its subqueries
+    pub fn children(&self) -> Vec<&Expression> {
+        match self {
+            Expression::Column(_)
+            | Expression::Literal(_)
+            | Expression::Subquery(_)
+            | Expression::Exists(_) => vec![],
+            Expression::BinaryOp { left, right, .. } => vec![left, right],
+            Expression::Not(expr) | Expression::Negate(expr) => vec![expr],
+            Expression::InList { expr, list, .. } => {
+                let mut children = vec![expr.as_ref()];
+                children.extend(list);
+                children
+            }
+            Expression::Aggregate { arg, .. } => arg.iter().map(|arg| arg.as_ref()).collect(),
+            Expression::InSubquery { expr, .. } => vec![expr],
+            Expression::Window { args, window, .. } => args
+                .iter()
+                .chain(&window.partition_by)
+                .chain(window.order_by.iter().map(|order| &order.expr))
+                .collect(),
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => operand
+                .iter()
+                .map(|operand| operand.as_ref())
+                .chain(
+                    branches
+                        .iter()
+                        .flat_map(|(condition, result)| [condition, result]),
+                )
+                .chain(otherwise.iter().map(|otherwise| otherwise.as_ref()))
+                .collect(),
+            Expression::Cast { expr, .. } => vec![expr],
+            Expression::Like { expr, pattern, .. } => vec![expr, pattern],
+            Expression::Between {
+                expr, low, high, ..
+            } => vec![expr, low, high],
+        }
+    }
+
+    /// Whether the expression computes an aggregate of a group of rows
+    pub fn has_aggregate(&self) -> bool {
+        matches!(self, Expression::Aggregate { .. })
+            || self.children().into_iter().any(|e| e.has_aggregate())
+    }
+
+    /// Whether the expression computes a window function
+    pub fn has_window(&self) -> bool {
+        matches!(self, Expression::Window { .. })
+            || self.children().into_iter().any(|e| e.has_window())
+    }
+
+    /// Whether the expression has a subquery
+    pub fn has_subquery(&self) -> bool {
+        matches!(
+            self,
+            Expression::Subquery(_) | Expression::Exists(_) | Expression::InSubquery { .. }
+        ) || self.children().into_iter().any(|e| e.has_subquery())
+    }
+
+    /// The subqueries of the expression, not the ones nested in them
+    pub fn subqueries(&self) -> Vec<&SelectStatement> {
+        let mut subqueries: Vec<_> = self
+            .children()
+            .into_iter()
+            .flat_map(Expression::subqueries)
+            .collect();
+        match self {
+            Expression::Subquery(select)
+            | Expression::Exists(select)
+            | Expression::InSubquery {
+                subquery: select, ..
+            } => subqueries.push(select),
+            _ => {}
+        }
+        subqueries
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Column(name) => write!(f, "{name}"),
+            Expression::Literal(Value::String(s)) => write!(f, "'{s}'"),
+            Expression::Literal(value) => write!(f, "{value}"),
+            Expression::BinaryOp { left, op, right } => write!(f, "({left} {op} {right})"),
+            Expression::Not(expr) => write!(f, "NOT {expr}"),
+            Expression::Negate(expr) => write!(f, "-{expr}"),
+            Expression::InList {
+                expr,
+                list,
+                negated,
+            } => {
+                let list: Vec<_> = list.iter().map(|e| e.to_string()).collect();
+                let not = if *negated { " NOT" } else { "" };
+                write!(f, "{expr}{not} IN ({})", list.join(", "))
+            }
+            Expression::Aggregate {
+                function,
+                arg: Some(arg),
+                distinct,
+            } => {
+                let distinct = if *distinct { "DISTINCT " } else { "" };
+                write!(f, "{function}({distinct}{arg})")
+            }
+            Expression::Aggregate {
+                function,
+                arg: None,
+                ..
+            } => write!(f, "{function}(*)"),
+            Expression::Subquery(select) => write!(f, "({select})"),
+            Expression::Exists(select) => write!(f, "EXISTS ({select})"),
+            Expression::InSubquery {
+                expr,
+                subquery,
+                negated,
+            } => {
+                let not = if *negated { " NOT" } else { "" };
+                write!(f, "{expr}{not} IN ({subquery})")
+            }
+            Expression::Window {
+                function: function @ WindowFunction::Aggregate(AggregateFunction::Count),
+                args,
+                window,
+            } if args.is_empty() => write!(f, "{function}(*) OVER {window}"),
+            Expression::Window {
+                function,
+                args,
+                window,
+            } => write!(f, "{function}({}) OVER {window}", join_display(args)),
+            Expression::Case {
+                operand,
+                branches,
+                otherwise,
+            } => {
+                write!(f, "CASE")?;
+                if let Some(operand) = operand {
+                    write!(f, " {operand}")?;
+                }
+                for (condition, result) in branches {
+                    write!(f, " WHEN {condition} THEN {result}")?;
+                }
+                if let Some(otherwise) = otherwise {
+                    write!(f, " ELSE {otherwise}")?;
+                }
+                write!(f, " END")
+            }
+            Expression::Cast { expr, type_info } => {
+                write!(
+                    f,
+                    "CAST({expr} AS {})",
+                    type_info.to_string().to_uppercase()
+                )
+            }
+            Expression::Like {
+                expr,
+                pattern,
+                negated,
+                case_insensitive,
+            } => {
+                let not = if *negated { " NOT" } else { "" };
+                let like = if *case_insensitive { "ILIKE" } else { "LIKE" };
+                write!(f, "{expr}{not} {like} {pattern}")
+            }
+            Expression::Between {
+                expr,
+                low,
+                high,
+                negated,
+            } => {
+                let not = if *negated { " NOT" } else { "" };
+                write!(f, "{expr}{not} BETWEEN {low} AND {high}")
+            }
+        }
+    }
+}
+
+/// `<left> <op> <right>`
+pub fn binary(left: Expression, op: BinaryOperator, right: Expression) -> Expression {
+    Expression::BinaryOp {
+        left: Box::new(left),
+        op,
+        right: Box::new(right),
+    }
+}
+
+// parses "<column>" or "<table>.<column>"
+pub(crate) fn column_name(input: RawSpan<'_>) -> ParseResult<'_, String> {
+    map(
+        pair(identifier, opt(preceded(char('.'), identifier))),
+        |(first, second)| match second {
+            Some(column) => format!("{first}.{column}"),
+            None => first,
+        },
+    )(input)
+}
+
+fn aggregate_function(input: RawSpan<'_>) -> ParseResult<'_, AggregateFunction> {
+    alt((
+        map(tag_no_case("count"), |_| AggregateFunction::Count),
+        map(tag_no_case("sum"), |_| AggregateFunction::Sum),
+        map(tag_no_case("min"), |_| AggregateFunction::Min),
+        map(tag_no_case("max"), |_| AggregateFunction::Max),
+        map(tag_no_case("avg"), |_| AggregateFunction::Avg),
+    ))(input)
+}
+
+// parses "<function>([DISTINCT] <expr>)" or "COUNT(*)"
+fn aggregate(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    map(
+        verify(
+            tuple((
+                aggregate_function,
+                multispace0,
+                pair(char('('), multispace0),
+                opt(pair(tag_no_case("distinct"), multispace1)),
+                alt((
+                    map(char('*'), |_| None),
+                    map(Expression::parse, |arg| Some(Box::new(arg))),
+                )),
+                pair(multispace0, char(')')),
+            )),
+            // only COUNT counts rows, all of them
+            |(function, _, _, distinct, arg, _)| {
+                arg.is_some() || (*function == AggregateFunction::Count && distinct.is_none())
+            },
+        ),
+        |(function, _, _, distinct, arg, _)| Expression::Aggregate {
+            function,
+            arg,
+            distinct: distinct.is_some(),
+        },
+    )(input)
+}
+
+// parses "UNBOUNDED PRECEDING", "<n> FOLLOWING", "CURRENT ROW", ...
+fn frame_bound(input: RawSpan<'_>) -> ParseResult<'_, FrameBound> {
+    alt((
+        map(
+            tuple((
+                tag_no_case("unbounded"),
+                multispace1,
+                tag_no_case("preceding"),
+            )),
+            |_| FrameBound::UnboundedPreceding,
+        ),
+        map(
+            tuple((
+                tag_no_case("unbounded"),
+                multispace1,
+                tag_no_case("following"),
+            )),
+            |_| FrameBound::UnboundedFollowing,
+        ),
+        map(
+            tuple((tag_no_case("current"), multispace1, tag_no_case("row"))),
+            |_| FrameBound::CurrentRow,
+        ),
+        map(
+            pair(terminated(u64, multispace1), tag_no_case("preceding")),
+            |(n, _)| FrameBound::Preceding(n),
+        ),
+        map(
+            pair(terminated(u64, multispace1), tag_no_case("following")),
+            |(n, _)| FrameBound::Following(n),
+        ),
+    ))(input)
+}
+
+impl<'a> Parse<'a> for Frame {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        let between = map(
+            tuple((
+                tag_no_case("between"),
+                multispace1,
+                frame_bound,
+                tuple((multispace1, tag_no_case("and"), multispace1)),
+                frame_bound,
+            )),
+            |(_, _, start, _, end)| Frame { start, end },
+        );
+        // a single bound is the start of the frame ending at the current row
+        let start = map(frame_bound, |start| Frame {
+            start,
+            end: FrameBound::CurrentRow,
+        });
+        context(
+            "Frame",
+            verify(
+                preceded(
+                    pair(tag_no_case("rows"), multispace1),
+                    alt((between, start)),
+                ),
+                |frame| {
+                    frame.start != FrameBound::UnboundedFollowing
+                        && frame.end != FrameBound::UnboundedPreceding
+                },
+            ),
+        )(input)
+    }
+}
+
+impl<'a> Parse<'a> for Window {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        map(
+            delimited(
+                pair(char('('), multispace0),
+                tuple((
+                    opt(terminated(
+                        preceded(
+                            tuple((
+                                tag_no_case("partition"),
+                                multispace1,
+                                tag_no_case("by"),
+                                multispace1,
+                            )),
+                            comma_sep(Expression::parse),
+                        ),
+                        multispace0,
+                    )),
+                    opt(terminated(
+                        preceded(
+                            tuple((
+                                tag_no_case("order"),
+                                multispace1,
+                                tag_no_case("by"),
+                                multispace1,
+                            )),
+                            comma_sep(OrderBy::parse),
+                        ),
+                        multispace0,
+                    )),
+                    opt(terminated(Frame::parse, multispace0)),
+                )),
+                char(')'),
+            ),
+            |(partition_by, order_by, frame)| Window {
+                partition_by: partition_by.unwrap_or_default(),
+                order_by: order_by.unwrap_or_default(),
+                frame,
+            },
+        )(input)
+    }
+}
+
+// parses "<function>(<args>) OVER <window>"
+fn window_function(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let ranking = map(
+        tuple((
+            alt((
+                map(tag_no_case("row_number"), |_| WindowFunction::RowNumber),
+                map(tag_no_case("rank"), |_| WindowFunction::Rank),
+                map(tag_no_case("dense_rank"), |_| WindowFunction::DenseRank),
+            )),
+            multispace0,
+            pair(char('('), multispace0),
+            char(')'),
+        )),
+        |(function, _, _, _)| (function, vec![]),
+    );
+    let offset = map(
+        tuple((
+            alt((
+                map(tag_no_case("lag"), |_| WindowFunction::Lag),
+                map(tag_no_case("lead"), |_| WindowFunction::Lead),
+            )),
+            multispace0,
+            delimited(
+                pair(char('('), multispace0),
+                comma_sep(Expression::parse),
+                pair(multispace0, char(')')),
+            ),
+        )),
+        |(function, _, args)| (function, args),
+    );
+    // the values of the frame are distinct anyway
+    let aggregated = map(
+        verify(aggregate, |expr| {
+            !matches!(expr, Expression::Aggregate { distinct: true, .. })
+        }),
+        |expr| match expr {
+            Expression::Aggregate { function, arg, .. } => (
+                WindowFunction::Aggregate(function),
+                arg.map(|arg| vec![*arg]).unwrap_or_default(),
+            ),
+            _ => unreachable!("parsed an aggregate"),
+        },
+    );
+
+    map(
+        pair(
+            alt((ranking, offset, aggregated)),
+            preceded(
+                tuple((multispace0, tag_no_case("over"), multispace0)),
+                Window::parse,
+            ),
+        ),
+        |((function, args), window)| Expression::Window {
+            function,
+            args,
+            window,
+        },
+    )(input)
+}
+
+// parses "(<select>)"
+pub(crate) fn subquery(input: RawSpan<'_>) -> ParseResult<'_, SelectStatement> {
+    delimited(
+        pair(char('('), multispace0),
+        SelectStatement::parse,
+        pair(multispace0, char(')')),
+    )(input)
+}
+
+// parses a literal, a column name, an aggregate, a subquery or a
+// parenthesized expression
+fn primary(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    alt((
+        map(subquery, |select| Expression::Subquery(Box::new(select))),
+        map(
+            preceded(pair(tag_no_case("exists"), multispace0), subquery),
+            |select| Expression::Exists(Box::new(select)),
+        ),
+        delimited(
+            pair(char('('), multispace0),
+            Expression::parse,
+            pair(multispace0, char(')')),
+        ),
+        map(parse_literal, Expression::Literal),
+        case,
+        cast,
+        window_function,
+        aggregate,
+        map(column_name, Expression::Column),
+    ))(input)
+}
+
+fn comparison_operator(input: RawSpan<'_>) -> ParseResult<'_, BinaryOperator> {
+    // longer operators first so `<=` doesn't parse as `<`
+    alt((
+        map(tag("<="), |_| BinaryOperator::LtEq),
+        map(tag(">="), |_| BinaryOperator::GtEq),
+        map(tag("!="), |_| BinaryOperator::NotEq),
+        map(tag("<>"), |_| BinaryOperator::NotEq),
+        map(tag("="), |_| BinaryOperator::Eq),
+        map(tag("<"), |_| BinaryOperator::Lt),
+        map(tag(">"), |_| BinaryOperator::Gt),
+    ))(input)
+}
+
+// parses "[NOT] IN (<expr>, ...)" or "[NOT] IN (<select>)"
+fn in_list(
+    input: RawSpan<'_>,
+) -> ParseResult<'_, (bool, Result<SelectStatement, Vec<Expression>>)> {
+    map(
+        tuple((
+            opt(pair(tag_no_case("not"), multispace1)),
+            tag_no_case("in"),
+            multispace0,
+            alt((
+                map(subquery, Ok),
+                map(
+                    delimited(
+                        pair(char('('), multispace0),
+                        comma_sep(Expression::parse),
+                        pair(multispace0, char(')')),
+                    ),
+                    Err,
+                ),
+            )),
+        )),
+        |(not, _, _, list)| (not.is_some(), list),
+    )(input)
+}
+
+// parses "-<expr>" or a primary expression
+fn unary(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    alt((
+        map(preceded(pair(char('-'), multispace0), unary), |expr| {
+            Expression::Negate(Box::new(expr))
+        }),
+        primary,
+    ))(input)
+}
+
+// parses "<expr> * <expr> / <expr> % ..."
+fn multiplicative(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (rest, (first, others)) = pair(
+        unary,
+        many0(pair(
+            preceded(
+                multispace0,
+                alt((
+                    map(char('*'), |_| BinaryOperator::Multiply),
+                    map(char('/'), |_| BinaryOperator::Divide),
+                    map(char('%'), |_| BinaryOperator::Modulo),
+                )),
+            ),
+            preceded(multispace0, unary),
+        )),
+    )(input)?;
+
+    let expr = others
+        .into_iter()
+        .fold(first, |left, (op, right)| binary(left, op, right));
+    Ok((rest, expr))
+}
+
+// parses "<expr> + <expr> - ..."
+fn additive(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (rest, (first, others)) = pair(
+        multiplicative,
+        many0(pair(
+            preceded(
+                multispace0,
+                alt((
+                    map(char('+'), |_| BinaryOperator::Plus),
+                    map(char('-'), |_| BinaryOperator::Minus),
+                )),
+            ),
+            preceded(multispace0, multiplicative),
+        )),
+    )(input)?;
+
+    let expr = others
+        .into_iter()
+        .fold(first, |left, (op, right)| binary(left, op, right));
+    Ok((rest, expr))
+}
+
+// parses "<expr> || <expr> || ..."
+fn concatenation(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (rest, (first, others)) = pair(
+        additive,
+        many0(preceded(
+            tuple((multispace0, tag("||"), multispace0)),
+            additive,
+        )),
+    )(input)?;
+
+    let expr = others.into_iter().fold(first, |left, right| {
+        binary(left, BinaryOperator::Concat, right)
+    });
+    Ok((rest, expr))
+}
+
+// parses "CASE [<operand>] WHEN <expr> THEN <expr> ... [ELSE <expr>] END"
+fn case(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    map(
+        tuple((
+            pair(tag_no_case("case"), multispace1),
+            opt(terminated(
+                preceded(not(tag_no_case("when")), Expression::parse),
+                multispace1,
+            )),
+            many1(map(
+                tuple((
+                    pair(tag_no_case("when"), multispace1),
+                    Expression::parse,
+                    tuple((multispace1, tag_no_case("then"), multispace1)),
+                    Expression::parse,
+                    multispace1,
+                )),
+                |(_, condition, _, result, _)| (condition, result),
+            )),
+            opt(delimited(
+                pair(tag_no_case("else"), multispace1),
+                Expression::parse,
+                multispace1,
+            )),
+            tag_no_case("end"),
+        )),
+        |(_, operand, branches, otherwise, _)| Expression::Case {
+            operand: operand.map(Box::new),
+            branches,
+            otherwise: otherwise.map(Box::new),
+        },
+    )(input)
+}
+
+// parses "CAST(<expr> AS <type>)"
+fn cast(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    map(
+        tuple((
+            tag_no_case("cast"),
+            multispace0,
+            pair(char('('), multispace0),
+            Expression::parse,
+            tuple((multispace1, tag_no_case("as"), multispace1)),
+            SqlTypeInfo::parse,
+            pair(multispace0, char(')')),
+        )),
+        |(_, _, _, expr, _, type_info, _)| Expression::Cast {
+            expr: Box::new(expr),
+            type_info,
+        },
+    )(input)
+}
+
+// parses "[NOT] BETWEEN <expr> AND <expr>"
+fn between(input: RawSpan<'_>) -> ParseResult<'_, (bool, Expression, Expression)> {
+    map(
+        tuple((
+            opt(pair(tag_no_case("not"), multispace1)),
+            pair(tag_no_case("between"), multispace1),
+            concatenation,
+            tuple((multispace1, tag_no_case("and"), multispace1)),
+            concatenation,
+        )),
+        |(not, _, low, _, high)| (not.is_some(), low, high),
+    )(input)
+}
+
+// parses "[NOT] LIKE <expr>" or "[NOT] ILIKE <expr>"
+fn like(input: RawSpan<'_>) -> ParseResult<'_, (bool, bool, Expression)> {
+    map(
+        tuple((
+            opt(pair(tag_no_case("not"), multispace1)),
+            alt((
+                map(tag_no_case("like"), |_| false),
+                map(tag_no_case("ilike"), |_| true),
+            )),
+            multispace1,
+            concatenation,
+        )),
+        |(not, case_insensitive, _, pattern)| (not.is_some(), case_insensitive, pattern),
+    )(input)
+}
+
+// parses "<expr> [<op> <expr> | [NOT] IN (...) | [NOT] BETWEEN ... | [NOT] LIKE ...] ...",
+// comparing the result of the comparisons before
+fn comparison(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (mut rest, mut left) = concatenation(input)?;
+
+    loop {
+        let (remaining, compared) = opt(preceded(
+            multispace0,
+            alt((
+                map(
+                    pair(comparison_operator, preceded(multispace0, concatenation)),
+                    |(op, right)| binary(left.clone(), op, right),
+                ),
+                map(between, |(negated, low, high)| Expression::Between {
+                    expr: Box::new(left.clone()),
+                    low: Box::new(low),
+                    high: Box::new(high),
+                    negated,
+                }),
+                map(like, |(negated, case_insensitive, pattern)| {
+                    Expression::Like {
+                        expr: Box::new(left.clone()),
+                        pattern: Box::new(pattern),
+                        negated,
+                        case_insensitive,
+                    }
+                }),
+                map(in_list, |(negated, list)| match list {
+                    Ok(subquery) => Expression::InSubquery {
+                        expr: Box::new(left.clone()),
+                        subquery: Box::new(subquery),
+                        negated,
+                    },
+                    Err(list) => Expression::InList {
+                        expr: Box::new(left.clone()),
+                        list,
+                        negated,
+                    },
+                }),
+            )),
+        ))(rest)?;
+        match compared {
+            Some(compared) => (rest, left) = (remaining, compared),
+            None => return Ok((rest, left)),
+        }
+    }
+}
+
+// parses "NOT <expr>" or a comparison
+fn negation(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    alt((
+        map(
+            preceded(pair(tag_no_case("not"), multispace1), negation),
+            |expr| Expression::Not(Box::new(expr)),
+        ),
+        comparison,
+    ))(input)
+}
+
+// parses "<expr> AND <expr> AND ..."
+fn conjunction(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (rest, (first, others)) = pair(
+        negation,
+        many0(preceded(
+            tuple((multispace1, tag_no_case("and"), multispace1)),
+            negation,
+        )),
+    )(input)?;
+
+    let expr = others.into_iter().fold(first, |left, right| {
+        binary(left, BinaryOperator::And, right)
+    });
+    Ok((rest, expr))
+}
+
+// parses "<expr> OR <expr> OR ..."
+fn disjunction(input: RawSpan<'_>) -> ParseResult<'_, Expression> {
+    let (rest, (first, others)) = pair(
+        conjunction,
+        many0(preceded(
+            tuple((multispace1, tag_no_case("or"), multispace1)),
+            conjunction,
+        )),
+    )(input)?;
+
+    let expr = others
+        .into_iter()
+        .fold(first, |left, right| binary(left, BinaryOperator::Or, right));
+    Ok((rest, expr))
+}
+
+impl<'a> Parse<'a> for Expression {
+    fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
+        context("Expression", disjunction)(input)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+
+    use super::*;
+
+    fn number(n: &str) -> Expression {
+        Expression::Literal(Value::Number(BigDecimal::from_str(n).unwrap()))
+    }
+
+    #[test]
+    fn test_precedence() {
+        let expected = binary(
+            binary(
+                Expression::Column("a".into()),
+                BinaryOperator::Eq,
+                number("1"),
+            ),
+            BinaryOperator::Or,
+            binary(
+                binary(
+                    Expression::Column("b".into()),
+                    BinaryOperator::GtEq,
+                    number("2"),
+                ),
+                BinaryOperator::And,
+                Expression::Not(Box::new(binary(
+                    Expression::Column("c".into()),
+                    BinaryOperator::NotEq,
+                    Expression::Literal(Value::String("x".into())),
+                ))),
+            ),
+        );
+
+        assert_eq!(
+            Expression::parse_from_raw("a = 1 OR b >= 2 and not c <> 'x'")
+                .unwrap()
+                .1,
+            expected
+        )
+    }
+
+    #[test]
+    fn test_in_list() {
+        let expected = Expression::InList {
+            expr: Box::new(Expression::Column("a".into())),
+            list: vec![number("1"), number("2")],
+            negated: true,
+        };
+
+        assert_eq!(
+            Expression::parse_from_raw("a NOT IN (1, 2)").unwrap().1,
+            expected
+        )
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let column = |name: &str| Expression::Column(name.into());
+        let expected = binary(
+            binary(
+                binary(
+                    column("a"),
+                    BinaryOperator::Plus,
+                    binary(column("b"), BinaryOperator::Multiply, number("2")),
+                ),
+                BinaryOperator::Concat,
+                Expression::Literal(Value::String("!".into())),
+            ),
+            BinaryOperator::Gt,
+            binary(
+                binary(column("c"), BinaryOperator::Minus, number("1")),
+                BinaryOperator::Modulo,
+                number("3"),
+            ),
+        );
+
+        let (_, expr) = Expression::parse_from_raw("a + b*2 || '!' > (c - 1) % 3").unwrap();
+        assert_eq!(expr, expected);
+        assert_eq!(expr.to_string(), "(((a + (b * 2)) || '!') > ((c - 1) % 3))");
+    }
+
+    #[test]
+    fn test_unary_minus_and_chained_comparisons() {
+        let (_, expr) = Expression::parse_from_raw("-a * 2 - -(b + 1)").unwrap();
+        assert_eq!(
+            expr,
+            binary(
+                binary(
+                    Expression::Negate(Box::new(Expression::Column("a".into()))),
+                    BinaryOperator::Multiply,
+                    number("2"),
+                ),
+                BinaryOperator::Minus,
+                Expression::Negate(Box::new(binary(
+                    Expression::Column("b".into()),
+                    BinaryOperator::Plus,
+                    number("1"),
+                ))),
+            )
+        );
+        assert_eq!(expr.to_string(), "((-a * 2) - -(b + 1))");
+
+        // comparisons compare the result of the ones before them
+        let (_, expr) = Expression::parse_from_raw("a > -5 = true != b IN (1)").unwrap();
+        assert_eq!(expr.to_string(), "(((a > -5) = true) != b) IN (1)");
+    }
+
+    #[test]
+    fn test_case_cast_like_between() {
+        let (_, expr) = Expression::parse_from_raw(
+            "case when a between 1 and 2 then 'low' when a not like 'x%' then b else c end",
+        )
+        .unwrap();
+        assert_eq!(
+            expr,
+            Expression::Case {
+                operand: None,
+                branches: vec![
+                    (
+                        Expression::Between {
+                            expr: Box::new(Expression::Column("a".into())),
+                            low: Box::new(number("1")),
+                            high: Box::new(number("2")),
+                            negated: false,
+                        },
+                        Expression::Literal(Value::String("low".into())),
+                    ),
+                    (
+                        Expression::Like {
+                            expr: Box::new(Expression::Column("a".into())),
+                            pattern: Box::new(Expression::Literal(Value::String("x%".into()))),
+                            negated: true,
+                            case_insensitive: false,
+                        },
+                        Expression::Column("b".into()),
+                    ),
+                ],
+                otherwise: Some(Box::new(Expression::Column("c".into()))),
+            }
+        );
+        assert_eq!(expr.columns(), ["a", "a", "b", "c"]);
+
+        // BETWEEN bounds end before AND
+        let (_, expr) =
+            Expression::parse_from_raw("a BETWEEN b AND c + 1 AND d ILIKE 'y'").unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "(a BETWEEN b AND (c + 1) AND d ILIKE 'y')"
+        );
+
+        let (_, expr) =
+            Expression::parse_from_raw("CASE a WHEN 1 THEN cast(b as string) END").unwrap();
+        assert_eq!(expr.to_string(), "CASE a WHEN 1 THEN CAST(b AS STRING) END");
+        assert!(Expression::parse_format_error("CASE ELSE 1 END").is_err());
+        assert!(Expression::parse_format_error("CAST(a AS float)").is_err());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let (_, expr) = Expression::parse_from_raw("count( * ) > Sum(t.a)").unwrap();
+        assert_eq!(
+            expr,
+            binary(
+                Expression::Aggregate {
+                    function: AggregateFunction::Count,
+                    arg: None,
+                    distinct: false,
+                },
+                BinaryOperator::Gt,
+                Expression::Aggregate {
+                    function: AggregateFunction::Sum,
+                    arg: Some(Box::new(Expression::Column("t.a".into()))),
+                    distinct: false,
+                },
+            )
+        );
+        assert_eq!(expr.to_string(), "(COUNT(*) > SUM(t.a))");
+        assert!(expr.has_aggregate());
+
+        // a column named like a function is still a column
+        let (_, expr) = Expression::parse_from_raw("count").unwrap();
+        assert_eq!(expr, Expression::Column("count".into()));
+        assert!(Expression::parse_format_error("max(*)").is_err());
+
+        let (_, expr) = Expression::parse_from_raw("COUNT( distinct a)").unwrap();
+        assert_eq!(
+            expr,
+            Expression::Aggregate {
+                function: AggregateFunction::Count,
+                arg: Some(Box::new(Expression::Column("a".into()))),
+                distinct: true,
+            }
+        );
+        assert_eq!(expr.to_string(), "COUNT(DISTINCT a)");
+        // a column whose name starts like the keyword
+        let (_, expr) = Expression::parse_from_raw("sum(distinctive)").unwrap();
+        assert_eq!(expr.to_string(), "SUM(distinctive)");
+        assert!(Expression::parse_format_error("count(distinct *)").is_err());
+    }
+
+    #[test]
+    fn test_window() {
+        let (_, expr) = Expression::parse_from_raw(
+            "sum(t.a) over ( partition by b, c order by d desc rows between 2 preceding and current row )",
+        )
+        .unwrap();
+        assert_eq!(
+            expr,
+            Expression::Window {
+                function: WindowFunction::Aggregate(AggregateFunction::Sum),
+                args: vec![Expression::Column("t.a".into())],
+                window: Window {
+                    partition_by: vec![
+                        Expression::Column("b".into()),
+                        Expression::Column("c".into())
+                    ],
+                    order_by: vec![OrderBy {
+                        expr: Expression::Column("d".into()),
+                        descending: true,
+                    }],
+                    frame: Some(Frame {
+                        start: FrameBound::Preceding(2),
+                        end: FrameBound::CurrentRow,
+                    }),
+                },
+            }
+        );
+        assert_eq!(
+            expr.to_string(),
+            "SUM(t.a) OVER (PARTITION BY b, c ORDER BY d DESC ROWS BETWEEN 2 PRECEDING AND CURRENT ROW)"
+        );
+        assert!(expr.has_window() && !expr.has_aggregate());
+        assert_eq!(expr.columns(), ["t.a", "b", "c", "d"]);
+
+        let (_, expr) = Expression::parse_from_raw("ROW_NUMBER() OVER ()").unwrap();
+        assert_eq!(expr.to_string(), "ROW_NUMBER() OVER ()");
+        let (_, expr) =
+            Expression::parse_from_raw("lag(a, 2, 0) OVER (ORDER BY b ROWS UNBOUNDED PRECEDING)")
+                .unwrap();
+        assert_eq!(
+            expr.to_string(),
+            "LAG(a, 2, 0) OVER (ORDER BY b ROWS BETWEEN UNBOUNDED PRECEDING AND CURRENT ROW)"
+        );
+        let (_, expr) = Expression::parse_from_raw("count(*) over (partition by a)").unwrap();
+        assert_eq!(expr.to_string(), "COUNT(*) OVER (PARTITION BY a)");
+
+        // frames can't start after their end
+        assert!(Expression::parse_format_error(
+            "SUM(a) OVER (ROWS BETWEEN UNBOUNDED FOLLOWING AND CURRENT ROW)"
+        )
+        .is_err());
+        assert!(Expression::parse_format_error("COUNT(DISTINCT a) OVER ()").is_err());
+        assert!(Expression::parse_format_error("RANK(a) OVER ()").is_err());
+    }
+}
@@ -1,6 +1,7 @@
 pub mod ast;
 pub mod commands;
 pub mod error;
+pub mod expression;
 pub mod parse;
 pub mod value;
 pub use commands::{Column, SqlTypeInfo};
@@ -21,9 +21,10 @@ pub type ParseResult<'a, T> = IResult<RawSpan<'a>, T, MyParseError<'a>>;
 
 /// Parse a un quoted sql identifier
 pub(crate) fn identifier(i: RawSpan) -> ParseResult<String> {
-    map(take_while1(|c: char| c.is_alphanumeric()), |s: RawSpan| {
-        s.fragment().to_string()
-    })(i)
+    map(
+        take_while1(|c: char| c.is_alphanumeric() || c == '_'),
+        |s: RawSpan| s.fragment().to_string(),
+    )(i)
 }
 
 /// Implement the parse function to more easily convert a span to sql command
@@ -4,21 +4,44 @@ use bigdecimal::BigDecimal;
 use derive_more::Display;
 use nom::{
     branch::alt,
-    bytes::complete::{take_until, take_while},
-    character::complete::multispace0,
+    bytes::complete::{take_until, take_while1},
+    character::complete::{multispace0, satisfy},
+    combinator::not,
     error::context,
     sequence::{preceded, terminated, tuple},
     Parser,
 };
-use nom_supreme::tag::complete::tag;
+use nom_supreme::tag::complete::{tag, tag_no_case};
 use serde::{Deserialize, Serialize};
 
 use crate::parse::{peek_then_cut, Parse, ParseResult, RawSpan};
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize, Display)]
 pub enum Value {
-    Number(BigDecimal), // TODO: should we make literals for ints vs floats?
+    Number(#[serde(with = "decimal_string")] BigDecimal), // TODO: should we make literals for ints vs floats?
     String(String),
+    Bool(bool),
+    /// No value, e.g. the sum of no rows
+    #[display(fmt = "NULL")]
+    Null,
+}
+
+/// bigdecimal deserializes through `deserialize_any`, which formats that
+/// aren't self describing (like bincode) don't support, so go through a string
+mod decimal_string {
+    use std::str::FromStr;
+
+    use bigdecimal::BigDecimal;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(n: &BigDecimal, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&n.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<BigDecimal, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        BigDecimal::from_str(&s).map_err(D::Error::custom)
+    }
 }
 
 /// Parse a single quoted string value
@@ -40,7 +63,7 @@ fn parse_string_value(input: RawSpan<'_>) -> ParseResult<'_, Value> {
 /// TODO: handle floats
 fn parse_number_value(input: RawSpan<'_>) -> ParseResult<'_, Value> {
     let (remaining, digits) =
-        context("Number Literal", take_while(|c: char| c.is_numeric()))(input)?;
+        context("Number Literal", take_while1(|c: char| c.is_numeric()))(input)?;
 
     let digits = digits.fragment();
 
@@ -50,19 +73,47 @@ fn parse_number_value(input: RawSpan<'_>) -> ParseResult<'_, Value> {
     ))
 }
 
+/// Parse `TRUE` or `FALSE`, but not a longer word starting with them
+fn parse_bool_value(input: RawSpan<'_>) -> ParseResult<'_, Value> {
+    context(
+        "Bool Literal",
+        terminated(
+            alt((
+                tag_no_case("true").map(|_| Value::Bool(true)),
+                tag_no_case("false").map(|_| Value::Bool(false)),
+            )),
+            not(satisfy(|c| c.is_alphanumeric() || c == '_')),
+        ),
+    )(input)
+}
+
+/// Parse `NULL`, but not a longer word starting with it
+fn parse_null_value(input: RawSpan<'_>) -> ParseResult<'_, Value> {
+    context(
+        "Null Literal",
+        terminated(
+            tag_no_case("null").map(|_| Value::Null),
+            not(satisfy(|c| c.is_alphanumeric() || c == '_')),
+        ),
+    )(input)
+}
+
 /// If string (has single quote) -> parse_string_value
-/// else -> parse_number_value
+/// else -> parse_bool_value, parse_null_value or parse_number_value
+pub(crate) fn parse_literal(input: RawSpan<'_>) -> ParseResult<'_, Value> {
+    alt((
+        peek_then_cut("'", parse_string_value),
+        parse_bool_value,
+        parse_null_value,
+        parse_number_value,
+    ))(input)
+}
+
 impl<'a> Parse<'a> for Value {
     fn parse(input: RawSpan<'a>) -> ParseResult<'a, Self> {
         context(
             "Value",
-            preceded(
-                multispace0,
-                terminated(
-                    alt((peek_then_cut("'", parse_string_value), parse_number_value)),
-                    multispace0,
-                ),
-            ),
+            preceded(multispace0, terminated(parse_literal, multispace0)),
         )(input)
     }
 }
@@ -72,6 +123,8 @@ impl<'a> Into<String> for Value {
         match self {
             Value::String(s) => s.to_string(),
             Value::Number(n) => n.to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Null => "NULL".to_string(),
         }
     }
 }
@@ -99,4 +152,21 @@ mod tests {
 
         assert_eq!(Value::parse_from_raw("123456").unwrap().1, expected)
     }
+
+    #[test]
+    fn test_bool() {
+        assert_eq!(Value::parse_from_raw("TRUE").unwrap().1, Value::Bool(true));
+        assert_eq!(
+            Value::parse_from_raw("false ").unwrap().1,
+            Value::Bool(false)
+        );
+        assert!(Value::parse_from_raw("trueish").is_err());
+    }
+
+    #[test]
+    fn test_null() {
+        assert_eq!(Value::parse_from_raw("NULL").unwrap().1, Value::Null);
+        assert_eq!(Value::parse_from_raw("null ").unwrap().1, Value::Null);
+        assert!(Value::parse_from_raw("nullable").is_err());
+    }
 }
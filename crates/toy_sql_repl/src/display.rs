@@ -1,7 +1,8 @@
 use tabled::builder::Builder;
-use toy_sql_execution::ExecResponse;
+use toy_sql_execution::{ExecResponse, QueryExecutionError};
 
-pub fn display_response(res: ExecResponse) {
+/// Print a response, failing if its rows can't be computed
+pub fn display_response(res: ExecResponse) -> Result<(), QueryExecutionError> {
     match res {
         ExecResponse::Select(table_iter) => {
             let mut builder = Builder::default();
@@ -15,11 +16,34 @@ pub fn display_response(res: ExecResponse) {
                 .collect();
 
             builder.set_header(&columns);
+            // columns can share a name, values are taken by position
             for row in table_iter {
-                builder.push_record(columns.iter().map(|col| row.get(col)));
+                let row = row?;
+                builder.push_record(row.values().iter().cloned());
+            }
+            println!("{}", builder.build())
+        }
+        ExecResponse::Explain(plan) => {
+            let mut builder = Builder::default();
+
+            let mut header = vec!["plan", "estimated rows"];
+            if plan.analyzed() {
+                header.extend(["rows", "time"]);
+            }
+            builder.set_header(header);
+            for node in &plan.nodes {
+                let mut record = vec![
+                    format!("{}{}", "  ".repeat(node.depth), node.description),
+                    format!("{:.0}", node.estimated_rows),
+                ];
+                if let Some(actual) = node.actual {
+                    record.extend([actual.rows.to_string(), format!("{:.3?}", actual.elapsed)]);
+                }
+                builder.push_record(record);
             }
             println!("{}", builder.build())
         }
         _ => println!("{res}"),
     }
+    Ok(())
 }
@@ -11,8 +11,8 @@ const HISTORY_FILE: &str = "./history.txt";
 mod display;
 
 fn display_exec_res(res: Result<ExecResponse, SQLError>) {
-    match res {
-        Ok(exec_res) => display_response(exec_res),
+    match res.and_then(|exec_res| Ok(display_response(exec_res)?)) {
+        Ok(()) => {}
         Err(e) => {
             let mut s = String::new();
             GraphicalReportHandler::new()
@@ -62,7 +62,11 @@ fn main() -> miette::Result<()> {
         println!("No previous history.");
     }
 
-    let mut exec = toy_sql_execution::Execution::new();
+    // tables are kept in memory unless a database file is passed
+    let mut exec = match std::env::args().nth(1) {
+        Some(path) => Execution::open(path).wrap_err("Opening database file")?,
+        None => Execution::new(),
+    };
 
     loop {
         let readline = rl.readline(">> ");